@@ -42,7 +42,7 @@ export async function importPassword(getDecryptPassword) {
     let decryptPassword = await getDecryptPassword();
     if (decryptPassword === null) return false;
 
-    let count = await rpc.import_password(store.masterPassword, decryptPassword.password, data);
+    let count = await rpc.import_password(store.masterPassword, decryptPassword.password, data, null);
     let msg = "导入了 " + count.insert + " 个密码";
     if (count.ignore > 0) {
         msg += ", 忽略了 " + count.ignore + " 个重复密码";
@@ -59,10 +59,10 @@ export async function importPassword(getDecryptPassword) {
 export async function exportPassword() {
     if (isWebView()) {
         let file = getCacheDir() + "/" + (new Date()).getTime();
-        await rpc.export_password(store.masterPassword, file);
+        await rpc.export_password(store.masterPassword, file, null);
         saveExportFile(file);
     } else {
-        let data = await rpc.export_password(store.masterPassword, null);
+        let data = await rpc.export_password(store.masterPassword, null, null);
         download(makeExportFilename(), JSON.stringify(data));
     }
 }
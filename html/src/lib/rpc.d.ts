@@ -2,47 +2,246 @@ declare class Rpc {
     // 是否设置了主密码
     is_master_password_set(): Promise<boolean>;
 
-    // 设置主密码
-    set_master_password(master_password: string): Promise<void>;
+    // 设置主密码，recovery 不为 null 时生成恢复密钥，只返回这一次
+    set_master_password(master_password: MasterPassword, recovery: RecoveryOption | null): Promise<RecoveryKit | null>;
 
-    // 验证主密码
-    verify_master_password(master_password: string): Promise<boolean>;
+    // 验证主密码，输入胁迫密码时改为打开诱饵保险库，同样返回 true
+    verify_master_password(master_password: MasterPassword): Promise<boolean>;
 
-    // 获取所有密码
-    list_password(master_password: string): Promise<Array<Item>>;
+    // 获取密码列表，option 为 null 时按添加顺序返回所有密码
+    list_password(master_password: MasterPassword, option: ListOption | null): Promise<Array<Item>>;
 
-    // 获取密码
-    get_password(master_password: string, id: number): Promise<Password>;
+    // 获取密码，并更新最后使用时间
+    get_password(master_password: MasterPassword, id: number): Promise<Password>;
 
     /**
      * 导入密码
      * @param master_password
      * @param decrypt_password 解密导入数据的密码
-     * @param source 文件或者要导入的数据
+     * @param source 文件、要导入的数据、KeePass 数据库文件、Bitwarden JSON 文件、1Password 1PUX 文件或者 CSV
+     *               (支持 Chrome/Edge、Firefox、Bitwarden 导出的格式，mapping 指定列名，null 为自动识别)
+     * @param strategy 名称相同、密码不同时的处理方式，null 为保留两者
      */
-    import_password(master_password: string, decrypt_password: string | null, source: string | Array<Array<String>>): Promise<Count>;
+    import_password(master_password: MasterPassword, decrypt_password: string | null, source: ImportSource, strategy: Strategy | null): Promise<Count>;
+
+    /**
+     * 预览导入结果，不写入数据
+     * @param master_password
+     * @param decrypt_password 解密导入数据的密码
+     * @param source 同 import_password
+     */
+    preview_import(master_password: MasterPassword, decrypt_password: string | null, source: ImportSource): Promise<Preview>;
 
     /**
      * 导出密码
      * @param master_password
      * @param file 文件， 如果不为 null，导出到此文件，否则返回导出的数据
+     * @param format 导出格式，null 为本应用的格式；其他格式返回 base64 编码的数据，
+     * encrypted 格式使用 passphrase 加密，不依赖主密码；age 和 pgp 格式加密给接收者的公钥；
+     * csv 和 json 格式未加密，需要再次输入主密码并确认；开启了密钥文件时本应用的格式和 kdbx 格式同样需要密钥文件才能打开
+     */
+    export_password(master_password: MasterPassword, file: string | null, format: 'vault' | 'kdbx' | { encrypted: { passphrase: string } } | { age: { recipients: Array<string> } } | { pgp: { keys: Array<string> } } | { csv: Plaintext } | { json: Plaintext } | null): Promise<Array<Array<String>> | string | null>;
+
+    /**
+     * 分页查看审计日志，最新的在前面，同时校验整个哈希链
+     * @param master_password
+     * @param before 上一页最后一条记录的 id，null 表示第一页
+     * @param limit 每页数量，默认 50，最多 1000
+     */
+    list_audit_log(master_password: MasterPassword, before: number | null, limit: number | null): Promise<AuditLog>;
+
+    // 本保险库的分享公钥，交给对方后对方可以把条目分享给本保险库，第一次调用时生成分享密钥
+    get_share_key(master_password: MasterPassword): Promise<ShareKey>;
+
+    /**
+     * 把条目加密分享给对方，附带已撤销的发给对方的分享，包括条目是否开启了两步验证
+     * @param master_password
+     * @param ids 分享的密码 id
+     * @param recipient 对方的分享公钥
+     * @param file 写入的文件，null 时返回分享的 JSON
      */
-    export_password(master_password: string, file: string | null): Promise<Array<Array<String>> | null>;
+    share_entries(master_password: MasterPassword, ids: Array<number>, recipient: string, file: string | null): Promise<string | null>;
+
+    // 撤销发出的分享，之后发给同一接收者的分享都附带撤销列表，已导入的条目不会从对方的保险库删除
+    revoke_share(master_password: MasterPassword, id: string): Promise<void>;
+
+    // 导出只有撤销列表的分享，用于不分享新条目时通知对方，file 为 null 时返回分享的 JSON
+    export_share_revocations(master_password: MasterPassword, recipient: string, file: string | null): Promise<string | null>;
+
+    /**
+     * 导入分享给本保险库的条目，同时记录分享附带的撤销列表，已撤销的分享无法导入；
+     * 不是发给本保险库的分享返回 InvalidShare，开启了两步验证的条目导入后同样标记
+     * @param master_password
+     * @param source 分享的文件或 JSON
+     * @param sender 要求发送者是这个分享公钥，null 时不检查，需要和对方核对返回的指纹
+     * @param strategy 名称相同、密码不同时的处理方式，null 为保留两者
+     */
+    import_share(master_password: MasterPassword, source: { file: string } | { data: string }, sender: string | null, strategy: Strategy | null): Promise<ShareImport>;
+
+    // 发出的分享，最新的在前面
+    list_sent_shares(master_password: MasterPassword): Promise<Array<SentShare>>;
+
+    // 导入的分享，最新的在前面
+    list_received_shares(master_password: MasterPassword): Promise<Array<ReceivedShare>>;
 
-    // 删除密码
-    delete_password(master_password: string, id: number): Promise<void>;
+    // 检查数据库完整性，并尝试解密所有密码和历史记录（包括回收站）
+    check_vault(master_password: MasterPassword): Promise<VaultCheck>;
+
+    // 把无法解密的密码（连同历史记录）和历史记录移到隔离区，返回移动的记录数
+    quarantine_vault(master_password: MasterPassword): Promise<number>;
+
+    // 保险库列表，默认保险库在最前面，opened 和 current 只反映当前连接
+    list_vaults(): Promise<Array<VaultItem>>;
+
+    // 创建保险库，使用单独的主密码，创建后需要打开才能使用
+    create_vault(name: string, master_password: MasterPassword): Promise<void>;
+
+    // 打开保险库并设为当前保险库，已打开时只切换当前保险库；密码相关的方法都作用于当前保险库，输入胁迫密码时打开诱饵保险库
+    // 当前保险库是每个连接单独的，没有打开过保险库的连接使用默认保险库
+    open_vault(name: string, master_password: MasterPassword): Promise<void>;
+
+    // 关闭保险库，需要保险库的主密码，关闭当前保险库后需要打开其他保险库才能使用
+    close_vault(name: string, master_password: MasterPassword): Promise<void>;
+
+    // 重命名保险库，默认保险库不能重命名
+    rename_vault(name: string, new_name: string, master_password: MasterPassword): Promise<void>;
+
+    // 删除保险库及其备份，默认保险库不能删除
+    delete_vault(name: string, master_password: MasterPassword): Promise<void>;
+
+    // 备份列表，最新的在前面
+    list_backup(master_password: MasterPassword): Promise<Array<Snapshot>>;
+
+    // 检查备份
+    verify_backup(master_password: MasterPassword, name: string): Promise<BackupVerify>;
+
+    // 恢复备份，恢复前会先备份当前数据库
+    restore_backup(master_password: MasterPassword, name: string): Promise<void>;
+
+    // 获取备份保留策略
+    get_backup_retention(): Promise<Retention>;
+
+    // 设置备份保留策略
+    set_backup_retention(master_password: MasterPassword, retention: Retention): Promise<void>;
+
+    // 删除密码，移到回收站
+    delete_password(master_password: MasterPassword, id: number): Promise<void>;
+
+    // 获取回收站中的密码，按删除时间倒序
+    list_trash(master_password: MasterPassword): Promise<Array<TrashItem>>;
+
+    // 从回收站恢复密码
+    restore_trash(master_password: MasterPassword, id: number): Promise<void>;
+
+    // 彻底删除回收站中的密码，id 为 null 时清空回收站
+    purge_trash(master_password: MasterPassword, id: number | null): Promise<void>;
+
+    // 获取回收站保留天数，0 表示不自动清理
+    get_trash_retention(): Promise<number>;
+
+    // 设置回收站保留天数，0 表示不自动清理
+    set_trash_retention(master_password: MasterPassword, days: number): Promise<void>;
 
     // 生成密码
     make_password(option: PasswordOption): Promise<String>;
 
     // 添加密码
-    add_password(master_password: String, name: String, password: String): Promise<void>;
+    add_password(master_password: MasterPassword, name: String, password: String): Promise<void>;
 
     // 更新密码
-    update_password(master_password: String, id: number, name: String, password: String): Promise<void>;
+    update_password(master_password: MasterPassword, id: number, name: String, password: String): Promise<void>;
 
     // 修改主密码
-    change_password(master_password: String, new_password: String): Promise<void>;
+    change_password(master_password: MasterPassword, new_password: String): Promise<void>;
+
+    // 设置是否开启了两步验证
+    set_two_factor(master_password: MasterPassword, id: number, enabled: boolean): Promise<void>;
+
+    /**
+     * 密码健康检查
+     * @param master_password
+     * @param max_age 超过多少天未修改算旧密码，null 为 365 天
+     */
+    password_health(master_password: MasterPassword, max_age: number | null): Promise<HealthReport>;
+
+    /**
+     * 导入 Have I Been Pwned 泄露密码数据，返回记录数
+     * @param master_password
+     * @param path 按哈希排序的文本文件、范围文件目录或二进制索引
+     */
+    load_breach_data(master_password: MasterPassword, path: String): Promise<number>;
+
+    // 检查所有密码是否泄露，只返回泄露的密码
+    check_breach(master_password: MasterPassword): Promise<Array<Breach>>;
+
+    // 检查单个密码的泄露次数
+    check_breach_password(password: String): Promise<number>;
+
+    // 获取密码的历史记录，按时间倒序
+    list_password_history(master_password: MasterPassword, id: number): Promise<Array<History>>;
+
+    // 获取某个历史密码
+    get_password_history(master_password: MasterPassword, id: number): Promise<String>;
+
+    // 恢复历史密码
+    restore_password_history(master_password: MasterPassword, id: number): Promise<void>;
+
+    // 获取每个密码保留的历史记录数
+    get_history_limit(): Promise<number>;
+
+    // 设置每个密码保留的历史记录数
+    set_history_limit(master_password: MasterPassword, limit: number): Promise<void>;
+
+    // 在对方设备上调用，允许其他设备加入同步，返回一次性的配对码，5 分钟内有效；需要先开启网络访问
+    sync_pair(master_password: MasterPassword): Promise<string>;
+
+    // 由加入的设备调用，用配对码派生的 id 取回加密的保险库密钥
+    sync_claim(id: string): Promise<string>;
+
+    // 加入 addr (host:port) 上的设备的同步，code 为对方显示的配对码。本地的密码改用对方的保险库密钥加密，
+    // 本地主密码不变；已经和其他设备同步时返回 AlreadySyncing
+    join_sync(master_password: MasterPassword, addr: string, code: string): Promise<void>;
+
+    // 由其他设备调用，发起同步前获取一次性的会话号
+    sync_session(): Promise<string>;
+
+    // 由其他设备调用，交换加密的变更，只有已解锁的保险库参与同步
+    sync_exchange(data: string): Promise<string>;
+
+    // 和所有已知地址的设备同步
+    sync_now(master_password: MasterPassword): Promise<Array<SyncReport>>;
+
+    // 同步的设备列表
+    list_sync_peers(master_password: MasterPassword): Promise<Array<SyncPeer>>;
+
+    // 删除同步的设备
+    remove_sync_peer(master_password: MasterPassword, id: number): Promise<void>;
+
+    // 同时在本地和其他设备上修改的密码
+    list_sync_conflicts(master_password: MasterPassword): Promise<Array<SyncConflict>>;
+
+    // 解决冲突，保留的内容会在下次同步时发送给其他设备
+    resolve_sync_conflict(master_password: MasterPassword, id: number, keep: 'local' | 'remote'): Promise<void>;
+
+    // 设置同步文件夹，null 表示关闭。文件夹中已有其他设备保存的保险库密钥时，本地的密码改用该密钥加密，
+    // peer_master_password 为该设备的主密码 (开启了密钥文件时包括密钥文件)，不设置时使用本地主密码；之后保险库解锁期间每分钟自动同步；已经和其他设备同步时返回 AlreadySyncing
+    set_sync_folder(master_password: MasterPassword, folder: string | null, peer_master_password?: MasterPassword | null): Promise<void>;
+
+    // 获取同步文件夹
+    get_sync_folder(): Promise<string | null>;
+
+    // 立即和同步文件夹中的其他设备同步
+    sync_folder(master_password: MasterPassword): Promise<FolderReport>;
+
+    // 设置 WebDAV 同步，null 表示关闭。目录中已有其他设备保存的保险库密钥时，本地的密码改用该密钥加密，
+    // peer_master_password 为该设备的主密码 (开启了密钥文件时包括密钥文件)，不设置时使用本地主密码；地址和账号加密保存
+    set_webdav(master_password: MasterPassword, config: WebDavConfig | null, peer_master_password?: MasterPassword | null): Promise<void>;
+
+    // 获取 WebDAV 设置
+    get_webdav(master_password: MasterPassword): Promise<WebDavConfig | null>;
+
+    // 立即和 WebDAV 同步，同时修改的密码会尝试合并，合并不了的保存为冲突
+    sync_webdav(master_password: MasterPassword): Promise<WebDavReport>;
 
     // 获取可从网络访问的端口号
     get_network_port(): Promise<number|null>;
@@ -52,18 +251,298 @@ declare class Rpc {
 
     // 关闭网络访问
     disable_network_access(): Promise<void>;
+
+    // 生成新的恢复密钥，替换已有的，shares 不为 null 时同时生成分片
+    set_recovery(master_password: MasterPassword, shares: Shares | null): Promise<RecoveryKit>;
+
+    // 删除恢复密钥，已有的恢复密钥和分片失效
+    remove_recovery(master_password: MasterPassword): Promise<void>;
+
+    // 是否设置了恢复密钥
+    is_recovery_set(): Promise<boolean>;
+
+    // 忘记主密码时使用恢复密钥或者足够的分片设置新的主密码，同时关闭密钥文件并删除胁迫密码；
+    // 同一客户端连续失败 5 次后 1 分钟内返回 TooManyAttempts
+    recover_master_password(secret: { key: string } | { shares: Array<string> }, new_password: string): Promise<void>;
+
+    // 开启或更换密钥文件，之后解锁需要主密码和新的密钥文件，key_file 为密钥文件内容，base64 编码；
+    // 设置了胁迫密码时返回 DuressPasswordSet
+    set_key_file(master_password: MasterPassword, key_file: string): Promise<void>;
+
+    // 关闭密钥文件，之后只需要主密码；设置了胁迫密码时返回 DuressPasswordSet
+    remove_key_file(master_password: MasterPassword): Promise<void>;
+
+    // 是否开启了密钥文件，开启后解锁需要同时提供密钥文件
+    is_key_file_set(): Promise<boolean>;
+
+    // 设置胁迫密码，解锁时输入胁迫密码会打开诱饵保险库，wipe 为 true 时同时销毁保险库密钥，
+    // 诱饵保险库已存在并且胁迫密码不变时保留其中的数据；开启了密钥文件时诱饵保险库使用同一个密钥文件
+    set_duress_password(master_password: MasterPassword, duress_password: string, wipe: boolean): Promise<void>;
+
+    // 删除胁迫密码，诱饵保险库换成随机密码加密的空保险库
+    remove_duress_password(master_password: MasterPassword): Promise<void>;
+
+    // 是否设置了胁迫密码
+    is_duress_password_set(master_password: MasterPassword): Promise<boolean>;
 }
 
 export declare var rpc: Rpc;
 
+// 保险库变化的通知，订阅后服务端在 rpc 的连接上发送 JSON-RPC 通知，重连后自动重新订阅
+declare class Notify {
+    // 订阅保险库，需要保险库的主密码，同一客户端连续输错时暂时拒绝
+    subscribe(name: string, master_password: MasterPassword): Promise<void>;
+
+    // 取消订阅保险库
+    unsubscribe(name: string): Promise<void>;
+
+    // 条目新建、修改、删除，移入回收站和从回收站恢复属于修改
+    on(method: 'entry_created' | 'entry_updated' | 'entry_deleted', listener: (params: { vault: string, id: number }) => void): () => void;
+
+    // 一次修改的条目太多或者丢失了通知，需要重新加载
+    on(method: 'entries_changed', listener: (params: { vault: string }) => void): () => void;
+
+    // 保险库被关闭
+    on(method: 'vault_locked', listener: (params: { vault: string }) => void): () => void;
+
+    // 网络访问开启或关闭，关闭时 port 为 null
+    on(method: 'network_changed', listener: (params: { port: number | null }) => void): () => void;
+}
+
+export declare var notify: Notify;
+
+// 主密码，开启密钥文件后需要同时提供密钥文件的内容，base64 编码
+declare type MasterPassword = string | { password: string, key_file: string };
+
 declare class Item {
     public id: number;
     public name: string;
+    // 创建时间戳（秒）
+    public created_at: number;
+    // 修改时间戳（秒）
+    public modified_at: number;
+    // 最后使用时间戳（秒）
+    public accessed_at: number | null;
+    // 是否开启了两步验证
+    public two_factor: boolean;
+    // 名称无法解密，name 为空，可以用 check_vault 检查并隔离
+    public corrupt: boolean;
+}
+
+declare class ListOption {
+    // 排序方式：添加顺序、名称、最近使用、最近修改
+    sort?: 'id' | 'name' | 'accessed' | 'modified';
+    offset?: number;
+    // 不设置表示不限制数量
+    limit?: number | null;
+}
+
+// 文件可以是本应用的格式或者加密导出格式；encrypted 为加密导出格式的数据，base64 编码；
+// age 使用身份文件 identity 解密；pgp 使用私钥文件 key 解密，私钥的密码为 decrypt_password
+declare type ImportSource = string | Array<Array<String>> | KdbxSource | { bitwarden: string } | { onepux: string } | { encrypted: string }
+    | { age: string, identity: string } | { pgp: string, key: string }
+    | { csv: { file: string } | { data: string }, mapping: CsvMapping | null };
+
+// skip: 跳过，overwrite: 覆盖已有密码，keep_both: 保留两者
+declare type Action = 'skip' | 'overwrite' | 'keep_both';
+
+// items 为逐条决定，键为 PreviewItem.index，没有指定的跳过
+declare type Strategy = Action | { items: { [index: number]: Action } };
+
+declare class PreviewItem {
+    // 在导入数据中的序号
+    index: number;
+    name: string;
+    // 名称相同的已有密码
+    id: number | null;
+}
+
+declare class Preview {
+    // 新密码
+    new: Array<PreviewItem>;
+    // 名称和密码都相同，不会导入
+    identical: Array<PreviewItem>;
+    // 名称相同、密码不同
+    conflict: Array<PreviewItem>;
+    warnings: Array<Warning>;
+}
+
+// 导出未加密数据前的确认
+declare class Plaintext {
+    // 再次输入的主密码，开启了密钥文件时使用 master_password 中的密钥文件验证
+    confirm_password: string;
+    // 确认知道导出的数据未加密
+    acknowledge_plaintext: boolean;
+}
+
+declare class AuditLog {
+    items: Array<AuditItem>;
+    // 哈希链中第一条校验失败的记录的 id，null 表示校验通过
+    broken: number | null;
+}
+
+declare class AuditItem {
+    id: number;
+    time: number;
+    // unlock: 解锁，detail 为打开的保险库名称，验证主密码时为空
+    // unlock_failed: 解锁时主密码错误，其他操作的密码错误不记录
+    // reveal: 查看密码，detail 为密码 id
+    // reveal_history: 查看历史密码，detail 为历史记录 id
+    // export: 导出加密数据，detail 为格式和文件
+    // import: 导入，detail 为新增和覆盖的数量，导入分享时包含发送者的指纹
+    // share: 分享条目，detail 为分享 id、数量和接收者的指纹
+    // revoke_share: 撤销分享，detail 为分享 id
+    // change_master_password: 修改主密码
+    // enable_network_access: 开启网络访问，detail 为端口
+    // disable_network_access: 关闭网络访问
+    // set_recovery: 生成恢复密钥，detail 为分片数量
+    // remove_recovery: 删除恢复密钥
+    // recover_master_password: 使用恢复密钥 (key) 或分片 (shares) 设置新的主密码
+    // recover_failed: 恢复密钥或分片无效
+    // set_key_file: 开启或更换密钥文件
+    // remove_key_file: 关闭密钥文件
+    // duress: 输入了胁迫密码，打开了诱饵保险库
+    // set_duress_password: 设置胁迫密码
+    // remove_duress_password: 删除胁迫密码
+    // export_plaintext: 导出未加密数据
+    // restore_backup: 恢复备份
+    // quarantine: 隔离损坏的数据
+    // sync_pair: 允许其他设备加入同步
+    // join_sync: 加入其他设备的同步
+    // set_sync_folder: 设置同步文件夹
+    // set_webdav: 设置 WebDAV 同步
+    event: string;
+    detail: string;
+    // 客户端地址，不是通过网络连接触发的事件为空
+    address: string;
+    // 是否加密保存，升级前和生成审计密钥前的记录未加密
+    encrypted: boolean;
+}
+
+declare class VaultItem {
+    name: string;
+    // 当前连接是否已打开
+    opened: boolean;
+    // 是否是当前保险库
+    current: boolean;
+}
+
+declare class CorruptedItem {
+    // vault: 密码，history: 历史记录
+    table: 'vault' | 'history';
+    id: number;
+    // 所属密码的 id
+    vault_id: number;
+    field: 'name' | 'password';
+}
+
+declare class VaultCheck {
+    // SQLite integrity_check 发现的问题，没有问题时为空
+    integrity: Array<string>;
+    // 无法解密的数据
+    corrupted: Array<CorruptedItem>;
+}
+
+declare class Snapshot {
+    name: string;
+    time: number;
+    // 文件大小（字节）
+    size: number;
+}
+
+declare class BackupVerify {
+    // 完整性检查是否通过
+    integrity: boolean;
+    // 密码数量
+    count: number;
+    // 当前主密码能否解锁备份，恢复后需要使用备份时的主密码
+    password: boolean;
+}
+
+// 保留策略，满足任意一条的备份都会保留
+declare class Retention {
+    // 最近几天每天保留最新的一个
+    daily: number;
+    // 最近几周每周保留最新的一个
+    weekly: number;
+    // 保留最新的几个
+    count: number;
+}
+
+declare class ShareKey {
+    // 分享公钥
+    public_key: string;
+    // 公钥指纹，用于和对方核对
+    fingerprint: string;
+}
+
+declare class ShareImport {
+    id: string;
+    // 发送者的分享公钥和指纹
+    sender: string;
+    fingerprint: string;
+    // 本次撤销的已导入分享的数量
+    revoked: number;
+    // 导入的数量，只有撤销列表时为 null
+    count: Count | null;
+}
+
+declare class SentShare {
+    id: string;
+    // 接收者的分享公钥和指纹
+    recipient: string;
+    fingerprint: string;
+    // 条目数量
+    count: number;
+    created_at: number;
+    // 撤销时间，没有撤销时为 null
+    revoked_at: number | null;
+}
+
+declare class ReceivedShare {
+    id: string;
+    // 发送者的分享公钥和指纹
+    sender: string;
+    fingerprint: string;
+    // 条目数量
+    count: number;
+    created_at: number;
+    imported_at: number;
+    // 发送者是否已撤销
+    revoked: boolean;
 }
 
 declare class Count {
     ignore: number;
     insert: number;
+    // 覆盖已有密码的数量
+    overwrite: number;
+    // 无法导入的内容
+    warnings: Array<Warning>;
+}
+
+declare class Warning {
+    // 条目名称
+    name: string;
+    // FieldSkipped: 字段未导入
+    // AttachmentSkipped: 附件未导入
+    // UnsupportedType: 不支持的条目类型
+    kind: string;
+    detail: string;
+}
+
+declare class KdbxSource {
+    // 数据库文件
+    kdbx: string;
+    // 密钥文件
+    key_file?: string | null;
+}
+
+declare class CsvMapping {
+    name?: string;
+    url?: string;
+    username?: string;
+    password?: string;
 }
 
 declare class Password {
@@ -71,6 +550,58 @@ declare class Password {
     password: string;
 }
 
+declare class HealthItem {
+    id: number;
+    name: string;
+    // 强度，0 最弱，4 最强
+    strength: number;
+    // 健康分，0 - 100
+    score: number;
+    weak: boolean;
+    reused: boolean;
+    old: boolean;
+    no_two_factor: boolean;
+    // 距上次修改的天数
+    age: number;
+}
+
+declare class HealthSummary {
+    total: number;
+    weak: number;
+    reused: number;
+    old: number;
+    no_two_factor: number;
+    healthy: number;
+    score: number;
+}
+
+declare class HealthReport {
+    items: Array<HealthItem>;
+    // 重复使用同一密码的分组，每组为密码 id
+    reused: Array<Array<number>>;
+    summary: HealthSummary;
+}
+
+declare class Breach {
+    id: number;
+    name: string;
+    // 泄露次数
+    count: number;
+}
+
+declare class TrashItem {
+    id: number;
+    name: string;
+    // 删除时间戳（秒）
+    deleted_at: number;
+}
+
+declare class History {
+    id: number;
+    // 时间戳（秒）
+    time: number;
+}
+
 declare class PasswordOption {
     len: number;
     uppercase: boolean;
@@ -78,3 +609,89 @@ declare class PasswordOption {
     digit: boolean;
     special: boolean;
 }
+
+declare class SyncReport {
+    peer: number;
+    addr: string;
+    // 发送的变更数量
+    sent: number;
+    // 应用的对方的变更数量
+    received: number;
+    // 新的冲突数量
+    conflicts: number;
+    // 同步失败时的错误
+    error: string | null;
+}
+
+declare class SyncPeer {
+    id: number;
+    // 对方的设备 id，第一次同步之前为 null
+    device: string | null;
+    // 对方的地址，对方发起同步时为 null
+    addr: string | null;
+    // 最后同步时间戳（秒）
+    synced_at: number | null;
+}
+
+declare class SyncEntry {
+    name: string;
+    password: string;
+    // 在回收站中
+    deleted: boolean;
+}
+
+declare class SyncConflict {
+    id: number;
+    // 对方的设备 id
+    peer: string;
+    // null 表示已彻底删除
+    local: SyncEntry | null;
+    remote: SyncEntry | null;
+    time: number;
+}
+
+declare class FolderReport {
+    // 写入日志的变更数量
+    sent: number;
+    // 应用的其他设备的变更数量
+    received: number;
+    // 新的冲突数量
+    conflicts: number;
+    // 日志被修改或损坏的设备 id
+    tampered: Array<string>;
+}
+
+declare class WebDavConfig {
+    // WebDAV 目录的地址，支持 http 和 https
+    url: string;
+    username?: string | null;
+    password?: string | null;
+}
+
+declare class WebDavReport {
+    // 上传的条目数量
+    sent: number;
+    // 应用的远端的变更数量
+    received: number;
+    // 新的冲突数量
+    conflicts: number;
+}
+
+declare class RecoveryOption {
+    // 同时把恢复密钥分成分片
+    shares: Shares | null;
+}
+
+declare class Shares {
+    // 恢复需要的分片数量，至少为 2
+    threshold: number;
+    // 分片总数
+    total: number;
+}
+
+declare class RecoveryKit {
+    // 恢复密钥，base32 编码，每 4 个字符用 - 分隔
+    key: string;
+    // 分片，没有要求分片时为空
+    shares: Array<string>;
+}
@@ -20,21 +20,97 @@ const handler = {
     }
 }
 
-export const rpc = new Proxy(new Client(getUrl()), handler);
+const client = new Client(getUrl('/ws'));
 
-function getUrl() {
+export const rpc = new Proxy(client, handler);
+
+/**
+ * 保险库变化的通知，subscribe 订阅后使用 on 监听。
+ * 服务端在 rpc 的连接上发送通知，连接断开重连后重新订阅
+ */
+class Notify {
+    constructor(client) {
+        this.client = new Proxy(client, handler);
+        // 订阅的保险库，名称 -> 主密码
+        this.vaults = {};
+        this.listeners = {};
+        client.onnotification = (method, params) => {
+            (this.listeners[method] || []).forEach(listener => listener(params));
+        };
+        client.onconnect = () => this.resubscribe();
+    }
+
+    async subscribe(name, master_password) {
+        await this.client.subscribe(name, master_password);
+        this.vaults[name] = master_password;
+    }
+
+    async unsubscribe(name) {
+        delete this.vaults[name];
+        await this.client.unsubscribe(name);
+    }
+
+    on(method, listener) {
+        let listeners = this.listeners[method] || (this.listeners[method] = []);
+        listeners.push(listener);
+        return () => {
+            let index = listeners.indexOf(listener);
+            if (index >= 0) {
+                listeners.splice(index, 1);
+            }
+        };
+    }
+
+    async resubscribe() {
+        try {
+            // 连接重连过，订阅已丢失
+            for (const [name, master_password] of Object.entries(this.vaults)) {
+                await this.client.call('subscribe', name, master_password);
+            }
+        } catch (e) {
+            console.error("subscribe failed: ", e);
+        }
+    }
+}
+
+export const notify = new Notify(client);
+
+function getUrl(path) {
     let host = location.host;
     if (process.env.NODE_ENV !== 'production') {
         // 端口写死为 8000，方便测试
         host = host.replace(/:\d+/, ":8000")
     }
     let protocol = location.protocol.replace(/^http/, 'ws');
-    return protocol + '//' + host + '/ws';
+    return protocol + '//' + host + path;
 }
 
 const msg = {
     WrongPassword: '密码错误',
     DeserializeFailed: '解析文件失败',
+    NoBreachData: '没有导入泄露密码数据',
+    PlaintextNotAcknowledged: '请确认导出未加密的数据',
+    BackupCorrupted: '备份已损坏',
+    Corrupted: '数据已损坏，请检查保险库',
+    NewerVersion: '备份的版本比应用新，请先升级应用',
+    VaultNotFound: '保险库不存在',
+    VaultExists: '保险库已存在',
+    InvalidVaultName: '保险库名称不合法',
+    InvalidRecoveryKey: '恢复密钥或分片无效',
+    RecoveryNotSet: '没有设置恢复密钥',
+    InvalidShares: '分片数量不合法',
+    InvalidKeyFile: '密钥文件无效',
+    InvalidDuressPassword: '胁迫密码不能为空或者和主密码相同',
+    DuressPasswordSet: '设置了胁迫密码时不能开启或关闭密钥文件，请先删除胁迫密码',
+    InvalidShareKey: '分享公钥格式错误',
+    InvalidShare: '分享无效或者不是分享给本保险库的',
+    UntrustedSender: '分享的发送者不是指定的公钥',
+    ShareRevoked: '分享已被发送者撤销',
+    ShareImported: '分享已经导入过',
+    ShareNotFound: '分享不存在',
+    InvalidPairingCode: '配对码错误或已过期',
+    AlreadySyncing: '已经和其他设备同步，请先移除其他设备',
+    TooManyAttempts: '密码错误次数太多，请稍后再试',
 }
 
 /**
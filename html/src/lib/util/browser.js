@@ -2,9 +2,10 @@
 
 /**
  * 读取文件
+ * @param {boolean} base64 是否读取为 base64 编码，用于二进制文件
  * @returns {Promise<string|null>} 如果没有选择文件，返回 null,
  */
-export async function read() {
+export async function read(base64 = false) {
     const input = document.createElement('input');
     input.style.display = 'none';
     input.type = 'file';
@@ -15,8 +16,13 @@ export async function read() {
     input.addEventListener('change', () => {
         choose = true;
         const reader = new FileReader();
-        reader.readAsText(input.files[0]);
-        reader.onload = () => ok(reader.result);
+        if (base64) {
+            reader.readAsDataURL(input.files[0]);
+            reader.onload = () => ok(reader.result.substring(reader.result.indexOf(',') + 1));
+        } else {
+            reader.readAsText(input.files[0]);
+            reader.onload = () => ok(reader.result);
+        }
         reader.onerror = ev => error(ev);
     })
 
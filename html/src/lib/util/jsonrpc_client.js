@@ -12,10 +12,12 @@ class Connector {
      * constructor
      * @param {string} url 地址
      * @param {messageCallback} onmessage 消息回调
+     * @param {function} onopen 连接成功回调
      */
-    constructor(url, onmessage) {
+    constructor(url, onmessage, onopen) {
         this.url = url;
         this.onmessage = onmessage;
+        this.onopen = onopen;
         this.state = DISCONNECTED;
         this.ws = null;
     }
@@ -48,6 +50,7 @@ class Connector {
             this.ws.onopen = _ => {
                 this.state = CONNECTED;
                 resolve(this.ws);
+                this.onopen();
             }
             this.ws.onerror = e => {
                 this.state = DISCONNECTED;
@@ -68,6 +71,10 @@ export default class Client {
     constructor(url) {
         this.id = 1;
         this.completer = {};
+        // 服务端发送的通知 (没有 id)，参数为方法名和参数
+        this.onnotification = null;
+        // 连接成功，包括断开后重新连接
+        this.onconnect = null;
         this.connector = new Connector(url, e => {
             let response = JSON.parse(e.data);
             if (response.id) {
@@ -78,10 +85,12 @@ export default class Client {
                     reject({response});
                 }
                 delete this.completer[response.id];
+            } else if (response.method && this.onnotification) {
+                this.onnotification(response.method, response.params);
             } else {
                 console.error("response without id: ", response)
             }
-        });
+        }, () => this.onconnect && this.onconnect());
     }
 
     /**
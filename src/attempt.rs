@@ -0,0 +1,69 @@
+// 输错密码的次数限制
+//
+// 按客户端 IP 计数，同一客户端重新连接 (端口变化) 不会清零，其他客户端输错也不影响本机。检查时先计入
+// 一次，验证通过后调用 reset 清零，从多个连接同时尝试也不会超过上限。
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// 连续输错的次数上限，达到后在 LOCKOUT 内拒绝
+const MAX_ATTEMPTS: u32 = 5;
+const LOCKOUT: Duration = Duration::from_secs(60);
+
+// 键为客户端的 IP、操作和保险库名称，不在连接中时 IP 为 None
+type Key = (Option<IpAddr>, &'static str, String);
+
+fn attempts() -> &'static Mutex<HashMap<Key, (u32, Instant)>> {
+    static ATTEMPTS: OnceLock<Mutex<HashMap<Key, (u32, Instant)>>> = OnceLock::new();
+    ATTEMPTS.get_or_init(Mutex::default)
+}
+
+fn key(addr: Option<SocketAddr>, action: &'static str, vault: &str) -> Key {
+    (addr.map(|v| v.ip()), action, vault.to_string())
+}
+
+// 检查客户端对保险库执行 action 的输错次数，允许时先计入一次
+pub fn check(addr: Option<SocketAddr>, action: &'static str, vault: &str) -> bool {
+    let mut attempts = attempts().lock().unwrap();
+    let now = Instant::now();
+    let (count, last) = attempts.entry(key(addr, action, vault)).or_insert((0, now));
+    if now.duration_since(*last) >= LOCKOUT {
+        *count = 0;
+    }
+    if *count >= MAX_ATTEMPTS {
+        return false;
+    }
+    *count += 1;
+    *last = now;
+    true
+}
+
+// 验证通过，清零
+pub fn reset(addr: Option<SocketAddr>, action: &'static str, vault: &str) {
+    attempts().lock().unwrap().remove(&key(addr, action, vault));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 按客户端 IP 计数，其他客户端输错不影响
+    #[test]
+    fn limit_attempts() {
+        let a = Some(SocketAddr::from(([192, 168, 1, 2], 2005)));
+        let b = Some(SocketAddr::from(([192, 168, 1, 3], 2005)));
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(check(a, "test", "attempts"));
+        }
+        assert!(!check(a, "test", "attempts"));
+        // 重新连接端口变化，仍然拒绝
+        let reconnect = Some(SocketAddr::from(([192, 168, 1, 2], 2006)));
+        assert!(!check(reconnect, "test", "attempts"));
+        assert!(check(b, "test", "attempts"));
+        assert!(check(a, "other", "attempts"));
+        reset(a, "test", "attempts");
+        assert!(check(a, "test", "attempts"));
+    }
+}
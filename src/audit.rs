@@ -0,0 +1,334 @@
+// 审计日志
+//
+// 日志只能追加 (触发器禁止修改和删除)，每条记录的 hash 是上一条记录的 hash 和本条记录的 HMAC-SHA256，
+// 修改、删除或插入中间的记录后校验失败。HMAC 密钥从审计私钥派生，没有保险库密钥无法重新计算哈希链。
+// 主密码错误时也要写日志，所以记录使用审计公钥 (age X25519) 加密，私钥使用保险库密钥加密保存，
+// 查看日志时才需要主密码。没有保险库密钥时写入的记录 hash 为空，下次有保险库密钥时按顺序补上，
+// 触发器只允许填写空的 hash。升级前的记录和生成审计密钥前的记录无法加密，保存为明文。
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+use age::secrecy::ExposeSecret;
+use age::x25519::Identity;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{derive_key, hmac_sha256, key_decrypt, key_encrypt};
+use crate::db::{get_conf, now, set_conf};
+use crate::recipient::x25519;
+use crate::server::client_addr;
+
+// 审计公钥
+const RECIPIENT: &str = "audit_recipient";
+
+// 使用保险库密钥加密的审计私钥
+const IDENTITY: &str = "audit_identity";
+
+// 从审计私钥派生 HMAC 密钥的 label
+const CHAIN: &str = "audit chain";
+
+// 读取上一条记录和写入必须连续，否则哈希链会分叉
+static APPEND: Mutex<()> = Mutex::new(());
+
+#[derive(Serialize, Deserialize)]
+pub struct Event {
+    // 事件类型，unlock、unlock_failed、reveal、export、import、sync_pair 等，见 rpc.d.ts 的 AuditItem
+    pub event: String,
+    pub detail: String,
+    // 客户端地址，不是通过网络连接触发的事件为空
+    #[serde(default)]
+    pub address: String,
+}
+
+#[derive(Serialize)]
+pub struct Record {
+    pub id: u64,
+    pub time: i64,
+    #[serde(flatten)]
+    pub event: Event,
+    // 是否加密保存
+    pub encrypted: bool,
+}
+
+// 记录事件，客户端地址取当前连接的地址，key 为保险库密钥
+pub fn record(conn: &Connection, key: &[u8], event: &str, detail: &str) -> crate::Result<()> {
+    let (data, encrypted) = encrypt(conn, event, detail)?;
+    append(conn, Some(key), now()?, &data, encrypted)
+}
+
+// 没有保险库密钥时记录事件，如主密码错误，下次有保险库密钥时加入哈希链
+pub fn record_locked(conn: &Connection, event: &str, detail: &str) -> crate::Result<()> {
+    let (data, encrypted) = encrypt(conn, event, detail)?;
+    append(conn, None, now()?, &data, encrypted)
+}
+
+fn encrypt(conn: &Connection, event: &str, detail: &str) -> crate::Result<(String, bool)> {
+    let event = Event {
+        event: event.to_string(),
+        detail: detail.to_string(),
+        address: client_addr()
+            .as_ref()
+            .map_or(String::new(), SocketAddr::to_string),
+    };
+    let data = serde_json::to_vec(&event).map_err(err!())?;
+    let (data, encrypted) = match get_conf(conn, RECIPIENT)? {
+        Some(recipient) => (x25519::encrypt(&data, &[recipient])?, true),
+        None => (data, false),
+    };
+    Ok((String::from_utf8(data).map_err(err!())?, encrypted))
+}
+
+// 追加一条记录，data 是加密后的 ASCII armor 或者未加密的 JSON。key 为保险库密钥，
+// 有审计密钥时先补上之前没有计算的 hash，再计算本条记录的 hash，否则 hash 留空
+pub fn append(
+    conn: &Connection,
+    key: Option<&[u8]>,
+    time: i64,
+    data: &str,
+    encrypted: bool,
+) -> crate::Result<()> {
+    let _guard = APPEND.lock().unwrap();
+    let mac_key = match key {
+        Some(key) => mac_key(conn, key)?,
+        None => None,
+    };
+    let hash = match mac_key {
+        Some(mac_key) => {
+            let prev = fill(conn, &mac_key)?;
+            hash(&mac_key, &prev, time, data, encrypted)?
+        }
+        None => String::new(),
+    };
+    const SQL: &str = "INSERT INTO audit (time, data, encrypted, hash) VALUES (?, ?, ?, ?)";
+    conn.execute(SQL, params![time, data, encrypted, hash])
+        .map_err(err!())?;
+    Ok(())
+}
+
+// 按顺序计算 hash 为空的记录的 hash，返回哈希链末尾的 hash。hash 为空的记录都在最后，
+// 中间出现时 verify 校验失败
+fn fill(conn: &Connection, mac_key: &[u8]) -> crate::Result<String> {
+    const LAST_SQL: &str = "SELECT hash FROM audit WHERE hash<>'' ORDER BY id DESC LIMIT 1";
+    let last: Option<String> = conn
+        .query_row(LAST_SQL, [], |row| row.get(0))
+        .optional()
+        .map_err(err!())?;
+    let mut prev = last.unwrap_or_default();
+    const SQL: &str = "SELECT id, time, data, encrypted FROM audit WHERE hash='' ORDER BY id";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let rows: Vec<(i64, i64, String, bool)> = stmt
+        .query_map([], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })
+        .map_err(err!())?
+        .collect::<Result<_, _>>()
+        .map_err(err!())?;
+    for (id, time, data, encrypted) in rows {
+        prev = hash(mac_key, &prev, time, &data, encrypted)?;
+        const UPDATE_SQL: &str = "UPDATE audit SET hash=? WHERE id=?";
+        conn.execute(UPDATE_SQL, params![prev, id])
+            .map_err(err!())?;
+    }
+    Ok(prev)
+}
+
+// 解锁时调用，没有审计密钥时生成，然后补上没有保险库密钥时写入的记录的 hash
+pub fn init(conn: &Connection, key: &[u8]) -> crate::Result<()> {
+    if get_conf(conn, RECIPIENT)?.is_none() {
+        let identity = Identity::generate();
+        let secret = key_encrypt(key, identity.to_string().expose_secret()).map_err(err!())?;
+        set_conf(conn, IDENTITY, &base64::encode(secret))?;
+        set_conf(conn, RECIPIENT, &identity.to_public().to_string())?;
+    }
+    let _guard = APPEND.lock().unwrap();
+    if let Some(mac_key) = mac_key(conn, key)? {
+        fill(conn, &mac_key)?;
+    }
+    Ok(())
+}
+
+// 保险库密钥变化时重新加密审计私钥
+pub fn reencrypt(conn: &Connection, key: &[u8], new_key: &[u8]) -> crate::Result<()> {
+    if let Some(identity) = identity(conn, key)? {
+        let secret = key_encrypt(new_key, identity).map_err(err!())?;
+        set_conf(conn, IDENTITY, &base64::encode(secret))?;
+    }
+    Ok(())
+}
+
+// 分页读取日志，最新的在前面，before 为上一页最后一条记录的 id
+pub fn list(
+    conn: &Connection,
+    key: &[u8],
+    before: Option<u64>,
+    limit: u32,
+) -> crate::Result<Vec<Record>> {
+    let identity = identity(conn, key)?;
+    const SQL: &str =
+        "SELECT id, time, data, encrypted FROM audit WHERE id<? ORDER BY id DESC LIMIT ?";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let mut rows = stmt
+        .query(params![before.unwrap_or(i64::MAX as u64), limit])
+        .map_err(err!())?;
+    let mut list = Vec::new();
+    while let Some(row) = rows.next().map_err(err!())? {
+        let data: String = row.get(2).map_err(err!())?;
+        let encrypted: bool = row.get(3).map_err(err!())?;
+        let data = match (encrypted, &identity) {
+            (true, Some(identity)) => x25519::decrypt(data.as_bytes(), identity)?,
+            (true, None) => return Err(err!(invalid_data("missing audit key"))),
+            (false, _) => data.into_bytes(),
+        };
+        list.push(Record {
+            id: row.get(0).map_err(err!())?,
+            time: row.get(1).map_err(err!())?,
+            event: serde_json::from_slice(&data).map_err(err!())?,
+            encrypted,
+        });
+    }
+    Ok(list)
+}
+
+// 校验哈希链，返回第一条校验失败的记录的 id。hash 为空的记录只能在最后
+pub fn verify(conn: &Connection, key: &[u8]) -> crate::Result<Option<u64>> {
+    let mac_key = mac_key(conn, key)?;
+    const SQL: &str = "SELECT id, time, data, encrypted, hash FROM audit ORDER BY id";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    let mut prev = String::new();
+    let mut unsealed = false;
+    while let Some(row) = rows.next().map_err(err!())? {
+        let time: i64 = row.get(1).map_err(err!())?;
+        let data: String = row.get(2).map_err(err!())?;
+        let encrypted: bool = row.get(3).map_err(err!())?;
+        let stored: String = row.get(4).map_err(err!())?;
+        if stored.is_empty() {
+            unsealed = true;
+            continue;
+        }
+        let valid = match mac_key {
+            Some(ref mac_key) if !unsealed => {
+                hash(mac_key, &prev, time, &data, encrypted)? == stored
+            }
+            _ => false,
+        };
+        if !valid {
+            return Ok(Some(row.get(0).map_err(err!())?));
+        }
+        prev = stored;
+    }
+    Ok(None)
+}
+
+// 解密审计私钥，没有生成审计密钥时返回 None
+fn identity(conn: &Connection, key: &[u8]) -> crate::Result<Option<Vec<u8>>> {
+    let secret = match get_conf(conn, IDENTITY)? {
+        Some(v) => base64::decode(v).map_err(err!())?,
+        None => return Ok(None),
+    };
+    match key_decrypt(key, secret).map_err(err!())? {
+        Some(v) => Ok(Some(v)),
+        None => Err(err!(invalid_data("wrong audit key"))),
+    }
+}
+
+// 哈希链的 HMAC 密钥，没有生成审计密钥时返回 None
+fn mac_key(conn: &Connection, key: &[u8]) -> crate::Result<Option<[u8; 32]>> {
+    match identity(conn, key)? {
+        Some(identity) => Ok(Some(derive_key(identity, CHAIN).map_err(err!())?)),
+        None => Ok(None),
+    }
+}
+
+fn hash(
+    mac_key: &[u8],
+    prev: &str,
+    time: i64,
+    data: &str,
+    encrypted: bool,
+) -> crate::Result<String> {
+    let text = format!("{}\n{}\n{}\n{}", prev, time, encrypted as u8, data);
+    let mac = hmac_sha256(mac_key, text.as_bytes()).map_err(err!())?;
+    Ok(base64::encode(mac))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::db::setup;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    fn open() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup(&mut conn, &tempdir().unwrap().path().join("backups")).unwrap();
+        init(&conn, &KEY).unwrap();
+        conn
+    }
+
+    fn hashes(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn.prepare("SELECT hash FROM audit ORDER BY id").unwrap();
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+        rows.map(|v| v.unwrap()).collect()
+    }
+
+    // 没有保险库密钥时写入的记录在下次有密钥时补上 hash
+    #[test]
+    fn seal_locked_records() {
+        let conn = open();
+        record(&conn, &KEY, "unlock", "").unwrap();
+        record_locked(&conn, "unlock_failed", "").unwrap();
+        record_locked(&conn, "unlock_failed", "").unwrap();
+        let before = hashes(&conn);
+        assert!(!before[0].is_empty());
+        assert!(before[1].is_empty() && before[2].is_empty());
+        assert_eq!(verify(&conn, &KEY).unwrap(), None);
+
+        record(&conn, &KEY, "reveal", "1").unwrap();
+        let after = hashes(&conn);
+        assert_eq!(after[0], before[0]);
+        assert!(after.iter().all(|v| !v.is_empty()));
+        assert_eq!(verify(&conn, &KEY).unwrap(), None);
+        assert_eq!(list(&conn, &KEY, None, 10).unwrap().len(), 4);
+    }
+
+    // 没有保险库密钥不能重新计算哈希链
+    #[test]
+    fn reject_forged_chain() {
+        let conn = open();
+        record(&conn, &KEY, "unlock", "").unwrap();
+        record(&conn, &KEY, "export", "csv").unwrap();
+        conn.execute("DROP TRIGGER audit_no_update", []).unwrap();
+        conn.execute("DROP TRIGGER audit_no_delete", []).unwrap();
+        conn.execute("DELETE FROM audit WHERE id=2", []).unwrap();
+        // 用不带密钥的 SHA-256 重新计算，以前的哈希链可以这样伪造
+        let (time, data): (i64, String) = conn
+            .query_row("SELECT time, data FROM audit WHERE id=1", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        let text = format!("\n{}\n1\n{}", time, data);
+        let forged = base64::encode(openssl::sha::sha256(text.as_bytes()));
+        conn.execute("UPDATE audit SET hash=? WHERE id=1", [forged])
+            .unwrap();
+        assert_eq!(verify(&conn, &KEY).unwrap(), Some(1));
+
+        // 把记录的 hash 清空伪装成没有密钥时写入的记录，后面已计算的记录校验失败
+        let conn = open();
+        record(&conn, &KEY, "unlock", "").unwrap();
+        record(&conn, &KEY, "export", "csv").unwrap();
+        conn.execute("DROP TRIGGER audit_no_update", []).unwrap();
+        conn.execute("UPDATE audit SET hash='' WHERE id=1", [])
+            .unwrap();
+        assert_eq!(verify(&conn, &KEY).unwrap(), Some(2));
+    }
+}
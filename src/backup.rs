@@ -0,0 +1,429 @@
+// 自动备份，使用 SQLite 在线备份 API 把每个已打开的保险库的数据库快照保存到保险库目录下的 backups
+//
+// 数据库中的密码已经用主密钥加密，快照和数据库一样不包含明文。
+// 写入后静默一段时间创建快照，距离最近的快照超过一天时也会创建快照，之后按保留策略删除旧快照。
+
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs::{create_dir_all, read_dir, remove_file, rename, OpenOptions};
+use std::io;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rusqlite::backup::Progress;
+use rusqlite::{Connection, DatabaseName, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::db::{check_version, now, setup};
+
+// 检查是否需要备份的间隔
+pub const TICK: Duration = Duration::from_secs(10);
+
+// 最后一次写入后静默多久创建快照（秒）
+const QUIET: i64 = 30;
+
+// 定时快照间隔（秒）
+const INTERVAL: i64 = 24 * 3600;
+
+const DAY: i64 = 24 * 3600;
+const WEEK: i64 = 7 * DAY;
+
+const PREFIX: &str = "database-";
+
+// 上次快照后有写入的保险库目录和最后一次写入的时间
+static CHANGES: Mutex<Vec<(PathBuf, i64)>> = Mutex::new(Vec::new());
+
+// 是否正在备份
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize)]
+pub struct Snapshot {
+    pub name: String,
+    pub time: i64,
+    pub size: u64,
+}
+
+// 保留策略，满足任意一条的快照都会保留
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Retention {
+    // 最近几天每天保留最新的一个
+    pub daily: u32,
+    // 最近几周每周保留最新的一个
+    pub weekly: u32,
+    // 保留最新的几个
+    pub count: u32,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            daily: 7,
+            weekly: 4,
+            count: 10,
+        }
+    }
+}
+
+pub struct Verify {
+    // 完整性检查是否通过
+    pub integrity: bool,
+    // 快照的数据库版本
+    pub version: Option<usize>,
+    // 密码数量
+    pub count: u64,
+    // 快照的主密钥，未设置主密码时为 None
+    pub key: Option<String>,
+    // 快照的密钥文件 KDF 参数，没有开启密钥文件时为 None
+    pub key_file: Option<String>,
+}
+
+// 保险库有写入，由 update hook 调用
+pub fn changed(vault_dir: &Path) {
+    let now = now().unwrap_or(0);
+    let mut changes = CHANGES.lock().unwrap();
+    match changes.iter_mut().find(|(dir, _)| dir == vault_dir) {
+        Some((_, time)) => *time = now,
+        None => changes.push((vault_dir.to_path_buf(), now)),
+    }
+}
+
+// 写入后是否已经静默足够长的时间，是则清除写入标记
+fn quiet(vault_dir: &Path, now: i64) -> bool {
+    let mut changes = CHANGES.lock().unwrap();
+    match changes.iter().position(|(dir, _)| dir == vault_dir) {
+        Some(i) if now - changes[i].1 >= QUIET => {
+            changes.remove(i);
+            true
+        }
+        _ => false,
+    }
+}
+
+// 定时调用，需要时为已打开的保险库 vaults 创建快照并删除旧快照
+pub fn auto<'a>(mut vaults: impl Iterator<Item = (&'a Path, &'a Connection)>) -> crate::Result<()> {
+    if RUNNING.swap(true, Ordering::Acquire) {
+        return Ok(());
+    }
+    let result = vaults.try_for_each(|(dir, conn)| run(dir, conn));
+    RUNNING.store(false, Ordering::Release);
+    result
+}
+
+fn run(vault_dir: &Path, conn: &Connection) -> crate::Result<()> {
+    let dir = dir(vault_dir);
+    let now = now()?;
+    let burst = quiet(vault_dir, now);
+    let due = match list(&dir)?.first() {
+        Some(latest) => now - latest.time >= INTERVAL,
+        None => true,
+    };
+    if !burst && !due {
+        return Ok(());
+    }
+    snapshot(conn, &dir)?;
+    rotate(&dir, retention(conn)?, now)
+}
+
+// 保险库的快照目录
+pub fn dir(vault_dir: &Path) -> PathBuf {
+    vault_dir.join("backups")
+}
+
+// 创建快照
+pub fn snapshot(conn: &Connection, dir: &Path) -> crate::Result<Snapshot> {
+    create_dir_all(dir).map_err(err!())?;
+    let time = now()?;
+    let name = format!("{}{}", PREFIX, time);
+    let path = dir.join(&name);
+    if !path.exists() {
+        // 先以 0600 创建临时文件，备份完成后再重命名，避免留下不完整的快照
+        let tmp = dir.join(format!("{}.tmp", name));
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        options.open(&tmp).map_err(err!())?;
+        if let Err(err) = conn.backup(DatabaseName::Main, &tmp, None) {
+            let _ = remove_file(&tmp);
+            return Err(err!(err));
+        }
+        rename(&tmp, &path).map_err(err!())?;
+    }
+    let size = path.metadata().map_err(err!())?.len();
+    Ok(Snapshot { name, time, size })
+}
+
+// 所有快照，最新的在前面
+pub fn list(dir: &Path) -> crate::Result<Vec<Snapshot>> {
+    let entries = match read_dir(dir) {
+        Ok(v) => v,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err!(err)),
+    };
+    let mut list = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(err!())?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(time) = parse(&name) {
+            let size = entry.metadata().map_err(err!())?.len();
+            list.push(Snapshot { name, time, size });
+        }
+    }
+    list.sort_by_key(|v| Reverse(v.time));
+    Ok(list)
+}
+
+// 快照路径，名称不合法时返回错误
+pub fn path(dir: &Path, name: &str) -> crate::Result<PathBuf> {
+    let path = match parse(name) {
+        Some(_) => dir.join(name),
+        None => return Err(err!(invalid_data("invalid snapshot name"))),
+    };
+    if !path.is_file() {
+        return Err(err!(io::Error::from(io::ErrorKind::NotFound)));
+    }
+    Ok(path)
+}
+
+// 检查快照的完整性
+pub fn verify(path: &Path) -> crate::Result<Verify> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let conn = Connection::open_with_flags(path, flags).map_err(err!())?;
+    let result: String = match conn.query_row("PRAGMA integrity_check", [], |row| row.get(0)) {
+        Ok(v) => v,
+        // 不是 SQLite 数据库
+        Err(rusqlite::Error::SqliteFailure(..)) => String::new(),
+        Err(err) => return Err(err!(err)),
+    };
+    let mut verify = Verify {
+        integrity: result == "ok",
+        version: None,
+        count: 0,
+        key: None,
+        key_file: None,
+    };
+    if !verify.integrity {
+        return Ok(verify);
+    }
+
+    let query = |sql| {
+        conn.query_row(sql, [], |row| row.get::<_, String>(0))
+            .optional()
+    };
+    let version = query("SELECT value FROM conf WHERE key='version'");
+    verify.version = version.ok().flatten().and_then(|v| v.parse().ok());
+    if verify.version.is_none() {
+        verify.integrity = false;
+        return Ok(verify);
+    }
+    verify.key = query("SELECT value FROM conf WHERE key='key'").map_err(err!())?;
+    verify.key_file = query("SELECT value FROM conf WHERE key='key_file'").map_err(err!())?;
+    verify.count = conn
+        .query_row("SELECT COUNT(0) FROM vault", [], |row| row.get(0))
+        .map_err(err!())?;
+    Ok(verify)
+}
+
+// 用快照替换数据库，之后升级到当前版本，快照的版本比程序新时返回错误
+pub fn restore(conn: &mut Connection, vault_dir: &Path, path: &Path) -> crate::Result<()> {
+    if let Some(version) = verify(path)?.version {
+        check_version(version)?;
+    }
+    conn.restore(DatabaseName::Main, path, None::<fn(Progress)>)
+        .map_err(err!())?;
+    setup(conn, &dir(vault_dir))?;
+    changed(vault_dir);
+    Ok(())
+}
+
+// 按保留策略删除旧快照，最新的快照总是保留
+pub fn rotate(dir: &Path, retention: Retention, now: i64) -> crate::Result<()> {
+    let list = list(dir)?;
+    let mut keep = HashSet::new();
+    let mut days = HashSet::new();
+    let mut weeks = HashSet::new();
+    for (i, snapshot) in list.iter().enumerate() {
+        let age = now - snapshot.time;
+        let keep_count = i == 0 || i < retention.count as usize;
+        let keep_daily = age < retention.daily as i64 * DAY && days.insert(snapshot.time / DAY);
+        let keep_weekly =
+            age < retention.weekly as i64 * WEEK && weeks.insert(snapshot.time / WEEK);
+        if keep_count || keep_daily || keep_weekly {
+            keep.insert(i);
+        }
+    }
+    for (i, snapshot) in list.iter().enumerate() {
+        if !keep.contains(&i) {
+            remove_file(dir.join(&snapshot.name)).map_err(err!())?;
+        }
+    }
+    Ok(())
+}
+
+// 读取保留策略
+pub fn retention(conn: &Connection) -> crate::Result<Retention> {
+    const SQL: &str = "SELECT value FROM conf WHERE key='backup_retention'";
+    let value: Option<String> = conn
+        .query_row(SQL, [], |row| row.get(0))
+        .optional()
+        .map_err(err!())?;
+    match value {
+        Some(value) => serde_json::from_str(&value).map_err(err!()),
+        None => Ok(Retention::default()),
+    }
+}
+
+fn parse(name: &str) -> Option<i64> {
+    let time = name.strip_prefix(PREFIX)?;
+    if time.is_empty() || !time.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    time.parse().ok()
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn names(dir: &Path) -> Vec<String> {
+        list(dir).unwrap().into_iter().map(|v| v.name).collect()
+    }
+
+    #[test]
+    fn snapshot_verify_restore() {
+        let vault_dir = tempdir().unwrap();
+        let dir = dir(vault_dir.path());
+        let mut conn = Connection::open(vault_dir.path().join("database")).unwrap();
+        setup(&mut conn, &dir).unwrap();
+        conn.execute("INSERT INTO vault (key, value) VALUES (x'01', x'02')", [])
+            .unwrap();
+
+        let snapshot = snapshot(&conn, &dir).unwrap();
+        assert_eq!(names(&dir), [snapshot.name.as_str()]);
+        let path = path(&dir, &snapshot.name).unwrap();
+        let result = verify(&path).unwrap();
+        assert!(result.integrity);
+        assert_eq!(result.version, Some(crate::db::supported_version()));
+        assert_eq!(result.count, 1);
+
+        conn.execute("DELETE FROM vault", []).unwrap();
+        restore(&mut conn, vault_dir.path(), &path).unwrap();
+        let count: u64 = conn
+            .query_row("SELECT COUNT(0) FROM vault", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+
+        // 不是数据库的文件
+        write(dir.join("database-1"), b"not a database").unwrap();
+        assert!(!verify(&dir.join("database-1")).unwrap().integrity);
+    }
+
+    #[test]
+    fn snapshot_names() {
+        let dir = tempdir().unwrap();
+        for name in ["database-", "database-1.tmp", "database-x", "other"] {
+            write(dir.path().join(name), b"").unwrap();
+            assert!(path(dir.path(), name).is_err());
+        }
+        assert!(path(dir.path(), "../database-1").is_err());
+        assert!(path(dir.path(), "database-2").is_err());
+        write(dir.path().join("database-2"), b"").unwrap();
+        assert!(path(dir.path(), "database-2").is_ok());
+        assert_eq!(names(dir.path()), ["database-2"]);
+        assert!(list(&dir.path().join("missing")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rotate_retention() {
+        let dir = tempdir().unwrap();
+        let now = 100 * WEEK;
+        // 最近两天每天两个，三周前一个，十周前一个
+        let times = [
+            now - 60,
+            now - 3600,
+            now - DAY - 60,
+            now - DAY - 3600,
+            now - 3 * WEEK,
+            now - 10 * WEEK,
+        ];
+        let create = || {
+            for time in times {
+                write(dir.path().join(format!("{}{}", PREFIX, time)), b"").unwrap();
+            }
+        };
+        let name = |time: i64| format!("{}{}", PREFIX, time);
+
+        create();
+        let retention = Retention {
+            daily: 7,
+            weekly: 4,
+            count: 1,
+        };
+        rotate(dir.path(), retention, now).unwrap();
+        assert_eq!(
+            names(dir.path()),
+            [name(times[0]), name(times[2]), name(times[4])]
+        );
+
+        create();
+        let retention = Retention {
+            daily: 0,
+            weekly: 0,
+            count: 2,
+        };
+        rotate(dir.path(), retention, now).unwrap();
+        assert_eq!(names(dir.path()), [name(times[0]), name(times[1])]);
+
+        // 最新的快照总是保留
+        create();
+        let retention = Retention {
+            daily: 0,
+            weekly: 0,
+            count: 0,
+        };
+        rotate(dir.path(), retention, now).unwrap();
+        assert_eq!(names(dir.path()), [name(times[0])]);
+    }
+
+    #[test]
+    fn quiet_after_change() {
+        let dir = tempdir().unwrap();
+        let now = now().unwrap();
+        assert!(!quiet(dir.path(), now + QUIET));
+        changed(dir.path());
+        assert!(!quiet(dir.path(), now));
+        assert!(quiet(dir.path(), now + QUIET + 1));
+        // 已经清除写入标记
+        assert!(!quiet(dir.path(), now + QUIET + 1));
+    }
+
+    // 审计日志和最后使用时间不算写入
+    #[test]
+    fn ignore_untracked_writes() {
+        let dir = tempdir().unwrap();
+        let conn = crate::server::open_database(dir.path()).unwrap();
+        let later = now().unwrap() + QUIET + 1;
+        conn.execute("INSERT INTO vault (key, value) VALUES (x'01', x'02')", [])
+            .unwrap();
+        assert!(quiet(dir.path(), later));
+
+        crate::db::untracked(|| conn.execute("UPDATE vault SET accessed_at=1", [])).unwrap();
+        crate::audit::append(&conn, None, 1, "reveal", false).unwrap();
+        assert!(!quiet(dir.path(), later));
+
+        conn.execute("UPDATE vault SET modified_at=1", []).unwrap();
+        assert!(quiet(dir.path(), later));
+    }
+}
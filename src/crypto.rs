@@ -1,5 +1,10 @@
 use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::md::Md;
+use openssl::pkey::{Id, PKey};
+use openssl::pkey_ctx::PkeyCtx;
 use openssl::rand::rand_bytes;
+use openssl::sign::Signer;
 use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
 use sha2::digest::Output;
 use sha2::{Digest, Sha256};
@@ -67,6 +72,26 @@ pub fn key_decrypt(
     }
 }
 
+// HMAC-SHA256
+pub fn hmac_sha256(key: impl AsRef<[u8]>, data: impl AsRef<[u8]>) -> Result<Vec<u8>, ErrorStack> {
+    let key = PKey::hmac(key.as_ref())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.update(data.as_ref())?;
+    signer.sign_to_vec()
+}
+
+// 从保险库密钥派生子密钥 (HKDF-SHA256)，label 区分用途，不直接把保险库密钥用于加密以外的用途
+pub fn derive_key(key: impl AsRef<[u8]>, label: &str) -> Result<[u8; 32], ErrorStack> {
+    let mut ctx = PkeyCtx::new_id(Id::HKDF)?;
+    ctx.derive_init()?;
+    ctx.set_hkdf_md(Md::sha256())?;
+    ctx.set_hkdf_key(key.as_ref())?;
+    ctx.add_hkdf_info(label.as_bytes())?;
+    let mut output = [0u8; 32];
+    ctx.derive(Some(&mut output))?;
+    Ok(output)
+}
+
 // 把密码转为 key
 fn calc_key(password: impl AsRef<[u8]>) -> Output<Sha256> {
     const SALT: &[u8] = &[
@@ -78,3 +103,18 @@ fn calc_key(password: impl AsRef<[u8]>) -> Output<Sha256> {
     hasher.update(SALT);
     hasher.finalize()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derive_subkey() {
+        let key = [7u8; 32];
+        let health = derive_key(key, "health").unwrap();
+        assert_eq!(health, derive_key(key, "health").unwrap());
+        assert_ne!(health, derive_key(key, "audit").unwrap());
+        assert_ne!(health, derive_key([8u8; 32], "health").unwrap());
+        assert_ne!(&health[..], &key[..]);
+    }
+}
@@ -1,4 +1,13 @@
-use rusqlite::Connection;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{Connection, OptionalExtension, Transaction};
+
+use crate::{audit, backup};
 
 static VERSION_0: &str = "create table vault
 (
@@ -14,26 +23,269 @@ create table conf
     value text
 );";
 
-static VERSIONS: &[&str] = &[VERSION_0];
+// 密码历史记录
+static VERSION_1: &str = "create table history
+(
+    id integer primary key autoincrement,
+    vault_id integer not null,
+    value blob not null,
+    time integer not null
+);
+create index history_vault_id_index on history (vault_id);";
+
+// 回收站，deleted_at 不为 NULL 表示已删除
+static VERSION_2: &str = "alter table vault add column deleted_at integer;
+create index vault_deleted_at_index on vault (deleted_at);";
+
+// 创建、修改、最后使用时间
+static VERSION_3: &str = "alter table vault add column created_at integer not null default 0;
+alter table vault add column modified_at integer not null default 0;
+alter table vault add column accessed_at integer;
+update vault set created_at=strftime('%s', 'now'), modified_at=strftime('%s', 'now');";
+
+// 是否开启了两步验证
+static VERSION_4: &str = "alter table vault add column two_factor integer not null default 0;";
+
+// 审计日志
+static VERSION_5: &str = "create table audit
+(
+    id integer primary key autoincrement,
+    time integer not null,
+    event text not null,
+    detail text not null
+);";
+
+// 隔离区，保存无法解密的密码和历史记录的原始数据
+static VERSION_6: &str = "create table quarantine
+(
+    id integer primary key autoincrement,
+    source text not null,
+    source_id integer not null,
+    vault_id integer not null,
+    key blob,
+    value blob not null,
+    time integer not null
+);";
+
+// 同步：条目的全局 id、版本向量、变更日志、彻底删除的条目、同步对象和冲突
+// 本地修改由触发器增加本设备的版本号并记录变更日志，应用对方的修改时直接设置版本向量
+static VERSION_7: &str = "alter table vault add column uuid text;
+alter table vault add column version text not null default '{}';
+insert into conf (key, value) values ('device_id', lower(hex(randomblob(16))));
+update vault set uuid=lower(hex(randomblob(16))), version=json_object((SELECT value FROM conf WHERE key='device_id'), 1);
+create unique index vault_uuid_uindex on vault (uuid);
+
+create table changelog
+(
+    seq integer primary key autoincrement,
+    uuid text not null
+);
+insert into changelog (uuid) select uuid from vault order by id;
+
+create table tombstone
+(
+    uuid text not null primary key,
+    version text not null
+);
+
+create table peer
+(
+    id integer primary key autoincrement,
+    device text,
+    addr text,
+    sent integer not null default 0,
+    received integer not null default 0,
+    synced_at integer
+);
+create unique index peer_device_uindex on peer (device);
+
+create table conflict
+(
+    id integer primary key autoincrement,
+    uuid text not null,
+    peer text not null,
+    data text not null,
+    time integer not null
+);
+create unique index conflict_uuid_peer_uindex on conflict (uuid, peer);
+
+create trigger vault_sync_insert after insert on vault when NEW.uuid is null
+begin
+    update vault set uuid=lower(hex(randomblob(16))), version=json_object((SELECT value FROM conf WHERE key='device_id'), 1) where id=NEW.id;
+    insert into changelog (uuid) select uuid from vault where id=NEW.id;
+end;
+
+create trigger vault_sync_update after update of key, value, deleted_at, two_factor on vault when NEW.version is OLD.version
+begin
+    update vault set version=json_set(version, '$.\"' || (SELECT value FROM conf WHERE key='device_id') || '\"', ifnull(json_extract(version, '$.\"' || (SELECT value FROM conf WHERE key='device_id') || '\"'), 0) + 1) where id=NEW.id;
+    insert into changelog (uuid) values (NEW.uuid);
+end;
+
+create trigger vault_sync_delete after delete on vault when OLD.uuid is not null
+begin
+    insert into tombstone (uuid, version) select OLD.uuid, json_set(OLD.version, '$.\"' || (SELECT value FROM conf WHERE key='device_id') || '\"', ifnull(json_extract(OLD.version, '$.\"' || (SELECT value FROM conf WHERE key='device_id') || '\"'), 0) + 1) where not exists (select 1 from tombstone where uuid=OLD.uuid);
+    insert into changelog (uuid) values (OLD.uuid);
+end;";
+
+// 文件夹同步：每个设备的操作日志读到或写到的位置
+// segment、record 为当前的段和下一条记录的序号，hash 为上一条记录的 sha256，seq 为本设备已写入日志的变更序号
+static VERSION_8: &str = "create table folder_log
+(
+    device text not null primary key,
+    segment integer not null default 0,
+    record integer not null default 0,
+    hash text not null default '',
+    seq integer not null default 0
+);";
+
+// 审计日志加密保存并加入 HMAC 哈希链，已有的记录无法加密，保留明文。没有保险库密钥时写入的记录
+// (包括升级时转换的记录) hash 为空，解锁时计算，只允许把空的 hash 改为计算的值
+static VERSION_9: &str = "alter table audit rename to audit_v5;
+create table audit
+(
+    id integer primary key autoincrement,
+    time integer not null,
+    -- 事件、详情、客户端地址的 JSON，使用审计公钥加密
+    data text not null,
+    encrypted integer not null,
+    -- 上一条记录的 hash 和本条记录的 HMAC，没有计算时为空
+    hash text not null
+);
+create trigger audit_no_update before update on audit
+when old.hash<>'' or new.id<>old.id or new.time<>old.time or new.data<>old.data or new.encrypted<>old.encrypted
+begin
+    select raise(abort, 'audit log is append-only');
+end;
+create trigger audit_no_delete before delete on audit
+begin
+    select raise(abort, 'audit log is append-only');
+end;";
+
+fn version_9(tx: &Transaction) -> crate::Result<()> {
+    tx.execute_batch(VERSION_9).map_err(err!())?;
+    let mut stmt = tx
+        .prepare("SELECT time, event, detail FROM audit_v5 ORDER BY id")
+        .map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    while let Some(row) = rows.next().map_err(err!())? {
+        let event = audit::Event {
+            event: row.get(1).map_err(err!())?,
+            detail: row.get(2).map_err(err!())?,
+            address: String::new(),
+        };
+        let data = serde_json::to_string(&event).map_err(err!())?;
+        audit::append(tx, None, row.get(0).map_err(err!())?, &data, false)?;
+    }
+    drop(rows);
+    drop(stmt);
+    tx.execute_batch("drop table audit_v5").map_err(err!())
+}
+
+// 条目分享：发出的分享，recipient 为接收者的分享公钥，revoked_at 为撤销时间；导入的分享，sender 为发送者的
+// 分享公钥；发送者撤销的分享，可能在导入之前收到
+static VERSION_10: &str = "create table share_sent
+(
+    id text not null primary key,
+    recipient text not null,
+    count integer not null,
+    created_at integer not null,
+    revoked_at integer
+);
+create table share_received
+(
+    id text not null,
+    sender text not null,
+    count integer not null,
+    created_at integer not null,
+    imported_at integer not null,
+    primary key (sender, id)
+);
+create table share_revoked
+(
+    sender text not null,
+    id text not null,
+    primary key (sender, id)
+);";
+
+// 升级步骤，第 n 个步骤把数据库从版本 n 升级到 n + 1
+enum Step {
+    Sql(&'static str),
+    // Rust 代码，和 SQL 在同一个事务中执行，用于重新加密等 SQL 无法完成的升级
+    Rust(fn(&Transaction) -> crate::Result<()>),
+}
+
+static STEPS: &[Step] = &[
+    Step::Sql(VERSION_0),
+    Step::Sql(VERSION_1),
+    Step::Sql(VERSION_2),
+    Step::Sql(VERSION_3),
+    Step::Sql(VERSION_4),
+    Step::Sql(VERSION_5),
+    Step::Sql(VERSION_6),
+    Step::Sql(VERSION_7),
+    Step::Sql(VERSION_8),
+    Step::Rust(version_9),
+    Step::Sql(VERSION_10),
+];
+
+// 升级数据库，升级前把数据库备份到 backup_dir，升级失败时回滚
+pub fn setup(conn: &mut Connection, backup_dir: &Path) -> crate::Result<()> {
+    migrate(conn, STEPS, backup_dir)
+}
 
-pub fn setup(conn: &mut Connection) -> crate::Result<()> {
-    let mut version = get_version(conn)?;
-    if version >= VERSIONS.len() {
+// 程序支持的数据库版本
+pub fn supported_version() -> usize {
+    STEPS.len()
+}
+
+// 检查数据库版本是否支持
+pub fn check_version(version: usize) -> crate::Result<()> {
+    if version > STEPS.len() {
+        return Err(err!(NewerVersion {
+            version,
+            supported: STEPS.len(),
+        }));
+    }
+    Ok(())
+}
+
+fn migrate(conn: &mut Connection, steps: &[Step], backup_dir: &Path) -> crate::Result<()> {
+    let from = get_version(conn)?;
+    if from > steps.len() {
+        return Err(err!(NewerVersion {
+            version: from,
+            supported: steps.len(),
+        }));
+    }
+    if from == steps.len() {
         return Ok(());
     }
+    if from > 0 {
+        backup::snapshot(conn, backup_dir)?;
+    }
 
     let tx = conn.transaction().map_err(err!())?;
-    while version < VERSIONS.len() {
-        tx.execute_batch(VERSIONS[version]).map_err(err!())?;
-        version += 1;
+    for step in &steps[from..] {
+        match step {
+            Step::Sql(sql) => tx.execute_batch(sql).map_err(err!())?,
+            Step::Rust(run) => run(&tx)?,
+        }
+    }
+
+    // 升级后检查数据库，有问题时回滚
+    let result: String = tx
+        .query_row("PRAGMA quick_check", [], |row| row.get(0))
+        .map_err(err!())?;
+    if result != "ok" {
+        return Err(err!(io::Error::new(io::ErrorKind::InvalidData, result)));
     }
 
-    let sql = if version == 1 {
+    let sql = if from == 0 {
         "INSERT INTO conf (key, value) VALUES ('version', ?)"
     } else {
         "UPDATE conf SET value=? WHERE key='version'"
     };
-    tx.execute(sql, [version]).map_err(err!())?;
+    tx.execute(sql, [steps.len()]).map_err(err!())?;
     tx.commit().map_err(err!())
 }
 
@@ -48,3 +300,374 @@ fn get_version(conn: &Connection) -> crate::Result<usize> {
         version.parse().map_err(err!())
     }
 }
+
+// 数据库版本比程序支持的版本新
+#[derive(Debug)]
+pub struct NewerVersion {
+    version: usize,
+    supported: usize,
+}
+
+impl Display for NewerVersion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "database version {} is newer than supported version {}, please upgrade the app",
+            self.version, self.supported
+        )
+    }
+}
+
+impl Error for NewerVersion {}
+
+thread_local! {
+    // 当前线程正在执行不算内容修改的写入
+    static UNTRACKED: Cell<bool> = const { Cell::new(false) };
+}
+
+// 执行不算内容修改的写入（如更新最后使用时间），不触发备份和变更通知
+pub fn untracked<T>(f: impl FnOnce() -> T) -> T {
+    UNTRACKED.set(true);
+    let result = f();
+    UNTRACKED.set(false);
+    result
+}
+
+// 由 update hook 调用，写入是否算内容修改，审计日志不算
+pub fn is_tracked(table: &str) -> bool {
+    table != "audit" && !UNTRACKED.get()
+}
+
+// 读取配置
+pub fn get_conf(conn: &Connection, key: &str) -> crate::Result<Option<String>> {
+    const SQL: &str = "SELECT value FROM conf WHERE key=?";
+    conn.query_row(SQL, [key], |row| row.get(0))
+        .optional()
+        .map_err(err!())
+}
+
+// 保存配置
+pub fn set_conf(conn: &Connection, key: &str, value: &str) -> crate::Result<()> {
+    const SQL: &str = "INSERT OR REPLACE INTO conf (key, value) VALUES (?, ?)";
+    conn.execute(SQL, [key, value]).map_err(err!())?;
+    Ok(())
+}
+
+// 删除配置
+pub fn delete_conf(conn: &Connection, key: &str) -> crate::Result<()> {
+    conn.execute("DELETE FROM conf WHERE key=?", [key])
+        .map_err(err!())?;
+    Ok(())
+}
+
+// 当前时间戳（秒）
+pub fn now() -> crate::Result<i64> {
+    let duration = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(err!())?;
+    Ok(duration.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+    use crate::share;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    // 各版本的数据库，tests/fixtures/migration/v{n}.sql
+    static FIXTURES: &[&str] = &[
+        include_str!("../tests/fixtures/migration/v1.sql"),
+        include_str!("../tests/fixtures/migration/v2.sql"),
+        include_str!("../tests/fixtures/migration/v3.sql"),
+        include_str!("../tests/fixtures/migration/v4.sql"),
+        include_str!("../tests/fixtures/migration/v5.sql"),
+        include_str!("../tests/fixtures/migration/v6.sql"),
+        include_str!("../tests/fixtures/migration/v7.sql"),
+        include_str!("../tests/fixtures/migration/v8.sql"),
+        include_str!("../tests/fixtures/migration/v9.sql"),
+        include_str!("../tests/fixtures/migration/v10.sql"),
+    ];
+
+    // 每个测试单独的备份目录，TempDir 释放时删除
+    fn backup_dir() -> (TempDir, PathBuf) {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path().join("backups");
+        (tmp, dir)
+    }
+
+    fn fixture(version: usize) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(FIXTURES[version - 1]).unwrap();
+        assert_eq!(get_version(&conn).unwrap(), version);
+        conn
+    }
+
+    // 表结构：表名、列名、类型、非空、默认值，以及索引
+    fn schema(conn: &Connection) -> Vec<String> {
+        let sql = "SELECT type, name FROM sqlite_master WHERE name NOT LIKE 'sqlite_%' ORDER BY type, name";
+        let mut stmt = conn.prepare(sql).unwrap();
+        let objects: Vec<(String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect();
+        let mut schema = Vec::new();
+        for (kind, name) in objects {
+            schema.push(format!("{} {}", kind, name));
+            if kind == "table" {
+                let sql = format!("PRAGMA table_info({})", name);
+                let mut stmt = conn.prepare(&sql).unwrap();
+                let columns = stmt
+                    .query_map([], |row| {
+                        let name: String = row.get(1)?;
+                        let kind: String = row.get(2)?;
+                        let not_null: bool = row.get(3)?;
+                        let default: Option<String> = row.get(4)?;
+                        Ok(format!("  {} {} {} {:?}", name, kind, not_null, default))
+                    })
+                    .unwrap();
+                schema.extend(columns.map(|v| v.unwrap()));
+            }
+        }
+        schema
+    }
+
+    fn fresh() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let (_tmp, dir) = backup_dir();
+        setup(&mut conn, &dir).unwrap();
+        // 新数据库不需要备份
+        assert!(!dir.exists());
+        conn
+    }
+
+    // 升级到最新版本，检查备份、表结构和原有数据
+    fn migrate_fixture(version: usize) -> Connection {
+        let mut conn = fixture(version);
+        let (_tmp, dir) = backup_dir();
+        setup(&mut conn, &dir).unwrap();
+        assert_eq!(get_version(&conn).unwrap(), STEPS.len());
+
+        let snapshots = backup::list(&dir).unwrap();
+        assert_eq!(snapshots.len(), 1);
+        let verify = backup::verify(&dir.join(&snapshots[0].name)).unwrap();
+        assert!(verify.integrity);
+        assert_eq!(verify.version, Some(version));
+
+        assert_eq!(schema(&conn), schema(&fresh()));
+        let sql = "SELECT key, value FROM vault ORDER BY id";
+        let mut stmt = conn.prepare(sql).unwrap();
+        let rows: Vec<(Vec<u8>, Vec<u8>)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .map(|v| v.unwrap())
+            .collect();
+        let expected = vec![
+            (b"name-1".to_vec(), b"password-1".to_vec()),
+            (b"name-2".to_vec(), b"password-2".to_vec()),
+        ];
+        assert_eq!(rows, expected);
+        let sql = "SELECT value FROM conf WHERE key='key'";
+        let key: String = conn.query_row(sql, [], |row| row.get(0)).unwrap();
+        assert_eq!(key, "c2FtcGxlIG1hc3RlciBrZXk=");
+        drop(stmt);
+        conn
+    }
+
+    fn count(conn: &Connection, sql: &str) -> u64 {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    // 每个升级步骤都有升级前版本的数据库，新增步骤时需要添加 v{n}.sql 和 migrate_v{n}
+    #[test]
+    fn fixture_for_every_step() {
+        assert_eq!(FIXTURES.len(), STEPS.len() - 1);
+        for version in 1..STEPS.len() {
+            fixture(version);
+        }
+    }
+
+    #[test]
+    fn fresh_database() {
+        let conn = fresh();
+        assert_eq!(get_version(&conn).unwrap(), STEPS.len());
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let conn = migrate_fixture(1);
+        assert_eq!(count(&conn, "SELECT COUNT(0) FROM history"), 0);
+        // 版本 3 把创建、修改时间设为升级时间
+        let sql = "SELECT COUNT(0) FROM vault WHERE created_at=0 OR modified_at=0";
+        assert_eq!(count(&conn, sql), 0);
+    }
+
+    #[test]
+    fn migrate_v2() {
+        let conn = migrate_fixture(2);
+        let sql = "SELECT COUNT(0) FROM history WHERE vault_id=1 AND value=x'6f6c64'";
+        assert_eq!(count(&conn, sql), 1);
+        let sql = "SELECT COUNT(0) FROM vault WHERE deleted_at IS NOT NULL";
+        assert_eq!(count(&conn, sql), 0);
+    }
+
+    #[test]
+    fn migrate_v3() {
+        let conn = migrate_fixture(3);
+        let sql = "SELECT COUNT(0) FROM vault WHERE id=2 AND deleted_at=1600000100";
+        assert_eq!(count(&conn, sql), 1);
+        let sql = "SELECT COUNT(0) FROM vault WHERE created_at=0";
+        assert_eq!(count(&conn, sql), 0);
+    }
+
+    #[test]
+    fn migrate_v4() {
+        let conn = migrate_fixture(4);
+        let sql = "SELECT COUNT(0) FROM vault WHERE id=1 AND created_at=1600000000 AND modified_at=1600000050 AND accessed_at=1600000060";
+        assert_eq!(count(&conn, sql), 1);
+        assert_eq!(
+            count(&conn, "SELECT COUNT(0) FROM vault WHERE two_factor=1"),
+            0
+        );
+    }
+
+    #[test]
+    fn migrate_v5() {
+        let conn = migrate_fixture(5);
+        let sql = "SELECT COUNT(0) FROM vault WHERE id=1 AND two_factor=1";
+        assert_eq!(count(&conn, sql), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(0) FROM audit"), 0);
+    }
+
+    #[test]
+    fn migrate_v6() {
+        let conn = migrate_fixture(6);
+        let sql =
+            "SELECT COUNT(0) FROM audit WHERE json_extract(data, '$.event')='export_plaintext'";
+        assert_eq!(count(&conn, sql), 1);
+        assert_eq!(count(&conn, "SELECT COUNT(0) FROM quarantine"), 0);
+    }
+
+    #[test]
+    fn migrate_v7() {
+        let conn = migrate_fixture(7);
+        assert_eq!(count(&conn, "SELECT COUNT(0) FROM quarantine"), 1);
+        // 已有的条目分配了 uuid 和本设备的版本号，并写入变更日志
+        let sql = "SELECT COUNT(DISTINCT uuid) FROM vault WHERE length(uuid)=32";
+        assert_eq!(count(&conn, sql), 2);
+        let sql = "SELECT COUNT(0) FROM vault, conf WHERE conf.key='device_id' AND json_extract(version, '$.\"' || conf.value || '\"')=1";
+        assert_eq!(count(&conn, sql), 2);
+        assert_eq!(count(&conn, "SELECT COUNT(0) FROM changelog"), 2);
+    }
+
+    #[test]
+    fn migrate_v8() {
+        let conn = migrate_fixture(8);
+        // 同步状态保留
+        assert_eq!(count(&conn, "SELECT COUNT(0) FROM tombstone"), 1);
+        assert_eq!(count(&conn, "SELECT received FROM peer"), 5);
+        assert_eq!(count(&conn, "SELECT MAX(seq) FROM changelog"), 3);
+        assert_eq!(count(&conn, "SELECT COUNT(0) FROM folder_log"), 0);
+    }
+
+    #[test]
+    fn migrate_v9() {
+        let conn = migrate_fixture(9);
+        assert_eq!(count(&conn, "SELECT record FROM folder_log"), 7);
+        // 已有的审计日志保留为明文，解锁时加入哈希链
+        let sql = "SELECT COUNT(0) FROM audit WHERE encrypted=0 AND json_extract(data, '$.detail')='csv: /tmp/export.csv'";
+        assert_eq!(count(&conn, sql), 1);
+        assert_eq!(audit::verify(&conn, &KEY).unwrap(), None);
+        // 只能追加
+        assert!(conn.execute("UPDATE audit SET time=0", []).is_err());
+        assert!(conn.execute("DELETE FROM audit", []).is_err());
+        audit::init(&conn, &KEY).unwrap();
+        audit::append(&conn, Some(&KEY), 1700000000, "{}", false).unwrap();
+        assert_eq!(audit::verify(&conn, &KEY).unwrap(), None);
+        // 计算过的 hash 不能修改
+        assert!(conn
+            .execute("UPDATE audit SET hash='' WHERE id=1", [])
+            .is_err());
+        conn.execute("DROP TRIGGER audit_no_update", []).unwrap();
+        conn.execute("UPDATE audit SET data='{\"event\":\"x\"}' WHERE id=1", [])
+            .unwrap();
+        assert_eq!(audit::verify(&conn, &KEY).unwrap(), Some(1));
+    }
+
+    #[test]
+    fn migrate_v10() {
+        let conn = migrate_fixture(10);
+        assert_eq!(audit::verify(&conn, &KEY).unwrap(), None);
+        // 还没有分享
+        assert!(share::list_sent(&conn).unwrap().is_empty());
+        assert!(share::list_received(&conn).unwrap().is_empty());
+        let sql = "SELECT COUNT(0) FROM conf WHERE key LIKE 'share_%'";
+        assert_eq!(count(&conn, sql), 0);
+    }
+
+    #[test]
+    fn refuse_newer_version() {
+        let mut conn = fixture(6);
+        let version = STEPS.len() + 1;
+        let sql = "UPDATE conf SET value=? WHERE key='version'";
+        conn.execute(sql, [version]).unwrap();
+        let (_tmp, dir) = backup_dir();
+        let err = setup(&mut conn, &dir).unwrap_err();
+        assert!(err.to_string().contains("newer than supported"));
+        assert_eq!(get_version(&conn).unwrap(), version);
+        assert!(!dir.exists());
+        assert!(check_version(version).is_err());
+        assert!(check_version(STEPS.len()).is_ok());
+    }
+
+    #[test]
+    fn rust_step() {
+        fn insert(tx: &Transaction) -> crate::Result<()> {
+            let sql = "INSERT INTO conf (key, value) VALUES ('rust', 'ok')";
+            tx.execute(sql, []).map_err(err!())?;
+            Ok(())
+        }
+        let steps = [Step::Sql(VERSION_0), Step::Rust(insert)];
+        let mut conn = Connection::open_in_memory().unwrap();
+        let (_tmp, dir) = backup_dir();
+        migrate(&mut conn, &steps, &dir).unwrap();
+        assert_eq!(get_version(&conn).unwrap(), 2);
+        let sql = "SELECT COUNT(0) FROM conf WHERE key='rust'";
+        assert_eq!(count(&conn, sql), 1);
+    }
+
+    #[test]
+    fn rollback_failed_step() {
+        fn fail(tx: &Transaction) -> crate::Result<()> {
+            tx.execute("DELETE FROM vault", []).map_err(err!())?;
+            Err(err!(io::Error::other("failed")))
+        }
+        let mut steps: Vec<Step> = STEPS
+            .iter()
+            .map(|v| match v {
+                Step::Sql(sql) => Step::Sql(sql),
+                Step::Rust(run) => Step::Rust(*run),
+            })
+            .collect();
+        steps.push(Step::Rust(fail));
+        let mut conn = fixture(6);
+        let (_tmp, dir) = backup_dir();
+        assert!(migrate(&mut conn, &steps, &dir).is_err());
+        assert_eq!(get_version(&conn).unwrap(), 6);
+        assert_eq!(count(&conn, "SELECT COUNT(0) FROM vault"), 2);
+        assert_eq!(
+            count(
+                &conn,
+                "SELECT COUNT(0) FROM sqlite_master WHERE name='quarantine'"
+            ),
+            0
+        );
+        // 升级前的备份保留
+        assert_eq!(backup::list(&dir).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,254 @@
+// 胁迫密码
+//
+// 出行时可以设置胁迫密码，解锁时输入胁迫密码会改为打开诱饵保险库，诱饵保险库和正常的保险库一样可以使用。
+// 可以选择同时销毁真实保险库的 conf 的 key (用随机数据覆盖)，之后真实的主密码无法解锁，只能使用
+// 恢复密钥或者其他设备上的副本恢复。
+//
+// 从外部看两种情况必须一致，所以执行相同的操作：都覆盖 key，诱饵模式写回原来的值，销毁模式写入等长的
+// 随机数据，之后都打开诱饵保险库。解锁时无论输入的是什么密码，都计算一次 seal 的 KDF 和一次主密码的 KDF，
+// 诱饵保险库的密钥保存在配置中，打开诱饵保险库不需要再计算。
+//
+// 配置保存在真实保险库的 conf 的 duress，包括用 keyfile::seal(胁迫密码 + 密钥文件) 加密的模式和诱饵保险库
+// 密钥，以及用保险库密钥加密的是否设置。没有设置胁迫密码时保存同样格式的随机数据，诱饵保险库目录也一直存在
+// (用随机密码加密的空保险库)，从磁盘上看不出是否设置了胁迫密码。诱饵保险库在真实保险库目录下的 decoy
+// 目录，主密码就是胁迫密码，密钥文件和真实保险库相同。
+//
+// 销毁模式只覆盖 key，以下副本不会被销毁：
+// - 备份中的 key，删除备份的耗时无法和诱饵模式一致
+// - 恢复密钥加密的 recovery，销毁后用它恢复
+// - 同步文件夹和 WebDAV 上的保险库密钥，仍然需要真实的主密码 (和密钥文件) 才能解密
+// 诱饵保险库只在输入胁迫密码的连接上代替真实保险库，其他连接和已打开的真实保险库 (包括同步) 不受影响。
+
+use openssl::rand::rand_bytes;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{key_decrypt, key_encrypt};
+use crate::db::{get_conf, set_conf};
+use crate::keyfile::{self, MasterPassword};
+
+// 诱饵保险库的目录名
+pub const DIR: &str = "decoy";
+
+// 胁迫密码的配置，JSON 格式
+const DURESS: &str = "duress";
+
+// 没有设置胁迫密码时的随机配置
+const MODE_NONE: u8 = 0;
+const MODE_DECOY: u8 = 1;
+const MODE_WIPE: u8 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct Config {
+    // keyfile::seal 加密的模式 + 诱饵保险库密钥
+    sealed: String,
+    // 保险库密钥加密的是否设置，base64 编码
+    set: String,
+}
+
+// 设置胁迫密码，duress 为胁迫密码和真实保险库的密钥文件，wipe 为 true 时输入胁迫密码后销毁保险库密钥
+pub fn set(
+    conn: &Connection,
+    key: &[u8],
+    duress: &MasterPassword,
+    decoy_key: &[u8],
+    wipe: bool,
+) -> crate::Result<()> {
+    let mode = if wipe { MODE_WIPE } else { MODE_DECOY };
+    save(conn, key, duress, mode, decoy_key)
+}
+
+// 删除胁迫密码，decoy 为随机生成的诱饵保险库密码，没有人知道，保存同样格式的配置
+pub fn clear(
+    conn: &Connection,
+    key: &[u8],
+    decoy: &MasterPassword,
+    decoy_key: &[u8],
+) -> crate::Result<()> {
+    save(conn, key, decoy, MODE_NONE, decoy_key)
+}
+
+fn save(
+    conn: &Connection,
+    key: &[u8],
+    password: &MasterPassword,
+    mode: u8,
+    decoy_key: &[u8],
+) -> crate::Result<()> {
+    let mut data = vec![mode];
+    data.extend_from_slice(decoy_key);
+    let key_file = password.key_file();
+    let sealed = keyfile::seal(password.password(), key_file.as_ref(), &data)?;
+    let set = key_encrypt(key, [(mode != MODE_NONE) as u8]).map_err(err!())?;
+    let config = Config {
+        sealed,
+        set: base64::encode(set),
+    };
+    set_conf(
+        conn,
+        DURESS,
+        &serde_json::to_string(&config).map_err(err!())?,
+    )
+}
+
+// 是否已有配置，没有时 (新建的保险库) 调用者在解锁后用 clear 生成
+pub fn exists(conn: &Connection) -> crate::Result<bool> {
+    Ok(get_conf(conn, DURESS)?.is_some())
+}
+
+// 是否设置了胁迫密码，需要保险库密钥
+pub fn is_set(conn: &Connection, key: &[u8]) -> crate::Result<bool> {
+    let config = match config(conn)? {
+        Some(v) => v,
+        None => return Ok(false),
+    };
+    let set = base64::decode(config.set).map_err(err!())?;
+    Ok(key_decrypt(key, set).map_err(err!())?.as_deref() == Some(&[1]))
+}
+
+// 保险库密钥变化时重新加密是否设置
+pub fn reencrypt(conn: &Connection, key: &[u8], new_key: &[u8]) -> crate::Result<()> {
+    let mut config = match config(conn)? {
+        Some(v) => v,
+        None => return Ok(()),
+    };
+    let set = base64::decode(&config.set).map_err(err!())?;
+    let set = match key_decrypt(key, set).map_err(err!())? {
+        Some(v) => v,
+        None => {
+            return Err(err!(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "wrong duress config"
+            )))
+        }
+    };
+    config.set = base64::encode(key_encrypt(new_key, set).map_err(err!())?);
+    set_conf(
+        conn,
+        DURESS,
+        &serde_json::to_string(&config).map_err(err!())?,
+    )
+}
+
+// 检查 master_password 是否是胁迫密码，是时覆盖保险库密钥，返回诱饵保险库密钥，调用者随后打开诱饵保险库
+pub fn check(
+    conn: &Connection,
+    master_password: &MasterPassword,
+) -> crate::Result<Option<Vec<u8>>> {
+    let config = match config(conn)? {
+        Some(v) => v,
+        None => {
+            // 还没有配置时同样计算一次 KDF
+            let key_file = master_password.key_file();
+            keyfile::seal(master_password.password(), key_file.as_ref(), &[MODE_NONE])?;
+            return Ok(None);
+        }
+    };
+    let (wipe, decoy_key) = match keyfile::unseal_exact(master_password, &config.sealed)? {
+        Some(data) => match data.split_first() {
+            Some((&MODE_DECOY, decoy_key)) => (false, decoy_key.to_vec()),
+            Some((&MODE_WIPE, decoy_key)) => (true, decoy_key.to_vec()),
+            _ => return Ok(None),
+        },
+        None => return Ok(None),
+    };
+
+    // 两种模式执行相同的读取和写入
+    let key = get_conf(conn, "key")?.unwrap_or_default();
+    let mut random = vec![0u8; base64::decode(&key).map_err(err!())?.len()];
+    rand_bytes(&mut random).map_err(err!())?;
+    let random = base64::encode(random);
+    let value = if wipe { random } else { key };
+    const SQL: &str = "UPDATE conf SET value=? WHERE key='key'";
+    conn.execute(SQL, [value]).map_err(err!())?;
+    Ok(Some(decoy_key))
+}
+
+fn config(conn: &Connection) -> crate::Result<Option<Config>> {
+    match get_conf(conn, DURESS)? {
+        Some(v) => Ok(Some(serde_json::from_str(&v).map_err(err!())?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{password_decrypt, password_encrypt};
+
+    const KEY: [u8; 32] = [7; 32];
+    const DECOY_KEY: [u8; 32] = [8; 32];
+
+    fn key(conn: &Connection) -> String {
+        get_conf(conn, "key").unwrap().unwrap()
+    }
+
+    fn password(password: &str) -> MasterPassword {
+        MasterPassword::Password(password.to_string())
+    }
+
+    #[test]
+    fn check_duress_password() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table conf (key text not null primary key, value text)")
+            .unwrap();
+        let wrapped = base64::encode(password_encrypt("password", KEY).unwrap());
+        conn.execute("insert into conf values ('key', ?)", [&wrapped])
+            .unwrap();
+
+        assert!(check(&conn, &password("duress")).unwrap().is_none());
+        assert!(!exists(&conn).unwrap());
+        set(&conn, &KEY, &password("duress"), &DECOY_KEY, false).unwrap();
+        assert!(is_set(&conn, &KEY).unwrap());
+        assert!(check(&conn, &password("password")).unwrap().is_none());
+        let decoy_key = check(&conn, &password("duress")).unwrap();
+        assert_eq!(decoy_key.as_deref(), Some(&DECOY_KEY[..]));
+        assert_eq!(key(&conn), wrapped);
+
+        // 销毁后长度不变，主密码无法解密
+        set(&conn, &KEY, &password("duress"), &DECOY_KEY, true).unwrap();
+        assert!(check(&conn, &password("duress")).unwrap().is_some());
+        assert_ne!(key(&conn), wrapped);
+        assert_eq!(key(&conn).len(), wrapped.len());
+        let data = base64::decode(key(&conn)).unwrap();
+        assert!(password_decrypt("password", data).unwrap().is_none());
+
+        // 删除后配置仍然存在，长度和设置时相同
+        let length = get_conf(&conn, DURESS).unwrap().unwrap().len();
+        clear(&conn, &KEY, &password("random"), &DECOY_KEY).unwrap();
+        assert!(exists(&conn).unwrap());
+        assert!(!is_set(&conn, &KEY).unwrap());
+        assert!(check(&conn, &password("duress")).unwrap().is_none());
+        assert!(check(&conn, &password("random")).unwrap().is_none());
+        assert_eq!(get_conf(&conn, DURESS).unwrap().unwrap().len(), length);
+
+        // 保险库密钥变化后仍然可以判断
+        set(&conn, &KEY, &password("duress"), &DECOY_KEY, false).unwrap();
+        reencrypt(&conn, &KEY, &DECOY_KEY).unwrap();
+        assert!(is_set(&conn, &DECOY_KEY).unwrap());
+    }
+
+    #[test]
+    fn duress_key_file() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table conf (key text not null primary key, value text)")
+            .unwrap();
+        let wrapped = base64::encode(password_encrypt("password", KEY).unwrap());
+        conn.execute("insert into conf values ('key', ?)", [&wrapped])
+            .unwrap();
+        let with_file = MasterPassword::KeyFile {
+            password: "duress".to_string(),
+            key_file: base64::encode(b"key file content"),
+        };
+
+        // 真实保险库开启了密钥文件时，胁迫密码同样需要密钥文件
+        set(&conn, &KEY, &with_file, &DECOY_KEY, false).unwrap();
+        assert!(check(&conn, &password("duress")).unwrap().is_none());
+        assert!(check(&conn, &with_file).unwrap().is_some());
+
+        // 没有开启时提供密钥文件同样拒绝
+        set(&conn, &KEY, &password("duress"), &DECOY_KEY, false).unwrap();
+        assert!(check(&conn, &with_file).unwrap().is_none());
+        assert!(check(&conn, &password("duress")).unwrap().is_some());
+    }
+}
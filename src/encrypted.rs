@@ -0,0 +1,202 @@
+// 加密导出格式，不依赖保险库密钥，使用单独的密码和 Argon2id 派生密钥
+//
+// 文件结构：
+// | 魔数 8 字节 | 版本 1 字节 | KDF 1 字节 | 内存 (KiB) 4 字节 | 迭代次数 4 字节 | 并行度 4 字节 |
+// | 盐 16 字节 | iv 12 字节 | 密文 | tag 16 字节 |
+//
+// 整数都是大端，密文之前的部分作为 AES-256-GCM 的附加数据。
+// 明文为 4 字节长度 + JSON `[[名称, 密码], ...]`，用 0 填充到 2 的幂次方，隐藏条目数量和长度。
+
+use std::io;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use openssl::rand::rand_bytes;
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+
+use crate::import::Import;
+
+// 魔数
+const MAGIC: &[u8] = b"VAULTENC";
+
+// 格式版本
+const VERSION: u8 = 1;
+
+// KDF 类型
+const KDF_ARGON2ID: u8 = 1;
+
+// 默认 KDF 参数
+const MEMORY: u32 = 64 * 1024;
+const ITERATIONS: u32 = 3;
+const PARALLELISM: u32 = 4;
+
+// 读取时允许的最大 KDF 参数，防止恶意文件耗尽资源
+const MAX_MEMORY: u32 = 1024 * 1024;
+const MAX_ITERATIONS: u32 = 100;
+const MAX_PARALLELISM: u32 = 16;
+
+const SALT_LEN: usize = 16;
+const IV_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + 2 + 12 + SALT_LEN + IV_LEN;
+
+// 填充后的最小长度
+const MIN_PADDED_LEN: usize = 4096;
+
+// 是否是加密导出格式
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+// 使用 passphrase 加密，passphrase 不能为空
+pub fn write(list: &[(String, String)], passphrase: &str) -> crate::Result<Vec<u8>> {
+    if passphrase.is_empty() {
+        return Err(err!(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "empty passphrase"
+        )));
+    }
+    let mut salt = [0u8; SALT_LEN];
+    rand_bytes(&mut salt).map_err(err!())?;
+    let mut iv = [0u8; IV_LEN];
+    rand_bytes(&mut iv).map_err(err!())?;
+
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(VERSION);
+    header.push(KDF_ARGON2ID);
+    header.extend_from_slice(&MEMORY.to_be_bytes());
+    header.extend_from_slice(&ITERATIONS.to_be_bytes());
+    header.extend_from_slice(&PARALLELISM.to_be_bytes());
+    header.extend_from_slice(&salt);
+    header.extend_from_slice(&iv);
+
+    let json = serde_json::to_vec(list).map_err(err!())?;
+    let mut plaintext = Vec::with_capacity(padded_len(json.len() + 4));
+    plaintext.extend_from_slice(&(json.len() as u32).to_be_bytes());
+    plaintext.extend_from_slice(&json);
+    plaintext.resize(padded_len(plaintext.len()), 0);
+
+    let key = derive_key(passphrase, &salt, MEMORY, ITERATIONS, PARALLELISM)?;
+    let mut tag = [0u8; TAG_LEN];
+    let cipher = Cipher::aes_256_gcm();
+    let ciphertext =
+        encrypt_aead(cipher, &key, Some(&iv), &header, &plaintext, &mut tag).map_err(err!())?;
+
+    let mut output = header;
+    output.extend_from_slice(&ciphertext);
+    output.extend_from_slice(&tag);
+    Ok(output)
+}
+
+// 使用 passphrase 解密，密码错误返回 None
+pub fn read(data: &[u8], passphrase: &str) -> crate::Result<Option<Import>> {
+    if !is_encrypted(data) || data.len() < HEADER_LEN + TAG_LEN {
+        return Err(err!(invalid_data("invalid file")));
+    }
+    let (header, body) = data.split_at(HEADER_LEN);
+    let mut reader = &header[MAGIC.len()..];
+    if take(&mut reader, 1)[0] != VERSION {
+        return Err(err!(invalid_data("unsupported version")));
+    }
+    if take(&mut reader, 1)[0] != KDF_ARGON2ID {
+        return Err(err!(invalid_data("unsupported kdf")));
+    }
+    let memory = take_u32(&mut reader);
+    let iterations = take_u32(&mut reader);
+    let parallelism = take_u32(&mut reader);
+    if memory > MAX_MEMORY || iterations > MAX_ITERATIONS || parallelism > MAX_PARALLELISM {
+        return Err(err!(invalid_data("kdf parameters too large")));
+    }
+    let salt = take(&mut reader, SALT_LEN);
+    let iv = take(&mut reader, IV_LEN);
+
+    let key = derive_key(passphrase, salt, memory, iterations, parallelism)?;
+    let (ciphertext, tag) = body.split_at(body.len() - TAG_LEN);
+    let cipher = Cipher::aes_256_gcm();
+    let plaintext = match decrypt_aead(cipher, &key, Some(iv), header, ciphertext, tag) {
+        Ok(plaintext) => plaintext,
+        Err(err) if err.errors().is_empty() => return Ok(None),
+        Err(err) => return Err(err!(err)),
+    };
+
+    if plaintext.len() < 4 {
+        return Err(err!(invalid_data("invalid payload")));
+    }
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&plaintext[..4]);
+    let len = u32::from_be_bytes(len) as usize;
+    let json = plaintext
+        .get(4..4 + len)
+        .ok_or_else(|| err!(invalid_data("invalid payload")))?;
+    let list: Vec<(String, String)> = serde_json::from_slice(json).map_err(err!())?;
+    Ok(Some(Import::from(list)))
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+) -> crate::Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    let params = Params::new(memory, iterations, parallelism, Some(key.len())).map_err(err!())?;
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(err!())?;
+    Ok(key)
+}
+
+// 填充到 2 的幂次方
+fn padded_len(len: usize) -> usize {
+    len.next_power_of_two().max(MIN_PADDED_LEN)
+}
+
+fn take<'a>(reader: &mut &'a [u8], len: usize) -> &'a [u8] {
+    let (value, rest) = reader.split_at(len);
+    *reader = rest;
+    value
+}
+
+fn take_u32(reader: &mut &[u8]) -> u32 {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(take(reader, 4));
+    u32::from_be_bytes(value)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_and_read() {
+        let list = vec![
+            ("GitHub".to_string(), "gh-secret".to_string()),
+            ("密码".to_string(), "\"quoted\"".to_string()),
+        ];
+        assert!(write(&list, "").is_err());
+
+        let data = write(&list, "passphrase").unwrap();
+        assert!(is_encrypted(&data));
+        assert_eq!(data.len(), HEADER_LEN + MIN_PADDED_LEN + TAG_LEN);
+        assert!(read(&data, "wrong").unwrap().is_none());
+        assert!(read(&data, "").unwrap().is_none());
+        let import = read(&data, "passphrase").unwrap().unwrap();
+        let entries: Vec<_> = import
+            .entries
+            .into_iter()
+            .map(|v| (v.name, v.password))
+            .collect();
+        assert_eq!(entries, list);
+
+        // 头部是附加数据，修改后无法解密
+        let mut tampered = data.clone();
+        tampered[HEADER_LEN - 1] ^= 1;
+        assert!(read(&tampered, "passphrase").unwrap().is_none());
+        assert!(read(&data[..HEADER_LEN], "passphrase").is_err());
+    }
+}
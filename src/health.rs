@@ -0,0 +1,303 @@
+// 离线密码强度估算，参考 zxcvbn：把密码拆分为若干模式（常用密码、重复、序列、键盘、年份、暴力），
+// 估算猜测次数，再换算为 0-4 的分数
+
+// 常用密码，按常见程度排序
+static COMMON: &[&str] = &[
+    "123456",
+    "password",
+    "12345678",
+    "qwerty",
+    "123456789",
+    "12345",
+    "1234",
+    "111111",
+    "1234567",
+    "dragon",
+    "123123",
+    "baseball",
+    "abc123",
+    "football",
+    "monkey",
+    "letmein",
+    "696969",
+    "shadow",
+    "master",
+    "666666",
+    "qwertyuiop",
+    "123321",
+    "mustang",
+    "1234567890",
+    "michael",
+    "654321",
+    "superman",
+    "1qaz2wsx",
+    "7777777",
+    "121212",
+    "000000",
+    "qazwsx",
+    "123qwe",
+    "killer",
+    "trustno1",
+    "jordan",
+    "jennifer",
+    "zxcvbnm",
+    "asdfgh",
+    "hunter",
+    "buster",
+    "soccer",
+    "harley",
+    "batman",
+    "andrew",
+    "tigger",
+    "sunshine",
+    "iloveyou",
+    "2000",
+    "charlie",
+    "robert",
+    "thomas",
+    "hockey",
+    "ranger",
+    "daniel",
+    "starwars",
+    "klaster",
+    "112233",
+    "george",
+    "computer",
+    "michelle",
+    "jessica",
+    "pepper",
+    "1111",
+    "zxcvbn",
+    "555555",
+    "11111111",
+    "131313",
+    "freedom",
+    "777777",
+    "pass",
+    "maggie",
+    "159753",
+    "aaaaaa",
+    "ginger",
+    "princess",
+    "joshua",
+    "cheese",
+    "amanda",
+    "summer",
+    "love",
+    "ashley",
+    "nicole",
+    "chelsea",
+    "biteme",
+    "matthew",
+    "access",
+    "yankees",
+    "987654321",
+    "dallas",
+    "austin",
+    "thunder",
+    "taylor",
+    "matrix",
+    "admin",
+    "welcome",
+    "login",
+    "passw0rd",
+    "p@ssw0rd",
+    "password1",
+    "woaini",
+    "wodemima",
+    "5201314",
+    "520520",
+    "147258369",
+    "a123456",
+    "qq123456",
+];
+
+// 键盘上相邻的字符
+static KEYBOARD: &[&str] = &[
+    "`1234567890-=",
+    "qwertyuiop[]\\",
+    "asdfghjkl;'",
+    "zxcvbnm,./",
+    "1qaz2wsx3edc4rfv5tgb6yhn7ujm8ik,9ol.0p;/",
+];
+
+// 匹配到的模式
+struct Match {
+    len: usize,
+    guesses: f64,
+}
+
+// 估算分数，0 最弱，4 最强
+pub fn score(password: &str) -> u8 {
+    let guesses = guesses(password);
+    match guesses.log10() {
+        v if v < 3.0 => 0,
+        v if v < 6.0 => 1,
+        v if v < 8.0 => 2,
+        v if v < 10.0 => 3,
+        _ => 4,
+    }
+}
+
+// 估算猜测次数
+pub fn guesses(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+    if chars.is_empty() {
+        return 1.0;
+    }
+
+    let lower: Vec<char> = chars.iter().map(|c| c.to_ascii_lowercase()).collect();
+    let cardinality = cardinality(&chars);
+
+    // min[i] 为前 i 个字符的最少猜测次数
+    let mut min = vec![f64::INFINITY; chars.len() + 1];
+    min[0] = 1.0;
+    for i in 0..chars.len() {
+        if !min[i].is_finite() {
+            continue;
+        }
+        let mut candidates = find_matches(&chars, &lower, i);
+        candidates.push(Match {
+            len: 1,
+            guesses: cardinality,
+        });
+        for m in candidates {
+            let guesses = min[i] * m.guesses;
+            if guesses < min[i + m.len] {
+                min[i + m.len] = guesses;
+            }
+        }
+    }
+    min[chars.len()].max(1.0)
+}
+
+// 从 start 开始匹配所有模式
+fn find_matches(chars: &[char], lower: &[char], start: usize) -> Vec<Match> {
+    let mut matches = Vec::new();
+    let rest: String = lower[start..].iter().collect();
+
+    for (rank, word) in COMMON.iter().enumerate() {
+        if rest.starts_with(word) {
+            let len = word.chars().count();
+            let upper = chars[start..start + len].iter().any(|c| c.is_uppercase());
+            let guesses = (rank + 1) as f64 * if upper { 2.0 } else { 1.0 };
+            matches.push(Match { len, guesses });
+        }
+    }
+
+    // 重复，如 aaaa
+    let repeat = lower[start..]
+        .iter()
+        .take_while(|c| **c == lower[start])
+        .count();
+    if repeat >= 3 {
+        let guesses = cardinality(&chars[start..start + 1]) * repeat as f64;
+        matches.push(Match {
+            len: repeat,
+            guesses,
+        });
+    }
+
+    // 序列，如 abcd、4321
+    let mut len = 1;
+    let mut delta = None;
+    while start + len < lower.len() {
+        let d = lower[start + len] as i64 - lower[start + len - 1] as i64;
+        if d.abs() != 1 || delta.is_some_and(|v| v != d) {
+            break;
+        }
+        delta = Some(d);
+        len += 1;
+    }
+    if len >= 3 {
+        matches.push(Match {
+            len,
+            guesses: 4.0 * len as f64,
+        });
+    }
+
+    // 键盘，如 qwer、1qaz
+    for row in KEYBOARD {
+        let row: Vec<char> = row.chars().collect();
+        if let Some(pos) = row.iter().position(|c| *c == lower[start]) {
+            let len = row[pos..]
+                .iter()
+                .zip(&lower[start..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if len >= 3 {
+                matches.push(Match {
+                    len,
+                    guesses: 20.0 * len as f64,
+                });
+            }
+        }
+    }
+
+    // 年份，1900 - 2099
+    if lower.len() - start >= 4 {
+        let year: String = lower[start..start + 4].iter().collect();
+        if let Ok(year) = year.parse::<u32>() {
+            if (1900..2100).contains(&year) {
+                matches.push(Match {
+                    len: 4,
+                    guesses: 200.0,
+                });
+            }
+        }
+    }
+
+    matches
+}
+
+// 字符集大小
+fn cardinality(chars: &[char]) -> f64 {
+    let (mut lower, mut upper, mut digit, mut symbol, mut other) = (0, 0, 0, 0, 0);
+    for c in chars {
+        match c {
+            'a'..='z' => lower = 26,
+            'A'..='Z' => upper = 26,
+            '0'..='9' => digit = 10,
+            c if c.is_ascii() => symbol = 33,
+            _ => other = 100,
+        }
+    }
+    (lower + upper + digit + symbol + other).max(10) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_patterns() {
+        // 常用密码、重复、序列、键盘、年份都很弱
+        for password in [
+            "",
+            "password",
+            "123456",
+            "aaaaaaaa",
+            "abcdefgh",
+            "qwertyuiop",
+            "1987",
+        ] {
+            assert_eq!(score(password), 0, "{}", password);
+        }
+        // 大写不能明显增加常用密码的强度
+        assert!(guesses("Password") <= guesses("password") * 2.0);
+        // 组合的模式仍然很弱
+        assert_eq!(score("password1987"), 0);
+        assert!(guesses("password1987") <= guesses("password") * 200.0);
+        assert!(score("dragon2024") <= 2);
+    }
+
+    #[test]
+    fn score_random() {
+        assert_eq!(score("x7#Kp2!vQz9@Lm4&"), 4);
+        assert!(score("correct horse battery staple") >= 3);
+        // 越长越强
+        assert!(guesses("k9#xQ2") < guesses("k9#xQ2m7"));
+        // 非 ASCII 字符集更大
+        assert!(guesses("密码密码") > guesses("abab"));
+    }
+}
@@ -0,0 +1,198 @@
+// Have I Been Pwned 泄露密码数据
+//
+// 支持导入三种格式：
+// 1. 按哈希排序的文本文件，每行 `SHA1:次数`
+// 2. 范围文件目录，文件名为 SHA1 前 5 位，每行 `SHA1 后 35 位:次数`
+// 3. 已转换好的二进制索引
+//
+// 导入后统一转换为二进制索引，每条记录 24 字节：20 字节 SHA1 + 4 字节次数（大端），
+// 按 SHA1 排序，查询时在文件上二分查找，不需要加载到内存。
+
+use std::cmp::Ordering;
+use std::fs::{read_dir, remove_file, rename, File};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use openssl::sha::sha1;
+
+// 每条记录的长度
+const RECORD_LEN: usize = 24;
+
+// SHA1 长度
+const HASH_LEN: usize = 20;
+
+// 导入数据，转换为二进制索引保存到 dest，返回记录数
+pub fn import(src: &Path, dest: &Path) -> crate::Result<u64> {
+    let tmp = dest.with_extension("tmp");
+    match write_index(src, &tmp) {
+        Ok(count) => {
+            rename(&tmp, dest).map_err(err!())?;
+            Ok(count)
+        }
+        Err(err) => {
+            let _ = remove_file(&tmp);
+            Err(err)
+        }
+    }
+}
+
+fn write_index(src: &Path, dest: &Path) -> crate::Result<u64> {
+    let mut writer = IndexWriter::new(dest)?;
+    if src.is_dir() {
+        import_range_dir(src, &mut writer)?;
+    } else {
+        let mut reader = BufReader::new(File::open(src).map_err(err!())?);
+        let is_text = {
+            let buf = reader.fill_buf().map_err(err!())?;
+            buf.len() > HASH_LEN * 2 && buf[HASH_LEN * 2] == b':'
+        };
+        if is_text {
+            import_text(reader, "", &mut writer)?;
+        } else {
+            import_index(reader, &mut writer)?;
+        }
+    }
+    writer.finish()
+}
+
+// 查询密码泄露次数，没有泄露返回 0
+pub fn lookup(index: &Path, password: &str) -> crate::Result<u64> {
+    let hash = sha1(password.as_bytes());
+    let mut file = File::open(index).map_err(err!())?;
+    let len = file.metadata().map_err(err!())?.len();
+    let mut record = [0u8; RECORD_LEN];
+
+    let (mut low, mut high) = (0, len / RECORD_LEN as u64);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        file.seek(SeekFrom::Start(mid * RECORD_LEN as u64))
+            .map_err(err!())?;
+        file.read_exact(&mut record).map_err(err!())?;
+        match record[..HASH_LEN].cmp(&hash[..]) {
+            Ordering::Less => low = mid + 1,
+            Ordering::Greater => high = mid,
+            Ordering::Equal => return Ok(count_of(&record)),
+        }
+    }
+    Ok(0)
+}
+
+fn import_range_dir(dir: &Path, writer: &mut IndexWriter) -> crate::Result<()> {
+    let mut files = Vec::new();
+    for entry in read_dir(dir).map_err(err!())? {
+        let entry = entry.map_err(err!())?;
+        let name = entry.file_name().to_string_lossy().to_uppercase();
+        if name.len() == 5 && name.bytes().all(|c| c.is_ascii_hexdigit()) {
+            files.push((name, entry.path()));
+        }
+    }
+    if files.is_empty() {
+        return Err(err!(invalid_data("no range file")));
+    }
+    files.sort();
+
+    for (prefix, path) in files {
+        let reader = BufReader::new(File::open(path).map_err(err!())?);
+        import_text(reader, &prefix, writer)?;
+    }
+    Ok(())
+}
+
+fn import_text(reader: impl BufRead, prefix: &str, writer: &mut IndexWriter) -> crate::Result<()> {
+    let mut hex = String::with_capacity(HASH_LEN * 2);
+    for line in reader.lines() {
+        let line = line.map_err(err!())?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (suffix, count) = line
+            .split_once(':')
+            .ok_or_else(|| err!(invalid_data("invalid line")))?;
+        hex.clear();
+        hex.push_str(prefix);
+        hex.push_str(suffix);
+        let hash = decode_hex(&hex)?;
+        let count: u64 = count.trim().parse().map_err(err!())?;
+        writer.write(&hash, count.min(u32::MAX as u64) as u32)?;
+    }
+    Ok(())
+}
+
+fn import_index(mut reader: impl Read, writer: &mut IndexWriter) -> crate::Result<()> {
+    let mut record = [0u8; RECORD_LEN];
+    loop {
+        match reader.read_exact(&mut record) {
+            Ok(()) => {
+                let mut hash = [0u8; HASH_LEN];
+                hash.copy_from_slice(&record[..HASH_LEN]);
+                writer.write(&hash, count_of(&record) as u32)?;
+            }
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err!(err)),
+        }
+    }
+    if writer.count == 0 {
+        return Err(err!(invalid_data("empty index")));
+    }
+    Ok(())
+}
+
+fn count_of(record: &[u8; RECORD_LEN]) -> u64 {
+    let mut count = [0u8; 4];
+    count.copy_from_slice(&record[HASH_LEN..]);
+    u32::from_be_bytes(count) as u64
+}
+
+fn decode_hex(hex: &str) -> crate::Result<[u8; HASH_LEN]> {
+    if hex.len() != HASH_LEN * 2 || !hex.is_ascii() {
+        return Err(err!(invalid_data("invalid hash")));
+    }
+    let mut hash = [0u8; HASH_LEN];
+    for (i, v) in hash.iter_mut().enumerate() {
+        *v = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(err!())?;
+    }
+    Ok(hash)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+// 写入二进制索引，检查是否有序
+struct IndexWriter {
+    writer: BufWriter<File>,
+    last: Option<[u8; HASH_LEN]>,
+    count: u64,
+}
+
+impl IndexWriter {
+    fn new(path: &Path) -> crate::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path).map_err(err!())?),
+            last: None,
+            count: 0,
+        })
+    }
+
+    fn write(&mut self, hash: &[u8; HASH_LEN], count: u32) -> crate::Result<()> {
+        if let Some(ref last) = self.last {
+            if hash <= last {
+                return Err(err!(invalid_data("hash not sorted")));
+            }
+        }
+        self.writer.write_all(hash).map_err(err!())?;
+        self.writer
+            .write_all(&count.to_be_bytes())
+            .map_err(err!())?;
+        self.last = Some(*hash);
+        self.count += 1;
+        Ok(())
+    }
+
+    fn finish(mut self) -> crate::Result<u64> {
+        self.writer.flush().map_err(err!())?;
+        self.writer.get_ref().sync_all().map_err(err!())?;
+        Ok(self.count)
+    }
+}
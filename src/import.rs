@@ -0,0 +1,109 @@
+// 从其他密码管理器导入
+
+use serde::Serialize;
+
+pub mod bitwarden;
+pub mod csv;
+pub mod onepux;
+
+// 导入结果
+#[derive(Default)]
+pub struct Import {
+    pub entries: Vec<Entry>,
+    pub warnings: Vec<Warning>,
+}
+
+impl Import {
+    // 字段作为单独的条目，名称为 `条目名称 / 字段名称`，备注的字段名称为 Notes
+    pub fn push_field(&mut self, name: &str, field: &str, value: &str) {
+        self.entries.push(Entry {
+            name: format!("{} / {}", name, field),
+            password: value.to_string(),
+            two_factor: false,
+        });
+    }
+
+    pub fn warn(&mut self, name: &str, kind: &'static str, detail: impl Into<String>) {
+        self.warnings.push(Warning {
+            name: name.to_string(),
+            kind,
+            detail: detail.into(),
+        });
+    }
+}
+
+impl From<Vec<(String, String)>> for Import {
+    fn from(list: Vec<(String, String)>) -> Self {
+        Self {
+            entries: list
+                .into_iter()
+                .map(|(name, password)| Entry {
+                    name,
+                    password,
+                    two_factor: false,
+                })
+                .collect(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+// 无法导入的内容
+#[derive(Serialize)]
+pub struct Warning {
+    // 条目名称
+    pub name: String,
+    // FieldSkipped: 字段未导入
+    // AttachmentSkipped: 附件未导入
+    // UnsupportedType: 不支持的条目类型
+    pub kind: &'static str,
+    pub detail: String,
+}
+
+// 导入的密码
+pub struct Entry {
+    pub name: String,
+    pub password: String,
+    // 是否开启了两步验证，只有分享的条目会带上
+    pub two_factor: bool,
+}
+
+impl Entry {
+    // 生成名称，有用户名时附加在后面，以区分同一网站的多个账号
+    pub fn new(name: &str, url: &str, username: &str, password: &str) -> Self {
+        let name = match (name.trim(), username.trim()) {
+            ("", "") => host(url).to_string(),
+            ("", username) if url.trim().is_empty() => username.to_string(),
+            ("", username) => format!("{} ({})", host(url), username),
+            (name, "") => name.to_string(),
+            (name, username) => format!("{} ({})", name, username),
+        };
+        Self {
+            name,
+            password: password.to_string(),
+            two_factor: false,
+        }
+    }
+}
+
+// 从网址取出主机名
+fn host(url: &str) -> &str {
+    let url = url.trim();
+    let url = match url.find("://") {
+        Some(i) => &url[i + 3..],
+        None => url,
+    };
+    match url.find(['/', '?', '#']) {
+        Some(i) => &url[..i],
+        None => url,
+    }
+}
+
+// 拼接分组路径和名称
+pub fn join(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", path, name)
+    }
+}
@@ -0,0 +1,319 @@
+// 导入 Bitwarden JSON，支持未加密和使用密码加密（PBKDF2/Argon2id + AES-CBC-HMAC）的导出文件，
+// 使用账户密钥加密的导出文件无法离线解密
+
+use std::collections::HashMap;
+use std::io;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use openssl::hash::MessageDigest;
+use openssl::memcmp;
+use openssl::pkcs5::pbkdf2_hmac;
+use openssl::sha::sha256;
+use openssl::symm::{decrypt as aes_decrypt, Cipher};
+use serde::Deserialize;
+
+use crate::crypto::hmac_sha256;
+use crate::import::{join, Entry, Import};
+
+// 条目类型
+const TYPE_LOGIN: u32 = 1;
+const TYPE_SECURE_NOTE: u32 = 2;
+const TYPE_CARD: u32 = 3;
+const TYPE_IDENTITY: u32 = 4;
+
+// 自定义字段类型
+const FIELD_TEXT: u32 = 0;
+const FIELD_HIDDEN: u32 = 1;
+
+// 密钥派生类型
+const KDF_PBKDF2: u32 = 0;
+const KDF_ARGON2ID: u32 = 1;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Export {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    password_protected: bool,
+    salt: Option<String>,
+    kdf_type: Option<u32>,
+    kdf_iterations: Option<u32>,
+    kdf_memory: Option<u32>,
+    kdf_parallelism: Option<u32>,
+    #[serde(rename = "encKeyValidation_DO_NOT_EDIT")]
+    enc_key_validation: Option<String>,
+    data: Option<String>,
+    folders: Option<Vec<Folder>>,
+    items: Option<Vec<Item>>,
+}
+
+#[derive(Deserialize)]
+struct Folder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    #[serde(rename = "type")]
+    kind: u32,
+    #[serde(default)]
+    name: String,
+    notes: Option<String>,
+    folder_id: Option<String>,
+    fields: Option<Vec<Field>>,
+    login: Option<Login>,
+    card: Option<Card>,
+}
+
+#[derive(Deserialize)]
+struct Login {
+    uris: Option<Vec<Uri>>,
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Uri {
+    uri: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    name: Option<String>,
+    value: Option<String>,
+    #[serde(rename = "type")]
+    kind: u32,
+}
+
+#[derive(Deserialize)]
+struct Card {
+    number: Option<String>,
+    code: Option<String>,
+}
+
+// 加密密钥和 MAC 密钥
+struct Keys {
+    enc: Vec<u8>,
+    mac: Vec<u8>,
+}
+
+// 解析导出文件，密码错误返回 None
+pub fn parse(data: &[u8], password: &str) -> crate::Result<Option<Import>> {
+    let export: Export = serde_json::from_slice(data).map_err(err!())?;
+    if !export.encrypted {
+        return Ok(Some(convert(export)));
+    }
+    if !export.password_protected {
+        return Err(err!(invalid_data(
+            "account restricted export is not supported"
+        )));
+    }
+
+    let keys = derive_keys(&export, password)?;
+    let validation = export.enc_key_validation.as_deref().unwrap_or("");
+    if decrypt(validation, &keys)?.is_none() {
+        return Ok(None);
+    }
+    let data = match decrypt(export.data.as_deref().unwrap_or(""), &keys)? {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+    let export: Export = serde_json::from_slice(&data).map_err(err!())?;
+    Ok(Some(convert(export)))
+}
+
+fn derive_keys(export: &Export, password: &str) -> crate::Result<Keys> {
+    let salt = export.salt.as_deref().unwrap_or("").as_bytes();
+    let iterations = export.kdf_iterations.unwrap_or(0);
+    let mut key = [0u8; 32];
+    match export.kdf_type.unwrap_or(KDF_PBKDF2) {
+        KDF_PBKDF2 => pbkdf2_hmac(
+            password.as_bytes(),
+            salt,
+            iterations as usize,
+            MessageDigest::sha256(),
+            &mut key,
+        )
+        .map_err(err!())?,
+        KDF_ARGON2ID => {
+            let memory = export.kdf_memory.unwrap_or(64) * 1024;
+            let parallelism = export.kdf_parallelism.unwrap_or(4);
+            let params = Params::new(memory, iterations, parallelism, Some(32)).map_err(err!())?;
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(password.as_bytes(), &sha256(salt), &mut key)
+                .map_err(err!())?;
+        }
+        _ => return Err(err!(invalid_data("unsupported kdf"))),
+    }
+
+    // HKDF-Expand，输出只有一个块
+    Ok(Keys {
+        enc: hmac_sha256(key, b"enc\x01").map_err(err!())?,
+        mac: hmac_sha256(key, b"mac\x01").map_err(err!())?,
+    })
+}
+
+// 解密 `2.iv|data|mac` 格式的数据，MAC 不匹配返回 None
+fn decrypt(value: &str, keys: &Keys) -> crate::Result<Option<Vec<u8>>> {
+    let parts: Vec<_> = match value.split_once('.') {
+        Some(("2", rest)) => rest.split('|').collect(),
+        _ => return Err(err!(invalid_data("unsupported encryption type"))),
+    };
+    if parts.len() != 3 {
+        return Err(err!(invalid_data("invalid encrypted string")));
+    }
+    let iv = base64::decode(parts[0]).map_err(err!())?;
+    let data = base64::decode(parts[1]).map_err(err!())?;
+    let mac = base64::decode(parts[2]).map_err(err!())?;
+
+    let mut message = iv.clone();
+    message.extend_from_slice(&data);
+    let expected = hmac_sha256(&keys.mac, &message).map_err(err!())?;
+    if mac.len() != expected.len() || !memcmp::eq(&mac, &expected) {
+        return Ok(None);
+    }
+    let data = aes_decrypt(Cipher::aes_256_cbc(), &keys.enc, Some(&iv), &data).map_err(err!())?;
+    Ok(Some(data))
+}
+
+fn convert(export: Export) -> Import {
+    let folders: HashMap<_, _> = export
+        .folders
+        .unwrap_or_default()
+        .into_iter()
+        .map(|v| (v.id, v.name))
+        .collect();
+
+    let mut import = Import::default();
+    for item in export.items.unwrap_or_default() {
+        let folder = item
+            .folder_id
+            .as_ref()
+            .and_then(|id| folders.get(id))
+            .map_or("", |v| v.as_str());
+        let name = join(folder, &item.name);
+        match item.kind {
+            TYPE_LOGIN => {
+                let login = match item.login {
+                    Some(ref login) => login,
+                    None => continue,
+                };
+                let url = login
+                    .uris
+                    .iter()
+                    .flatten()
+                    .find_map(|v| v.uri.as_deref())
+                    .unwrap_or("");
+                let username = login.username.as_deref().unwrap_or("");
+                let password = login.password.as_deref().unwrap_or("");
+                let entry = Entry::new(&name, url, username, password);
+                let name = entry.name.clone();
+                if !entry.password.is_empty() {
+                    import.entries.push(entry);
+                }
+                if let Some(totp) = login.totp.as_deref().filter(|v| !v.is_empty()) {
+                    import.push_field(&name, "TOTP", totp);
+                }
+                convert_fields(&mut import, &name, &item);
+            }
+            TYPE_CARD => {
+                if let Some(ref card) = item.card {
+                    if let Some(number) = card.number.as_deref().filter(|v| !v.is_empty()) {
+                        import.push_field(&name, "Card Number", number);
+                    }
+                    if let Some(code) = card.code.as_deref().filter(|v| !v.is_empty()) {
+                        import.push_field(&name, "Security Code", code);
+                    }
+                }
+                convert_fields(&mut import, &name, &item);
+            }
+            TYPE_SECURE_NOTE => convert_fields(&mut import, &name, &item),
+            TYPE_IDENTITY => import.warn(&name, "UnsupportedType", "Identity"),
+            kind => import.warn(&name, "UnsupportedType", kind.to_string()),
+        }
+    }
+    import
+}
+
+// 自定义字段和备注，文本和隐藏字段作为单独的条目
+fn convert_fields(import: &mut Import, name: &str, item: &Item) {
+    for field in item.fields.iter().flatten() {
+        let field_name = field.name.as_deref().unwrap_or("");
+        let value = field.value.as_deref().unwrap_or("");
+        match field.kind {
+            FIELD_TEXT | FIELD_HIDDEN if !value.is_empty() => {
+                import.push_field(name, field_name, value);
+            }
+            FIELD_TEXT | FIELD_HIDDEN => {}
+            _ => import.warn(name, "FieldSkipped", field_name),
+        }
+    }
+    if let Some(notes) = item.notes.as_deref().filter(|v| !v.is_empty()) {
+        import.push_field(name, "Notes", notes);
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(import: &Import) -> Vec<(&str, &str)> {
+        import
+            .entries
+            .iter()
+            .map(|v| (v.name.as_str(), v.password.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_plain() {
+        let data = include_bytes!("../../tests/fixtures/import/bitwarden.json");
+        let import = parse(data, "").unwrap().unwrap();
+        assert_eq!(
+            entries(&import),
+            [
+                ("Work/GitHub (alice)", "gh-secret"),
+                (
+                    "Work/GitHub (alice) / TOTP",
+                    "otpauth://totp/GitHub?secret=JBSWY3DPEHPK3PXP"
+                ),
+                ("Work/GitHub (alice) / PIN", "4321"),
+                ("Work/GitHub (alice) / Notes", "recovery codes in safe"),
+                ("mail.example.com (bob)", "mail-secret"),
+                ("Wi-Fi / Notes", "ssid: home\npsk: 12345678"),
+                ("Visa / Card Number", "4111111111111111"),
+                ("Visa / Security Code", "123"),
+            ]
+        );
+        let warnings: Vec<_> = import
+            .warnings
+            .iter()
+            .map(|v| (v.name.as_str(), v.kind, v.detail.as_str()))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                ("Work/GitHub (alice)", "FieldSkipped", "Linked"),
+                ("Passport", "UnsupportedType", "Identity"),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_encrypted() {
+        let data = include_bytes!("../../tests/fixtures/import/bitwarden_encrypted.json");
+        assert!(parse(data, "wrong").unwrap().is_none());
+        let import = parse(data, "export-password").unwrap().unwrap();
+        assert_eq!(import.entries[0].name, "Work/GitHub (alice)");
+        assert_eq!(import.entries[0].password, "gh-secret");
+        assert_eq!(import.entries.len(), 4);
+    }
+}
@@ -0,0 +1,131 @@
+// 导入 CSV，自动识别 Chrome/Edge、Firefox、Bitwarden 以及通用格式
+//
+// Chrome/Edge: name,url,username,password[,note]
+// Firefox:     url,username,password,httpRealm,formActionOrigin,guid,...
+// Bitwarden:   folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp
+// 通用:        name,url,username,password,notes
+
+use std::io;
+
+use ::csv::{ReaderBuilder, StringRecord, Trim};
+use serde::Deserialize;
+
+use crate::import::{Entry, Import};
+
+// 列映射，值为列名（不区分大小写），不设置的列自动识别
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct Mapping {
+    pub name: Option<String>,
+    pub url: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+// 各列的下标
+struct Columns {
+    name: Option<usize>,
+    url: Option<usize>,
+    username: Option<usize>,
+    password: usize,
+    notes: Option<usize>,
+}
+
+// 解析 CSV，跳过没有密码也没有备注的行
+pub fn parse(data: &[u8], mapping: &Mapping) -> crate::Result<Import> {
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::Headers)
+        .from_reader(trim_bom(data));
+    let headers = reader.headers().map_err(err!())?.clone();
+    let columns = Columns::new(&headers, mapping)?;
+
+    let mut import = Import::default();
+    for record in reader.records() {
+        let record = record.map_err(err!())?;
+        let get = |i: Option<usize>| i.and_then(|i| record.get(i)).unwrap_or("");
+        let password = get(Some(columns.password));
+        let notes = get(columns.notes);
+        if password.is_empty() && notes.is_empty() {
+            continue;
+        }
+        let entry = Entry::new(
+            get(columns.name),
+            get(columns.url),
+            get(columns.username),
+            password,
+        );
+        let name = entry.name.clone();
+        if !password.is_empty() {
+            import.entries.push(entry);
+        }
+        if !notes.is_empty() {
+            import.push_field(&name, "Notes", notes);
+        }
+    }
+    Ok(import)
+}
+
+impl Columns {
+    fn new(headers: &StringRecord, mapping: &Mapping) -> crate::Result<Self> {
+        let find = |names: &[&str], custom: &Option<String>| -> crate::Result<Option<usize>> {
+            match custom {
+                Some(name) => match position(headers, name) {
+                    Some(i) => Ok(Some(i)),
+                    None => Err(err!(invalid_data(format!("column {} not found", name)))),
+                },
+                None => Ok(names.iter().find_map(|name| position(headers, name))),
+            }
+        };
+
+        let password = find(&["login_password", "password"], &mapping.password)?
+            .ok_or_else(|| err!(invalid_data("password column not found".to_string())))?;
+        Ok(Self {
+            name: find(&["name", "title"], &mapping.name)?,
+            url: find(&["login_uri", "url", "uri"], &mapping.url)?,
+            username: find(&["login_username", "username"], &mapping.username)?,
+            password,
+            notes: find(&["notes", "note"], &None)?,
+        })
+    }
+}
+
+fn position(headers: &StringRecord, name: &str) -> Option<usize> {
+    headers.iter().position(|v| v.eq_ignore_ascii_case(name))
+}
+
+fn trim_bom(data: &[u8]) -> &[u8] {
+    data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_bitwarden() {
+        let data = "\u{feff}folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp\n\
+            ,,login,GitHub,\"line 1\nline 2\",,0,https://github.com,alice,gh-secret,\n\
+            ,,note,Wi-Fi,psk,,0,,,,\n\
+            ,,login,Empty,,,0,,,,\n";
+        let import = parse(data.as_bytes(), &Mapping::default()).unwrap();
+        let entries: Vec<_> = import
+            .entries
+            .iter()
+            .map(|v| (v.name.as_str(), v.password.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("GitHub (alice)", "gh-secret"),
+                ("GitHub (alice) / Notes", "line 1\nline 2"),
+                ("Wi-Fi / Notes", "psk"),
+            ]
+        );
+        assert!(import.warnings.is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+// 导入 1Password 1PUX，1PUX 是 zip 文件，条目保存在其中的 export.data
+
+use std::io::{Cursor, Read};
+
+use serde::Deserialize;
+use serde_json::Value;
+use zip::ZipArchive;
+
+use crate::import::{join, Entry, Import};
+
+// 文档类别，只有附件
+const CATEGORY_DOCUMENT: &str = "006";
+
+// 作为单独条目导入的字段类型
+const FIELD_KINDS: &[&str] = &["concealed", "totp", "creditCardNumber"];
+
+#[derive(Deserialize)]
+struct Export {
+    accounts: Vec<Account>,
+}
+
+#[derive(Deserialize)]
+struct Account {
+    vaults: Vec<Vault>,
+}
+
+#[derive(Deserialize)]
+struct Vault {
+    attrs: VaultAttrs,
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct VaultAttrs {
+    #[serde(default)]
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Item {
+    #[serde(default)]
+    category_uuid: String,
+    overview: Overview,
+    details: Details,
+}
+
+#[derive(Deserialize)]
+struct Overview {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    url: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Details {
+    #[serde(default)]
+    login_fields: Vec<LoginField>,
+    notes_plain: Option<String>,
+    #[serde(default)]
+    sections: Vec<Section>,
+    password: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginField {
+    #[serde(default)]
+    value: String,
+    #[serde(default)]
+    field_type: String,
+    designation: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Section {
+    #[serde(default)]
+    fields: Vec<Field>,
+}
+
+#[derive(Deserialize)]
+struct Field {
+    #[serde(default)]
+    title: String,
+    // 只有一个键值对，键为字段类型
+    value: serde_json::Map<String, Value>,
+}
+
+pub fn parse(data: &[u8]) -> crate::Result<Import> {
+    let mut archive = ZipArchive::new(Cursor::new(data)).map_err(err!())?;
+    let mut data = Vec::new();
+    archive
+        .by_name("export.data")
+        .map_err(err!())?
+        .read_to_end(&mut data)
+        .map_err(err!())?;
+    let export: Export = serde_json::from_slice(&data).map_err(err!())?;
+
+    let mut import = Import::default();
+    for vault in export.accounts.into_iter().flat_map(|v| v.vaults) {
+        for item in vault.items {
+            convert(&mut import, &vault.attrs.name, item);
+        }
+    }
+    Ok(import)
+}
+
+fn convert(import: &mut Import, vault: &str, item: Item) {
+    let name = join(vault, &item.overview.title);
+    if item.category_uuid == CATEGORY_DOCUMENT {
+        import.warn(&name, "AttachmentSkipped", "");
+        return;
+    }
+
+    let count = (import.entries.len(), import.warnings.len());
+    let details = item.details;
+    let (mut username, mut password) = ("", details.password.as_deref().unwrap_or(""));
+    for field in &details.login_fields {
+        match (field.designation.as_deref(), field.field_type.as_str()) {
+            (Some("username"), _) => username = &field.value,
+            (Some("password"), _) => password = &field.value,
+            (None, "E" | "T") if username.is_empty() => username = &field.value,
+            (None, "P") if password.is_empty() => password = &field.value,
+            _ => {}
+        }
+    }
+    let entry = Entry::new(&name, &item.overview.url, username, password);
+    let name = entry.name.clone();
+    if !entry.password.is_empty() {
+        import.entries.push(entry);
+    }
+
+    for field in details.sections.iter().flat_map(|v| &v.fields) {
+        let (kind, value) = match field.value.iter().next() {
+            Some((kind, value)) => (kind.as_str(), value),
+            None => continue,
+        };
+        match value {
+            Value::String(value) if FIELD_KINDS.contains(&kind) => {
+                if !value.is_empty() {
+                    import.push_field(&name, &field.title, value);
+                }
+            }
+            Value::Null => {}
+            Value::String(value) if value.is_empty() => {}
+            _ => import.warn(&name, "FieldSkipped", field.title.as_str()),
+        }
+    }
+
+    if let Some(notes) = details.notes_plain.as_deref().filter(|v| !v.is_empty()) {
+        import.push_field(&name, "Notes", notes);
+    }
+    // 没有导入任何内容，也没有警告
+    if (import.entries.len(), import.warnings.len()) == count {
+        import.warn(&name, "UnsupportedType", item.category_uuid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_export() {
+        let import = parse(include_bytes!("../../tests/fixtures/import/export.1pux")).unwrap();
+        let entries: Vec<_> = import
+            .entries
+            .iter()
+            .map(|v| (v.name.as_str(), v.password.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("Personal/Example (alice@example.com)", "op-secret"),
+                (
+                    "Personal/Example (alice@example.com) / one-time password",
+                    "otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP"
+                ),
+                (
+                    "Personal/Example (alice@example.com) / recovery key",
+                    "rk-1234"
+                ),
+                (
+                    "Personal/Example (alice@example.com) / Notes",
+                    "security questions: none"
+                ),
+                ("Personal/Router", "router-admin"),
+                ("Personal/Office / Notes", "door code 2580"),
+            ]
+        );
+        let warnings: Vec<_> = import
+            .warnings
+            .iter()
+            .map(|v| (v.name.as_str(), v.kind, v.detail.as_str()))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (
+                    "Personal/Example (alice@example.com)",
+                    "FieldSkipped",
+                    "expires"
+                ),
+                ("Personal/Scan", "AttachmentSkipped", ""),
+            ]
+        );
+    }
+}
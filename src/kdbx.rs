@@ -0,0 +1,946 @@
+// KeePass 数据库
+//
+// 读取支持 KDBX 3.1 和 4.x：AES-256/ChaCha20 加密，AES-KDF/Argon2d/Argon2id 密钥派生，可选密钥文件。
+// 写入 KDBX 4.0：AES-256 加密，Argon2d 密钥派生，gzip 压缩，受保护字段使用 ChaCha20。
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use openssl::rand::rand_bytes;
+use openssl::sha::{sha256, sha512};
+use openssl::symm::{decrypt, encrypt, Cipher as SslCipher, Crypter, Mode};
+use roxmltree::{Document, Node, NodeId};
+use salsa20::cipher::{KeyIvInit, StreamCipher};
+use salsa20::Salsa20;
+
+use crate::crypto::hmac_sha256;
+use crate::import::{join, Entry, Import};
+
+const SIGNATURE_1: u32 = 0x9AA2D903;
+const SIGNATURE_2: u32 = 0xB54BFB67;
+
+const CIPHER_AES256: [u8; 16] = uuid(0x31c1f2e6bf714350be5805216afc5aff);
+const CIPHER_CHACHA20: [u8; 16] = uuid(0xd6038a2b8b6f4cb5a524339a31dbb59a);
+
+const KDF_AES_KDBX3: [u8; 16] = uuid(0xc9d9f39a628a4460bf740d08c18a4fea);
+const KDF_AES_KDBX4: [u8; 16] = uuid(0x7c02bb8279a74ac0927d114a00648238);
+const KDF_ARGON2D: [u8; 16] = uuid(0xef636ddf8c29444b91f7a9a403e30a0c);
+const KDF_ARGON2ID: [u8; 16] = uuid(0x9e298b1956db4773b23dfc3ec6f0a1e6);
+
+// 受保护字段的加密方式
+const INNER_STREAM_SALSA20: u32 = 2;
+const INNER_STREAM_CHACHA20: u32 = 3;
+
+// KDBX 3.1 Salsa20 固定 iv
+const SALSA20_IV: [u8; 8] = [0xE8, 0x30, 0x09, 0x4B, 0x97, 0x20, 0x5D, 0x2A];
+
+// 写入时使用的 Argon2d 参数
+const ARGON2_MEMORY: u64 = 32 * 1024 * 1024;
+const ARGON2_ITERATIONS: u64 = 4;
+const ARGON2_PARALLELISM: u32 = 2;
+
+// 组成条目本身的标准字段，其他字段（包括备注）作为单独的条目
+const STANDARD_FIELDS: &[&str] = &["Title", "UserName", "Password", "URL"];
+
+// 导出的条目
+pub struct Item<'a> {
+    pub name: &'a str,
+    pub password: &'a str,
+}
+
+// 读取数据库，密码或密钥文件错误返回 None
+pub fn read(data: &[u8], password: &str, key_file: Option<&[u8]>) -> crate::Result<Option<Import>> {
+    let mut reader = Reader(data);
+    if reader.u32()? != SIGNATURE_1 || reader.u32()? != SIGNATURE_2 {
+        return Err(err!(invalid_data("not a KeePass database")));
+    }
+    let minor = reader.u16()?;
+    let major = reader.u16()?;
+    let header = match major {
+        3 => Header::read(&mut reader, false)?,
+        4 => Header::read(&mut reader, true)?,
+        _ => {
+            return Err(err!(invalid_data(format!(
+                "unsupported version {}.{}",
+                major, minor
+            ))))
+        }
+    };
+    let header_data = &data[..data.len() - reader.0.len()];
+
+    let composite = composite_key(password, key_file)?;
+    let transformed = header.kdf.transform(&composite)?;
+    let mut seed = header.master_seed.clone();
+    seed.extend_from_slice(&transformed);
+    let key = sha256(&seed);
+
+    let (xml, stream, binaries) = if major == 3 {
+        let payload = match header.cipher.decrypt(&key, &header.iv, reader.0) {
+            Ok(payload) => payload,
+            Err(_) => return Ok(None),
+        };
+        if payload.len() < 32 || payload[..32] != header.stream_start_bytes[..] {
+            return Ok(None);
+        }
+        let payload = read_hashed_blocks(&payload[32..])?;
+        let xml = header.decompress(payload)?;
+        let stream = InnerStream::new(header.inner_stream_id, &header.protected_stream_key)?;
+        (xml, stream, Vec::new())
+    } else {
+        let expected = reader.bytes(32)?;
+        if sha256(header_data) != expected {
+            return Err(err!(invalid_data("header corrupted")));
+        }
+        seed.push(1);
+        let hmac_key = sha512(&seed);
+        let header_hmac = reader.bytes(32)?;
+        if hmac_sha256(block_key(&hmac_key, u64::MAX), header_data).map_err(err!())? != header_hmac
+        {
+            return Ok(None);
+        }
+        let payload = read_hmac_blocks(reader.0, &hmac_key)?;
+        let payload = header.cipher.decrypt(&key, &header.iv, &payload)?;
+        let payload = header.decompress(payload)?;
+
+        // 内部头：加密流类型、加密流密钥、附件
+        let mut reader = Reader(&payload);
+        let mut stream_id = 0;
+        let mut stream_key = Vec::new();
+        let mut binaries = Vec::new();
+        loop {
+            let id = reader.u8()?;
+            let size = reader.u32()? as usize;
+            let data = reader.bytes(size)?;
+            match id {
+                0 => break,
+                1 => stream_id = Reader(data).u32()?,
+                2 => stream_key = data.to_vec(),
+                // 第一个字节是标志位
+                3 if !data.is_empty() => binaries.push(data[1..].to_vec()),
+                _ => {}
+            }
+        }
+        let stream = InnerStream::new(stream_id, &stream_key)?;
+        (reader.0.to_vec(), stream, binaries)
+    };
+    parse_xml(&xml, stream, binaries).map(Some)
+}
+
+// 写入 KDBX 4.0 数据库，key_file 为密钥文件的原始内容
+pub fn write(items: &[Item], password: &str, key_file: Option<&[u8]>) -> crate::Result<Vec<u8>> {
+    let mut master_seed = [0u8; 32];
+    let mut iv = [0u8; 16];
+    let mut salt = [0u8; 32];
+    let mut stream_key = [0u8; 64];
+    rand_bytes(&mut master_seed).map_err(err!())?;
+    rand_bytes(&mut iv).map_err(err!())?;
+    rand_bytes(&mut salt).map_err(err!())?;
+    rand_bytes(&mut stream_key).map_err(err!())?;
+
+    let kdf = Kdf::Argon2 {
+        algorithm: Algorithm::Argon2d,
+        version: Version::V0x13,
+        salt: salt.to_vec(),
+        memory: ARGON2_MEMORY,
+        iterations: ARGON2_ITERATIONS,
+        parallelism: ARGON2_PARALLELISM,
+    };
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&SIGNATURE_1.to_le_bytes());
+    header.extend_from_slice(&SIGNATURE_2.to_le_bytes());
+    header.extend_from_slice(&0u16.to_le_bytes());
+    header.extend_from_slice(&4u16.to_le_bytes());
+    write_field(&mut header, 2, &CIPHER_AES256);
+    write_field(&mut header, 3, &1u32.to_le_bytes());
+    write_field(&mut header, 4, &master_seed);
+    write_field(&mut header, 7, &iv);
+    write_field(&mut header, 11, &kdf.to_variant_dictionary());
+    write_field(&mut header, 0, b"\r\n\r\n");
+
+    let transformed = kdf.transform(&composite_key(password, key_file)?)?;
+    let mut seed = master_seed.to_vec();
+    seed.extend_from_slice(&transformed);
+    let key = sha256(&seed);
+    seed.push(1);
+    let hmac_key = sha512(&seed);
+
+    let mut inner = Vec::new();
+    write_field(&mut inner, 1, &INNER_STREAM_CHACHA20.to_le_bytes());
+    write_field(&mut inner, 2, &stream_key);
+    write_field(&mut inner, 0, &[]);
+    let mut stream = InnerStream::new(INNER_STREAM_CHACHA20, &stream_key)?;
+    inner.extend_from_slice(write_xml(items, &mut stream)?.as_bytes());
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&inner).map_err(err!())?;
+    let payload = encoder.finish().map_err(err!())?;
+    let payload = encrypt(SslCipher::aes_256_cbc(), &key, Some(&iv), &payload).map_err(err!())?;
+
+    let mut output = header.clone();
+    output.extend_from_slice(&sha256(&header));
+    output
+        .extend_from_slice(&hmac_sha256(block_key(&hmac_key, u64::MAX), &header).map_err(err!())?);
+    write_hmac_blocks(&mut output, &payload, &hmac_key)?;
+    Ok(output)
+}
+
+const fn uuid(v: u128) -> [u8; 16] {
+    v.to_be_bytes()
+}
+
+fn invalid_data(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(err!(invalid_data("unexpected end of data")));
+        }
+        let (data, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> crate::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> crate::Result<u16> {
+        let mut v = [0u8; 2];
+        v.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_le_bytes(v))
+    }
+
+    fn u32(&mut self) -> crate::Result<u32> {
+        let mut v = [0u8; 4];
+        v.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_le_bytes(v))
+    }
+
+    fn u64(&mut self) -> crate::Result<u64> {
+        let mut v = [0u8; 8];
+        v.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(v))
+    }
+}
+
+enum Cipher {
+    Aes256,
+    ChaCha20,
+}
+
+impl Cipher {
+    fn decrypt(&self, key: &[u8], iv: &[u8], data: &[u8]) -> crate::Result<Vec<u8>> {
+        match self {
+            Cipher::Aes256 => {
+                decrypt(SslCipher::aes_256_cbc(), key, Some(iv), data).map_err(err!())
+            }
+            Cipher::ChaCha20 => {
+                // openssl 的 iv 为 4 字节计数器 + 12 字节 nonce
+                let mut full_iv = [0u8; 16];
+                full_iv[4..].copy_from_slice(iv);
+                decrypt(SslCipher::chacha20(), key, Some(&full_iv), data).map_err(err!())
+            }
+        }
+    }
+}
+
+enum Kdf {
+    Aes {
+        seed: Vec<u8>,
+        rounds: u64,
+    },
+    Argon2 {
+        algorithm: Algorithm,
+        version: Version,
+        salt: Vec<u8>,
+        memory: u64,
+        iterations: u64,
+        parallelism: u32,
+    },
+}
+
+impl Kdf {
+    fn transform(&self, composite: &[u8; 32]) -> crate::Result<[u8; 32]> {
+        match self {
+            Kdf::Aes { seed, rounds } => {
+                let mut crypter = Crypter::new(SslCipher::aes_256_ecb(), Mode::Encrypt, seed, None)
+                    .map_err(err!())?;
+                crypter.pad(false);
+                let mut key = composite.to_vec();
+                let mut output = vec![0u8; 32 + 16];
+                for _ in 0..*rounds {
+                    crypter.update(&key, &mut output).map_err(err!())?;
+                    key.copy_from_slice(&output[..32]);
+                }
+                Ok(sha256(&key))
+            }
+            Kdf::Argon2 {
+                algorithm,
+                version,
+                salt,
+                memory,
+                iterations,
+                parallelism,
+            } => {
+                let params = Params::new(
+                    (*memory / 1024) as u32,
+                    *iterations as u32,
+                    *parallelism,
+                    Some(32),
+                )
+                .map_err(err!())?;
+                let mut key = [0u8; 32];
+                Argon2::new(*algorithm, *version, params)
+                    .hash_password_into(composite, salt, &mut key)
+                    .map_err(err!())?;
+                Ok(key)
+            }
+        }
+    }
+
+    fn from_variant_dictionary(data: &[u8]) -> crate::Result<Self> {
+        let mut reader = Reader(data);
+        reader.u16()?;
+        let mut dict = HashMap::new();
+        loop {
+            let kind = reader.u8()?;
+            if kind == 0 {
+                break;
+            }
+            let len = reader.u32()? as usize;
+            let name = String::from_utf8_lossy(reader.bytes(len)?).to_string();
+            let len = reader.u32()? as usize;
+            dict.insert(name, reader.bytes(len)?);
+        }
+
+        let bytes = |name: &str| {
+            dict.get(name)
+                .copied()
+                .ok_or_else(|| err!(invalid_data(format!("missing kdf parameter {}", name))))
+        };
+        let u64 = |name: &str| -> crate::Result<u64> {
+            let v = bytes(name)?;
+            match v.len() {
+                4 => Reader(v).u32().map(|v| v as u64),
+                _ => Reader(v).u64(),
+            }
+        };
+
+        let id = bytes("$UUID")?;
+        if id == KDF_AES_KDBX3 || id == KDF_AES_KDBX4 {
+            Ok(Kdf::Aes {
+                seed: bytes("S")?.to_vec(),
+                rounds: u64("R")?,
+            })
+        } else if id == KDF_ARGON2D || id == KDF_ARGON2ID {
+            let version = match u64("V")? {
+                0x10 => Version::V0x10,
+                _ => Version::V0x13,
+            };
+            Ok(Kdf::Argon2 {
+                algorithm: if id == KDF_ARGON2D {
+                    Algorithm::Argon2d
+                } else {
+                    Algorithm::Argon2id
+                },
+                version,
+                salt: bytes("S")?.to_vec(),
+                memory: u64("M")?,
+                iterations: u64("I")?,
+                parallelism: u64("P")? as u32,
+            })
+        } else {
+            Err(err!(invalid_data("unsupported kdf")))
+        }
+    }
+
+    fn to_variant_dictionary(&self) -> Vec<u8> {
+        let mut dict = Vec::new();
+        dict.extend_from_slice(&0x0100u16.to_le_bytes());
+        let mut item = |kind: u8, name: &str, value: &[u8]| {
+            dict.push(kind);
+            dict.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            dict.extend_from_slice(name.as_bytes());
+            dict.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            dict.extend_from_slice(value);
+        };
+        match self {
+            Kdf::Aes { seed, rounds } => {
+                item(0x42, "$UUID", &KDF_AES_KDBX4);
+                item(0x05, "R", &rounds.to_le_bytes());
+                item(0x42, "S", seed);
+            }
+            Kdf::Argon2 {
+                algorithm,
+                version,
+                salt,
+                memory,
+                iterations,
+                parallelism,
+            } => {
+                let id = match algorithm {
+                    Algorithm::Argon2id => KDF_ARGON2ID,
+                    _ => KDF_ARGON2D,
+                };
+                item(0x42, "$UUID", &id);
+                item(0x42, "S", salt);
+                item(0x04, "P", &parallelism.to_le_bytes());
+                item(0x05, "M", &memory.to_le_bytes());
+                item(0x05, "I", &iterations.to_le_bytes());
+                item(0x04, "V", &(*version as u32).to_le_bytes());
+            }
+        }
+        dict.push(0);
+        dict
+    }
+}
+
+struct Header {
+    cipher: Cipher,
+    compressed: bool,
+    master_seed: Vec<u8>,
+    iv: Vec<u8>,
+    kdf: Kdf,
+    // 以下只有 KDBX 3.1 使用
+    protected_stream_key: Vec<u8>,
+    stream_start_bytes: Vec<u8>,
+    inner_stream_id: u32,
+}
+
+impl Header {
+    fn read(reader: &mut Reader, v4: bool) -> crate::Result<Self> {
+        let mut cipher = None;
+        let mut compressed = false;
+        let mut master_seed = Vec::new();
+        let mut iv = Vec::new();
+        let mut kdf = None;
+        let mut transform_seed = Vec::new();
+        let mut transform_rounds = 0;
+        let mut protected_stream_key = Vec::new();
+        let mut stream_start_bytes = Vec::new();
+        let mut inner_stream_id = 0;
+        loop {
+            let id = reader.u8()?;
+            let size = if v4 {
+                reader.u32()? as usize
+            } else {
+                reader.u16()? as usize
+            };
+            let data = reader.bytes(size)?;
+            match id {
+                0 => break,
+                2 if data == CIPHER_AES256 => cipher = Some(Cipher::Aes256),
+                2 if data == CIPHER_CHACHA20 => cipher = Some(Cipher::ChaCha20),
+                2 => return Err(err!(invalid_data("unsupported cipher"))),
+                3 => compressed = Reader(data).u32()? == 1,
+                4 => master_seed = data.to_vec(),
+                5 => transform_seed = data.to_vec(),
+                6 => transform_rounds = Reader(data).u64()?,
+                7 => iv = data.to_vec(),
+                8 => protected_stream_key = data.to_vec(),
+                9 => stream_start_bytes = data.to_vec(),
+                10 => inner_stream_id = Reader(data).u32()?,
+                11 => kdf = Some(Kdf::from_variant_dictionary(data)?),
+                _ => {}
+            }
+        }
+
+        let kdf = match kdf {
+            Some(kdf) => kdf,
+            None if !v4 => Kdf::Aes {
+                seed: transform_seed,
+                rounds: transform_rounds,
+            },
+            None => return Err(err!(invalid_data("missing kdf parameters"))),
+        };
+        Ok(Self {
+            cipher: cipher.ok_or_else(|| err!(invalid_data("missing cipher")))?,
+            compressed,
+            master_seed,
+            iv,
+            kdf,
+            protected_stream_key,
+            stream_start_bytes,
+            inner_stream_id,
+        })
+    }
+
+    fn decompress(&self, data: Vec<u8>) -> crate::Result<Vec<u8>> {
+        if !self.compressed {
+            return Ok(data);
+        }
+        let mut output = Vec::new();
+        GzDecoder::new(&data[..])
+            .read_to_end(&mut output)
+            .map_err(err!())?;
+        Ok(output)
+    }
+}
+
+// 主密钥：SHA256(SHA256(密码) || 密钥文件)
+fn composite_key(password: &str, key_file: Option<&[u8]>) -> crate::Result<[u8; 32]> {
+    let mut data = sha256(password.as_bytes()).to_vec();
+    if let Some(key_file) = key_file {
+        data.extend_from_slice(&key_file_key(key_file)?);
+    }
+    Ok(sha256(&data))
+}
+
+// 解析密钥文件：XML（1.0/2.0），32 字节二进制，64 位十六进制，其他文件取 SHA256
+fn key_file_key(data: &[u8]) -> crate::Result<Vec<u8>> {
+    if let Ok(text) = std::str::from_utf8(data) {
+        let text = text.trim_start_matches('\u{feff}').trim();
+        if let Ok(doc) = Document::parse(text) {
+            let version = find_child(doc.root_element(), &["Meta", "Version"])
+                .and_then(|v| v.text())
+                .unwrap_or("1.0");
+            if let Some(node) = find_child(doc.root_element(), &["Key", "Data"]) {
+                let value: String = node
+                    .text()
+                    .unwrap_or("")
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .collect();
+                return if version.starts_with("2.") {
+                    decode_hex(&value)
+                } else {
+                    base64::decode(value).map_err(err!())
+                };
+            }
+        }
+        if let Ok(key) = decode_hex(text) {
+            return Ok(key);
+        }
+    }
+    if data.len() == 32 {
+        return Ok(data.to_vec());
+    }
+    Ok(sha256(data).to_vec())
+}
+
+// 解码 32 字节的十六进制密钥
+fn decode_hex(hex: &str) -> crate::Result<Vec<u8>> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(err!(invalid_data("invalid hex")));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(err!()))
+        .collect()
+}
+
+// KDBX 4 每个块的 HMAC 密钥
+fn block_key(hmac_key: &[u8], index: u64) -> Vec<u8> {
+    let mut data = index.to_le_bytes().to_vec();
+    data.extend_from_slice(hmac_key);
+    sha512(&data).to_vec()
+}
+
+// KDBX 3.1 块：序号(4) + SHA256(32) + 长度(4) + 数据
+fn read_hashed_blocks(data: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut reader = Reader(data);
+    let mut output = Vec::new();
+    loop {
+        reader.u32()?;
+        let hash = reader.bytes(32)?;
+        let size = reader.u32()? as usize;
+        if size == 0 {
+            break;
+        }
+        let data = reader.bytes(size)?;
+        if sha256(data) != hash {
+            return Err(err!(invalid_data("block corrupted")));
+        }
+        output.extend_from_slice(data);
+    }
+    Ok(output)
+}
+
+// KDBX 4 块：HMAC(32) + 长度(4) + 数据
+fn read_hmac_blocks(data: &[u8], hmac_key: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut reader = Reader(data);
+    let mut output = Vec::new();
+    for index in 0u64.. {
+        let hmac = reader.bytes(32)?;
+        let size = reader.u32()?;
+        let data = reader.bytes(size as usize)?;
+        let mut message = index.to_le_bytes().to_vec();
+        message.extend_from_slice(&size.to_le_bytes());
+        message.extend_from_slice(data);
+        if hmac_sha256(block_key(hmac_key, index), &message).map_err(err!())? != hmac {
+            return Err(err!(invalid_data("block corrupted")));
+        }
+        if size == 0 {
+            break;
+        }
+        output.extend_from_slice(data);
+    }
+    Ok(output)
+}
+
+fn write_hmac_blocks(output: &mut Vec<u8>, data: &[u8], hmac_key: &[u8]) -> crate::Result<()> {
+    const BLOCK_SIZE: usize = 1024 * 1024;
+    let mut blocks: Vec<&[u8]> = data.chunks(BLOCK_SIZE).collect();
+    blocks.push(&[]);
+    for (index, block) in blocks.into_iter().enumerate() {
+        let size = (block.len() as u32).to_le_bytes();
+        let mut message = (index as u64).to_le_bytes().to_vec();
+        message.extend_from_slice(&size);
+        message.extend_from_slice(block);
+        let hmac = hmac_sha256(block_key(hmac_key, index as u64), &message).map_err(err!())?;
+        output.extend_from_slice(&hmac);
+        output.extend_from_slice(&size);
+        output.extend_from_slice(block);
+    }
+    Ok(())
+}
+
+fn write_field(output: &mut Vec<u8>, id: u8, data: &[u8]) {
+    output.push(id);
+    output.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    output.extend_from_slice(data);
+}
+
+// 受保护字段的加密流，按文档顺序依次异或
+enum InnerStream {
+    None,
+    Salsa20(Box<Salsa20>),
+    ChaCha20(Box<Crypter>),
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> crate::Result<Self> {
+        match id {
+            INNER_STREAM_SALSA20 => {
+                let key = sha256(key);
+                Ok(InnerStream::Salsa20(Box::new(Salsa20::new(
+                    &key.into(),
+                    &SALSA20_IV.into(),
+                ))))
+            }
+            INNER_STREAM_CHACHA20 => {
+                let hash = sha512(key);
+                let mut iv = [0u8; 16];
+                iv[4..].copy_from_slice(&hash[32..44]);
+                let crypter =
+                    Crypter::new(SslCipher::chacha20(), Mode::Encrypt, &hash[..32], Some(&iv))
+                        .map_err(err!())?;
+                Ok(InnerStream::ChaCha20(Box::new(crypter)))
+            }
+            0 => Ok(InnerStream::None),
+            _ => Err(err!(invalid_data("unsupported inner stream"))),
+        }
+    }
+
+    fn apply(&mut self, data: &mut Vec<u8>) -> crate::Result<()> {
+        match self {
+            InnerStream::None => {}
+            InnerStream::Salsa20(cipher) => cipher.apply_keystream(data),
+            InnerStream::ChaCha20(crypter) => {
+                let mut output = vec![0u8; data.len() + 16];
+                let len = crypter.update(data, &mut output).map_err(err!())?;
+                output.truncate(len);
+                *data = output;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn find_child<'a, 'b>(node: Node<'a, 'b>, path: &[&str]) -> Option<Node<'a, 'b>> {
+    let mut node = node;
+    for name in path {
+        node = node
+            .children()
+            .find(|v| v.is_element() && v.has_tag_name(*name))?;
+    }
+    Some(node)
+}
+
+fn parse_xml(
+    xml: &[u8],
+    mut stream: InnerStream,
+    mut binaries: Vec<Vec<u8>>,
+) -> crate::Result<Import> {
+    let xml = std::str::from_utf8(xml).map_err(err!())?;
+    let doc = Document::parse(xml.trim_start_matches('\u{feff}')).map_err(err!())?;
+    let root = doc.root_element();
+
+    // 受保护字段必须按文档顺序解密
+    let mut protected = HashMap::new();
+    for node in root.descendants() {
+        if node.has_tag_name("Value") && node.attribute("Protected") == Some("True") {
+            let mut value = base64::decode(node.text().unwrap_or("")).map_err(err!())?;
+            stream.apply(&mut value)?;
+            protected.insert(node.id(), String::from_utf8_lossy(&value).to_string());
+        }
+    }
+
+    // KDBX 3.1 附件在 Meta/Binaries 中
+    if let Some(node) = find_child(root, &["Meta", "Binaries"]) {
+        for binary in node.children().filter(|v| v.has_tag_name("Binary")) {
+            let data = base64::decode(binary.text().unwrap_or("")).map_err(err!())?;
+            let data = if binary.attribute("Compressed") == Some("True") {
+                let mut output = Vec::new();
+                GzDecoder::new(&data[..])
+                    .read_to_end(&mut output)
+                    .map_err(err!())?;
+                output
+            } else {
+                data
+            };
+            let id: usize = binary
+                .attribute("ID")
+                .unwrap_or("")
+                .parse()
+                .map_err(err!())?;
+            if binaries.len() <= id {
+                binaries.resize(id + 1, Vec::new());
+            }
+            binaries[id] = data;
+        }
+    }
+
+    let recycle_bin = find_child(root, &["Meta", "RecycleBinUUID"]).and_then(|v| v.text());
+    let mut import = Import::default();
+    if let Some(group) = find_child(root, &["Root", "Group"]) {
+        let context = Context {
+            protected: &protected,
+            binaries: &binaries,
+            recycle_bin,
+        };
+        parse_group(group, "", &context, &mut import);
+    }
+    Ok(import)
+}
+
+struct Context<'a> {
+    protected: &'a HashMap<NodeId, String>,
+    binaries: &'a [Vec<u8>],
+    recycle_bin: Option<&'a str>,
+}
+
+// 解析分组，条目名称加上分组路径（不含根分组）
+fn parse_group(group: Node, path: &str, context: &Context, import: &mut Import) {
+    for node in group.children().filter(|v| v.is_element()) {
+        match node.tag_name().name() {
+            "Entry" => parse_entry(node, path, context, import),
+            "Group" => {
+                let uuid = find_child(node, &["UUID"]).and_then(|v| v.text());
+                if uuid.is_some() && uuid == context.recycle_bin {
+                    continue;
+                }
+                let name = find_child(node, &["Name"])
+                    .and_then(|v| v.text())
+                    .unwrap_or("");
+                parse_group(node, &join(path, name), context, import);
+            }
+            _ => {}
+        }
+    }
+}
+
+// 解析条目，自定义字段和附件作为单独的条目
+fn parse_entry(entry: Node, path: &str, context: &Context, import: &mut Import) {
+    let mut fields = Vec::new();
+    for node in entry.children().filter(|v| v.has_tag_name("String")) {
+        let key = find_child(node, &["Key"])
+            .and_then(|v| v.text())
+            .unwrap_or("");
+        let value = match find_child(node, &["Value"]) {
+            Some(v) => match context.protected.get(&v.id()) {
+                Some(value) => value.clone(),
+                None => v.text().unwrap_or("").to_string(),
+            },
+            None => String::new(),
+        };
+        fields.push((key.to_string(), value));
+    }
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(k, _)| k == name)
+            .map_or("", |(_, v)| v.as_str())
+    };
+
+    let title = join(path, field("Title"));
+    let main = Entry::new(&title, field("URL"), field("UserName"), field("Password"));
+    let name = main.name.clone();
+    if !main.password.is_empty() {
+        import.entries.push(main);
+    }
+    for (key, value) in &fields {
+        if !STANDARD_FIELDS.contains(&key.as_str()) && !value.is_empty() {
+            import.push_field(&name, key, value);
+        }
+    }
+
+    for node in entry.children().filter(|v| v.has_tag_name("Binary")) {
+        let key = find_child(node, &["Key"])
+            .and_then(|v| v.text())
+            .unwrap_or("");
+        let data = find_child(node, &["Value"])
+            .and_then(|v| v.attribute("Ref"))
+            .and_then(|v| v.parse::<usize>().ok())
+            .and_then(|v| context.binaries.get(v));
+        if let Some(data) = data {
+            // 文本附件原样保存，二进制附件保存为 base64
+            let value = match std::str::from_utf8(data) {
+                Ok(text) => text.to_string(),
+                Err(_) => base64::encode(data),
+            };
+            import.push_field(&name, key, &value);
+        }
+    }
+}
+
+fn write_xml(items: &[Item], stream: &mut InnerStream) -> crate::Result<String> {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\" standalone=\"yes\"?>\n<KeePassFile>\n\
+         <Meta><Generator>Vault</Generator><DatabaseName>Vault</DatabaseName></Meta>\n<Root>\n",
+    );
+    xml.push_str(&format!(
+        "<Group><UUID>{}</UUID><Name>Vault</Name>\n",
+        random_uuid()?
+    ));
+    for item in items {
+        let mut password = item.password.as_bytes().to_vec();
+        stream.apply(&mut password)?;
+        xml.push_str(&format!(
+            "<Entry><UUID>{}</UUID>\
+             <String><Key>Title</Key><Value>{}</Value></String>\
+             <String><Key>Password</Key><Value Protected=\"True\">{}</Value></String>\
+             </Entry>\n",
+            random_uuid()?,
+            escape(item.name),
+            base64::encode(password)
+        ));
+    }
+    xml.push_str("</Group>\n</Root>\n</KeePassFile>\n");
+    Ok(xml)
+}
+
+fn random_uuid() -> crate::Result<String> {
+    let mut uuid = [0u8; 16];
+    rand_bytes(&mut uuid).map_err(err!())?;
+    Ok(base64::encode(uuid))
+}
+
+fn escape(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&apos;"),
+            // XML 1.0 不允许的控制字符
+            c if (c as u32) < 0x20 && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => output.push(c),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(import: &Import) -> Vec<(&str, &str)> {
+        import
+            .entries
+            .iter()
+            .map(|v| (v.name.as_str(), v.password.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn write_and_read() {
+        let items = [
+            Item {
+                name: "GitHub (alice)",
+                password: "gh-secret",
+            },
+            Item {
+                name: "p<&>\"' 密码",
+                password: "<Value Protected=\"True\">&amp;",
+            },
+        ];
+        let data = write(&items, "password", None).unwrap();
+        let import = read(&data, "password", None).unwrap().unwrap();
+        assert_eq!(
+            entries(&import),
+            [
+                ("GitHub (alice)", "gh-secret"),
+                ("p<&>\"' 密码", "<Value Protected=\"True\">&amp;"),
+            ]
+        );
+        assert!(read(&data, "wrong", None).unwrap().is_none());
+
+        // 使用密钥文件时缺少密钥文件不能打开
+        let key_file = b"key file content";
+        let data = write(&items, "password", Some(key_file)).unwrap();
+        assert!(read(&data, "password", None).unwrap().is_none());
+        assert!(read(&data, "password", Some(b"other")).unwrap().is_none());
+        let import = read(&data, "password", Some(key_file)).unwrap().unwrap();
+        assert_eq!(entries(&import).len(), 2);
+    }
+
+    // tests/fixtures/kdbx 由独立的 generate.py 生成
+    #[test]
+    fn read_kdbx4() {
+        let data = include_bytes!("../tests/fixtures/kdbx/kdbx4.kdbx");
+        assert!(read(data, "wrong", None).unwrap().is_none());
+        let import = read(data, "fixture-password", None).unwrap().unwrap();
+        assert_eq!(
+            entries(&import),
+            [
+                ("GitHub (alice)", "gh-secret"),
+                ("GitHub (alice) / Notes", "recovery codes in attachment"),
+                ("GitHub (alice) / PIN", "4321"),
+                (
+                    "GitHub (alice) / backup-codes.txt",
+                    "1111-2222\n3333-4444\n"
+                ),
+                ("Email/Gmail", "p<&>\"' 密码"),
+            ]
+        );
+        assert!(import.warnings.is_empty());
+    }
+
+    #[test]
+    fn read_kdbx3_with_key_file() {
+        let data = include_bytes!("../tests/fixtures/kdbx/kdbx3.kdbx");
+        let key_file = include_bytes!("../tests/fixtures/kdbx/kdbx3.keyx");
+        assert!(read(data, "fixture-password", None).unwrap().is_none());
+        assert!(read(data, "fixture-password", Some(b"other"))
+            .unwrap()
+            .is_none());
+        let import = read(data, "fixture-password", Some(key_file))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            entries(&import),
+            [
+                ("GitHub (alice)", "gh-secret"),
+                ("GitHub (alice) / Notes", "recovery codes in attachment"),
+                ("GitHub (alice) / PIN", "4321"),
+                (
+                    "GitHub (alice) / backup-codes.txt",
+                    "1111-2222\n3333-4444\n"
+                ),
+                ("Email/Gmail", "p<&>\"' 密码"),
+            ]
+        );
+    }
+}
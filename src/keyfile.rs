@@ -0,0 +1,363 @@
+// 密钥文件，主密码之外的第二个解锁因素
+//
+// 开启后保险库密钥使用 Argon2id(主密码 + 密钥文件内容的 SHA-256) 派生的密钥加密，保存在 conf 的 key，
+// 盐和 KDF 参数保存在 conf 的 key_file，解锁时需要同时提供主密码和密钥文件。没有开启时 key 仍然只用
+// 主密码加密。每次调用都要派生密钥，所以使用 OWASP 建议的最低参数。
+//
+// 保存在保险库以外的密钥 (同步文件夹、WebDAV、导出的文件) 使用 seal 加密，自带随机的盐和 KDF 参数，
+// 开启了密钥文件时同样需要密钥文件才能解密。
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use openssl::rand::rand_bytes;
+use openssl::sha::sha256;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{key_decrypt, key_encrypt, password_decrypt, password_encrypt};
+use crate::db::{delete_conf, get_conf, set_conf};
+
+// 派生密钥的 KDF 参数，JSON 格式
+const KEY_FILE: &str = "key_file";
+
+const MEMORY: u32 = 19 * 1024;
+const ITERATIONS: u32 = 2;
+const PARALLELISM: u32 = 1;
+
+// 读取时允许的最大 KDF 参数，备份可能被替换
+const MAX_MEMORY: u32 = 1024 * 1024;
+const MAX_ITERATIONS: u32 = 100;
+const MAX_PARALLELISM: u32 = 16;
+
+const SALT_LEN: usize = 16;
+
+// 主密码，开启密钥文件后还需要密钥文件
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum MasterPassword {
+    Password(String),
+    // key_file 为密钥文件的内容，base64 编码
+    KeyFile { password: String, key_file: String },
+}
+
+impl MasterPassword {
+    pub fn password(&self) -> &str {
+        match self {
+            MasterPassword::Password(password) => password,
+            MasterPassword::KeyFile { password, .. } => password,
+        }
+    }
+
+    // 密钥文件内容的 SHA-256，没有提供或者格式错误时返回 None
+    pub fn key_file(&self) -> Option<[u8; 32]> {
+        match self {
+            MasterPassword::Password(_) => None,
+            MasterPassword::KeyFile { key_file, .. } => hash(key_file),
+        }
+    }
+
+    // 密钥文件的原始内容，没有提供或者格式错误时返回 None
+    pub fn key_file_content(&self) -> Option<Vec<u8>> {
+        match self {
+            MasterPassword::Password(_) => None,
+            MasterPassword::KeyFile { key_file, .. } => {
+                base64::decode(key_file).ok().filter(|v| !v.is_empty())
+            }
+        }
+    }
+
+    // 替换主密码，保留密钥文件
+    pub fn with_password(&self, password: String) -> MasterPassword {
+        match self {
+            MasterPassword::Password(_) => MasterPassword::Password(password),
+            MasterPassword::KeyFile { key_file, .. } => MasterPassword::KeyFile {
+                password,
+                key_file: key_file.clone(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Kdf {
+    salt: String,
+    memory: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+// 保存在保险库以外的保险库密钥
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    kdf: Kdf,
+    // 是否需要密钥文件
+    #[serde(default)]
+    key_file: bool,
+    // 加密的保险库密钥，base64 编码
+    key: String,
+}
+
+impl Kdf {
+    fn new() -> crate::Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        rand_bytes(&mut salt).map_err(err!())?;
+        Ok(Self {
+            salt: base64::encode(salt),
+            memory: MEMORY,
+            iterations: ITERATIONS,
+            parallelism: PARALLELISM,
+        })
+    }
+}
+
+// 密钥文件内容的 SHA-256，content 为 base64 编码，格式错误或者内容为空时返回 None
+pub fn hash(content: &str) -> Option<[u8; 32]> {
+    let data = base64::decode(content).ok()?;
+    (!data.is_empty()).then(|| sha256(&data))
+}
+
+// 是否开启了密钥文件
+pub fn is_set(conn: &Connection) -> crate::Result<bool> {
+    Ok(kdf(conn)?.is_some())
+}
+
+// 读取 KDF 参数，没有开启密钥文件时返回 None
+pub fn kdf(conn: &Connection) -> crate::Result<Option<String>> {
+    get_conf(conn, KEY_FILE)
+}
+
+// 开启了密钥文件时返回 master_password 中的密钥文件，用于修改主密码等操作时保持密钥文件不变
+pub fn current(
+    conn: &Connection,
+    master_password: &MasterPassword,
+) -> crate::Result<Option<[u8; 32]>> {
+    Ok(match is_set(conn)? {
+        true => master_password.key_file(),
+        false => None,
+    })
+}
+
+// 加密保险库密钥，返回 base64 编码的结果，由调用者保存到 conf 的 key。
+// key_file 不为 None 时重新生成盐并开启密钥文件，否则只用主密码加密并关闭密钥文件
+pub fn wrap(
+    conn: &Connection,
+    password: &str,
+    key_file: Option<&[u8; 32]>,
+    key: &[u8],
+) -> crate::Result<String> {
+    let wrapped = match key_file {
+        Some(hash) => {
+            let kdf = Kdf::new()?;
+            let derived = derive(&kdf, password, Some(hash))?;
+            set_conf(
+                conn,
+                KEY_FILE,
+                &serde_json::to_string(&kdf).map_err(err!())?,
+            )?;
+            key_encrypt(derived, key).map_err(err!())?
+        }
+        None => {
+            delete_conf(conn, KEY_FILE)?;
+            password_encrypt(password, key).map_err(err!())?
+        }
+    };
+    Ok(base64::encode(wrapped))
+}
+
+// 解密保险库密钥，kdf 为 conf 的 key_file，主密码或密钥文件错误、缺少密钥文件、没有开启密钥文件
+// 但提供了密钥文件时返回 None
+pub fn unwrap(
+    kdf: Option<&str>,
+    master_password: &MasterPassword,
+    wrapped: &[u8],
+) -> crate::Result<Option<Vec<u8>>> {
+    let kdf = match (kdf, master_password.key_file()) {
+        (Some(v), _) => v,
+        (None, Some(_)) => return Ok(None),
+        (None, None) => {
+            return password_decrypt(master_password.password(), wrapped).map_err(err!())
+        }
+    };
+    let hash = match master_password.key_file() {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let kdf: Kdf = serde_json::from_str(kdf).map_err(err!())?;
+    let derived = derive(&kdf, master_password.password(), Some(&hash))?;
+    key_decrypt(derived, wrapped).map_err(err!())
+}
+
+// 用 Argon2id(主密码 + 密钥文件) 加密保险库密钥，用于保存到同步文件夹等保险库以外的地方，返回 JSON。
+// key_file 一般为 current 的结果，没有开启密钥文件时为 None
+pub fn seal(password: &str, key_file: Option<&[u8; 32]>, key: &[u8]) -> crate::Result<String> {
+    let kdf = Kdf::new()?;
+    let derived = derive(&kdf, password, key_file)?;
+    let key = base64::encode(key_encrypt(derived, key).map_err(err!())?);
+    let key_file = key_file.is_some();
+    serde_json::to_string(&Sealed { kdf, key_file, key }).map_err(err!())
+}
+
+// 解密 seal 加密的保险库密钥，主密码或密钥文件错误、缺少密钥文件、数据被修改时返回 None。
+// 加密时没有使用密钥文件则忽略提供的密钥文件，其他设备可能没有开启密钥文件
+pub fn unseal(master_password: &MasterPassword, sealed: &str) -> crate::Result<Option<Vec<u8>>> {
+    open(master_password, sealed, false)
+}
+
+// 同 unseal，但提供了加密时没有使用的密钥文件时同样返回 None，和 unwrap 一致。
+// 无论是否需要密钥文件都计算一次 KDF
+pub fn unseal_exact(
+    master_password: &MasterPassword,
+    sealed: &str,
+) -> crate::Result<Option<Vec<u8>>> {
+    open(master_password, sealed, true)
+}
+
+fn open(
+    master_password: &MasterPassword,
+    sealed: &str,
+    exact: bool,
+) -> crate::Result<Option<Vec<u8>>> {
+    let sealed: Sealed = serde_json::from_str(sealed).map_err(err!())?;
+    // exact 时直接使用提供的密钥文件，和加密时不一致会解密失败
+    let hash = match (sealed.key_file, master_password.key_file()) {
+        (_, hash) if exact => hash,
+        (true, Some(hash)) => Some(hash),
+        (true, None) => return Ok(None),
+        (false, _) => None,
+    };
+    let key = base64::decode(&sealed.key).map_err(err!())?;
+    // 太短的数据 key_decrypt 会原样返回
+    if key.len() <= 12 + 16 {
+        return Ok(None);
+    }
+    let derived = derive(&sealed.kdf, master_password.password(), hash.as_ref())?;
+    key_decrypt(derived, key).map_err(err!())
+}
+
+fn derive(kdf: &Kdf, password: &str, hash: Option<&[u8; 32]>) -> crate::Result<[u8; 32]> {
+    if kdf.memory > MAX_MEMORY
+        || kdf.iterations > MAX_ITERATIONS
+        || kdf.parallelism > MAX_PARALLELISM
+    {
+        return Err(err!(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "kdf parameters too large"
+        )));
+    }
+    let salt = base64::decode(&kdf.salt).map_err(err!())?;
+    let mut input = password.as_bytes().to_vec();
+    if let Some(hash) = hash {
+        input.extend_from_slice(hash);
+    }
+
+    let mut key = [0u8; 32];
+    let params = Params::new(kdf.memory, kdf.iterations, kdf.parallelism, Some(key.len()))
+        .map_err(err!())?;
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(&input, &salt, &mut key)
+        .map_err(err!())?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_and_unwrap() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table conf (key text not null primary key, value text)")
+            .unwrap();
+        let key = [7u8; 32];
+        let content = base64::encode(b"key file content");
+        let with_file = MasterPassword::KeyFile {
+            password: "password".to_string(),
+            key_file: content.clone(),
+        };
+        let wrong_file = MasterPassword::KeyFile {
+            password: "password".to_string(),
+            key_file: base64::encode(b"other content"),
+        };
+        let password_only = MasterPassword::Password("password".to_string());
+        let open = |mp: &MasterPassword, wrapped: &str| {
+            let wrapped = base64::decode(wrapped).unwrap();
+            unwrap(kdf(&conn).unwrap().as_deref(), mp, &wrapped).unwrap()
+        };
+
+        let wrapped = wrap(&conn, "password", hash(&content).as_ref(), &key).unwrap();
+        assert!(is_set(&conn).unwrap());
+        assert_eq!(open(&with_file, &wrapped).unwrap(), key);
+        assert!(open(&wrong_file, &wrapped).is_none());
+        assert!(open(&password_only, &wrapped).is_none());
+
+        // 关闭后只需要主密码，提供密钥文件时同样拒绝，和开启了密钥文件的保险库一致
+        let wrapped = wrap(&conn, "password", None, &key).unwrap();
+        assert!(!is_set(&conn).unwrap());
+        assert_eq!(open(&password_only, &wrapped).unwrap(), key);
+        assert!(open(&with_file, &wrapped).is_none());
+    }
+
+    #[test]
+    fn seal_and_unseal() {
+        let key = [7u8; 32];
+        let password = MasterPassword::Password("password".to_string());
+        let sealed = seal("password", None, &key).unwrap();
+        assert_eq!(unseal(&password, &sealed).unwrap(), Some(key.to_vec()));
+        let wrong = MasterPassword::Password("wrong".to_string());
+        assert_eq!(unseal(&wrong, &sealed).unwrap(), None);
+
+        // 每次使用不同的盐
+        let other: Sealed = serde_json::from_str(&seal("password", None, &key).unwrap()).unwrap();
+        let sealed: Sealed = serde_json::from_str(&sealed).unwrap();
+        assert_ne!(sealed.kdf.salt, other.kdf.salt);
+        assert_eq!(sealed.kdf.memory, MEMORY);
+
+        // 被替换为超大参数时拒绝，不会耗尽内存
+        let huge = Sealed {
+            kdf: Kdf {
+                memory: MAX_MEMORY + 1,
+                ..sealed.kdf
+            },
+            key_file: false,
+            key: sealed.key,
+        };
+        let huge = serde_json::to_string(&huge).unwrap();
+        assert!(unseal(&password, &huge).is_err());
+    }
+
+    #[test]
+    fn seal_with_key_file() {
+        let key = [7u8; 32];
+        let content = base64::encode(b"key file content");
+        let with_file = MasterPassword::KeyFile {
+            password: "password".to_string(),
+            key_file: content.clone(),
+        };
+        let wrong_file = MasterPassword::KeyFile {
+            password: "password".to_string(),
+            key_file: base64::encode(b"other content"),
+        };
+        let password_only = MasterPassword::Password("password".to_string());
+
+        let sealed = seal("password", hash(&content).as_ref(), &key).unwrap();
+        assert_eq!(unseal(&with_file, &sealed).unwrap(), Some(key.to_vec()));
+        assert_eq!(unseal(&wrong_file, &sealed).unwrap(), None);
+        assert_eq!(unseal(&password_only, &sealed).unwrap(), None);
+
+        // 去掉标记也不能只用主密码解密
+        let mut stripped: Sealed = serde_json::from_str(&sealed).unwrap();
+        stripped.key_file = false;
+        let stripped = serde_json::to_string(&stripped).unwrap();
+        assert_eq!(unseal(&password_only, &stripped).unwrap(), None);
+
+        // 其他设备没有开启密钥文件时忽略本地的密钥文件，unseal_exact 不忽略
+        let sealed = seal("password", None, &key).unwrap();
+        assert_eq!(unseal(&with_file, &sealed).unwrap(), Some(key.to_vec()));
+        assert_eq!(unseal_exact(&with_file, &sealed).unwrap(), None);
+        let exact = unseal_exact(&password_only, &sealed).unwrap();
+        assert_eq!(exact, Some(key.to_vec()));
+        assert_eq!(
+            with_file.with_password("other".to_string()).key_file(),
+            hash(&content)
+        );
+    }
+}
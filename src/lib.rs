@@ -6,7 +6,23 @@ use crate::error::*;
 mod error;
 #[cfg(target_os = "android")]
 mod android;
+mod attempt;
+mod audit;
+mod backup;
 mod crypto;
 mod db;
+mod duress;
+mod encrypted;
+mod health;
+mod hibp;
+mod import;
+mod kdbx;
+mod keyfile;
+mod notify;
+mod plaintext;
+mod recipient;
+mod recovery;
 mod server;
 mod service;
+mod share;
+mod sync;
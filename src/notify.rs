@@ -0,0 +1,347 @@
+// 保险库变化的通知
+//
+// 服务端在 /ws 连接上主动发送 JSON-RPC 通知 (没有 id)。客户端调用 subscribe(保险库名称, 主密码)
+// 订阅保险库，unsubscribe(保险库名称) 取消订阅，只收到订阅了的保险库的通知。订阅属于连接，连接断开后
+// 需要重新订阅。通知先放入连接的队列，由 server 在 Handler 写入的两帧之间发送。通知的类型:
+//
+// entry_created/entry_updated/entry_deleted {vault, id}: 条目新建、修改、删除，移入回收站和恢复属于修改
+// entries_changed {vault}: 一次修改的条目太多，或者客户端没有及时获取丢失了通知，需要重新加载
+// vault_locked {vault}: 保险库被关闭
+// network_changed {port}: 网络访问开启或关闭，关闭时 port 为 null，发送给所有订阅了保险库的连接
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::mem::take;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+
+use rusqlite::hooks::Action;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+// 每个连接未发送的通知的数量，超过时丢弃，改为通知重新加载
+const CAPACITY: usize = 256;
+
+// 一次提交修改的条目超过这个数量时只发送 entries_changed
+const MAX_ENTRIES: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Notification {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: Value,
+}
+
+impl Notification {
+    fn new(method: &'static str, params: Value) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            method,
+            params,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Subscriber {
+    // 订阅的保险库，目录 -> 名称
+    vaults: HashMap<PathBuf, String>,
+    queue: Vec<Notification>,
+    // 等待通知的连接
+    waker: Option<Waker>,
+}
+
+impl Subscriber {
+    fn push(&mut self, method: &'static str, params: Value) {
+        if self.queue.len() >= CAPACITY {
+            // 丢失了通知，让客户端重新加载订阅的保险库
+            self.queue = (self.vaults.values())
+                .map(|name| Notification::new("entries_changed", json!({ "vault": name })))
+                .collect();
+        }
+        self.queue.push(Notification::new(method, params));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+// 已连接的客户端，键为客户端地址，不在连接中时为 None
+fn subscribers() -> &'static Mutex<HashMap<Option<SocketAddr>, Subscriber>> {
+    static SUBSCRIBERS: OnceLock<Mutex<HashMap<Option<SocketAddr>, Subscriber>>> = OnceLock::new();
+    SUBSCRIBERS.get_or_init(Mutex::default)
+}
+
+// vault 为保险库目录，None 表示和保险库无关
+fn send(vault: Option<&Path>, method: &'static str, params: Value) {
+    for subscriber in subscribers().lock().unwrap().values_mut() {
+        let mut params = params.clone();
+        match vault {
+            Some(dir) => match subscriber.vaults.get(dir) {
+                Some(name) => params["vault"] = json!(name),
+                None => continue,
+            },
+            None if subscriber.vaults.is_empty() => continue,
+            None => {}
+        }
+        subscriber.push(method, params);
+    }
+}
+
+// 订阅保险库，dir 为保险库目录，name 为客户端使用的名称
+pub fn subscribe(addr: Option<SocketAddr>, dir: PathBuf, name: String) {
+    let mut subscribers = subscribers().lock().unwrap();
+    subscribers
+        .entry(addr)
+        .or_default()
+        .vaults
+        .insert(dir, name);
+}
+
+// 取消订阅保险库，没有订阅其他保险库时丢弃未发送的通知
+pub fn unsubscribe(addr: Option<SocketAddr>, name: &str) {
+    let mut subscribers = subscribers().lock().unwrap();
+    if let Some(subscriber) = subscribers.get_mut(&addr) {
+        subscriber.vaults.retain(|_, v| v != name);
+        if subscriber.vaults.is_empty() {
+            subscriber.queue.clear();
+        }
+    }
+}
+
+// 新的 WebSocket 连接，订阅前就登记，等待通知时不会错过之后的订阅
+pub fn connect(addr: Option<SocketAddr>) {
+    subscribers().lock().unwrap().entry(addr).or_default();
+}
+
+// 连接断开
+pub fn disconnect(addr: Option<SocketAddr>) {
+    subscribers().lock().unwrap().remove(&addr);
+}
+
+// 取出连接未发送的通知
+pub fn drain(addr: Option<SocketAddr>) -> Vec<Notification> {
+    let mut subscribers = subscribers().lock().unwrap();
+    subscribers
+        .get_mut(&addr)
+        .map_or_else(Vec::new, |v| take(&mut v.queue))
+}
+
+// 取出连接未发送的通知，没有时登记 cx，有新的通知时唤醒
+pub fn poll_drain(addr: Option<SocketAddr>, cx: &mut Context<'_>) -> Poll<Vec<Notification>> {
+    let mut subscribers = subscribers().lock().unwrap();
+    let subscriber = match subscribers.get_mut(&addr) {
+        Some(v) => v,
+        None => return Poll::Pending,
+    };
+    if subscriber.queue.is_empty() {
+        subscriber.waker = Some(cx.waker().clone());
+        return Poll::Pending;
+    }
+    Poll::Ready(take(&mut subscriber.queue))
+}
+
+// 保险库被关闭
+pub fn vault_locked(dir: &Path) {
+    send(Some(dir), "vault_locked", json!({}));
+}
+
+// 网络访问开启或关闭
+pub fn network_changed(port: Option<u16>) {
+    send(None, "network_changed", json!({ "port": port }));
+}
+
+// 一次提交修改的条目，None 表示不确定修改了哪些条目，只通知重新加载
+type Batch = (PathBuf, Option<BTreeMap<i64, Action>>);
+
+thread_local! {
+    // 本线程已经提交、还没有发送的条目修改
+    static COMMITTED: RefCell<Vec<Batch>> = RefCell::default();
+}
+
+// 数据库连接上当前事务的条目修改。提交钩子在真正写入之前调用，这时只把修改交给本线程，
+// 释放数据库连接后由 flush 发送；提交失败时 SQLite 会回滚，回滚钩子撤回这次提交的修改
+#[derive(Clone)]
+pub struct Changes {
+    dir: PathBuf,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: BTreeMap<i64, Action>,
+    // 调用了提交钩子之后还没有新的修改，这时的回滚可能是提交失败
+    committing: bool,
+}
+
+impl Changes {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            state: Arc::default(),
+        }
+    }
+
+    // 同一事务多次修改同一条目时合并，新建后修改仍是新建，新建后删除不通知
+    pub fn record(&self, action: Action, id: i64) {
+        let mut state = self.state.lock().unwrap();
+        state.committing = false;
+        let entries = &mut state.entries;
+        match (entries.get(&id), action) {
+            (Some(Action::SQLITE_INSERT), Action::SQLITE_DELETE) => {
+                entries.remove(&id);
+            }
+            (Some(Action::SQLITE_INSERT), _) => {}
+            (_, action) => {
+                entries.insert(id, action);
+            }
+        }
+    }
+
+    pub fn commit(&self) {
+        let mut state = self.state.lock().unwrap();
+        let entries = take(&mut state.entries);
+        state.committing = !entries.is_empty();
+        if state.committing {
+            COMMITTED.with(|v| v.borrow_mut().push((self.dir.clone(), Some(entries))));
+        }
+    }
+
+    pub fn rollback(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        if !take(&mut state.committing) {
+            return;
+        }
+        // 提交失败，或者提交成功后开始了没有修改的事务又回滚，分不清时只通知重新加载
+        COMMITTED.with(|v| {
+            let mut committed = v.borrow_mut();
+            if let Some(last) = committed.iter_mut().rev().find(|v| v.0 == self.dir) {
+                last.1 = None;
+            }
+        });
+    }
+}
+
+// 发送本线程已经提交的条目修改。在释放数据库连接后调用，这时事务都已结束
+pub fn flush() {
+    let committed = COMMITTED.with(|v| take(&mut *v.borrow_mut()));
+    for (dir, entries) in committed {
+        let entries = match entries {
+            Some(v) if v.len() <= MAX_ENTRIES => v,
+            _ => {
+                send(Some(&dir), "entries_changed", json!({}));
+                continue;
+            }
+        };
+        for (id, action) in entries {
+            let method = match action {
+                Action::SQLITE_INSERT => "entry_created",
+                Action::SQLITE_DELETE => "entry_deleted",
+                _ => "entry_updated",
+            };
+            send(Some(&dir), method, json!({ "id": id }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rusqlite::Connection;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::server::open_database;
+
+    fn addr(port: u16) -> Option<SocketAddr> {
+        Some(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    fn queued(addr: Option<SocketAddr>) -> Vec<&'static str> {
+        let mut subscribers = subscribers().lock().unwrap();
+        let queue = take(&mut subscribers.get_mut(&addr).unwrap().queue);
+        queue.into_iter().map(|v| v.method).collect()
+    }
+
+    fn add(conn: &Connection) {
+        let sql = "INSERT INTO vault (key, value, created_at, modified_at) VALUES (randomblob(16), x'00', 1, 1)";
+        conn.execute(sql, []).unwrap();
+    }
+
+    // 提交成功并释放连接后才发送，回滚的不发送
+    #[test]
+    fn send_after_commit() {
+        let tmp = tempdir().unwrap();
+        let conn = open_database(tmp.path()).unwrap();
+        let addr = addr(2001);
+        subscribe(addr, tmp.path().to_path_buf(), "notify".to_string());
+
+        let tx = conn.unchecked_transaction().unwrap();
+        add(&tx);
+        flush();
+        assert!(queued(addr).is_empty());
+        tx.commit().unwrap();
+        flush();
+        assert_eq!(queued(addr), ["entry_created"]);
+
+        let tx = conn.unchecked_transaction().unwrap();
+        add(&tx);
+        drop(tx);
+        flush();
+        assert!(queued(addr).is_empty());
+
+        // 其他连接持有读锁，提交失败，不发送
+        let reader = Connection::open(tmp.path().join("database")).unwrap();
+        reader.execute_batch("BEGIN; SELECT * FROM vault;").unwrap();
+        conn.busy_timeout(Duration::ZERO).unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        add(&tx);
+        assert!(tx.commit().is_err());
+        flush();
+        assert!(queued(addr).is_empty());
+        reader.execute_batch("ROLLBACK").unwrap();
+
+        unsubscribe(addr, "notify");
+        assert!(subscribers().lock().unwrap()[&addr].vaults.is_empty());
+        disconnect(addr);
+    }
+
+    // 只发送订阅的保险库的通知，积压太多时改为通知重新加载
+    #[test]
+    fn queue() {
+        let a = addr(2002);
+        let b = addr(2003);
+        subscribe(a, PathBuf::from("notify-a"), "a".to_string());
+        subscribe(b, PathBuf::from("notify-b"), "b".to_string());
+        vault_locked(Path::new("notify-a"));
+        assert_eq!(queued(a), ["vault_locked"]);
+        assert!(queued(b).is_empty());
+
+        for _ in 0..CAPACITY + 1 {
+            vault_locked(Path::new("notify-b"));
+        }
+        assert_eq!(queued(b), ["entries_changed", "vault_locked"]);
+        disconnect(a);
+        disconnect(b);
+    }
+
+    // 没有通知时登记等待的连接，新的通知唤醒它
+    #[tokio::test]
+    async fn wake_on_notification() {
+        let addr = addr(2004);
+        connect(addr);
+        subscribe(addr, PathBuf::from("notify-wake"), "wake".to_string());
+        let task = tokio::spawn(std::future::poll_fn(move |cx| poll_drain(addr, cx)));
+        tokio::task::yield_now().await;
+        vault_locked(Path::new("notify-wake"));
+        assert_eq!(
+            task.await.unwrap(),
+            [Notification::new("vault_locked", json!({"vault": "wake"}))]
+        );
+        disconnect(addr);
+    }
+}
@@ -0,0 +1,65 @@
+// 未加密的导出格式，CSV 兼容 Chrome 的导入格式，JSON 兼容 Bitwarden 的导入格式
+
+use serde::Serialize;
+
+// Bitwarden 登录条目类型
+const TYPE_LOGIN: u32 = 1;
+
+#[derive(Serialize)]
+struct Export<'a> {
+    encrypted: bool,
+    folders: Vec<()>,
+    items: Vec<Item<'a>>,
+}
+
+#[derive(Serialize)]
+struct Item<'a> {
+    #[serde(rename = "type")]
+    kind: u32,
+    name: &'a str,
+    favorite: bool,
+    login: Login<'a>,
+}
+
+#[derive(Serialize)]
+struct Login<'a> {
+    uris: Vec<()>,
+    username: Option<&'a str>,
+    password: &'a str,
+}
+
+// Chrome 格式的 CSV: name,url,username,password,note
+pub fn csv(list: &[(String, String)]) -> crate::Result<Vec<u8>> {
+    let mut writer = ::csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(["name", "url", "username", "password", "note"])
+        .map_err(err!())?;
+    for (name, password) in list {
+        writer
+            .write_record([name.as_str(), "", "", password.as_str(), ""])
+            .map_err(err!())?;
+    }
+    writer.into_inner().map_err(|e| err!(e.into_error()))
+}
+
+// Bitwarden 格式的未加密 JSON
+pub fn json(list: &[(String, String)]) -> crate::Result<Vec<u8>> {
+    let export = Export {
+        encrypted: false,
+        folders: Vec::new(),
+        items: list
+            .iter()
+            .map(|(name, password)| Item {
+                kind: TYPE_LOGIN,
+                name,
+                favorite: false,
+                login: Login {
+                    uris: Vec::new(),
+                    username: None,
+                    password,
+                },
+            })
+            .collect(),
+    };
+    serde_json::to_vec_pretty(&export).map_err(err!())
+}
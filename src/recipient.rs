@@ -0,0 +1,17 @@
+// 加密给接收者，导出时使用接收者的公钥加密，导入时使用身份文件或私钥解密，不需要主密码
+//
+// 加密的数据为 JSON `[[名称, 密码], ...]`
+
+use crate::import::Import;
+
+pub mod pgp;
+pub mod x25519;
+
+pub fn to_json(list: &[(String, String)]) -> crate::Result<Vec<u8>> {
+    serde_json::to_vec(list).map_err(err!())
+}
+
+pub fn from_json(data: &[u8]) -> crate::Result<Import> {
+    let list: Vec<(String, String)> = serde_json::from_slice(data).map_err(err!())?;
+    Ok(Import::from(list))
+}
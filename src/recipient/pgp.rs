@@ -0,0 +1,891 @@
+// OpenPGP (RFC 4880)，只实现导出导入需要的部分：
+// 加密：v4 RSA 和 ECDH Curve25519 公钥，AES-256，带 MDC 的加密数据包，输出 ASCII armor 格式
+// 解密：RSA 和 ECDH Curve25519 私钥（未加密或使用 S2K 加密），支持 ZIP/ZLIB 压缩
+// 不支持 AEAD：OCB 加密数据包（GnuPG 2.3+）、v2 加密数据包和 v6 密钥（RFC 9580），遇到时明确报错
+
+use std::io::{self, Read};
+
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use openssl::aes::{unwrap_key, wrap_key, AesKey};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::derive::Deriver;
+use openssl::hash::{Hasher, MessageDigest};
+use openssl::pkey::{HasPrivate, Id, PKey, PKeyRef};
+use openssl::rand::rand_bytes;
+use openssl::rsa::{Padding, Rsa};
+use openssl::sha::sha1;
+use openssl::symm::{decrypt as sym_decrypt, encrypt as sym_encrypt, Cipher};
+
+// 包类型
+const TAG_PKESK: u8 = 1;
+const TAG_SIGNATURE: u8 = 2;
+const TAG_SECRET_KEY: u8 = 5;
+const TAG_PUBLIC_KEY: u8 = 6;
+const TAG_SECRET_SUBKEY: u8 = 7;
+const TAG_COMPRESSED: u8 = 8;
+const TAG_LITERAL: u8 = 11;
+const TAG_PUBLIC_SUBKEY: u8 = 14;
+const TAG_SEIPD: u8 = 18;
+const TAG_AEAD: u8 = 20;
+
+// 公钥算法
+const ALGO_RSA: u8 = 1;
+const ALGO_RSA_ENCRYPT: u8 = 2;
+const ALGO_RSA_SIGN: u8 = 3;
+const ALGO_ELGAMAL: u8 = 16;
+const ALGO_DSA: u8 = 17;
+const ALGO_ECDH: u8 = 18;
+const ALGO_ECDSA: u8 = 19;
+const ALGO_EDDSA: u8 = 22;
+
+// 对称算法
+const SYM_AES128: u8 = 7;
+const SYM_AES192: u8 = 8;
+const SYM_AES256: u8 = 9;
+
+// 哈希算法
+const HASH_SHA1: u8 = 2;
+const HASH_SHA256: u8 = 8;
+const HASH_SHA384: u8 = 9;
+const HASH_SHA512: u8 = 10;
+
+// 压缩算法
+const COMPRESS_NONE: u8 = 0;
+const COMPRESS_ZIP: u8 = 1;
+const COMPRESS_ZLIB: u8 = 2;
+
+// 密钥标志：加密通信、加密存储
+const FLAG_ENCRYPT: u8 = 0x04 | 0x08;
+
+// Curve25519 的 OID 1.3.6.1.4.1.3029.1.5.1
+const OID_CURVE25519: &[u8] = &[0x2B, 0x06, 0x01, 0x04, 0x01, 0x97, 0x55, 0x01, 0x05, 0x01];
+
+// 加密公钥 keys，每个为 ASCII armor 或二进制格式的公钥，可以包含多个证书
+pub fn encrypt(data: &[u8], keys: &[String]) -> crate::Result<Vec<u8>> {
+    let mut recipients = Vec::new();
+    for key in keys {
+        let packets = packets(&dearmor(key.as_bytes())?)?;
+        recipients.extend(encryption_keys(&packets)?);
+    }
+    if recipients.is_empty() {
+        return Err(err!(invalid_data("no public key")));
+    }
+
+    let mut session = [0u8; 32];
+    rand_bytes(&mut session).map_err(err!())?;
+    let mut message = vec![SYM_AES256];
+    message.extend_from_slice(&session);
+    message.extend_from_slice(&checksum(&session).to_be_bytes());
+
+    let mut output = Vec::new();
+    for key in &recipients {
+        let body = encrypt_session_key(key, &message)?;
+        write_packet(&mut output, TAG_PKESK, &body);
+    }
+
+    // 16 字节随机前缀，最后两个字节重复一次
+    let mut plaintext = vec![0u8; 16];
+    rand_bytes(&mut plaintext).map_err(err!())?;
+    plaintext.extend_from_within(14..16);
+    // 二进制格式，没有文件名，时间为 0
+    let mut literal = vec![b'b', 0, 0, 0, 0, 0];
+    literal.extend_from_slice(data);
+    write_packet(&mut plaintext, TAG_LITERAL, &literal);
+    // MDC 包
+    plaintext.extend_from_slice(&[0xD3, 0x14]);
+    let mdc = sha1(&plaintext);
+    plaintext.extend_from_slice(&mdc);
+
+    let mut body = vec![1];
+    let cipher = Cipher::aes_256_cfb128();
+    body.extend(sym_encrypt(cipher, &session, Some(&[0u8; 16]), &plaintext).map_err(err!())?);
+    write_packet(&mut output, TAG_SEIPD, &body);
+    Ok(armor(&output))
+}
+
+// 使用私钥 secret_key 解密，私钥密码错误返回 None
+pub fn decrypt(data: &[u8], secret_key: &[u8], passphrase: &str) -> crate::Result<Option<Vec<u8>>> {
+    let keys = packets(&dearmor(secret_key)?)?;
+    let keys: Vec<_> = keys
+        .iter()
+        .filter(|v| v.tag == TAG_SECRET_KEY || v.tag == TAG_SECRET_SUBKEY)
+        .filter_map(|v| parse_public(&v.body).ok())
+        .collect();
+    if keys.is_empty() {
+        return Err(err!(invalid_data("no secret key")));
+    }
+
+    let message = packets(&dearmor(data)?)?;
+    let aead = |v: &Packet| v.tag == TAG_AEAD || (v.tag == TAG_SEIPD && v.body.first() == Some(&2));
+    if message.iter().any(aead) {
+        return Err(err!(invalid_data("AEAD encrypted data is not supported")));
+    }
+    let mut session = None;
+    'outer: for packet in message.iter().filter(|v| v.tag == TAG_PKESK) {
+        let mut reader = Reader(&packet.body);
+        if reader.u8()? != 3 {
+            continue;
+        }
+        let key_id = reader.bytes(8)?;
+        reader.u8()?;
+        for (key, rest) in &keys {
+            // key id 全为 0 表示隐藏了接收者
+            if key_id != key.key_id() && key_id != [0u8; 8] {
+                continue;
+            }
+            let secret = match decrypt_secret(rest, passphrase)? {
+                Some(secret) => secret,
+                None => return Ok(None),
+            };
+            if let Some(key) = decrypt_session_key(key, &secret, reader.0)? {
+                session = Some(key);
+                break 'outer;
+            }
+        }
+    }
+    let (cipher, key) = session.ok_or_else(|| err!(invalid_data("no matching secret key")))?;
+
+    let packet = message
+        .iter()
+        .find(|v| v.tag == TAG_SEIPD)
+        .ok_or_else(|| err!(invalid_data("no encrypted data")))?;
+    if packet.body.first() != Some(&1) {
+        return Err(err!(invalid_data("unsupported encrypted data")));
+    }
+    let (cipher, _) = sym_cipher(cipher)?;
+    let plaintext =
+        sym_decrypt(cipher, &key, Some(&[0u8; 16]), &packet.body[1..]).map_err(err!())?;
+    if plaintext.len() < 18 + 22 || plaintext[14..16] != plaintext[16..18] {
+        return Err(err!(invalid_data("decryption failed")));
+    }
+    let (content, mdc) = plaintext.split_at(plaintext.len() - 20);
+    if content[content.len() - 2..] != [0xD3, 0x14] || sha1(content) != mdc {
+        return Err(err!(invalid_data("modification detected")));
+    }
+    literal(&content[18..content.len() - 2]).map(Some)
+}
+
+// 公钥
+struct PublicKey {
+    fingerprint: [u8; 20],
+    algorithm: u8,
+    material: Material,
+}
+
+impl PublicKey {
+    fn key_id(&self) -> &[u8] {
+        &self.fingerprint[12..]
+    }
+
+    fn can_encrypt(&self) -> bool {
+        match self.material {
+            Material::Rsa { .. } => self.algorithm != ALGO_RSA_SIGN,
+            Material::Ecdh { .. } => true,
+            Material::Other => false,
+        }
+    }
+}
+
+enum Material {
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+    // point 为 0x40 + 32 字节公钥，hash 和 cipher 为 KDF 参数
+    Ecdh {
+        point: Vec<u8>,
+        hash: u8,
+        cipher: u8,
+    },
+    // 不支持加密
+    Other,
+}
+
+// 解析 v4 公钥，私钥包中公钥之后是私钥数据，一起返回
+fn parse_public(body: &[u8]) -> crate::Result<(PublicKey, &[u8])> {
+    let mut reader = Reader(body);
+    if reader.u8()? != 4 {
+        return Err(err!(invalid_data("unsupported key version")));
+    }
+    reader.bytes(4)?;
+    let algorithm = reader.u8()?;
+    let material = match algorithm {
+        ALGO_RSA | ALGO_RSA_ENCRYPT | ALGO_RSA_SIGN => {
+            let n = reader.mpi()?.to_vec();
+            let e = reader.mpi()?.to_vec();
+            Material::Rsa { n, e }
+        }
+        ALGO_ECDH => {
+            let len = reader.u8()? as usize;
+            let oid = reader.bytes(len)?;
+            let point = reader.mpi()?.to_vec();
+            let len = reader.u8()? as usize;
+            let kdf = reader.bytes(len)?;
+            if oid == OID_CURVE25519 && point.len() == 33 && point[0] == 0x40 && kdf.len() == 3 {
+                Material::Ecdh {
+                    point,
+                    hash: kdf[1],
+                    cipher: kdf[2],
+                }
+            } else {
+                Material::Other
+            }
+        }
+        ALGO_ELGAMAL | ALGO_DSA => {
+            let count = if algorithm == ALGO_DSA { 4 } else { 3 };
+            for _ in 0..count {
+                reader.mpi()?;
+            }
+            Material::Other
+        }
+        ALGO_ECDSA | ALGO_EDDSA => {
+            let len = reader.u8()? as usize;
+            reader.bytes(len)?;
+            reader.mpi()?;
+            Material::Other
+        }
+        _ => return Err(err!(invalid_data("unsupported key algorithm"))),
+    };
+
+    let public = &body[..body.len() - reader.0.len()];
+    let mut hashed = vec![0x99];
+    hashed.extend_from_slice(&(public.len() as u16).to_be_bytes());
+    hashed.extend_from_slice(public);
+    let key = PublicKey {
+        fingerprint: sha1(&hashed),
+        algorithm,
+        material,
+    };
+    Ok((key, reader.0))
+}
+
+// 每个证书中用于加密的密钥，优先使用最新的子密钥
+fn encryption_keys(packets: &[Packet]) -> crate::Result<Vec<PublicKey>> {
+    // 每个证书的密钥和密钥标志，无法解析的密钥为 None
+    let mut certs: Vec<Vec<(Option<PublicKey>, Option<u8>)>> = Vec::new();
+    for packet in packets {
+        match packet.tag {
+            TAG_PUBLIC_KEY => {
+                let key = parse_public(&packet.body).ok().map(|v| v.0);
+                certs.push(vec![(key, None)]);
+            }
+            TAG_PUBLIC_SUBKEY => {
+                if let Some(cert) = certs.last_mut() {
+                    let key = parse_public(&packet.body).ok().map(|v| v.0);
+                    cert.push((key, None));
+                }
+            }
+            TAG_SIGNATURE => {
+                let key = certs.last_mut().and_then(|v| v.last_mut());
+                if let (Some(key), Some(flags)) = (key, key_flags(&packet.body)) {
+                    key.1 = Some(flags);
+                }
+            }
+            _ => {}
+        }
+    }
+    if certs.is_empty() {
+        return Err(err!(invalid_data("no public key")));
+    }
+
+    let mut list = Vec::with_capacity(certs.len());
+    for mut cert in certs {
+        let can_encrypt = |v: &(Option<PublicKey>, Option<u8>)| match v {
+            (Some(key), flags) => {
+                key.can_encrypt() && !matches!(flags, Some(v) if v & FLAG_ENCRYPT == 0)
+            }
+            _ => false,
+        };
+        let index = match cert.iter().skip(1).rposition(can_encrypt) {
+            Some(index) => index + 1,
+            None if can_encrypt(&cert[0]) => 0,
+            None => return Err(err!(invalid_data("no encryption key"))),
+        };
+        if let Some(key) = cert.swap_remove(index).0 {
+            list.push(key);
+        }
+    }
+    Ok(list)
+}
+
+// 自签名中的密钥标志
+fn key_flags(body: &[u8]) -> Option<u8> {
+    let mut reader = Reader(body);
+    if reader.u8().ok()? != 4 {
+        return None;
+    }
+    // 只看证书自签名、子密钥绑定签名和直接密钥签名
+    if !matches!(reader.u8().ok()?, 0x10..=0x13 | 0x18 | 0x1F) {
+        return None;
+    }
+    reader.bytes(2).ok()?;
+    let len = reader.u16().ok()? as usize;
+    let mut reader = Reader(reader.bytes(len).ok()?);
+    while !reader.0.is_empty() {
+        let first = reader.u8().ok()? as usize;
+        let len = match first {
+            0..=191 => first,
+            192..=254 => ((first - 192) << 8) + reader.u8().ok()? as usize + 192,
+            _ => reader.u32().ok()? as usize,
+        };
+        let data = reader.bytes(len).ok()?;
+        // 类型 27 为密钥标志
+        if let Some((27, flags)) = data.split_first().map(|(kind, v)| (kind & 0x7F, v)) {
+            return flags.first().copied();
+        }
+    }
+    None
+}
+
+// 加密会话密钥，返回 PKESK 包
+fn encrypt_session_key(key: &PublicKey, message: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut body = vec![3];
+    body.extend_from_slice(key.key_id());
+    body.push(key.algorithm);
+    match key.material {
+        Material::Rsa { ref n, ref e } => {
+            let n = BigNum::from_slice(n).map_err(err!())?;
+            let e = BigNum::from_slice(e).map_err(err!())?;
+            let rsa = Rsa::from_public_components(n, e).map_err(err!())?;
+            let mut output = vec![0u8; rsa.size() as usize];
+            let len = rsa
+                .public_encrypt(message, &mut output, Padding::PKCS1)
+                .map_err(err!())?;
+            write_mpi(&mut body, &output[..len]);
+        }
+        Material::Ecdh {
+            ref point,
+            hash,
+            cipher,
+        } => {
+            let ephemeral = PKey::generate_x25519().map_err(err!())?;
+            let shared = x25519(&ephemeral, &point[1..])?;
+            let kek = ecdh_kek(key, hash, cipher, &shared)?;
+
+            // PKCS#5 填充到 8 字节的倍数
+            let pad = 8 - message.len() % 8;
+            let mut message = message.to_vec();
+            message.resize(message.len() + pad, pad as u8);
+            let kek = AesKey::new_encrypt(&kek).map_err(|_| err!(invalid_data("invalid kek")))?;
+            let mut wrapped = vec![0u8; message.len() + 8];
+            wrap_key(&kek, None, &mut wrapped, &message)
+                .map_err(|_| err!(invalid_data("wrap key failed")))?;
+
+            let mut ephemeral_point = vec![0x40];
+            ephemeral_point.extend(ephemeral.raw_public_key().map_err(err!())?);
+            write_mpi(&mut body, &ephemeral_point);
+            body.push(wrapped.len() as u8);
+            body.extend_from_slice(&wrapped);
+        }
+        Material::Other => return Err(err!(invalid_data("unsupported key algorithm"))),
+    }
+    Ok(body)
+}
+
+// 解密会话密钥，返回对称算法和密钥，解密失败返回 None
+fn decrypt_session_key(
+    key: &PublicKey,
+    secret: &[u8],
+    data: &[u8],
+) -> crate::Result<Option<(u8, Vec<u8>)>> {
+    let mut reader = Reader(data);
+    let mut secret = Reader(secret);
+    let message = match key.material {
+        Material::Rsa { ref n, ref e } => {
+            let ciphertext = reader.mpi()?;
+            let rsa = rsa_private_key(n, e, &mut secret)?;
+            let size = rsa.size() as usize;
+            let mut input = vec![0u8; size.saturating_sub(ciphertext.len())];
+            input.extend_from_slice(ciphertext);
+            let mut output = vec![0u8; size];
+            match rsa.private_decrypt(&input, &mut output, Padding::PKCS1) {
+                Ok(len) => output[..len].to_vec(),
+                Err(_) => return Ok(None),
+            }
+        }
+        Material::Ecdh { hash, cipher, .. } => {
+            let ephemeral = reader.mpi()?;
+            let len = reader.u8()? as usize;
+            let wrapped = reader.bytes(len)?;
+            if ephemeral.len() != 33 || wrapped.len() < 16 {
+                return Ok(None);
+            }
+
+            // 私钥是大端存储的，X25519 使用小端
+            let scalar = secret.mpi()?;
+            if scalar.len() > 32 {
+                return Ok(None);
+            }
+            let mut native = [0u8; 32];
+            native[32 - scalar.len()..].copy_from_slice(scalar);
+            native.reverse();
+            let private = PKey::private_key_from_raw_bytes(&native, Id::X25519).map_err(err!())?;
+            let shared = x25519(&private, &ephemeral[1..])?;
+            let kek = ecdh_kek(key, hash, cipher, &shared)?;
+
+            let kek = AesKey::new_decrypt(&kek).map_err(|_| err!(invalid_data("invalid kek")))?;
+            let mut message = vec![0u8; wrapped.len() - 8];
+            if unwrap_key(&kek, None, &mut message, wrapped).is_err() {
+                return Ok(None);
+            }
+            let pad = *message.last().unwrap_or(&0) as usize;
+            if pad == 0 || pad > 8 || pad > message.len() {
+                return Ok(None);
+            }
+            message.truncate(message.len() - pad);
+            message
+        }
+        Material::Other => return Ok(None),
+    };
+
+    // 对称算法 + 密钥 + 2 字节校验和
+    if message.len() < 3 {
+        return Ok(None);
+    }
+    let (cipher, rest) = (message[0], &message[1..]);
+    let (session, sum) = rest.split_at(rest.len() - 2);
+    match sym_cipher(cipher) {
+        Ok((_, len)) if len == session.len() && checksum(session).to_be_bytes() == sum => {
+            Ok(Some((cipher, session.to_vec())))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn rsa_private_key(
+    n: &[u8],
+    e: &[u8],
+    secret: &mut Reader,
+) -> crate::Result<Rsa<openssl::pkey::Private>> {
+    let d = BigNum::from_slice(secret.mpi()?).map_err(err!())?;
+    let p = BigNum::from_slice(secret.mpi()?).map_err(err!())?;
+    let q = BigNum::from_slice(secret.mpi()?).map_err(err!())?;
+
+    let mut ctx = BigNumContext::new().map_err(err!())?;
+    let one = BigNum::from_u32(1).map_err(err!())?;
+    let mut p1 = BigNum::new().map_err(err!())?;
+    p1.checked_sub(&p, &one).map_err(err!())?;
+    let mut q1 = BigNum::new().map_err(err!())?;
+    q1.checked_sub(&q, &one).map_err(err!())?;
+    let mut dmp1 = BigNum::new().map_err(err!())?;
+    dmp1.checked_rem(&d, &p1, &mut ctx).map_err(err!())?;
+    let mut dmq1 = BigNum::new().map_err(err!())?;
+    dmq1.checked_rem(&d, &q1, &mut ctx).map_err(err!())?;
+    let mut iqmp = BigNum::new().map_err(err!())?;
+    iqmp.mod_inverse(&q, &p, &mut ctx).map_err(err!())?;
+
+    let n = BigNum::from_slice(n).map_err(err!())?;
+    let e = BigNum::from_slice(e).map_err(err!())?;
+    Rsa::from_private_components(n, e, d, p, q, dmp1, dmq1, iqmp).map_err(err!())
+}
+
+fn x25519<T: HasPrivate>(private: &PKeyRef<T>, public: &[u8]) -> crate::Result<Vec<u8>> {
+    let public = PKey::public_key_from_raw_bytes(public, Id::X25519).map_err(err!())?;
+    let mut deriver = Deriver::new(private).map_err(err!())?;
+    deriver.set_peer(&public).map_err(err!())?;
+    deriver.derive_to_vec().map_err(err!())
+}
+
+// RFC 6637 的 KDF
+fn ecdh_kek(key: &PublicKey, hash: u8, cipher: u8, shared: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut param = vec![OID_CURVE25519.len() as u8];
+    param.extend_from_slice(OID_CURVE25519);
+    param.extend_from_slice(&[ALGO_ECDH, 3, 1, hash, cipher]);
+    param.extend_from_slice(b"Anonymous Sender    ");
+    param.extend_from_slice(&key.fingerprint);
+
+    let mut hasher = Hasher::new(digest(hash)?).map_err(err!())?;
+    hasher.update(&[0, 0, 0, 1]).map_err(err!())?;
+    hasher.update(shared).map_err(err!())?;
+    hasher.update(&param).map_err(err!())?;
+    let output = hasher.finish().map_err(err!())?;
+    let (_, len) = sym_cipher(cipher)?;
+    if output.len() < len {
+        return Err(err!(invalid_data("kdf hash too short")));
+    }
+    Ok(output[..len].to_vec())
+}
+
+// 解密私钥数据，密码错误返回 None
+fn decrypt_secret(data: &[u8], passphrase: &str) -> crate::Result<Option<Vec<u8>>> {
+    let mut reader = Reader(data);
+    let usage = reader.u8()?;
+    match usage {
+        // 未加密，最后是 2 字节校验和
+        0 if reader.0.len() >= 2 => Ok(Some(reader.0[..reader.0.len() - 2].to_vec())),
+        // 254 最后是 SHA1，255 最后是 2 字节校验和
+        254 | 255 => {
+            let cipher = reader.u8()?;
+            let kind = reader.u8()?;
+            let hash = reader.u8()?;
+            let (salt, count) = match kind {
+                0 => (&[][..], 0),
+                1 => (reader.bytes(8)?, 0),
+                3 => {
+                    let salt = reader.bytes(8)?;
+                    let c = reader.u8()? as usize;
+                    (salt, (16 + (c & 15)) << ((c >> 4) + 6))
+                }
+                _ => return Err(err!(invalid_data("unsupported s2k"))),
+            };
+            let (ssl_cipher, len) = sym_cipher(cipher)?;
+            let iv = reader.bytes(16)?;
+            let key = s2k(passphrase, hash, salt, count, len)?;
+            let data = sym_decrypt(ssl_cipher, &key, Some(iv), reader.0).map_err(err!())?;
+
+            let check_len = if usage == 254 { 20 } else { 2 };
+            if data.len() < check_len {
+                return Ok(None);
+            }
+            let (secret, check) = data.split_at(data.len() - check_len);
+            let valid = if usage == 254 {
+                sha1(secret) == check
+            } else {
+                checksum(secret).to_be_bytes() == check
+            };
+            Ok(if valid { Some(secret.to_vec()) } else { None })
+        }
+        253 => Err(err!(invalid_data(
+            "AEAD protected secret key is not supported"
+        ))),
+        _ => Err(err!(invalid_data("unsupported secret key protection"))),
+    }
+}
+
+// 迭代加盐的 S2K
+fn s2k(
+    passphrase: &str,
+    hash: u8,
+    salt: &[u8],
+    count: usize,
+    len: usize,
+) -> crate::Result<Vec<u8>> {
+    let mut input = salt.to_vec();
+    input.extend_from_slice(passphrase.as_bytes());
+    let count = count.max(input.len());
+
+    let mut key = Vec::with_capacity(len);
+    let mut preload = 0;
+    while key.len() < len {
+        // 哈希输出不够长时，使用多个哈希，依次在前面填充 0
+        let mut hasher = Hasher::new(digest(hash)?).map_err(err!())?;
+        hasher.update(&vec![0u8; preload]).map_err(err!())?;
+        let mut remain = if input.is_empty() { 0 } else { count };
+        while remain > 0 {
+            let n = remain.min(input.len());
+            hasher.update(&input[..n]).map_err(err!())?;
+            remain -= n;
+        }
+        key.extend_from_slice(&hasher.finish().map_err(err!())?);
+        preload += 1;
+    }
+    key.truncate(len);
+    Ok(key)
+}
+
+// 取出字面数据包的内容，跳过签名等其他包
+fn literal(data: &[u8]) -> crate::Result<Vec<u8>> {
+    for packet in packets(data)? {
+        match packet.tag {
+            TAG_COMPRESSED => {
+                let (algorithm, body) = packet
+                    .body
+                    .split_first()
+                    .ok_or_else(|| err!(invalid_data("invalid compressed data")))?;
+                let mut output = Vec::new();
+                match *algorithm {
+                    COMPRESS_NONE => output.extend_from_slice(body),
+                    COMPRESS_ZIP => {
+                        DeflateDecoder::new(body)
+                            .read_to_end(&mut output)
+                            .map_err(err!())?;
+                    }
+                    COMPRESS_ZLIB => {
+                        ZlibDecoder::new(body)
+                            .read_to_end(&mut output)
+                            .map_err(err!())?;
+                    }
+                    _ => return Err(err!(invalid_data("unsupported compression"))),
+                }
+                return literal(&output);
+            }
+            TAG_LITERAL => {
+                let mut reader = Reader(&packet.body);
+                reader.u8()?;
+                let len = reader.u8()? as usize;
+                reader.bytes(len + 4)?;
+                return Ok(reader.0.to_vec());
+            }
+            _ => {}
+        }
+    }
+    Err(err!(invalid_data("no literal data")))
+}
+
+fn sym_cipher(algorithm: u8) -> crate::Result<(Cipher, usize)> {
+    match algorithm {
+        SYM_AES128 => Ok((Cipher::aes_128_cfb128(), 16)),
+        SYM_AES192 => Ok((Cipher::aes_192_cfb128(), 24)),
+        SYM_AES256 => Ok((Cipher::aes_256_cfb128(), 32)),
+        _ => Err(err!(invalid_data("unsupported cipher"))),
+    }
+}
+
+fn digest(algorithm: u8) -> crate::Result<MessageDigest> {
+    match algorithm {
+        HASH_SHA1 => Ok(MessageDigest::sha1()),
+        HASH_SHA256 => Ok(MessageDigest::sha256()),
+        HASH_SHA384 => Ok(MessageDigest::sha384()),
+        HASH_SHA512 => Ok(MessageDigest::sha512()),
+        _ => Err(err!(invalid_data("unsupported hash"))),
+    }
+}
+
+// 所有字节之和
+fn checksum(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |sum, v| sum.wrapping_add(*v as u16))
+}
+
+struct Packet {
+    tag: u8,
+    body: Vec<u8>,
+}
+
+// 解析包，支持新旧两种格式和分段长度
+fn packets(data: &[u8]) -> crate::Result<Vec<Packet>> {
+    let mut reader = Reader(data);
+    let mut list = Vec::new();
+    while !reader.0.is_empty() {
+        let header = reader.u8()?;
+        if header & 0x80 == 0 {
+            return Err(err!(invalid_data("invalid packet")));
+        }
+        let packet = if header & 0x40 != 0 {
+            let mut body = Vec::new();
+            loop {
+                let first = reader.u8()? as usize;
+                let len = match first {
+                    0..=191 => first,
+                    192..=223 => ((first - 192) << 8) + reader.u8()? as usize + 192,
+                    255 => reader.u32()? as usize,
+                    // 分段长度，后面还有数据
+                    _ => {
+                        body.extend_from_slice(reader.bytes(1 << (first & 0x1F))?);
+                        continue;
+                    }
+                };
+                body.extend_from_slice(reader.bytes(len)?);
+                break;
+            }
+            Packet {
+                tag: header & 0x3F,
+                body,
+            }
+        } else {
+            let len = match header & 3 {
+                0 => reader.u8()? as usize,
+                1 => reader.u16()? as usize,
+                2 => reader.u32()? as usize,
+                // 长度不确定，直到数据结束
+                _ => reader.0.len(),
+            };
+            Packet {
+                tag: (header >> 2) & 0x0F,
+                body: reader.bytes(len)?.to_vec(),
+            }
+        };
+        list.push(packet);
+    }
+    Ok(list)
+}
+
+// 使用新格式写入包
+fn write_packet(output: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    output.push(0xC0 | tag);
+    match body.len() {
+        len if len < 192 => output.push(len as u8),
+        len if len < 8384 => {
+            let len = len - 192;
+            output.push((len >> 8) as u8 + 192);
+            output.push(len as u8);
+        }
+        len => {
+            output.push(255);
+            output.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+    output.extend_from_slice(body);
+}
+
+fn write_mpi(output: &mut Vec<u8>, value: &[u8]) {
+    let start = value.iter().position(|v| *v != 0).unwrap_or(value.len());
+    let value = &value[start..];
+    let bits = match value.first() {
+        Some(v) => value.len() * 8 - v.leading_zeros() as usize,
+        None => 0,
+    };
+    output.extend_from_slice(&(bits as u16).to_be_bytes());
+    output.extend_from_slice(value);
+}
+
+// 去掉 ASCII armor，不是 armor 格式的原样返回
+fn dearmor(data: &[u8]) -> crate::Result<Vec<u8>> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN PGP ") => text,
+        _ => return Ok(data.to_vec()),
+    };
+
+    // 0: 在 armor 外，1: 头部，2: 数据
+    let mut state = 0;
+    let mut body = String::new();
+    let mut output = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with("-----BEGIN PGP ") {
+            state = 1;
+        } else if line.starts_with("-----END PGP ") {
+            output.extend(base64::decode(&body).map_err(err!())?);
+            body.clear();
+            state = 0;
+        } else if state == 1 && line.is_empty() {
+            state = 2;
+        } else if state == 1 && !line.contains(": ") {
+            // 没有空行分隔头部
+            body.push_str(line);
+            state = 2;
+        } else if state == 2 && !(line.len() == 5 && line.starts_with('=')) {
+            // 跳过校验和
+            body.push_str(line);
+        }
+    }
+    Ok(output)
+}
+
+fn armor(data: &[u8]) -> Vec<u8> {
+    let mut output = b"-----BEGIN PGP MESSAGE-----\n\n".to_vec();
+    for line in base64::encode(data).as_bytes().chunks(64) {
+        output.extend_from_slice(line);
+        output.push(b'\n');
+    }
+    output.push(b'=');
+    output.extend_from_slice(base64::encode(&crc24(data).to_be_bytes()[1..]).as_bytes());
+    output.extend_from_slice(b"\n-----END PGP MESSAGE-----\n");
+    output
+}
+
+fn crc24(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xB704CE;
+    for v in data {
+        crc ^= (*v as u32) << 16;
+        for _ in 0..8 {
+            crc <<= 1;
+            if crc & 0x1000000 != 0 {
+                crc ^= 0x1864CFB;
+            }
+        }
+    }
+    crc & 0xFFFFFF
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// 大端读取
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> crate::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(err!(invalid_data("unexpected end of data")));
+        }
+        let (data, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(data)
+    }
+
+    fn u8(&mut self) -> crate::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> crate::Result<u16> {
+        let mut v = [0u8; 2];
+        v.copy_from_slice(self.bytes(2)?);
+        Ok(u16::from_be_bytes(v))
+    }
+
+    fn u32(&mut self) -> crate::Result<u32> {
+        let mut v = [0u8; 4];
+        v.copy_from_slice(self.bytes(4)?);
+        Ok(u32::from_be_bytes(v))
+    }
+
+    // 2 字节位数 + 数据
+    fn mpi(&mut self) -> crate::Result<&'a [u8]> {
+        let bits = self.u16()? as usize;
+        self.bytes(bits.div_ceil(8))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // tests/fixtures/pgp 由 GnuPG 2.2 生成，见 generate.sh
+    const RSA_PUBLIC: &str = include_str!("../../tests/fixtures/pgp/rsa.pub.asc");
+    const RSA_SECRET: &[u8] = include_bytes!("../../tests/fixtures/pgp/rsa.sec.asc");
+    const ECC_PUBLIC: &str = include_str!("../../tests/fixtures/pgp/ecc.pub.asc");
+    const ECC_SECRET: &[u8] = include_bytes!("../../tests/fixtures/pgp/ecc.sec.asc");
+    const MESSAGE: &[u8] = br#"[["GitHub","gh-secret"]]"#;
+
+    #[test]
+    fn decrypt_gpg_messages() {
+        let rsa = include_bytes!("../../tests/fixtures/pgp/rsa.msg.asc");
+        let ecc = include_bytes!("../../tests/fixtures/pgp/ecc.msg.gpg");
+        let both = include_bytes!("../../tests/fixtures/pgp/both.msg.asc");
+        for (message, secret) in [
+            (&rsa[..], RSA_SECRET),
+            (&ecc[..], ECC_SECRET),
+            (&both[..], RSA_SECRET),
+            (&both[..], ECC_SECRET),
+        ] {
+            let data = decrypt(message, secret, "key-password").unwrap();
+            assert_eq!(data.as_deref(), Some(MESSAGE));
+            assert!(decrypt(message, secret, "wrong").unwrap().is_none());
+        }
+        assert!(decrypt(rsa, ECC_SECRET, "key-password").is_err());
+    }
+
+    #[test]
+    fn encrypt_to_gpg_keys() {
+        let keys = [RSA_PUBLIC.to_string(), ECC_PUBLIC.to_string()];
+        let message = encrypt(MESSAGE, &keys).unwrap();
+        for secret in [RSA_SECRET, ECC_SECRET] {
+            let data = decrypt(&message, secret, "key-password").unwrap();
+            assert_eq!(data.as_deref(), Some(MESSAGE));
+        }
+
+        // 使用加密子密钥，而不是只能签名的主密钥
+        let sent = packets(&dearmor(&message).unwrap()).unwrap();
+        let public = packets(&dearmor(ECC_PUBLIC.as_bytes()).unwrap()).unwrap();
+        let subkey = encryption_keys(&public).unwrap().remove(0);
+        assert_eq!(subkey.algorithm, ALGO_ECDH);
+        assert_eq!(&sent[1].body[1..9], subkey.key_id());
+    }
+
+    #[test]
+    fn reject_aead() {
+        let mut message = Vec::new();
+        let rsa =
+            packets(&dearmor(include_bytes!("../../tests/fixtures/pgp/rsa.msg.asc")).unwrap())
+                .unwrap();
+        write_packet(&mut message, TAG_PKESK, &rsa[0].body);
+        write_packet(&mut message, TAG_SEIPD, &[2, SYM_AES256, 2, 16]);
+        assert!(decrypt(&message, RSA_SECRET, "key-password").is_err());
+
+        let mut message = Vec::new();
+        write_packet(&mut message, TAG_PKESK, &rsa[0].body);
+        write_packet(&mut message, TAG_AEAD, &[1, SYM_AES256, 2, 16]);
+        assert!(decrypt(&message, RSA_SECRET, "key-password").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+// age X25519 接收者，输出 ASCII armor 格式，读取时也支持二进制格式
+
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use age::armor::{ArmoredReader, ArmoredWriter, Format};
+use age::x25519::Recipient;
+use age::{Decryptor, Encryptor, Identity, IdentityFile};
+
+// 加密给 recipients，每个接收者为 `age1` 开头的公钥
+pub fn encrypt(data: &[u8], recipients: &[String]) -> crate::Result<Vec<u8>> {
+    let mut list = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        let recipient = Recipient::from_str(recipient.trim()).map_err(|e| err!(invalid_data(e)))?;
+        list.push(recipient);
+    }
+    let encryptor = Encryptor::with_recipients(list.iter().map(|v| v as &dyn age::Recipient))
+        .map_err(err!())?;
+
+    let mut output = Vec::new();
+    let armor = ArmoredWriter::wrap_output(&mut output, Format::AsciiArmor).map_err(err!())?;
+    let mut writer = encryptor.wrap_output(armor).map_err(err!())?;
+    writer.write_all(data).map_err(err!())?;
+    writer
+        .finish()
+        .and_then(|armor| armor.finish())
+        .map_err(err!())?;
+    Ok(output)
+}
+
+// 使用身份文件解密
+pub fn decrypt(data: &[u8], identity: &[u8]) -> crate::Result<Vec<u8>> {
+    let identities = IdentityFile::from_buffer(identity)
+        .map_err(err!())?
+        .into_identities()
+        .map_err(err!())?;
+    let decryptor = Decryptor::new(ArmoredReader::new(data)).map_err(err!())?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|v| v.as_ref() as &dyn Identity))
+        .map_err(err!())?;
+    let mut output = Vec::new();
+    reader.read_to_end(&mut output).map_err(err!())?;
+    Ok(output)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
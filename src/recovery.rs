@@ -0,0 +1,251 @@
+// 忘记主密码时恢复保险库
+//
+// 恢复密钥是 32 字节随机数，用它加密保险库密钥保存在 conf 的 recovery，可以选择把恢复密钥
+// 分成 Shamir 分片，任意 threshold 份分片可以还原恢复密钥。恢复密钥同时使用保险库密钥加密保存在
+// recovery_secret，保险库密钥变化时 (加入其他设备的同步) 用它重新加密。
+//
+// 恢复密钥和分片使用 base32 编码，每 4 个字符用 - 分隔，方便打印和抄写，末尾有 2 字节校验和。
+
+use openssl::rand::rand_bytes;
+use openssl::sha::sha256;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{key_decrypt, key_encrypt};
+use crate::db::{get_conf, set_conf};
+
+pub mod shamir;
+
+// 使用恢复密钥加密的保险库密钥
+const RECOVERY: &str = "recovery";
+
+// 使用保险库密钥加密的恢复密钥
+const SECRET: &str = "recovery_secret";
+
+const KEY_LEN: usize = 32;
+
+const TAG_KEY: u8 = 1;
+const TAG_SHARE: u8 = 2;
+
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+// 生成的恢复密钥和分片
+#[derive(Serialize)]
+pub struct Kit {
+    pub key: String,
+    pub shares: Vec<String>,
+}
+
+// 用于恢复的恢复密钥或者分片
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Secret {
+    Key(String),
+    Shares(Vec<String>),
+}
+
+// 分片数量，任意 threshold 份可以恢复
+#[derive(Deserialize)]
+pub struct Shares {
+    pub threshold: u8,
+    pub total: u8,
+}
+
+impl Shares {
+    pub fn valid(&self) -> bool {
+        2 <= self.threshold && self.threshold <= self.total
+    }
+}
+
+// 生成新的恢复密钥，替换已有的，之前的恢复密钥和分片失效
+pub fn create(conn: &Connection, key: &[u8], shares: Option<&Shares>) -> crate::Result<Kit> {
+    let mut secret = [0u8; KEY_LEN];
+    rand_bytes(&mut secret).map_err(err!())?;
+    save(conn, key, &secret)?;
+
+    let mut kit = Kit {
+        key: encode(TAG_KEY, &secret),
+        shares: Vec::new(),
+    };
+    if let Some(shares) = shares {
+        let id = &sha256(&secret)[..2];
+        for (x, y) in shamir::split(&secret, shares.threshold, shares.total)? {
+            let mut data = id.to_vec();
+            data.extend_from_slice(&[shares.threshold, x]);
+            data.extend_from_slice(&y);
+            kit.shares.push(encode(TAG_SHARE, &data));
+        }
+    }
+    Ok(kit)
+}
+
+// 删除恢复密钥
+pub fn remove(conn: &Connection) -> crate::Result<()> {
+    const SQL: &str = "DELETE FROM conf WHERE key IN (?, ?)";
+    conn.execute(SQL, [RECOVERY, SECRET]).map_err(err!())?;
+    Ok(())
+}
+
+// 是否设置了恢复密钥
+pub fn is_set(conn: &Connection) -> crate::Result<bool> {
+    Ok(get_conf(conn, RECOVERY)?.is_some())
+}
+
+// 使用恢复密钥或分片解密保险库密钥，格式错误、分片不够或者不匹配时返回 None
+pub fn recover(conn: &Connection, secret: &Secret) -> crate::Result<Option<Vec<u8>>> {
+    let secret = match secret {
+        Secret::Key(key) => match decode(key) {
+            Some((TAG_KEY, data)) if data.len() == KEY_LEN => data,
+            _ => return Ok(None),
+        },
+        Secret::Shares(shares) => match combine(shares) {
+            Some(v) => v,
+            None => return Ok(None),
+        },
+    };
+    let wrapped = match get_conf(conn, RECOVERY)? {
+        Some(v) => base64::decode(v).map_err(err!())?,
+        None => return Ok(None),
+    };
+    key_decrypt(secret, wrapped).map_err(err!())
+}
+
+// 保险库密钥变化时重新加密
+pub fn reencrypt(conn: &Connection, key: &[u8], new_key: &[u8]) -> crate::Result<()> {
+    let secret = match get_conf(conn, SECRET)? {
+        Some(v) => base64::decode(v).map_err(err!())?,
+        None => return Ok(()),
+    };
+    match key_decrypt(key, secret).map_err(err!())? {
+        Some(secret) => save(conn, new_key, &secret),
+        None => Err(err!(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "wrong recovery secret"
+        ))),
+    }
+}
+
+fn save(conn: &Connection, key: &[u8], secret: &[u8]) -> crate::Result<()> {
+    let wrapped = key_encrypt(secret, key).map_err(err!())?;
+    set_conf(conn, RECOVERY, &base64::encode(wrapped))?;
+    let secret = key_encrypt(key, secret).map_err(err!())?;
+    set_conf(conn, SECRET, &base64::encode(secret))
+}
+
+// 合并分片，分片属于同一组、数量足够时返回恢复密钥
+fn combine(shares: &[String]) -> Option<Vec<u8>> {
+    let mut id = None;
+    let mut threshold = 0;
+    let mut points: Vec<(u8, Vec<u8>)> = Vec::new();
+    for share in shares {
+        let data = match decode(share)? {
+            (TAG_SHARE, data) if data.len() == KEY_LEN + 4 => data,
+            _ => return None,
+        };
+        if id.get_or_insert(data[..2].to_vec()) != &data[..2] {
+            return None;
+        }
+        threshold = data[2];
+        let x = data[3];
+        if !points.iter().any(|v| v.0 == x) {
+            points.push((x, data[4..].to_vec()));
+        }
+    }
+    if points.len() < threshold as usize || points.is_empty() {
+        return None;
+    }
+    let secret = shamir::combine(&points);
+    (id.as_deref() == Some(&sha256(&secret)[..2])).then_some(secret)
+}
+
+// 编码为 base32，包含类型和校验和
+fn encode(tag: u8, data: &[u8]) -> String {
+    let mut bytes = vec![tag];
+    bytes.extend_from_slice(data);
+    let checksum = sha256(&bytes);
+    bytes.extend_from_slice(&checksum[..2]);
+
+    let mut text = String::new();
+    for (i, chunk) in bytes.chunks(5).enumerate() {
+        let mut buf = [0u8; 5];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let value = buf.iter().fold(0u64, |acc, &b| acc << 8 | b as u64);
+        let chars = (chunk.len() * 8).div_ceil(5);
+        for j in 0..chars {
+            let index = (value >> (35 - j * 5)) & 0x1f;
+            if (i * 8 + j) % 4 == 0 && i + j > 0 {
+                text.push('-');
+            }
+            text.push(ALPHABET[index as usize] as char);
+        }
+    }
+    text
+}
+
+// 解码 base32，忽略大小写、空白和 -，校验和错误时返回 None
+fn decode(text: &str) -> Option<(u8, Vec<u8>)> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in text.chars() {
+        if c == '-' || c.is_whitespace() {
+            continue;
+        }
+        let c = c.to_ascii_uppercase() as u8;
+        let value = ALPHABET.iter().position(|&v| v == c)? as u32;
+        buffer = buffer << 5 | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if bytes.len() < 3 {
+        return None;
+    }
+    let (data, checksum) = bytes.split_at(bytes.len() - 2);
+    if sha256(data)[..2] != *checksum {
+        return None;
+    }
+    Some((data[0], data[1..].to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recover_vault_key() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("create table conf (key text not null primary key, value text)")
+            .unwrap();
+        let key = [7u8; KEY_LEN];
+        let shares = Shares {
+            threshold: 2,
+            total: 3,
+        };
+        let kit = create(&conn, &key, Some(&shares)).unwrap();
+        assert_eq!(kit.shares.len(), 3);
+
+        let secret = Secret::Key(kit.key.to_lowercase().replace('-', " "));
+        assert_eq!(recover(&conn, &secret).unwrap().unwrap(), key);
+        let secret = Secret::Shares(vec![kit.shares[2].clone(), kit.shares[0].clone()]);
+        assert_eq!(recover(&conn, &secret).unwrap().unwrap(), key);
+
+        // 分片不够、重复、抄错
+        let secret = Secret::Shares(vec![kit.shares[1].clone(), kit.shares[1].clone()]);
+        assert!(recover(&conn, &secret).unwrap().is_none());
+        let mut typo = kit.key.clone();
+        typo.replace_range(..1, if typo.starts_with('A') { "B" } else { "A" });
+        assert!(recover(&conn, &Secret::Key(typo)).unwrap().is_none());
+
+        // 保险库密钥变化后仍然可以恢复，重新生成后旧的恢复密钥失效
+        let new_key = [8u8; KEY_LEN];
+        reencrypt(&conn, &key, &new_key).unwrap();
+        let secret = Secret::Key(kit.key.clone());
+        assert_eq!(recover(&conn, &secret).unwrap().unwrap(), new_key);
+        create(&conn, &new_key, None).unwrap();
+        assert!(recover(&conn, &secret).unwrap().is_none());
+    }
+}
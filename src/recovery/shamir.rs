@@ -0,0 +1,96 @@
+// Shamir 秘密分享，在 GF(256) 上按字节分享，x 取 1..=total
+
+use openssl::rand::rand_bytes;
+
+// 把 secret 分成 total 份，任意 threshold 份可以恢复，返回 (x, y)
+pub fn split(secret: &[u8], threshold: u8, total: u8) -> crate::Result<Vec<(u8, Vec<u8>)>> {
+    let mut shares: Vec<_> = (1..=total)
+        .map(|x| (x, Vec::with_capacity(secret.len())))
+        .collect();
+    let mut coefficients = vec![0u8; threshold as usize - 1];
+    for &byte in secret {
+        rand_bytes(&mut coefficients).map_err(err!())?;
+        for (x, y) in shares.iter_mut() {
+            // 秦九韶算法计算多项式的值，常数项是 secret
+            let value = coefficients
+                .iter()
+                .rev()
+                .fold(0, |acc, &c| mul(acc, *x) ^ c);
+            y.push(mul(value, *x) ^ byte);
+        }
+    }
+    Ok(shares)
+}
+
+// 拉格朗日插值计算 x=0 的值，x 不能重复，所有 y 长度相同
+pub fn combine(shares: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let len = shares.first().map_or(0, |v| v.1.len());
+    let mut secret = vec![0u8; len];
+    for (i, (xi, yi)) in shares.iter().enumerate() {
+        let mut basis = 1;
+        for (j, (xj, _)) in shares.iter().enumerate() {
+            if i != j {
+                basis = mul(basis, div(*xj, xj ^ xi));
+            }
+        }
+        for (s, y) in secret.iter_mut().zip(yi) {
+            *s ^= mul(*y, basis);
+        }
+    }
+    secret
+}
+
+// GF(256) 乘法，模 x^8 + x^4 + x^3 + x + 1
+fn mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+// GF(256) 除法，a * b^254
+fn div(a: u8, b: u8) -> u8 {
+    let mut inverse = 1;
+    for _ in 0..254 {
+        inverse = mul(inverse, b);
+    }
+    mul(a, inverse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_and_combine() {
+        let secret: Vec<u8> = (0..32).collect();
+        let shares = split(&secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        // 任意 3 份都能恢复
+        assert_eq!(combine(&shares[..3]), secret);
+        assert_eq!(
+            combine(&[shares[4].clone(), shares[0].clone(), shares[2].clone()]),
+            secret
+        );
+        assert_eq!(combine(&shares), secret);
+        // 2 份不能恢复
+        assert_ne!(combine(&shares[1..3]), secret);
+    }
+
+    #[test]
+    fn field() {
+        for a in 1..=255u8 {
+            assert_eq!(mul(div(1, a), a), 1);
+        }
+        assert_eq!(mul(0x57, 0x83), 0xc1);
+    }
+}
@@ -1,38 +1,49 @@
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::fmt::{Display, Formatter};
-use std::fs::create_dir;
+use std::fs::{create_dir, create_dir_all, rename};
 use std::future::Future;
-use std::io;
+use std::io::{self, Cursor};
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::sync::{Arc, Once, RwLock, RwLockReadGuard};
-use std::task::{Context, Poll};
+use std::ptr::addr_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Once, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::task::{ready, Context, Poll};
 use std::time::Duration;
 
 use log::{error, info};
 use rusqlite::{Connection, OpenFlags};
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 use tokio::sync::oneshot::{channel, Sender};
+use tokio::task::spawn_blocking;
+use tokio::time::interval;
 use tokio_native_tls::native_tls::{Identity, Protocol};
 use tokio_native_tls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{Control, Data, OpCode};
+use tokio_tungstenite::tungstenite::protocol::frame::{Frame, FrameHeader};
 use ws_jsonrpc::handler::Handler;
 use ws_jsonrpc::ws::request::Request;
 use ws_jsonrpc::ws::response::{Response, NOT_FOUND, OK};
 use ws_jsonrpc::ws::websocket::WebSocket;
 
-use crate::db::setup;
+use crate::backup;
+use crate::db::{is_tracked, setup};
+use crate::duress;
+use crate::notify::{self, Changes};
 use crate::service::methods;
+use crate::sync::{self, folder};
 
 static mut SERVER: Option<RwLock<Server>> = None;
 
 fn server() -> &'static RwLock<Server> {
     static INIT: Once = Once::new();
     INIT.call_once(|| unsafe { SERVER = Some(RwLock::new(Server::new())) });
-    unsafe { SERVER.as_ref().unwrap() }
+    unsafe { (*addr_of!(SERVER)).as_ref().unwrap() }
 }
 
 fn reset_server() {
@@ -55,20 +66,91 @@ enum Message {
     Shutdown,
 }
 
+// 默认保险库，数据库在数据目录下，其他保险库在 <data_dir>/vaults/<name> 下
+pub const DEFAULT_VAULT: &str = "default";
+
+// 已打开的保险库
+struct Vault {
+    // 保险库目录，包含数据库和备份
+    dir: PathBuf,
+    conn: Connection,
+}
+
 struct Server {
     addr: Option<SocketAddr>,
     channel: Option<UnboundedSender<Message>>,
-    db: Option<Connection>,
+    vaults: HashMap<String, Vault>,
+    // 每个连接的当前保险库，密码相关的 RPC 都作用于当前保险库，互不影响
+    // 键为客户端地址，不在连接中调用时为 None；没有选择过保险库的连接使用默认保险库
+    current: HashMap<Option<SocketAddr>, String>,
+    // 每个连接解锁过的保险库，list_vaults 只告诉连接它自己打开的保险库
+    unlocked: HashMap<Option<SocketAddr>, HashSet<String>>,
+    // 每个连接输入胁迫密码后打开的诱饵保险库和对应的保险库名称，只有该连接使用，其他连接仍然使用真实保险库
+    decoys: HashMap<Option<SocketAddr>, (String, Vault)>,
+    data_dir: Option<PathBuf>,
 }
 
 impl Server {
-    pub const fn new() -> Server {
+    pub fn new() -> Server {
         Server {
             addr: None,
             channel: None,
-            db: None,
+            vaults: HashMap::new(),
+            current: HashMap::new(),
+            unlocked: HashMap::new(),
+            decoys: HashMap::new(),
+            data_dir: None,
+        }
+    }
+
+    // 当前连接的当前保险库名称
+    fn current_name(&self) -> &str {
+        self.current
+            .get(&client_addr())
+            .map_or(DEFAULT_VAULT, String::as_str)
+    }
+
+    fn current(&self) -> Option<&Vault> {
+        self.vault(self.current_name())
+    }
+
+    // 把保险库设为当前连接的当前保险库，记为当前连接解锁过
+    fn set_current(&mut self, name: &str) {
+        let addr = client_addr();
+        self.current.insert(addr, name.to_string());
+        self.unlocked
+            .entry(addr)
+            .or_default()
+            .insert(name.to_string());
+    }
+
+    // 当前连接看到的保险库，打开了诱饵保险库时为诱饵保险库
+    fn vault(&self, name: &str) -> Option<&Vault> {
+        match self.decoys.get(&client_addr()) {
+            Some((decoy, vault)) if decoy == name => Some(vault),
+            _ => self.vaults.get(name),
+        }
+    }
+
+    fn vault_mut(&mut self, name: &str) -> Option<&mut Vault> {
+        match self.decoys.get_mut(&client_addr()) {
+            Some((decoy, vault)) if decoy == name => Some(vault),
+            _ => self.vaults.get_mut(name),
         }
     }
+
+    // 当前连接是否用诱饵保险库代替保险库 name
+    fn has_decoy(&self, name: &str) -> bool {
+        self.decoys
+            .get(&client_addr())
+            .is_some_and(|(decoy, _)| decoy == name)
+    }
+
+    // 所有连接打开的保险库，包括诱饵保险库
+    fn all(&self) -> impl Iterator<Item = &Vault> {
+        let decoys = self.decoys.values().map(|(_, vault)| vault);
+        self.vaults.values().chain(decoys)
+    }
 }
 
 #[derive(Default)]
@@ -92,7 +174,7 @@ impl NetworkServer {
         })
     }
 
-    fn accept(&self) -> Accept {
+    fn accept(&self) -> Accept<'_> {
         Accept(&self.listener)
     }
 
@@ -114,6 +196,8 @@ impl<'a> Future for Accept<'a> {
     }
 }
 
+// 启动期间一直持有写锁，避免重复启动
+#[allow(clippy::await_holding_lock, clippy::readonly_write_lock)]
 pub async fn start(
     addr: impl ToSocketAddrs,
     data_dir: &str,
@@ -128,19 +212,30 @@ pub async fn start(
             let mut sig_term = signal(SignalKind::terminate()).map_err(err!())?;
             let (tx, mut rx) = unbounded_channel();
 
-            let db = init_database(data_dir)?;
+            let data_dir = PathBuf::from(data_dir);
+            if !data_dir.exists() {
+                create_dir(&data_dir).map_err(err!())?;
+            }
+            let conn = open_database(&data_dir)?;
             let listener = TcpListener::bind(addr).await.map_err(err!())?;
             let addr = listener.local_addr().map_err(err!())?;
             info!("server started at {}", addr);
 
             server.addr = Some(addr);
             server.channel = Some(tx);
-            server.db = Some(db);
+            let vault = Vault {
+                dir: data_dir.clone(),
+                conn,
+            };
+            server.vaults.insert(DEFAULT_VAULT.to_string(), vault);
+            server.data_dir = Some(data_dir);
             drop(guard);
             on_started(addr);
 
             let mut network_server = NetworkServer::default();
             let handler = create_handler();
+            let mut backup_timer = interval(backup::TICK);
+            let mut sync_timer = interval(folder::TICK);
             loop {
                 tokio::select! {
                     accept = listener.accept() => {
@@ -148,7 +243,7 @@ pub async fn start(
                             Ok((stream, addr)) => {
                                 let handler = Arc::clone(&handler);
                                 tokio::spawn(async move {
-                                    if let Err(err) = handle_client(stream, &handler).await {
+                                    if let Err(err) = serve_client(stream, addr, &handler).await {
                                         error!("{} {:?}", addr, err);
                                     }
                                 });
@@ -163,7 +258,7 @@ pub async fn start(
                                 let acceptor = Arc::clone(network_server.acceptor.as_ref().unwrap());
                                 tokio::spawn(async move {
                                     match acceptor.accept(stream).await.map_err(err!()) {
-                                        Ok(stream) => if let Err(err) = handle_client(stream, &handler).await {
+                                        Ok(stream) => if let Err(err) = serve_client(stream, addr, &handler).await {
                                             error!("{} {:?}", addr, err);
                                         }
                                         Err(err) => error!("{} {:?}", addr, err),
@@ -181,7 +276,11 @@ pub async fn start(
                                 }
                                 None => match NetworkServer::create().await {
                                     Ok(v) => {
-                                        let _ = reply.send(v.port().unwrap().map_err(err!()));
+                                        let port = v.port().unwrap().map_err(err!());
+                                        if let Ok(port) = port {
+                                            notify::network_changed(Some(port));
+                                        }
+                                        let _ = reply.send(port);
                                         network_server = v;
                                     },
                                     Err(err) => {
@@ -191,6 +290,7 @@ pub async fn start(
                             }
                             Some(Message::CloseAnyAddr) => {
                                 network_server = NetworkServer::default();
+                                notify::network_changed(None);
                                 info!("network server stopped");
                             }
                             Some(Message::QueryAnyAddrPort(reply)) => {
@@ -203,6 +303,20 @@ pub async fn start(
                             None => unreachable!(),
                         }
                     }
+                    _ = backup_timer.tick() => {
+                        spawn_blocking(|| {
+                            if let Err(err) = backup::auto(db().all()) {
+                                error!("backup failed {:?}", err);
+                            }
+                        });
+                    }
+                    _ = sync_timer.tick() => {
+                        spawn_blocking(|| {
+                            if let Err(err) = folder::auto() {
+                                error!("folder sync failed {:?}", err);
+                            }
+                        });
+                    }
                     _ = sig_int.recv() => {
                         info!("catch SIGINT, stopping");
                         break;
@@ -255,11 +369,8 @@ pub fn close_any_addr() -> crate::Result<()> {
 
 #[allow(dead_code)]
 pub fn stop() {
-    match server().write().unwrap().channel {
-        Some(ref sender) => {
-            let _ = sender.send(Message::Shutdown);
-        }
-        None => {}
+    if let Some(ref sender) = server().write().unwrap().channel {
+        let _ = sender.send(Message::Shutdown);
     }
 }
 
@@ -289,24 +400,325 @@ pub fn db() -> DB<'static> {
     DB(server().read().unwrap())
 }
 
+// 释放数据库连接后发送已提交的修改的通知
+impl<'a> Drop for DB<'a> {
+    fn drop(&mut self) {
+        notify::flush();
+    }
+}
+
 impl<'a> DB<'a> {
+    // 当前保险库的连接，没有打开保险库时返回错误
     pub fn conn(&self) -> Result<&Connection, Unavailable> {
-        self.0.db.as_ref().ok_or(Unavailable)
+        self.0.current().map(|v| &v.conn).ok_or(Unavailable)
+    }
+
+    // 所有已打开的保险库的目录和连接，包括各个连接的诱饵保险库
+    pub fn all(&self) -> impl Iterator<Item = (&Path, &Connection)> {
+        self.0.all().map(|v| (v.dir.as_path(), &v.conn))
+    }
+}
+
+pub struct DBMut<'a>(RwLockWriteGuard<'a, Server>);
+
+// 独占数据库连接，用于恢复备份
+pub fn db_mut() -> DBMut<'static> {
+    DBMut(server().write().unwrap())
+}
+
+impl<'a> Drop for DBMut<'a> {
+    fn drop(&mut self) {
+        notify::flush();
+    }
+}
+
+impl<'a> DBMut<'a> {
+    pub fn conn(&mut self) -> Result<&mut Connection, Unavailable> {
+        let server = &mut *self.0;
+        let name = server.current_name().to_string();
+        server
+            .vault_mut(&name)
+            .map(|v| &mut v.conn)
+            .ok_or(Unavailable)
+    }
+}
+
+// 当前连接的当前保险库的名称，没有打开时返回 None
+pub fn current_vault() -> Option<String> {
+    let server = server().read().unwrap();
+    let name = server.current_name();
+    server.vault(name).is_some().then(|| name.to_string())
+}
+
+// 当前保险库的目录
+pub fn vault_dir() -> crate::Result<PathBuf> {
+    match server().read().unwrap().current() {
+        Some(vault) => Ok(vault.dir.clone()),
+        None => Err(err!(Unavailable)),
+    }
+}
+
+// 保险库的目录
+pub fn vault_path(name: &str) -> crate::Result<PathBuf> {
+    let data_dir = data_dir()?;
+    if name == DEFAULT_VAULT {
+        Ok(data_dir)
+    } else {
+        Ok(data_dir.join("vaults").join(name))
+    }
+}
+
+// 当前连接解锁过并且仍然打开的保险库的名称，不包括其他连接打开的保险库
+pub fn opened_vaults() -> Vec<String> {
+    let server = server().read().unwrap();
+    match server.unlocked.get(&client_addr()) {
+        Some(names) => (names.iter())
+            .filter(|name| server.vault(name).is_some())
+            .cloned()
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+// 对已打开的保险库执行 f，当前连接打开了诱饵保险库时使用诱饵保险库，没有打开时返回 None
+pub fn with_vault<T>(name: &str, f: impl FnOnce(&Connection) -> T) -> Option<T> {
+    let result = server().read().unwrap().vault(name).map(|v| f(&v.conn));
+    notify::flush();
+    result
+}
+
+// 当前保险库是否是诱饵保险库
+pub fn is_decoy() -> bool {
+    let server = server().read().unwrap();
+    server.has_decoy(server.current_name())
+}
+
+// 对已打开的真实保险库执行 f，忽略当前连接打开的诱饵保险库，没有打开时返回 None
+pub fn with_real_vault<T>(name: &str, f: impl FnOnce(&Connection) -> T) -> Option<T> {
+    let result = server()
+        .read()
+        .unwrap()
+        .vaults
+        .get(name)
+        .map(|v| f(&v.conn));
+    notify::flush();
+    result
+}
+
+// 对目录为 dir 的已打开保险库执行 f，没有打开时返回 None。后台任务每次访问数据库时重新获取，
+// 中间读写文件时不占用锁，诱饵保险库的目录不同，不会被替换的保险库误用
+pub fn with_vault_dir<T>(dir: &Path, f: impl FnOnce(&Connection) -> T) -> Option<T> {
+    let result = server()
+        .read()
+        .unwrap()
+        .all()
+        .find(|v| v.dir == dir)
+        .map(|v| f(&v.conn));
+    notify::flush();
+    result
+}
+
+// 已打开的保险库的目录，当前连接打开的是诱饵保险库时为诱饵保险库的目录
+pub fn opened_vault_dir(name: &str) -> Option<PathBuf> {
+    let server = server().read().unwrap();
+    server.vault(name).map(|v| v.dir.clone())
+}
+
+// 打开保险库并设为当前保险库
+pub fn open_vault(name: &str, conn: Connection) -> crate::Result<()> {
+    let dir = vault_path(name)?;
+    let mut server = server().write().unwrap();
+    server.vaults.insert(name.to_string(), Vault { dir, conn });
+    server.set_current(name);
+    Ok(())
+}
+
+// 在当前连接上用诱饵保险库代替保险库并设为当前保险库，名称不变。其他连接和已打开的真实保险库不受影响，
+// 同一个连接只保留最后打开的诱饵保险库
+pub fn open_decoy(name: &str, dir: PathBuf, conn: Connection) {
+    let mut server = server().write().unwrap();
+    server
+        .decoys
+        .insert(client_addr(), (name.to_string(), Vault { dir, conn }));
+    server.set_current(name);
+}
+
+// 关闭当前连接代替保险库 name 的诱饵保险库，输入真实的主密码后换回真实保险库
+pub fn close_decoy(name: &str) {
+    let mut server = server().write().unwrap();
+    if server.has_decoy(name) {
+        server.decoys.remove(&client_addr());
+    }
+}
+
+// 测试用，打开保险库但不设为当前保险库，不需要数据目录
+#[cfg(test)]
+pub fn open_test_vault(name: &str, dir: PathBuf, conn: Connection) {
+    let mut server = server().write().unwrap();
+    server.vaults.insert(name.to_string(), Vault { dir, conn });
+}
+
+// 切换当前连接的当前保险库，保险库没有打开时返回 false
+pub fn switch_vault(name: &str) -> bool {
+    let mut server = server().write().unwrap();
+    if server.vault(name).is_none() {
+        return false;
+    }
+    server.set_current(name);
+    true
+}
+
+// 关闭保险库，以它为当前保险库的连接需要重新打开，不会改用其他保险库。当前连接打开的是诱饵保险库时
+// 只关闭诱饵保险库，否则同时关闭其他连接代替它的诱饵保险库
+pub fn close_vault(name: &str) {
+    let mut server = server().write().unwrap();
+    if server.has_decoy(name) {
+        let addr = client_addr();
+        server.decoys.remove(&addr);
+        if let Some(names) = server.unlocked.get_mut(&addr) {
+            names.remove(name);
+        }
+        return;
+    }
+    server.decoys.retain(|_, (decoy, _)| decoy != name);
+    for names in server.unlocked.values_mut() {
+        names.remove(name);
+    }
+    if let Some(vault) = server.vaults.remove(name) {
+        sync::lock(&vault.dir);
+        notify::vault_locked(&vault.dir);
+    }
+}
+
+// 重命名保险库，已打开的保险库先关闭，重命名后重新打开
+pub fn rename_vault(name: &str, new_name: &str) -> crate::Result<()> {
+    let from = vault_path(name)?;
+    let to = vault_path(new_name)?;
+    if let Some(parent) = to.parent() {
+        create_dir_all(parent).map_err(err!())?;
+    }
+    let mut server = server().write().unwrap();
+    let removed = server.vaults.remove(name);
+    // 重新打开后需要再次解锁才能同步
+    if let Some(vault) = &removed {
+        sync::lock(&vault.dir);
+    }
+    let opened = removed.is_some();
+    // 各个连接代替它的诱饵保险库同样关闭后重新打开
+    let decoys: Vec<_> = server
+        .decoys
+        .iter()
+        .filter(|(_, (decoy, _))| decoy == name)
+        .map(|(addr, _)| *addr)
+        .collect();
+    server.decoys.retain(|_, (decoy, _)| decoy != name);
+    // 重命名失败时在原来的位置重新打开
+    let result = rename(&from, &to).map_err(err!());
+    if result.is_ok() {
+        // 以它为当前保险库的连接继续使用它
+        for current in server.current.values_mut() {
+            if current == name {
+                *current = new_name.to_string();
+            }
+        }
+        for names in server.unlocked.values_mut() {
+            if names.remove(name) {
+                names.insert(new_name.to_string());
+            }
+        }
+    }
+    let (name, dir) = match result {
+        Ok(_) => (new_name, to),
+        Err(_) => (name, from),
+    };
+    for addr in decoys {
+        let dir = dir.join(duress::DIR);
+        let conn = open_database(&dir)?;
+        let vault = Vault { dir, conn };
+        server.decoys.insert(addr, (name.to_string(), vault));
+    }
+    if opened {
+        let conn = open_database(&dir)?;
+        server.vaults.insert(name.to_string(), Vault { dir, conn });
     }
+    result
+}
+
+// 数据目录
+pub fn data_dir() -> crate::Result<PathBuf> {
+    match server().read().unwrap().data_dir {
+        Some(ref dir) => Ok(dir.clone()),
+        None => Err(err!(Unavailable)),
+    }
+}
+
+tokio::task_local! {
+    // 当前连接的客户端地址，用于审计日志
+    static CLIENT: SocketAddr;
+}
+
+// 当前连接的客户端地址，不在连接中时返回 None
+pub fn client_addr() -> Option<SocketAddr> {
+    CLIENT.try_with(|addr| *addr).ok()
 }
 
-fn init_database(data_dir: &str) -> crate::Result<Connection> {
-    let mut path = PathBuf::from(data_dir);
-    if !path.exists() {
-        create_dir(&path).map_err(err!())?;
+// 处理连接，断开后清除连接的当前保险库
+async fn serve_client(
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    addr: SocketAddr,
+    handler: &Arc<Handler>,
+) -> crate::Result<()> {
+    let stream = NotifyStream::new(stream, addr);
+    let result = CLIENT.scope(addr, handle_client(stream, handler)).await;
+    let mut server = server().write().unwrap();
+    server.current.remove(&Some(addr));
+    server.unlocked.remove(&Some(addr));
+    server.decoys.remove(&Some(addr));
+    drop(server);
+    notify::disconnect(Some(addr));
+    // 丢弃没有使用的同步会话号
+    let _ = sync::take_session(addr);
+    result
+}
+
+// 测试用，以客户端 addr 的身份执行 f
+#[cfg(test)]
+pub fn with_client<T>(addr: Option<SocketAddr>, f: impl FnOnce() -> T) -> T {
+    match addr {
+        Some(addr) => CLIENT.sync_scope(addr, f),
+        None => f(),
     }
-    path.push("database");
+}
 
+// 打开保险库目录下的数据库，不存在时创建
+pub fn open_database(dir: &Path) -> crate::Result<Connection> {
     let flags = OpenFlags::SQLITE_OPEN_READ_WRITE
         | OpenFlags::SQLITE_OPEN_CREATE
         | OpenFlags::SQLITE_OPEN_FULL_MUTEX;
-    let mut db = Connection::open_with_flags(path, flags).map_err(err!())?;
-    setup(&mut db)?;
+    let mut db = Connection::open_with_flags(dir.join("database"), flags).map_err(err!())?;
+    setup(&mut db, &backup::dir(dir))?;
+    let dir = dir.to_path_buf();
+    let changes = Changes::new(&dir);
+    db.update_hook(Some({
+        let changes = changes.clone();
+        move |action, _: &str, table: &str, id| {
+            if !is_tracked(table) {
+                return;
+            }
+            backup::changed(&dir);
+            if table == "vault" {
+                changes.record(action, id);
+            }
+        }
+    }));
+    db.commit_hook(Some({
+        let changes = changes.clone();
+        move || {
+            changes.commit();
+            false
+        }
+    }));
+    db.rollback_hook(Some(move || changes.rollback()));
     Ok(db)
 }
 
@@ -318,8 +730,8 @@ fn create_handler() -> Arc<Handler> {
 
 const TIMEOUT: Duration = Duration::from_secs(60);
 
-async fn handle_client(
-    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: NotifyStream<S>,
     handler: &Arc<Handler>,
 ) -> crate::Result<()> {
     let mut buf = vec![0u8; 1024];
@@ -337,10 +749,15 @@ async fn handle_client(
             response.add_header("content-type", "text/javascript");
             response.write(&mut stream).await.map_err(err!())?;
         }
-        "/ws" => match WebSocket::upgrade(&req, stream).await.map_err(err!())? {
-            Some(ws) => handler.handle(ws).await.map_err(err!())?,
-            None => {}
-        },
+        "/ws" => {
+            let upgraded = Arc::clone(&stream.upgraded);
+            if let Some(ws) = WebSocket::upgrade(&req, stream).await.map_err(err!())? {
+                // 握手的响应已经写入，之后写入的都是 WebSocket 帧，可以在帧之间插入通知
+                upgraded.store(true, Ordering::Release);
+                notify::connect(client_addr());
+                handler.handle(ws).await.map_err(err!())?
+            }
+        }
         _ => {
             let response = Response::status(NOT_FOUND);
             response.write(&mut stream).await.map_err(err!())?;
@@ -348,3 +765,308 @@ async fn handle_client(
     }
     Ok(())
 }
+
+// 在 /ws 连接上发送通知。ws_jsonrpc 的 Handler 拥有连接，只回复请求，不能主动发送消息，所以包装交给
+// 它的连接，跟踪 Handler 写入的帧，在两条完整的消息之间写入通知帧 (服务端的帧不加掩码)。Handler 等待
+// 请求 (poll_read) 时登记唤醒，有通知时立即发送；Handler 正在写入时等它写完当前帧再发送
+struct NotifyStream<S> {
+    inner: S,
+    addr: SocketAddr,
+    // WebSocket 握手完成后才插入通知，之前写入的是 HTTP 响应
+    upgraded: Arc<AtomicBool>,
+    // Handler 正在写入的帧头部已写入的部分，和帧的负载还没写入的长度
+    header: Vec<u8>,
+    remaining: u64,
+    // Handler 写入了没有结束的分片消息，这时不能插入其他消息
+    fragmented: bool,
+    // Handler 已发送关闭帧，之后不再发送通知
+    closed: bool,
+    // 正在写入的通知帧和已写入的长度
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> NotifyStream<S> {
+    fn new(inner: S, addr: SocketAddr) -> Self {
+        Self {
+            inner,
+            addr,
+            upgraded: Arc::default(),
+            header: Vec::new(),
+            remaining: 0,
+            fragmented: false,
+            closed: false,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    // 是否需要跟踪帧
+    fn framed(&self) -> bool {
+        self.upgraded.load(Ordering::Acquire) && !self.closed
+    }
+
+    // 是否在两条消息之间
+    fn at_boundary(&self) -> bool {
+        self.framed() && self.header.is_empty() && self.remaining == 0 && !self.fragmented
+    }
+
+    // 写完正在写入的通知帧
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.pending.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+        while self.written < self.pending.len() {
+            let buf = &self.pending[self.written..];
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    // 在两条消息之间写入排队的通知。wait 为 true 时没有通知也登记唤醒，用于 Handler 等待请求时
+    fn poll_notify(&mut self, cx: &mut Context<'_>, wait: bool) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.poll_pending(cx))?;
+            if !self.at_boundary() {
+                return Poll::Ready(Ok(()));
+            }
+            let addr = Some(self.addr);
+            let notifications = match wait {
+                true => match notify::poll_drain(addr, cx) {
+                    Poll::Ready(v) => v,
+                    Poll::Pending => return Poll::Ready(Ok(())),
+                },
+                false => notify::drain(addr),
+            };
+            if notifications.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            for notification in notifications {
+                let text = serde_json::to_vec(&notification).map_err(io::Error::other)?;
+                let frame = Frame::message(text, OpCode::Data(Data::Text), true);
+                frame.format(&mut self.pending).map_err(io::Error::other)?;
+            }
+        }
+    }
+
+    // buf 中可以一次写入的长度，只写到当前帧的头部或负载结束，之后才能插入通知
+    fn frame_len(&self, buf: &[u8]) -> io::Result<usize> {
+        if !self.framed() {
+            return Ok(buf.len());
+        }
+        if self.remaining > 0 {
+            return Ok(usize::try_from(self.remaining).map_or(buf.len(), |v| v.min(buf.len())));
+        }
+        let data = [&self.header, buf].concat();
+        let mut cursor = Cursor::new(&data);
+        match FrameHeader::parse(&mut cursor).map_err(io::Error::other)? {
+            Some(_) => Ok(cursor.position() as usize - self.header.len()),
+            None => Ok(buf.len()),
+        }
+    }
+
+    // Handler 写入了 written
+    fn advance(&mut self, written: &[u8]) -> io::Result<()> {
+        if !self.framed() {
+            return Ok(());
+        }
+        if self.remaining > 0 {
+            self.remaining -= written.len() as u64;
+            return Ok(());
+        }
+        self.header.extend_from_slice(written);
+        let mut cursor = Cursor::new(&self.header);
+        if let Some((header, len)) = FrameHeader::parse(&mut cursor).map_err(io::Error::other)? {
+            match header.opcode {
+                OpCode::Data(_) => self.fragmented = !header.is_final,
+                OpCode::Control(Control::Close) => self.closed = true,
+                OpCode::Control(_) => {}
+            }
+            self.remaining = len;
+            self.header.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for NotifyStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // 通知没有写完时也继续读取，写入的唤醒已登记
+        if let Poll::Ready(Err(err)) = this.poll_notify(cx, true) {
+            return Poll::Ready(Err(err));
+        }
+        Pin::new(&mut this.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for NotifyStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_notify(cx, false))?;
+        let len = this.frame_len(buf)?;
+        let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]))?;
+        this.advance(&buf[..n])?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_notify(cx, false))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 每个连接单独的当前保险库，切换不影响其他连接
+    #[test]
+    fn current_vault_per_client() {
+        let a = Some("127.0.0.1:1001".parse().unwrap());
+        let b = Some("127.0.0.1:1002".parse().unwrap());
+        for name in ["client-a", "client-b"] {
+            let conn = Connection::open_in_memory().unwrap();
+            open_test_vault(name, PathBuf::from(name), conn);
+        }
+        assert!(with_client(a, || switch_vault("client-a")));
+        assert!(with_client(b, || switch_vault("client-b")));
+        assert!(!with_client(b, || switch_vault("missing")));
+        assert_eq!(with_client(a, current_vault).as_deref(), Some("client-a"));
+        assert_eq!(with_client(b, current_vault).as_deref(), Some("client-b"));
+        assert_eq!(with_client(a, vault_dir).unwrap(), Path::new("client-a"));
+        // 只列出自己打开的保险库
+        assert_eq!(with_client(a, opened_vaults), ["client-a"]);
+        assert_eq!(with_client(b, opened_vaults), ["client-b"]);
+
+        // 关闭后不会改用其他保险库
+        close_vault("client-a");
+        assert!(with_client(a, current_vault).is_none());
+        assert!(with_client(a, || db().conn().is_err()));
+        assert!(with_client(a, opened_vaults).is_empty());
+        assert_eq!(with_client(b, current_vault).as_deref(), Some("client-b"));
+    }
+
+    // 诱饵保险库只在输入胁迫密码的连接上代替真实保险库
+    #[test]
+    fn decoy_per_client() {
+        let a = Some("127.0.0.1:1003".parse().unwrap());
+        let b = Some("127.0.0.1:1004".parse().unwrap());
+        let real = PathBuf::from("decoy-test");
+        let decoy = real.join(duress::DIR);
+        open_test_vault(
+            "decoy-test",
+            real.clone(),
+            Connection::open_in_memory().unwrap(),
+        );
+        assert!(with_client(a, || switch_vault("decoy-test")));
+        let conn = Connection::open_in_memory().unwrap();
+        with_client(b, || open_decoy("decoy-test", decoy.clone(), conn));
+
+        assert_eq!(with_client(a, vault_dir).unwrap(), real);
+        assert_eq!(with_client(b, vault_dir).unwrap(), decoy);
+        assert!(!with_client(a, is_decoy));
+        assert!(with_client(b, is_decoy));
+        assert_eq!(with_client(b, current_vault).as_deref(), Some("decoy-test"));
+        assert_eq!(
+            with_client(a, || opened_vault_dir("decoy-test")),
+            Some(real.clone())
+        );
+        assert_eq!(
+            with_client(b, || opened_vault_dir("decoy-test")),
+            Some(decoy.clone())
+        );
+        // 真实保险库仍然打开，诱饵保险库也参与备份
+        assert!(with_client(b, || with_real_vault("decoy-test", |_| ())).is_some());
+        assert!(with_client(b, || db().all().any(|(dir, _)| dir == decoy)));
+
+        // 关闭诱饵保险库不影响其他连接
+        with_client(b, || close_vault("decoy-test"));
+        assert_eq!(with_client(b, vault_dir).unwrap(), real);
+        assert_eq!(with_client(a, vault_dir).unwrap(), real);
+
+        // 输入真实的主密码后换回真实保险库
+        let conn = Connection::open_in_memory().unwrap();
+        with_client(b, || open_decoy("decoy-test", decoy.clone(), conn));
+        with_client(b, || close_decoy("decoy-test"));
+        assert!(!with_client(b, is_decoy));
+        assert_eq!(with_client(b, vault_dir).unwrap(), real);
+
+        // 关闭真实保险库时同时关闭其他连接的诱饵保险库
+        let conn = Connection::open_in_memory().unwrap();
+        with_client(b, || open_decoy("decoy-test", decoy.clone(), conn));
+        with_client(a, || close_vault("decoy-test"));
+        assert!(with_client(a, current_vault).is_none());
+        assert!(with_client(b, current_vault).is_none());
+    }
+
+    // 负载为文本的帧
+    fn text_frames(mut data: &[u8]) -> Vec<String> {
+        let mut frames = Vec::new();
+        while !data.is_empty() {
+            let mut cursor = Cursor::new(data);
+            let (_, len) = FrameHeader::parse(&mut cursor).unwrap().unwrap();
+            let start = cursor.position() as usize;
+            let end = start + len as usize;
+            frames.push(String::from_utf8(data[start..end].to_vec()).unwrap());
+            data = &data[end..];
+        }
+        frames
+    }
+
+    // 通知只插入在 Handler 写入的两条消息之间，Handler 等待请求时发送
+    #[tokio::test]
+    async fn notify_between_frames() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let addr: SocketAddr = "127.0.0.1:1005".parse().unwrap();
+        let (inner, mut peer) = tokio::io::duplex(4096);
+        let mut stream = NotifyStream::new(inner, addr);
+        stream.upgraded.store(true, Ordering::Release);
+        notify::connect(Some(addr));
+        notify::subscribe(Some(addr), PathBuf::from("push-test"), "push".to_string());
+
+        let mut reply = Vec::new();
+        let frame = Frame::message(b"reply".to_vec(), OpCode::Data(Data::Text), true);
+        frame.format(&mut reply).unwrap();
+        stream.write_all(&reply[..1]).await.unwrap();
+        notify::vault_locked(Path::new("push-test"));
+        stream.write_all(&reply[1..]).await.unwrap();
+        let mut buf = [0u8; 1];
+        let read = tokio::time::timeout(Duration::from_millis(50), stream.read(&mut buf)).await;
+        assert!(read.is_err());
+
+        let mut data = vec![0u8; 4096];
+        let n = peer.read(&mut data).await.unwrap();
+        let frames = text_frames(&data[..n]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0], "reply");
+        let notification: serde_json::Value = serde_json::from_str(&frames[1]).unwrap();
+        assert_eq!(notification["method"], "vault_locked");
+        assert_eq!(notification["params"]["vault"], "push");
+        assert!(notification.get("id").is_none());
+        notify::disconnect(Some(addr));
+    }
+}
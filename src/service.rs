@@ -1,21 +1,44 @@
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::convert::Infallible;
-use std::fs::{read, remove_file, OpenOptions};
+#[cfg(unix)]
+use std::fs::Permissions;
+use std::fs::{create_dir_all, read, read_dir, remove_dir_all, remove_file, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
 
 use log::error;
 use openssl::rand::rand_bytes;
 use rand::distributions::{Distribution, Uniform};
 use rand::prelude::SliceRandom;
-use rusqlite::{params, ToSql};
+use rusqlite::{params, Connection, Params};
 use serde::{Deserialize, Serialize};
 use serde_json::{from_slice, json};
+use tokio::task::spawn_blocking;
 use ws_jsonrpc::response::Error as RpcError;
 use ws_jsonrpc::{method, rpc, Method};
 
-use crate::crypto::{key_encrypt, password_decrypt, password_encrypt};
-use crate::server::{close_any_addr, db, listen_any_addr, query_network_port};
+use crate::backup::{Retention, Snapshot};
+use crate::crypto::{derive_key, hmac_sha256, key_encrypt, password_decrypt};
+use crate::db::{get_conf, now, set_conf};
+use crate::import::{bitwarden, csv, onepux, Import};
+use crate::keyfile::MasterPassword;
+use crate::server::{
+    client_addr, close_any_addr, current_vault, data_dir, db, db_mut, is_decoy, listen_any_addr,
+    open_database, opened_vault_dir, opened_vaults, query_network_port, vault_dir, vault_path,
+    with_real_vault, with_vault, Unavailable, DEFAULT_VAULT,
+};
 use crate::service::Error::WrongPassword;
+use crate::sync::client::Client;
+use crate::sync::folder::FolderReport;
+use crate::sync::webdav::{self, with_conn, Config as WebDavConfig, HttpError};
+use crate::sync::PeerError;
+use crate::{
+    attempt, audit, backup, db, duress, encrypted, health, hibp, import, kdbx, keyfile, notify,
+    plaintext, recipient, recovery, server, share, sync,
+};
 
 #[derive(Debug)]
 enum Error {
@@ -25,6 +48,75 @@ enum Error {
     // json 解析失败
     DeserializeFailed,
 
+    // 没有导入泄露密码数据
+    NoBreachData,
+
+    // 导出未加密数据时没有确认
+    PlaintextNotAcknowledged,
+
+    // 备份损坏
+    BackupCorrupted,
+
+    // 数据损坏，主密码正确但无法解密
+    Corrupted,
+
+    // 备份的数据库版本比程序新
+    NewerVersion,
+
+    // 保险库不存在
+    VaultNotFound,
+
+    // 保险库已存在
+    VaultExists,
+
+    // 保险库名称不合法，或者是不能重命名、删除的默认保险库
+    InvalidVaultName,
+
+    // 恢复密钥或分片无效，或者分片数量不够
+    InvalidRecoveryKey,
+
+    // 没有设置恢复密钥
+    RecoveryNotSet,
+
+    // 分片数量不合法，至少 2 份才能恢复，不能超过总数
+    InvalidShares,
+
+    // 密钥文件为空或者不是 base64 编码
+    InvalidKeyFile,
+
+    // 胁迫密码为空或者和主密码相同
+    InvalidDuressPassword,
+
+    // 设置了胁迫密码时不能开启或关闭密钥文件，诱饵保险库的密钥文件设置需要和真实保险库一致
+    DuressPasswordSet,
+
+    // 分享公钥格式错误
+    InvalidShareKey,
+
+    // 分享格式错误、签名无效，或者不是分享给本保险库的
+    InvalidShare,
+
+    // 分享的发送者不是指定的公钥
+    UntrustedSender,
+
+    // 分享已被发送者撤销
+    ShareRevoked,
+
+    // 分享已经导入过
+    ShareImported,
+
+    // 发出的分享不存在
+    ShareNotFound,
+
+    // 配对码错误或者已过期
+    InvalidPairingCode,
+
+    // 已经和其他设备同步，改用新的保险库密钥后无法再和它们同步
+    AlreadySyncing,
+
+    // 输错密码的次数太多，稍后再试
+    TooManyAttempts,
+
     // 其他错误
     Any(crate::Error),
 }
@@ -59,63 +151,205 @@ fn is_master_password_set() -> crate::Result<bool> {
     Ok(count == 1)
 }
 
-// 设置主密码
+// 设置主密码时是否生成恢复密钥
+#[derive(Deserialize)]
+struct RecoveryOption {
+    // 同时把恢复密钥分成分片
+    shares: Option<recovery::Shares>,
+}
+
+// 设置主密码，recovery 不为空时生成恢复密钥，只返回这一次
 #[rpc]
-fn set_master_password(master_password: String) -> crate::Result<()> {
+fn set_master_password(
+    master_password: MasterPassword,
+    recovery: Option<RecoveryOption>,
+) -> Result<Option<recovery::Kit>, Error> {
+    let shares = recovery.as_ref().and_then(|v| v.shares.as_ref());
+    if shares.is_some_and(|v| !v.valid()) {
+        return Err(Error::InvalidShares);
+    }
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let key = init_master_key(&tx, master_password)?;
+    let kit = match recovery {
+        Some(_) => Some(recovery::create(&tx, &key, shares)?),
+        None => None,
+    };
+    tx.commit().map_err(err!())?;
+    Ok(kit)
+}
+
+// 生成主密钥，使用主密码加密保存，返回主密钥
+fn init_master_key(conn: &Connection, master_password: MasterPassword) -> crate::Result<Vec<u8>> {
     let mut key = [0u8; 32];
     rand_bytes(&mut key).map_err(err!())?;
+    audit::init(conn, &key)?;
 
-    let key = password_encrypt(master_password, key).map_err(err!())?;
-    let key = base64::encode(key);
+    let key_file = master_password.key_file();
+    let encrypted = keyfile::wrap(conn, master_password.password(), key_file.as_ref(), &key)?;
 
     const SQL: &str = "INSERT INTO conf (key, value) VALUES ('key', ?)";
-    db().conn()
-        .map_err(err!())?
-        .execute(SQL, [key])
-        .map_err(err!())?;
-    Ok(())
+    conn.execute(SQL, [encrypted]).map_err(err!())?;
+    Ok(key.to_vec())
 }
 
-// 验证主密码
+// 验证主密码，输入胁迫密码时改为打开诱饵保险库，同样返回 true
 #[rpc]
-fn verify_master_password(master_password: String) -> Result<bool, Error> {
-    match decrypt_master_key(master_password) {
+fn verify_master_password(master_password: MasterPassword) -> Result<bool, Error> {
+    let name = current_vault().ok_or(err!(Unavailable))?;
+    match unlock_vault(&name, &master_password, "") {
         Ok(_) => Ok(true),
         Err(WrongPassword) => Ok(false),
         Err(e) => Err(e),
     }
 }
 
+// 解锁并打开保险库，设为当前保险库。输入胁迫密码时改为打开诱饵保险库，名称和响应都和真实保险库相同；
+// 已打开诱饵保险库时输入真实的主密码会换回真实保险库
+fn unlock_vault(name: &str, master_password: &MasterPassword, detail: &str) -> Result<(), Error> {
+    // 输入了胁迫密码时返回诱饵保险库密钥，否则保存同步密钥
+    let unlock = |conn: &Connection| -> Result<Option<Vec<u8>>, Error> {
+        // 无论输入的是什么密码都计算主密码和胁迫密码的 KDF，耗时一致
+        let result = master_key(conn, master_password);
+        if let Some(decoy_key) = duress::check(conn, master_password)? {
+            audit::record_locked(conn, "duress", detail)?;
+            return Ok(Some(decoy_key));
+        }
+        // 只在明确解锁时记录失败，其他 RPC 验证主密码不写数据库
+        let key = match result {
+            Err(WrongPassword) => {
+                audit::record_locked(conn, "unlock_failed", detail)?;
+                return Err(WrongPassword);
+            }
+            result => result?,
+        };
+        audit::init(conn, &key)?;
+        let dir = vault_path(name)?;
+        if !duress::exists(conn)? {
+            clear_duress(conn, &dir, &key, master_password.key_file().is_some())?;
+        }
+        sync::unlock(&dir, conn, &key)?;
+        audit::record(conn, &key, "unlock", detail)?;
+        Ok(None)
+    };
+    let decoy = match with_real_vault(name, unlock) {
+        Some(decoy) => decoy?,
+        None => {
+            let conn = open_existing_vault(name)?;
+            let decoy = unlock(&conn)?;
+            if decoy.is_none() {
+                server::open_vault(name, conn)?;
+            }
+            decoy
+        }
+    };
+    if let Some(key) = decoy {
+        let dir = vault_path(name)?.join(duress::DIR);
+        let conn = open_database(&dir)?;
+        audit::init(&conn, &key)?;
+        audit::record(&conn, &key, "unlock", detail)?;
+        // 只在当前连接上代替真实保险库，其他连接不受影响
+        server::open_decoy(name, dir, conn);
+    } else {
+        server::close_decoy(name);
+        server::switch_vault(name);
+    }
+    Ok(())
+}
+
 #[derive(Serialize)]
 struct Item {
     id: u64,
     name: String,
+    created_at: i64,
+    modified_at: i64,
+    accessed_at: Option<i64>,
+    two_factor: bool,
+    // 名称无法解密，name 为空
+    corrupt: bool,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Sort {
+    // 添加顺序
+    #[default]
+    Id,
+    // 名称
+    Name,
+    // 最近使用
+    Accessed,
+    // 最近修改
+    Modified,
 }
 
-// 获取所有密码
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ListOption {
+    sort: Sort,
+    offset: usize,
+    // None 表示不限制数量
+    limit: Option<usize>,
+}
+
+// 获取密码列表，option 为 None 时按添加顺序返回所有密码
+// 名称无法解密的条目标记为 corrupt，不影响其他条目，可以用 check_vault 检查并隔离
 #[rpc]
-fn list_password(master_password: String) -> Result<Vec<Item>, Error> {
+fn list_password(
+    master_password: MasterPassword,
+    option: Option<ListOption>,
+) -> Result<Vec<Item>, Error> {
     let key = decrypt_master_key(master_password)?;
+    let option = option.unwrap_or_default();
     let db = db();
-    let mut stmt = db
-        .conn()
-        .map_err(err!())?
-        .prepare("SELECT id, key FROM vault ORDER BY id")
-        .map_err(err!())?;
-    let mut rows = stmt.query([]).map_err(err!())?;
+    purge_expired_trash(db.conn().map_err(err!())?)?;
+
+    // 名称是加密的，只能全部解密后排序
+    let by_name = option.sort == Sort::Name;
+    let order = match option.sort {
+        Sort::Id | Sort::Name => "id",
+        Sort::Accessed => "accessed_at IS NULL, accessed_at DESC, id DESC",
+        Sort::Modified => "modified_at DESC, id DESC",
+    };
+    let (limit, offset) = if by_name {
+        (-1, 0)
+    } else {
+        (option.limit.map_or(-1, |v| v as i64), option.offset)
+    };
+    let sql = format!(
+        "SELECT id, key, created_at, modified_at, accessed_at, two_factor FROM vault WHERE deleted_at IS NULL ORDER BY {} LIMIT ? OFFSET ?",
+        order
+    );
+    let mut stmt = db.conn().map_err(err!())?.prepare(&sql).map_err(err!())?;
+    let mut rows = stmt.query(params![limit, offset]).map_err(err!())?;
     let mut list = Vec::new();
-    loop {
-        match rows.next().map_err(err!())? {
-            Some(row) => {
-                let name: Vec<u8> = row.get(1).map_err(err!())?;
-                let name = key_decrypt(&key, name)?;
-                list.push(Item {
-                    id: row.get(0).map_err(err!())?,
-                    name: String::from_utf8(name).map_err(err!())?,
-                })
+    while let Some(row) = rows.next().map_err(err!())? {
+        let id = row.get(0).map_err(err!())?;
+        let name: Vec<u8> = row.get(1).map_err(err!())?;
+        let name = match key_decrypt(&key, name).map(String::from_utf8) {
+            Ok(Ok(name)) => Some(name),
+            Ok(Err(_)) | Err(Error::Corrupted) => {
+                error!("vault entry {} is corrupted", id);
+                None
             }
-            None => break,
-        }
+            Err(err) => return Err(err),
+        };
+        list.push(Item {
+            id,
+            corrupt: name.is_none(),
+            name: name.unwrap_or_default(),
+            created_at: row.get(2).map_err(err!())?,
+            modified_at: row.get(3).map_err(err!())?,
+            accessed_at: row.get(4).map_err(err!())?,
+            two_factor: row.get(5).map_err(err!())?,
+        })
+    }
+
+    if by_name {
+        list.sort_by_cached_key(|item| item.name.to_lowercase());
+        let limit = option.limit.unwrap_or(usize::MAX);
+        list = list.into_iter().skip(option.offset).take(limit).collect();
     }
     Ok(list)
 }
@@ -126,16 +360,19 @@ struct Password {
     password: String,
 }
 
-// 获取单个密码
+// 获取单个密码，并更新最后使用时间
 #[rpc]
-fn get_password(master_password: String, id: u64) -> Result<Password, Error> {
+fn get_password(master_password: MasterPassword, id: u64) -> Result<Password, Error> {
     let key = decrypt_master_key(master_password)?;
-    const SQL: &str = "SELECT key, value FROM vault WHERE id=?";
-    let (name, password): (Vec<u8>, Vec<u8>) = db()
-        .conn()
-        .map_err(err!())?
-        .query_row(SQL, [id], |row| Ok((row.get(0)?, row.get(1)?)))
-        .map_err(err!())?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let (name, password) = live_entry(conn, id)?;
+    const UPDATE_SQL: &str = "UPDATE vault SET accessed_at=? WHERE id=?";
+    db::untracked(|| {
+        conn.execute(UPDATE_SQL, params![now()?, id])
+            .map_err(err!())
+    })?;
+    audit::record(conn, &key, "reveal", &id.to_string())?;
 
     let name = key_decrypt(&key, name)?;
     let password = key_decrypt(key, password)?;
@@ -145,6 +382,13 @@ fn get_password(master_password: String, id: u64) -> Result<Password, Error> {
     })
 }
 
+// 读取不在回收站中的条目的名称和密码，条目不存在或者已移到回收站时返回 QueryReturnedNoRows
+fn live_entry(conn: &Connection, id: u64) -> crate::Result<(Vec<u8>, Vec<u8>)> {
+    const SQL: &str = "SELECT key, value FROM vault WHERE id=? AND deleted_at IS NULL";
+    conn.query_row(SQL, [id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(err!())
+}
+
 // 生成密码
 #[rpc]
 fn make_password(option: PasswordOption) -> Result<String, Infallible> {
@@ -173,276 +417,2522 @@ fn make_password(option: PasswordOption) -> Result<String, Infallible> {
 
 // 添加密码
 #[rpc]
-fn add_password(master_password: String, name: String, password: String) -> Result<(), Error> {
+fn add_password(
+    master_password: MasterPassword,
+    name: String,
+    password: String,
+) -> Result<(), Error> {
     let key = decrypt_master_key(master_password)?;
     let name = key_encrypt(&key, name).map_err(err!())?;
     let password = key_encrypt(key, password).map_err(err!())?;
 
-    const SQL: &str = "INSERT INTO vault (key, value) VALUES (?, ?)";
+    let now = now()?;
+    const SQL: &str = "INSERT INTO vault (key, value, created_at, modified_at) VALUES (?, ?, ?, ?)";
     db().conn()
         .map_err(err!())?
-        .execute(SQL, params![name, password])
+        .execute(SQL, params![name, password, now, now])
         .map_err(err!())?;
     Ok(())
 }
 
 // 修改密码
 #[rpc]
-fn change_password(master_password: String, new_password: String) -> Result<(), Error> {
-    let key = decrypt_master_key(master_password)?;
-    let new_key = password_encrypt(new_password, key).map_err(err!())?;
+fn change_password(master_password: MasterPassword, new_password: String) -> Result<(), Error> {
+    let key = decrypt_master_key(&master_password)?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let key_file = keyfile::current(&tx, &master_password)?;
+    let new_key = keyfile::wrap(&tx, &new_password, key_file.as_ref(), &key)?;
     const SQL: &str = "UPDATE conf SET value=? WHERE key='key'";
-    db().conn()
-        .map_err(err!())?
-        .execute(SQL, [base64::encode(new_key)])
-        .map_err(err!())?;
+    tx.execute(SQL, [new_key]).map_err(err!())?;
+    audit::record(&tx, &key, "change_master_password", "")?;
+    tx.commit().map_err(err!())?;
     Ok(())
 }
 
-// 更新密码
+// 生成新的恢复密钥，替换已有的，shares 不为空时同时生成分片
 #[rpc]
-fn update_password(
-    master_password: String,
-    id: u64,
-    name: String,
-    password: String,
-) -> Result<(), Error> {
+fn set_recovery(
+    master_password: MasterPassword,
+    shares: Option<recovery::Shares>,
+) -> Result<recovery::Kit, Error> {
+    if shares.as_ref().is_some_and(|v| !v.valid()) {
+        return Err(Error::InvalidShares);
+    }
     let key = decrypt_master_key(master_password)?;
-    let name = key_encrypt(&key, name).map_err(err!())?;
-    let password = key_encrypt(key, password).map_err(err!())?;
-    const SQL: &str = "UPDATE vault SET key=?, value=? WHERE id=?";
-    db().conn()
-        .map_err(err!())?
-        .execute(SQL, params![name, password, id])
-        .map_err(err!())?;
-    Ok(())
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let kit = recovery::create(&tx, &key, shares.as_ref())?;
+    let detail = match shares {
+        Some(shares) => format!("{}/{}", shares.threshold, shares.total),
+        None => String::new(),
+    };
+    audit::record(&tx, &key, "set_recovery", &detail)?;
+    tx.commit().map_err(err!())?;
+    Ok(kit)
 }
 
-// 删除密码
+// 删除恢复密钥，已有的恢复密钥和分片失效
 #[rpc]
-fn delete_password(master_password: String, id: u64) -> Result<(), Error> {
-    decrypt_master_key(master_password)?;
-    db().conn()
-        .map_err(err!())?
-        .execute("DELETE FROM vault WHERE id=?", [id])
-        .map_err(err!())?;
+fn remove_recovery(master_password: MasterPassword) -> Result<(), Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    recovery::remove(conn)?;
+    audit::record(conn, &key, "remove_recovery", "")?;
     Ok(())
 }
 
-#[derive(Serialize)]
-struct Count {
-    ignore: usize,
-    insert: usize,
-}
-
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum Source {
-    // 文件
-    File(String),
-    // 数据
-    Data(Vec<(String, String)>),
+// 是否设置了恢复密钥
+#[rpc]
+fn is_recovery_set() -> crate::Result<bool> {
+    real_conf(recovery::is_set)
 }
 
-// 导入密码
+// 忘记主密码时使用恢复密钥或者足够的分片设置新的主密码。不需要主密码，同一客户端连续失败时暂时拒绝
 #[rpc]
-fn import_password(
-    master_password: String,
-    decrypt_password: Option<String>,
-    source: Source,
-) -> Result<Count, Error> {
-    let key = decrypt_master_key(&master_password)?;
-    let mut data = match source {
-        Source::File(path) => {
-            let data = read(&path).map_err(err!());
-            let _ = remove_file(path);
-            match from_slice(&data?) {
-                Ok(data) => data,
-                Err(err) => {
-                    error!("{:?}", err);
-                    return Err(Error::DeserializeFailed);
-                }
-            }
-        }
-        Source::Data(data) => data,
-    };
-
-    let mut count = Count {
-        ignore: 0,
-        insert: 0,
-    };
-    if data.is_empty() {
-        return Ok(count);
+fn recover_master_password(secret: recovery::Secret, new_password: String) -> Result<(), Error> {
+    // 诱饵保险库和真实保险库的结果一致，诱饵保险库没有恢复密钥，会返回 InvalidRecoveryKey
+    if !real_conf(recovery::is_set)? {
+        return Err(Error::RecoveryNotSet);
     }
-
-    let decrypt_password = decrypt_password.unwrap_or(master_password);
-    let decrypt_key = base64::decode(data.pop().unwrap().0).map_err(err!())?;
-    let decrypt_key = match password_decrypt(decrypt_password, decrypt_key).map_err(err!())? {
-        Some(key) => key,
-        None => return Err(WrongPassword),
-    };
-
-    let entries = get_all_password_as_map(&key)?;
-    let mut insert = Vec::new();
-    for (name, password) in data {
-        let name = key_decrypt(&decrypt_key, &base64::decode(&name).map_err(err!())?)?;
-        let password = key_decrypt(&decrypt_key, &base64::decode(&password).map_err(err!())?)?;
-        match entries.get(&name) {
-            Some(v) if v.contains(&password) => count.ignore += 1,
-            _ => {
-                count.insert += 1;
-                insert.push(key_encrypt(&key, name).map_err(err!())?);
-                insert.push(key_encrypt(&key, password).map_err(err!())?);
-            }
-        }
+    let name = current_vault().ok_or(err!(Unavailable))?;
+    let dir = vault_dir()?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let key = recover_key(conn, &name, &secret)?;
+    let kind = secret_kind(&secret);
+    // 密钥文件可能也丢失了，恢复后只需要新的主密码。胁迫密码的密钥文件设置不再一致，一并删除
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let new_key = keyfile::wrap(&tx, &new_password, None, &key)?;
+    const SQL: &str = "UPDATE conf SET value=? WHERE key='key'";
+    tx.execute(SQL, [new_key]).map_err(err!())?;
+    if duress::exists(&tx)? {
+        clear_duress(&tx, &dir, &key, false)?;
     }
+    audit::record(&tx, &key, "recover_master_password", kind)?;
+    tx.commit().map_err(err!())?;
+    Ok(())
+}
 
-    if !insert.is_empty() {
-        let mut values = Vec::with_capacity(insert.len());
-        for v in &insert {
-            values.push(v.to_sql().map_err(err!())?);
+// 用恢复密钥或分片解密保险库 name 的密钥，失败时记录审计日志，同一客户端连续失败时暂时拒绝
+fn recover_key(conn: &Connection, name: &str, secret: &recovery::Secret) -> Result<Vec<u8>, Error> {
+    if !attempt::check(client_addr(), "recover", name) {
+        return Err(Error::TooManyAttempts);
+    }
+    match recovery::recover(conn, secret)? {
+        Some(key) => {
+            attempt::reset(client_addr(), "recover", name);
+            Ok(key)
+        }
+        None => {
+            audit::record_locked(conn, "recover_failed", secret_kind(secret))?;
+            Err(Error::InvalidRecoveryKey)
         }
-
-        let placeholder: Vec<_> = (0..insert.len()).step_by(2).map(|_| "(?, ?)").collect();
-        let sql = &format!(
-            "INSERT INTO vault (key, value) VALUES {}",
-            placeholder.join(", ")
-        );
-        let values: Vec<&dyn ToSql> = values.iter().map(|v| -> &dyn ToSql { v }).collect();
-        db().conn()
-            .map_err(err!())?
-            .execute(sql, values.as_slice())
-            .map_err(err!())?;
     }
-
-    Ok(count)
 }
 
-// 所有密码
-fn get_all_password_as_map(key: &[u8]) -> Result<HashMap<Vec<u8>, HashSet<Vec<u8>>>, Error> {
-    let mut map: HashMap<Vec<u8>, HashSet<Vec<u8>>> = HashMap::new();
-    for (name, password) in get_all_password()? {
-        let name = key_decrypt(key, &name)?;
-        let password = key_decrypt(key, &password)?;
-        match map.entry(name) {
-            Entry::Occupied(mut entry) => {
-                entry.get_mut().insert(password);
-            }
-            Entry::Vacant(entry) => {
-                let mut s = HashSet::new();
-                s.insert(password);
-                entry.insert(s);
-            }
-        }
+// 审计日志中的恢复方式
+fn secret_kind(secret: &recovery::Secret) -> &'static str {
+    match secret {
+        recovery::Secret::Key(_) => "key",
+        recovery::Secret::Shares(_) => "shares",
     }
-    Ok(map)
 }
 
-// 所有密码
-fn get_all_password() -> crate::Result<Vec<(Vec<u8>, Vec<u8>)>> {
-    let mut list = Vec::new();
+// 开启或更换密钥文件，之后解锁需要主密码和新的密钥文件，key_file 为密钥文件内容，base64 编码
+#[rpc]
+fn set_key_file(master_password: MasterPassword, key_file: String) -> Result<(), Error> {
+    let hash = keyfile::hash(&key_file).ok_or(Error::InvalidKeyFile)?;
+    let key = decrypt_master_key(&master_password)?;
+    let dir = vault_dir()?;
     let db = db();
-    let mut stmt = db
-        .conn()
-        .map_err(err!())?
-        .prepare("SELECT key, value FROM vault ORDER BY id")
-        .map_err(err!())?;
-    let mut rows = stmt.query([]).map_err(err!())?;
-    loop {
-        match rows.next().map_err(err!())? {
-            Some(row) => {
-                let name: Vec<u8> = row.get(0).map_err(err!())?;
-                let value: Vec<u8> = row.get(1).map_err(err!())?;
-                list.push((name, value));
-            }
-            None => break,
-        }
+    let conn = db.conn().map_err(err!())?;
+    if duress::is_set(conn, &key)? {
+        return Err(Error::DuressPasswordSet);
     }
-    Ok(list)
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let new_key = keyfile::wrap(&tx, master_password.password(), Some(&hash), &key)?;
+    const SQL: &str = "UPDATE conf SET value=? WHERE key='key'";
+    tx.execute(SQL, [new_key]).map_err(err!())?;
+    if duress::exists(&tx)? {
+        clear_duress(&tx, &dir, &key, true)?;
+    }
+    audit::record(&tx, &key, "set_key_file", "")?;
+    tx.commit().map_err(err!())?;
+    Ok(())
 }
 
-// 导出密码, 如果 file 不为 None，导出到 file，返回 None，否则返回数据
+// 关闭密钥文件，之后只需要主密码
 #[rpc]
-fn export_password(
-    master_password: String,
-    file: Option<String>,
-) -> Result<Option<Vec<(String, String)>>, Error> {
+fn remove_key_file(master_password: MasterPassword) -> Result<(), Error> {
     let key = decrypt_master_key(&master_password)?;
+    let dir = vault_dir()?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    if duress::is_set(conn, &key)? {
+        return Err(Error::DuressPasswordSet);
+    }
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let new_key = keyfile::wrap(&tx, master_password.password(), None, &key)?;
+    const SQL: &str = "UPDATE conf SET value=? WHERE key='key'";
+    tx.execute(SQL, [new_key]).map_err(err!())?;
+    if duress::exists(&tx)? {
+        clear_duress(&tx, &dir, &key, false)?;
+    }
+    audit::record(&tx, &key, "remove_key_file", "")?;
+    tx.commit().map_err(err!())?;
+    Ok(())
+}
 
-    let mut list: Vec<_> = get_all_password()?
-        .into_iter()
-        .map(|(name, value)| (base64::encode(name), base64::encode(value)))
-        .collect();
+// 是否开启了密钥文件，开启后解锁需要同时提供密钥文件
+#[rpc]
+fn is_key_file_set() -> crate::Result<bool> {
+    real_conf(keyfile::is_set)
+}
 
-    if !list.is_empty() {
-        let encrypt_key = password_encrypt(master_password, key).map_err(err!())?;
-        list.push((base64::encode(encrypt_key), String::new()));
+// 读取真实保险库的设置，打开诱饵保险库时返回的结果需要和真实保险库一致
+fn real_conf<T>(f: impl FnOnce(&Connection) -> crate::Result<T>) -> crate::Result<T> {
+    if !is_decoy() {
+        return f(db().conn().map_err(err!())?);
     }
+    let dir = vault_dir()?;
+    let dir = dir.parent().ok_or(err!(Unavailable))?;
+    f(&open_database(dir)?)
+}
 
-    match file {
-        Some(file) => {
-            let file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(file)
-                .map_err(err!())?;
-            serde_json::to_writer(file, &list).map_err(err!())?;
-            Ok(None)
-        }
-        None => Ok(Some(list)),
+// 设置胁迫密码，解锁时输入胁迫密码会打开诱饵保险库，wipe 为 true 时同时销毁保险库密钥。
+// 诱饵保险库已存在并且胁迫密码不变时保留其中的数据，否则重新创建空的诱饵保险库
+#[rpc]
+fn set_duress_password(
+    master_password: MasterPassword,
+    duress_password: String,
+    wipe: bool,
+) -> Result<(), Error> {
+    let key = decrypt_master_key(&master_password)?;
+    if duress_password.is_empty() || duress_password == master_password.password() {
+        return Err(Error::InvalidDuressPassword);
     }
+    let dir = vault_dir()?.join(duress::DIR);
+    // 诱饵保险库使用和真实保险库相同的密钥文件，master_password 已经验证过，开启了密钥文件时包括密钥文件
+    let duress = master_password.with_password(duress_password);
+    let decoy_key = match dir.join("database").is_file() {
+        true => match master_key(&open_database(&dir)?, &duress) {
+            Err(WrongPassword) => None,
+            result => Some(result?),
+        },
+        false => None,
+    };
+    let decoy_key = match decoy_key {
+        Some(v) => v,
+        None => create_decoy(&dir, duress.clone())?,
+    };
+
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    duress::set(conn, &key, &duress, &decoy_key, wipe)?;
+    audit::record(conn, &key, "set_duress_password", "")?;
+    Ok(())
 }
 
+// 删除胁迫密码，诱饵保险库换成随机密码加密的空保险库
 #[rpc]
-async fn get_network_port() -> Result<Option<u16>, Error> {
-    Ok(query_network_port().await?)
+fn remove_duress_password(master_password: MasterPassword) -> Result<(), Error> {
+    let key = decrypt_master_key(&master_password)?;
+    let dir = vault_dir()?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    clear_duress(conn, &dir, &key, master_password.key_file().is_some())?;
+    audit::record(conn, &key, "remove_duress_password", "")?;
+    Ok(())
 }
 
+// 是否设置了胁迫密码，需要主密码，避免不知道主密码时看出设置了胁迫密码
 #[rpc]
-async fn enable_network_access() -> Result<u16, Error> {
-    Ok(listen_any_addr().await?)
+fn is_duress_password_set(master_password: MasterPassword) -> Result<bool, Error> {
+    let key = decrypt_master_key(&master_password)?;
+    Ok(duress::is_set(db().conn().map_err(err!())?, &key)?)
 }
 
-#[rpc]
-fn disable_network_access() -> Result<(), Error> {
-    Ok(close_any_addr()?)
+// 重新创建空的诱饵保险库，返回诱饵保险库密钥
+fn create_decoy(dir: &Path, master_password: MasterPassword) -> crate::Result<Vec<u8>> {
+    if dir.exists() {
+        remove_dir_all(dir).map_err(err!())?;
+    }
+    create_dir_all(dir).map_err(err!())?;
+    init_master_key(&open_database(dir)?, master_password)
 }
 
-// 解密密码加密使用的 key
-fn decrypt_master_key(password: impl AsRef<[u8]>) -> Result<Vec<u8>, Error> {
-    const SQL: &str = "SELECT value FROM conf WHERE key='key'";
-    let key: String = db()
+// 保存没有设置胁迫密码时的配置：随机密码加密的空诱饵保险库和同样格式的随机配置，从磁盘上看和设置了
+// 胁迫密码一样。dir 为真实保险库目录，key_file 为真实保险库是否开启了密钥文件
+fn clear_duress(conn: &Connection, dir: &Path, key: &[u8], key_file: bool) -> crate::Result<()> {
+    let mut random = [0u8; 32];
+    rand_bytes(&mut random).map_err(err!())?;
+    let password = base64::encode(random);
+    let decoy = match key_file {
+        true => {
+            rand_bytes(&mut random).map_err(err!())?;
+            MasterPassword::KeyFile {
+                password,
+                key_file: base64::encode(random),
+            }
+        }
+        false => MasterPassword::Password(password),
+    };
+    let decoy_key = create_decoy(&dir.join(duress::DIR), decoy.clone())?;
+    duress::clear(conn, key, &decoy, &decoy_key)
+}
+
+// 更新密码，密码有变化时旧密码保存到历史记录
+#[rpc]
+fn update_password(
+    master_password: MasterPassword,
+    id: u64,
+    name: String,
+    password: String,
+) -> Result<(), Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    let tx = db
         .conn()
         .map_err(err!())?
-        .query_row(SQL, [], |row| row.get(0))
+        .unchecked_transaction()
         .map_err(err!())?;
-    let key = base64::decode(key).map_err(err!())?;
-    match password_decrypt(password.as_ref(), &key).map_err(err!())? {
-        Some(key) => Ok(key),
-        None => Err(WrongPassword),
+
+    let (_, old) = live_entry(&tx, id)?;
+    if key_decrypt(&key, &old)? != password.as_bytes() {
+        save_history(&tx, id, &old)?;
     }
+
+    let name = key_encrypt(&key, name).map_err(err!())?;
+    let password = key_encrypt(key, password).map_err(err!())?;
+    const SQL: &str = "UPDATE vault SET key=?, value=?, modified_at=? WHERE id=?";
+    tx.execute(SQL, params![name, password, now()?, id])
+        .map_err(err!())?;
+    tx.commit().map_err(err!())?;
+    Ok(())
 }
 
-fn key_decrypt(key: impl AsRef<[u8]>, data: impl AsRef<[u8]>) -> Result<Vec<u8>, Error> {
-    match crate::crypto::key_decrypt(key.as_ref(), data.as_ref()).map_err(err!())? {
-        Some(data) => Ok(data),
-        None => Err(WrongPassword),
-    }
+// 删除密码，移到回收站
+#[rpc]
+fn delete_password(master_password: MasterPassword, id: u64) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    const SQL: &str = "UPDATE vault SET deleted_at=? WHERE id=? AND deleted_at IS NULL";
+    db().conn()
+        .map_err(err!())?
+        .execute(SQL, params![now()?, id])
+        .map_err(err!())?;
+    Ok(())
 }
 
-#[derive(Deserialize)]
-struct PasswordOption {
-    len: usize,
-    uppercase: bool,
-    lowercase: bool,
-    digit: bool,
-    special: bool,
+#[derive(Serialize)]
+struct TrashItem {
+    id: u64,
+    name: String,
+    deleted_at: i64,
 }
 
-impl PasswordOption {
-    fn chars(&self) -> Vec<&'static [u8]> {
-        static UPPERCASE: &'static [u8] = &[
+// 获取回收站中的密码，按删除时间倒序
+#[rpc]
+fn list_trash(master_password: MasterPassword) -> Result<Vec<TrashItem>, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    purge_expired_trash(db.conn().map_err(err!())?)?;
+    const SQL: &str =
+        "SELECT id, key, deleted_at FROM vault WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC";
+    let mut stmt = db.conn().map_err(err!())?.prepare(SQL).map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    let mut list = Vec::new();
+    while let Some(row) = rows.next().map_err(err!())? {
+        let name: Vec<u8> = row.get(1).map_err(err!())?;
+        let name = key_decrypt(&key, name)?;
+        list.push(TrashItem {
+            id: row.get(0).map_err(err!())?,
+            name: String::from_utf8(name).map_err(err!())?,
+            deleted_at: row.get(2).map_err(err!())?,
+        });
+    }
+    Ok(list)
+}
+
+// 从回收站恢复密码
+#[rpc]
+fn restore_trash(master_password: MasterPassword, id: u64) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    const SQL: &str = "UPDATE vault SET deleted_at=NULL WHERE id=?";
+    db().conn()
+        .map_err(err!())?
+        .execute(SQL, [id])
+        .map_err(err!())?;
+    Ok(())
+}
+
+// 彻底删除回收站中的密码，id 为 None 时清空回收站
+#[rpc]
+fn purge_trash(master_password: MasterPassword, id: Option<u64>) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    match id {
+        Some(id) => purge(conn, "id=? AND deleted_at IS NOT NULL", [id])?,
+        None => purge(conn, "deleted_at IS NOT NULL", [])?,
+    }
+    Ok(())
+}
+
+// 获取回收站保留天数，0 表示不自动清理
+#[rpc]
+fn get_trash_retention() -> crate::Result<u64> {
+    trash_retention(db().conn().map_err(err!())?)
+}
+
+// 设置回收站保留天数，0 表示不自动清理
+#[rpc]
+fn set_trash_retention(master_password: MasterPassword, days: u64) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    set_conf(conn, "trash_retention", &days.to_string())?;
+    purge_expired_trash(conn)?;
+    Ok(())
+}
+
+// 默认回收站保留天数
+const DEFAULT_TRASH_RETENTION: u64 = 30;
+
+fn trash_retention(conn: &Connection) -> crate::Result<u64> {
+    match get_conf(conn, "trash_retention")? {
+        Some(days) => days.parse().map_err(err!()),
+        None => Ok(DEFAULT_TRASH_RETENTION),
+    }
+}
+
+// 彻底删除回收站中超过保留天数的密码
+fn purge_expired_trash(conn: &Connection) -> crate::Result<()> {
+    let days = trash_retention(conn)?;
+    if days == 0 {
+        return Ok(());
+    }
+    // 保留天数太大时不会过期
+    let now = now()?;
+    let before = i64::try_from(days)
+        .ok()
+        .and_then(|days| days.checked_mul(24 * 3600))
+        .and_then(|seconds| now.checked_sub(seconds));
+    match before {
+        Some(before) => purge(conn, "deleted_at IS NOT NULL AND deleted_at<=?", [before]),
+        None => Ok(()),
+    }
+}
+
+// 彻底删除满足条件的密码及其历史记录
+fn purge(conn: &Connection, condition: &str, params: impl Params + Copy) -> crate::Result<()> {
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let sql = format!(
+        "DELETE FROM history WHERE vault_id IN (SELECT id FROM vault WHERE {})",
+        condition
+    );
+    tx.execute(&sql, params).map_err(err!())?;
+    let sql = format!("DELETE FROM vault WHERE {}", condition);
+    tx.execute(&sql, params).map_err(err!())?;
+    tx.commit().map_err(err!())
+}
+
+#[derive(Serialize)]
+struct History {
+    id: u64,
+    time: i64,
+}
+
+// 获取密码的历史记录，按时间倒序
+#[rpc]
+fn list_password_history(master_password: MasterPassword, id: u64) -> Result<Vec<History>, Error> {
+    decrypt_master_key(master_password)?;
+    let db = db();
+    let mut stmt = db
+        .conn()
+        .map_err(err!())?
+        .prepare("SELECT id, time FROM history WHERE vault_id=? ORDER BY id DESC")
+        .map_err(err!())?;
+    let mut rows = stmt.query([id]).map_err(err!())?;
+    let mut list = Vec::new();
+    while let Some(row) = rows.next().map_err(err!())? {
+        list.push(History {
+            id: row.get(0).map_err(err!())?,
+            time: row.get(1).map_err(err!())?,
+        });
+    }
+    Ok(list)
+}
+
+// 获取某个历史密码
+#[rpc]
+fn get_password_history(master_password: MasterPassword, id: u64) -> Result<String, Error> {
+    let key = decrypt_master_key(master_password)?;
+    const SQL: &str = "SELECT value FROM history WHERE id=?";
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let password: Vec<u8> = conn
+        .query_row(SQL, [id], |row| row.get(0))
+        .map_err(err!())?;
+    audit::record(conn, &key, "reveal_history", &id.to_string())?;
+    let password = key_decrypt(key, password)?;
+    Ok(String::from_utf8(password).map_err(err!())?)
+}
+
+// 恢复历史密码，当前密码保存到历史记录
+#[rpc]
+fn restore_password_history(master_password: MasterPassword, id: u64) -> Result<(), Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    let tx = db
+        .conn()
+        .map_err(err!())?
+        .unchecked_transaction()
+        .map_err(err!())?;
+
+    const SELECT_SQL: &str =
+        "SELECT h.vault_id, h.value, v.value FROM history h JOIN vault v ON v.id=h.vault_id WHERE h.id=?";
+    let (vault_id, password, old): (u64, Vec<u8>, Vec<u8>) = tx
+        .query_row(SELECT_SQL, [id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .map_err(err!())?;
+    if key_decrypt(&key, &password)? == key_decrypt(&key, &old)? {
+        return Ok(());
+    }
+
+    save_history(&tx, vault_id, &old)?;
+    const SQL: &str = "UPDATE vault SET value=?, modified_at=? WHERE id=?";
+    tx.execute(SQL, params![password, now()?, vault_id])
+        .map_err(err!())?;
+    tx.commit().map_err(err!())?;
+    Ok(())
+}
+
+// 获取每个密码保留的历史记录数
+#[rpc]
+fn get_history_limit() -> crate::Result<usize> {
+    history_limit(db().conn().map_err(err!())?)
+}
+
+// 设置每个密码保留的历史记录数，超出的旧记录会被删除
+#[rpc]
+fn set_history_limit(master_password: MasterPassword, limit: usize) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    let db = db();
+    let tx = db
+        .conn()
+        .map_err(err!())?
+        .unchecked_transaction()
+        .map_err(err!())?;
+    set_conf(&tx, "history_limit", &limit.to_string())?;
+    const SQL: &str = "DELETE FROM history WHERE id NOT IN (
+        SELECT id FROM (
+            SELECT id, ROW_NUMBER() OVER (PARTITION BY vault_id ORDER BY id DESC) AS n FROM history
+        ) WHERE n <= ?
+    )";
+    tx.execute(SQL, [limit]).map_err(err!())?;
+    tx.commit().map_err(err!())?;
+    Ok(())
+}
+
+// 默认每个密码保留的历史记录数
+const DEFAULT_HISTORY_LIMIT: usize = 10;
+
+fn history_limit(conn: &Connection) -> crate::Result<usize> {
+    match get_conf(conn, "history_limit")? {
+        Some(limit) => limit.parse().map_err(err!()),
+        None => Ok(DEFAULT_HISTORY_LIMIT),
+    }
+}
+
+// 保存历史密码，并删除超出数量限制的旧记录
+fn save_history(conn: &Connection, id: u64, password: &[u8]) -> Result<(), Error> {
+    let limit = history_limit(conn)?;
+    if limit == 0 {
+        return Ok(());
+    }
+
+    const SQL: &str = "INSERT INTO history (vault_id, value, time) VALUES (?, ?, ?)";
+    conn.execute(SQL, params![id, password, now()?])
+        .map_err(err!())?;
+
+    const DELETE_SQL: &str = "DELETE FROM history WHERE vault_id=? AND id NOT IN (
+        SELECT id FROM history WHERE vault_id=? ORDER BY id DESC LIMIT ?
+    )";
+    conn.execute(DELETE_SQL, params![id, id, limit])
+        .map_err(err!())?;
+    Ok(())
+}
+
+// 设置是否开启了两步验证
+#[rpc]
+fn set_two_factor(master_password: MasterPassword, id: u64, enabled: bool) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    Ok(update_two_factor(
+        db().conn().map_err(err!())?,
+        id,
+        enabled,
+    )?)
+}
+
+// 条目不存在或者已移到回收站时返回 QueryReturnedNoRows，和 live_entry 一致
+fn update_two_factor(conn: &Connection, id: u64, enabled: bool) -> crate::Result<()> {
+    const SQL: &str = "UPDATE vault SET two_factor=? WHERE id=? AND deleted_at IS NULL";
+    match conn.execute(SQL, params![enabled, id]).map_err(err!())? {
+        0 => Err(err!(rusqlite::Error::QueryReturnedNoRows)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize)]
+struct HealthItem {
+    id: u64,
+    name: String,
+    // 强度，0 最弱，4 最强
+    strength: u8,
+    // 健康分，0 - 100
+    score: u8,
+    weak: bool,
+    reused: bool,
+    old: bool,
+    no_two_factor: bool,
+    // 距上次修改的天数
+    age: u64,
+}
+
+#[derive(Serialize, Default)]
+struct HealthSummary {
+    total: usize,
+    weak: usize,
+    reused: usize,
+    old: usize,
+    no_two_factor: usize,
+    // 没有问题的密码数
+    healthy: usize,
+    // 健康分，0 - 100
+    score: u8,
+}
+
+#[derive(Serialize)]
+struct HealthReport {
+    items: Vec<HealthItem>,
+    // 重复使用同一密码的分组，每组为密码 id
+    reused: Vec<Vec<u64>>,
+    summary: HealthSummary,
+}
+
+// 默认超过多少天未修改算旧密码
+const DEFAULT_MAX_AGE: u64 = 365;
+
+// 密码健康检查：弱密码、重复密码、旧密码、未开启两步验证
+#[rpc]
+fn password_health(
+    master_password: MasterPassword,
+    max_age: Option<u64>,
+) -> Result<HealthReport, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let hash_key = derive_key(&key, "health").map_err(err!())?;
+    let max_age = max_age.unwrap_or(DEFAULT_MAX_AGE);
+    let now = now()?;
+
+    let db = db();
+    const SQL: &str =
+        "SELECT id, key, value, modified_at, two_factor FROM vault WHERE deleted_at IS NULL ORDER BY id";
+    let mut stmt = db.conn().map_err(err!())?.prepare(SQL).map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    let mut items = Vec::new();
+    // 用 HMAC 分组，不在内存中保留明文
+    let mut groups: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    while let Some(row) = rows.next().map_err(err!())? {
+        let name: Vec<u8> = row.get(1).map_err(err!())?;
+        let password: Vec<u8> = row.get(2).map_err(err!())?;
+        let modified_at: i64 = row.get(3).map_err(err!())?;
+        let two_factor: bool = row.get(4).map_err(err!())?;
+        let name = String::from_utf8(key_decrypt(&key, name)?).map_err(err!())?;
+        let password = String::from_utf8(key_decrypt(&key, password)?).map_err(err!())?;
+
+        let hash = hmac_sha256(hash_key, &password).map_err(err!())?;
+        groups.entry(hash).or_default().push(items.len());
+
+        let strength = health::score(&password);
+        let age = (now - modified_at).max(0) as u64 / (24 * 3600);
+        items.push(HealthItem {
+            id: row.get(0).map_err(err!())?,
+            name,
+            strength,
+            score: 0,
+            weak: strength < 3,
+            reused: false,
+            old: age > max_age,
+            no_two_factor: !two_factor,
+            age,
+        });
+    }
+
+    let mut reused = Vec::new();
+    for (_, group) in groups {
+        if group.len() > 1 {
+            for &i in &group {
+                items[i].reused = true;
+            }
+            reused.push(group.iter().map(|&i| items[i].id).collect());
+        }
+    }
+
+    let mut summary = HealthSummary {
+        total: items.len(),
+        ..Default::default()
+    };
+    let mut total_score = 0;
+    for item in &mut items {
+        let mut score = 100 - (4 - item.strength as i32) * 15;
+        if item.reused {
+            score -= 25;
+        }
+        if item.old {
+            score -= 10;
+        }
+        if item.no_two_factor {
+            score -= 5;
+        }
+        item.score = score.max(0) as u8;
+        total_score += item.score as usize;
+
+        summary.weak += item.weak as usize;
+        summary.reused += item.reused as usize;
+        summary.old += item.old as usize;
+        summary.no_two_factor += item.no_two_factor as usize;
+        if !item.weak && !item.reused && !item.old {
+            summary.healthy += 1;
+        }
+    }
+    if !items.is_empty() {
+        summary.score = (total_score / items.len()) as u8;
+    }
+
+    Ok(HealthReport {
+        items,
+        reused,
+        summary,
+    })
+}
+
+// 泄露密码数据文件名
+const BREACH_DATA_FILE: &str = "hibp";
+
+// 导入 Have I Been Pwned 泄露密码数据，返回记录数
+#[rpc]
+async fn load_breach_data(master_password: MasterPassword, path: String) -> Result<u64, Error> {
+    decrypt_master_key(master_password)?;
+    let dest = data_dir()?.join(BREACH_DATA_FILE);
+    let count = spawn_blocking(move || hibp::import(Path::new(&path), &dest))
+        .await
+        .map_err(err!())??;
+    Ok(count)
+}
+
+#[derive(Serialize)]
+struct Breach {
+    id: u64,
+    name: String,
+    // 泄露次数
+    count: u64,
+}
+
+// 检查所有密码是否泄露，只返回泄露的密码
+#[rpc]
+fn check_breach(master_password: MasterPassword) -> Result<Vec<Breach>, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let index = breach_data()?;
+    let db = db();
+    const SQL: &str = "SELECT id, key, value FROM vault WHERE deleted_at IS NULL ORDER BY id";
+    let mut stmt = db.conn().map_err(err!())?.prepare(SQL).map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    let mut list = Vec::new();
+    while let Some(row) = rows.next().map_err(err!())? {
+        let password: Vec<u8> = row.get(2).map_err(err!())?;
+        let password = String::from_utf8(key_decrypt(&key, password)?).map_err(err!())?;
+        let count = hibp::lookup(&index, &password)?;
+        if count > 0 {
+            let name: Vec<u8> = row.get(1).map_err(err!())?;
+            list.push(Breach {
+                id: row.get(0).map_err(err!())?,
+                name: String::from_utf8(key_decrypt(&key, name)?).map_err(err!())?,
+                count,
+            });
+        }
+    }
+    Ok(list)
+}
+
+// 检查单个密码（如生成的密码）的泄露次数
+#[rpc]
+fn check_breach_password(password: String) -> Result<u64, Error> {
+    Ok(hibp::lookup(&breach_data()?, &password)?)
+}
+
+fn breach_data() -> Result<PathBuf, Error> {
+    let path = data_dir()?.join(BREACH_DATA_FILE);
+    if path.exists() {
+        Ok(path)
+    } else {
+        Err(Error::NoBreachData)
+    }
+}
+
+#[derive(Serialize, Default)]
+struct Count {
+    ignore: usize,
+    insert: usize,
+    // 覆盖已有密码的数量
+    overwrite: usize,
+    // 无法导入的内容
+    warnings: Vec<import::Warning>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Source {
+    // 文件
+    File(String),
+    // 数据
+    Data(Vec<(String, String)>),
+    // KeePass 数据库文件，key_file 为密钥文件
+    Kdbx {
+        kdbx: String,
+        key_file: Option<String>,
+    },
+    // Bitwarden JSON 文件，支持未加密和使用密码加密的
+    Bitwarden {
+        bitwarden: String,
+    },
+    // 1Password 1PUX 文件
+    OnePux {
+        onepux: String,
+    },
+    // 加密导出格式的数据，base64 编码，文件使用 File
+    Encrypted {
+        encrypted: String,
+    },
+    // age 加密的文件，identity 为身份文件
+    Age {
+        age: String,
+        identity: String,
+    },
+    // OpenPGP 加密的文件，key 为私钥文件，私钥的密码为 decrypt_password
+    Pgp {
+        pgp: String,
+        key: String,
+    },
+    // CSV 文件或数据，支持 Chrome/Edge、Firefox、Bitwarden 导出的格式，mapping 可以指定列名
+    Csv {
+        csv: CsvSource,
+        mapping: Option<csv::Mapping>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum CsvSource {
+    // 文件
+    File(String),
+    // 数据
+    Data(String),
+}
+
+// 名称相同、密码不同时的处理方式
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    // 跳过
+    Skip,
+    // 覆盖已有密码，旧密码保存到历史记录
+    Overwrite,
+    // 保留两者
+    KeepBoth,
+}
+
+// 导入策略
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum Strategy {
+    Skip,
+    Overwrite,
+    #[default]
+    KeepBoth,
+    // 逐条决定，键为预览结果中的 index，没有指定的跳过
+    Items(HashMap<usize, Action>),
+}
+
+impl Strategy {
+    fn action(&self, index: usize) -> Action {
+        match self {
+            Strategy::Skip => Action::Skip,
+            Strategy::Overwrite => Action::Overwrite,
+            Strategy::KeepBoth => Action::KeepBoth,
+            Strategy::Items(items) => items.get(&index).copied().unwrap_or(Action::Skip),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PreviewItem {
+    // 在导入数据中的序号
+    index: usize,
+    name: String,
+    // 名称相同的已有密码
+    id: Option<u64>,
+}
+
+// 导入预览
+#[derive(Serialize)]
+struct Preview {
+    // 新密码
+    new: Vec<PreviewItem>,
+    // 名称和密码都相同，不会导入
+    identical: Vec<PreviewItem>,
+    // 名称相同、密码不同
+    conflict: Vec<PreviewItem>,
+    // 无法导入的内容
+    warnings: Vec<import::Warning>,
+}
+
+// 导入密码，strategy 为 None 时保留两者
+#[rpc]
+fn import_password(
+    master_password: MasterPassword,
+    decrypt_password: Option<String>,
+    source: Source,
+    strategy: Option<Strategy>,
+) -> Result<Count, Error> {
+    let key = decrypt_master_key(&master_password)?;
+    let name = matches!(source, Source::Csv { .. }).then_some("csv");
+    let import = read_source(master_password, decrypt_password, source, true)?;
+    let count = merge_password(&key, import, &strategy.unwrap_or_default())?;
+    audit_import(db().conn().map_err(err!())?, &key, &count, name)?;
+    Ok(count)
+}
+
+// 预览导入结果，不写入数据，也不删除导入的文件
+#[rpc]
+fn preview_import(
+    master_password: MasterPassword,
+    decrypt_password: Option<String>,
+    source: Source,
+) -> Result<Preview, Error> {
+    let key = decrypt_master_key(&master_password)?;
+    let import = read_source(master_password, decrypt_password, source, false)?;
+    preview(&key, import)
+}
+
+// 本应用格式最后一项的标记，表示保险库密钥用 keyfile::seal 加密
+const SEALED: &str = "sealed";
+
+// 读取要导入的数据，remove 为 true 时删除本应用格式的导入文件
+fn read_source(
+    master_password: MasterPassword,
+    decrypt_password: Option<String>,
+    source: Source,
+    remove: bool,
+) -> Result<Import, Error> {
+    let decrypt_password =
+        decrypt_password.unwrap_or_else(|| master_password.password().to_string());
+    let mut data = match source {
+        Source::Kdbx { kdbx, key_file } => {
+            let data = read(kdbx).map_err(err!())?;
+            let key_file = match key_file {
+                Some(path) => Some(read(path).map_err(err!())?),
+                None => None,
+            };
+            return parsed(kdbx::read(&data, &decrypt_password, key_file.as_deref()));
+        }
+        Source::Bitwarden { bitwarden } => {
+            let data = read(bitwarden).map_err(err!())?;
+            return parsed(bitwarden::parse(&data, &decrypt_password));
+        }
+        Source::OnePux { onepux } => {
+            let data = read(onepux).map_err(err!())?;
+            return parsed(onepux::parse(&data).map(Some));
+        }
+        Source::Age { age, identity } => {
+            let data = read(age).map_err(err!())?;
+            let identity = read(identity).map_err(err!())?;
+            let import = recipient::x25519::decrypt(&data, &identity)
+                .and_then(|data| recipient::from_json(&data));
+            return parsed(import.map(Some));
+        }
+        Source::Pgp { pgp, key } => {
+            let data = read(pgp).map_err(err!())?;
+            let key = read(key).map_err(err!())?;
+            let import = recipient::pgp::decrypt(&data, &key, &decrypt_password)
+                .and_then(|data| data.map(|data| recipient::from_json(&data)).transpose());
+            return parsed(import);
+        }
+        Source::Encrypted { encrypted } => {
+            let data = base64::decode(encrypted).map_err(err!())?;
+            return parsed(encrypted::read(&data, &decrypt_password));
+        }
+        Source::Csv { csv, mapping } => return read_csv(csv, mapping, remove),
+        Source::File(path) => {
+            let data = read(&path).map_err(err!());
+            if remove {
+                let _ = remove_file(path);
+            }
+            let data = data?;
+            if encrypted::is_encrypted(&data) {
+                return parsed(encrypted::read(&data, &decrypt_password));
+            }
+            match from_slice(&data) {
+                Ok(data) => data,
+                Err(err) => {
+                    error!("{:?}", err);
+                    return Err(Error::DeserializeFailed);
+                }
+            }
+        }
+        Source::Data(data) => data,
+    };
+
+    let mut import = Import::default();
+    if data.is_empty() {
+        return Ok(import);
+    }
+
+    let (decrypt_key, marker) = data.pop().unwrap();
+    let decrypt_key = base64::decode(decrypt_key).map_err(err!())?;
+    let decrypt_key = if marker == SEALED {
+        let sealed = String::from_utf8(decrypt_key).map_err(err!())?;
+        let master_password = master_password.with_password(decrypt_password);
+        keyfile::unseal(&master_password, &sealed)?
+    } else {
+        // 旧版本导出的文件只用主密码加密
+        password_decrypt(decrypt_password, decrypt_key).map_err(err!())?
+    };
+    let decrypt_key = decrypt_key.ok_or(WrongPassword)?;
+
+    for (name, password) in data {
+        let name = key_decrypt(&decrypt_key, &base64::decode(&name).map_err(err!())?)?;
+        let password = key_decrypt(&decrypt_key, &base64::decode(&password).map_err(err!())?)?;
+        import.entries.push(import::Entry {
+            name: String::from_utf8(name).map_err(err!())?,
+            password: String::from_utf8(password).map_err(err!())?,
+            two_factor: false,
+        });
+    }
+    Ok(import)
+}
+
+// 读取 CSV，remove 为 true 时删除导入的文件
+fn read_csv(
+    source: CsvSource,
+    mapping: Option<csv::Mapping>,
+    remove: bool,
+) -> Result<Import, Error> {
+    let data = match source {
+        CsvSource::File(path) => {
+            let data = read(&path).map_err(err!());
+            if remove {
+                let _ = remove_file(path);
+            }
+            data?
+        }
+        CsvSource::Data(data) => data.into_bytes(),
+    };
+    parsed(csv::parse(&data, &mapping.unwrap_or_default()).map(Some))
+}
+
+// 解析结果，None 表示密码错误
+fn parsed(import: crate::Result<Option<Import>>) -> Result<Import, Error> {
+    match import {
+        Ok(Some(import)) => Ok(import),
+        Ok(None) => Err(WrongPassword),
+        Err(err) => {
+            error!("{:?}", err);
+            Err(Error::DeserializeFailed)
+        }
+    }
+}
+
+// 名称对应的 id 和密码
+type PasswordMap = HashMap<Vec<u8>, Vec<(u64, Vec<u8>)>>;
+
+// 导入的密码与已有密码比较的结果
+enum Status {
+    New,
+    Identical,
+    // 名称相同的已有密码
+    Conflict(u64),
+}
+
+fn status(entries: &PasswordMap, name: &[u8], password: &[u8]) -> Status {
+    match entries.get(name) {
+        Some(v) if v.iter().any(|(_, p)| p == password) => Status::Identical,
+        Some(v) => Status::Conflict(v[0].0),
+        None => Status::New,
+    }
+}
+
+fn preview(key: &[u8], import: Import) -> Result<Preview, Error> {
+    let db = db();
+    let entries = get_all_password_as_map(db.conn().map_err(err!())?, key)?;
+    let mut preview = Preview {
+        new: Vec::new(),
+        identical: Vec::new(),
+        conflict: Vec::new(),
+        warnings: import.warnings,
+    };
+    for (index, entry) in import.entries.into_iter().enumerate() {
+        let status = status(&entries, entry.name.as_bytes(), entry.password.as_bytes());
+        let (list, id) = match status {
+            Status::New => (&mut preview.new, None),
+            Status::Identical => (&mut preview.identical, None),
+            Status::Conflict(id) => (&mut preview.conflict, Some(id)),
+        };
+        list.push(PreviewItem {
+            index,
+            name: entry.name,
+            id,
+        });
+    }
+    Ok(preview)
+}
+
+// 记录导入的数量
+fn audit_import(
+    conn: &Connection,
+    key: &[u8],
+    count: &Count,
+    source: Option<&str>,
+) -> crate::Result<()> {
+    let mut detail = format!("{} inserted, {} overwritten", count.insert, count.overwrite);
+    if let Some(source) = source {
+        detail = format!("{}: {}", source, detail);
+    }
+    audit::record(conn, key, "import", &detail)
+}
+
+// 合并密码，忽略名称和密码都相同的，名称相同、密码不同的按 strategy 处理，在一个事务中完成
+fn merge_password(key: &[u8], import: Import, strategy: &Strategy) -> Result<Count, Error> {
+    let db = db();
+    let tx = db
+        .conn()
+        .map_err(err!())?
+        .unchecked_transaction()
+        .map_err(err!())?;
+    let count = merge(&tx, key, import, strategy)?;
+    tx.commit().map_err(err!())?;
+    Ok(count)
+}
+
+// 合并密码，由调用者管理事务。导入的条目开启了两步验证时同样标记
+fn merge(
+    conn: &Connection,
+    key: &[u8],
+    import: Import,
+    strategy: &Strategy,
+) -> Result<Count, Error> {
+    let mut count = Count {
+        warnings: import.warnings,
+        ..Default::default()
+    };
+    let entries = get_all_password_as_map(conn, key)?;
+    let now = now()?;
+
+    const INSERT_SQL: &str =
+        "INSERT INTO vault (key, value, created_at, modified_at, two_factor) VALUES (?, ?, ?, ?, ?)";
+    const SELECT_SQL: &str = "SELECT value FROM vault WHERE id=?";
+    const UPDATE_SQL: &str =
+        "UPDATE vault SET value=?, modified_at=?, two_factor=two_factor OR ? WHERE id=?";
+    let insert = |name: Vec<u8>, password: Vec<u8>, two_factor: bool| -> Result<(), Error> {
+        let name = key_encrypt(key, name).map_err(err!())?;
+        let password = key_encrypt(key, password).map_err(err!())?;
+        conn.execute(INSERT_SQL, params![name, password, now, now, two_factor])
+            .map_err(err!())?;
+        Ok(())
+    };
+    for (index, entry) in import.entries.into_iter().enumerate() {
+        let two_factor = entry.two_factor;
+        let (name, password) = (entry.name.into_bytes(), entry.password.into_bytes());
+        match status(&entries, &name, &password) {
+            Status::Identical => count.ignore += 1,
+            Status::New => {
+                insert(name, password, two_factor)?;
+                count.insert += 1;
+            }
+            Status::Conflict(id) => match strategy.action(index) {
+                Action::Skip => count.ignore += 1,
+                Action::KeepBoth => {
+                    insert(name, password, two_factor)?;
+                    count.insert += 1;
+                }
+                Action::Overwrite => {
+                    let old: Vec<u8> = conn
+                        .query_row(SELECT_SQL, [id], |row| row.get(0))
+                        .map_err(err!())?;
+                    save_history(conn, id, &old)?;
+                    let password = key_encrypt(key, password).map_err(err!())?;
+                    conn.execute(UPDATE_SQL, params![password, now, two_factor, id])
+                        .map_err(err!())?;
+                    count.overwrite += 1;
+                }
+            },
+        }
+    }
+    Ok(count)
+}
+
+// 所有密码，名称对应的 id 和密码，按 id 排序
+fn get_all_password_as_map(conn: &Connection, key: &[u8]) -> Result<PasswordMap, Error> {
+    let mut map = PasswordMap::new();
+    const SQL: &str = "SELECT id, key, value FROM vault WHERE deleted_at IS NULL ORDER BY id";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    while let Some(row) = rows.next().map_err(err!())? {
+        let id: u64 = row.get(0).map_err(err!())?;
+        let name: Vec<u8> = row.get(1).map_err(err!())?;
+        let password: Vec<u8> = row.get(2).map_err(err!())?;
+        let name = key_decrypt(key, &name)?;
+        let password = key_decrypt(key, &password)?;
+        map.entry(name).or_default().push((id, password));
+    }
+    Ok(map)
+}
+
+// 所有密码，解密后的名称和密码
+fn get_all_password_decrypted(key: &[u8]) -> Result<Vec<(String, String)>, Error> {
+    let mut list = Vec::new();
+    for (name, password) in get_all_password()? {
+        let name = String::from_utf8(key_decrypt(key, name)?).map_err(err!())?;
+        let password = String::from_utf8(key_decrypt(key, password)?).map_err(err!())?;
+        list.push((name, password));
+    }
+    Ok(list)
+}
+
+// 所有密码
+fn get_all_password() -> crate::Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut list = Vec::new();
+    let db = db();
+    let mut stmt = db
+        .conn()
+        .map_err(err!())?
+        .prepare("SELECT key, value FROM vault WHERE deleted_at IS NULL ORDER BY id")
+        .map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    while let Some(row) = rows.next().map_err(err!())? {
+        let name: Vec<u8> = row.get(0).map_err(err!())?;
+        let value: Vec<u8> = row.get(1).map_err(err!())?;
+        list.push((name, value));
+    }
+    Ok(list)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+enum ExportFormat {
+    // 本应用的格式
+    #[default]
+    Vault,
+    // KeePass KDBX 4，使用主密码加密
+    Kdbx,
+    // 加密导出格式，使用 passphrase 加密，不依赖保险库密钥
+    Encrypted {
+        passphrase: String,
+    },
+    // 加密给 age X25519 接收者，recipients 为 `age1` 开头的公钥
+    Age {
+        recipients: Vec<String>,
+    },
+    // 加密给 OpenPGP 公钥，keys 为 ASCII armor 格式的公钥
+    Pgp {
+        keys: Vec<String>,
+    },
+    // Chrome 格式的 CSV，未加密
+    Csv(Plaintext),
+    // Bitwarden 格式的 JSON，未加密
+    Json(Plaintext),
+}
+
+impl ExportFormat {
+    // 未加密格式的名称和确认信息
+    fn plaintext(&self) -> Option<(&'static str, &Plaintext)> {
+        match self {
+            ExportFormat::Csv(confirm) => Some(("csv", confirm)),
+            ExportFormat::Json(confirm) => Some(("json", confirm)),
+            _ => None,
+        }
+    }
+
+    // 格式名称，用于审计日志
+    fn name(&self) -> &'static str {
+        match self {
+            ExportFormat::Vault => "vault",
+            ExportFormat::Kdbx => "kdbx",
+            ExportFormat::Encrypted { .. } => "encrypted",
+            ExportFormat::Age { .. } => "age",
+            ExportFormat::Pgp { .. } => "pgp",
+            ExportFormat::Csv(_) => "csv",
+            ExportFormat::Json(_) => "json",
+        }
+    }
+}
+
+// 导出未加密数据前的确认
+#[derive(Deserialize)]
+struct Plaintext {
+    // 再次输入的主密码，和 master_password 的密钥文件一起验证
+    confirm_password: String,
+    // 确认知道导出的数据未加密
+    #[serde(default)]
+    acknowledge_plaintext: bool,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Exported {
+    // 本应用的格式
+    List(Vec<(String, String)>),
+    // 二进制格式，base64 编码
+    Data(String),
+}
+
+// 导出密码, 如果 file 不为 None，导出到 file，返回 None，否则返回数据
+#[rpc]
+fn export_password(
+    master_password: MasterPassword,
+    file: Option<String>,
+    format: Option<ExportFormat>,
+) -> Result<Option<Exported>, Error> {
+    let key = decrypt_master_key(&master_password)?;
+    let format = format.unwrap_or_default();
+    let unencrypted = format.plaintext();
+    if let Some((_, confirm)) = unencrypted {
+        // 单独验证再次输入的主密码，不能只和同一个请求中的 master_password 比较
+        let confirm_password = master_password.with_password(confirm.confirm_password.clone());
+        if decrypt_master_key(confirm_password)? != key {
+            return Err(WrongPassword);
+        }
+        if !confirm.acknowledge_plaintext {
+            return Err(Error::PlaintextNotAcknowledged);
+        }
+    }
+    let unencrypted = unencrypted.is_some();
+    let name = format.name();
+
+    let data = match format {
+        ExportFormat::Vault => {
+            let mut list: Vec<_> = get_all_password()?
+                .into_iter()
+                .map(|(name, value)| (base64::encode(name), base64::encode(value)))
+                .collect();
+
+            if !list.is_empty() {
+                let key_file = keyfile::current(db().conn().map_err(err!())?, &master_password)?;
+                let sealed = keyfile::seal(master_password.password(), key_file.as_ref(), &key)?;
+                list.push((base64::encode(sealed), SEALED.to_string()));
+            }
+
+            match file {
+                Some(_) => serde_json::to_vec(&list).map_err(err!())?,
+                None => return Ok(Some(Exported::List(list))),
+            }
+        }
+        ExportFormat::Kdbx => {
+            let list = get_all_password_decrypted(&key)?;
+            let items: Vec<_> = list
+                .iter()
+                .map(|(name, password)| kdbx::Item { name, password })
+                .collect();
+            // 开启了密钥文件时 KeePass 同样需要该密钥文件才能打开
+            let key_file = match keyfile::is_set(db().conn().map_err(err!())?)? {
+                true => Some(
+                    master_password
+                        .key_file_content()
+                        .ok_or(Error::InvalidKeyFile)?,
+                ),
+                false => None,
+            };
+            kdbx::write(&items, master_password.password(), key_file.as_deref())?
+        }
+        ExportFormat::Encrypted { passphrase } => {
+            encrypted::write(&get_all_password_decrypted(&key)?, &passphrase)?
+        }
+        ExportFormat::Age { recipients } => {
+            let data = recipient::to_json(&get_all_password_decrypted(&key)?)?;
+            recipient::x25519::encrypt(&data, &recipients)?
+        }
+        ExportFormat::Pgp { keys } => {
+            let data = recipient::to_json(&get_all_password_decrypted(&key)?)?;
+            recipient::pgp::encrypt(&data, &keys)?
+        }
+        ExportFormat::Csv(_) => plaintext::csv(&get_all_password_decrypted(&key)?)?,
+        ExportFormat::Json(_) => plaintext::json(&get_all_password_decrypted(&key)?)?,
+    };
+
+    let event = if unencrypted {
+        "export_plaintext"
+    } else {
+        "export"
+    };
+    let detail = match file {
+        Some(ref file) => format!("{}: {}", name, file),
+        None => name.to_string(),
+    };
+    audit::record(db().conn().map_err(err!())?, &key, event, &detail)?;
+
+    match file {
+        Some(file) => {
+            write_private(&file, &data)?;
+            Ok(None)
+        }
+        None => Ok(Some(Exported::Data(base64::encode(data)))),
+    }
+}
+
+// 写入导出的文件，只有自己可以读写
+fn write_private(file: &str, data: &[u8]) -> crate::Result<()> {
+    let mut options = OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(file).map_err(err!())?;
+    // 文件已存在时 mode 不生效
+    #[cfg(unix)]
+    file.set_permissions(Permissions::from_mode(0o600))
+        .map_err(err!())?;
+    file.write_all(data).map_err(err!())
+}
+
+// 本保险库的分享公钥
+#[derive(Serialize)]
+struct ShareKey {
+    public_key: String,
+    // 公钥指纹，用于和对方核对
+    fingerprint: String,
+}
+
+// 本保险库的分享公钥，交给对方后对方可以把条目分享给本保险库，第一次调用时生成分享密钥
+#[rpc]
+fn get_share_key(master_password: MasterPassword) -> Result<ShareKey, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let public_key = share::public_key(db().conn().map_err(err!())?, &key)?;
+    Ok(ShareKey {
+        fingerprint: share::fingerprint(&public_key),
+        public_key,
+    })
+}
+
+// 把条目加密分享给 recipient (对方的分享公钥)，附带已撤销的发给对方的分享。
+// 如果 file 不为 None，写入 file，返回 None，否则返回分享的 JSON
+#[rpc]
+fn share_entries(
+    master_password: MasterPassword,
+    ids: Vec<u64>,
+    recipient: String,
+    file: Option<String>,
+) -> Result<Option<String>, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let recipient = recipient.trim();
+    if !share::valid_public_key(recipient) {
+        return Err(Error::InvalidShareKey);
+    }
+    let db = db();
+    let tx = db
+        .conn()
+        .map_err(err!())?
+        .unchecked_transaction()
+        .map_err(err!())?;
+    let mut entries = Vec::with_capacity(ids.len());
+    const SQL: &str = "SELECT key, value, two_factor FROM vault WHERE id=? AND deleted_at IS NULL";
+    for id in ids {
+        let (name, password, two_factor): (Vec<u8>, Vec<u8>, bool) = tx
+            .query_row(SQL, [id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(err!())?;
+        entries.push(share::Entry {
+            name: String::from_utf8(key_decrypt(&key, name)?).map_err(err!())?,
+            password: String::from_utf8(key_decrypt(&key, password)?).map_err(err!())?,
+            two_factor,
+        });
+    }
+    let envelope = share::seal(&tx, &key, recipient, Some(&entries), now()?)?;
+    let detail = format!(
+        "{}: {} entries to {}",
+        envelope.id,
+        entries.len(),
+        share::fingerprint(recipient)
+    );
+    audit::record(&tx, &key, "share", &detail)?;
+    tx.commit().map_err(err!())?;
+    write_share(&envelope, file)
+}
+
+// 撤销发出的分享，之后发给同一接收者的分享都附带撤销列表，已导入的条目不会从对方的保险库删除
+#[rpc]
+fn revoke_share(master_password: MasterPassword, id: String) -> Result<(), Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    if !share::revoke(conn, &id, now()?)? {
+        return Err(Error::ShareNotFound);
+    }
+    audit::record(conn, &key, "revoke_share", &id)?;
+    Ok(())
+}
+
+// 导出只有撤销列表的分享，用于不分享新条目时通知 recipient，
+// 如果 file 不为 None，写入 file，返回 None，否则返回分享的 JSON
+#[rpc]
+fn export_share_revocations(
+    master_password: MasterPassword,
+    recipient: String,
+    file: Option<String>,
+) -> Result<Option<String>, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let recipient = recipient.trim();
+    if !share::valid_public_key(recipient) {
+        return Err(Error::InvalidShareKey);
+    }
+    let envelope = share::seal(db().conn().map_err(err!())?, &key, recipient, None, now()?)?;
+    write_share(&envelope, file)
+}
+
+fn write_share(envelope: &share::Envelope, file: Option<String>) -> Result<Option<String>, Error> {
+    let data = serde_json::to_string(envelope).map_err(err!())?;
+    match file {
+        Some(file) => {
+            write_private(&file, data.as_bytes())?;
+            Ok(None)
+        }
+        None => Ok(Some(data)),
+    }
+}
+
+// 收到的分享
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ShareSource {
+    // 文件
+    File(String),
+    // 分享的 JSON
+    Data(String),
+}
+
+#[derive(Serialize)]
+struct ShareImport {
+    id: String,
+    // 发送者的分享公钥和指纹，没有指定 sender 时需要和发送者核对指纹
+    sender: String,
+    fingerprint: String,
+    // 本次撤销的已导入分享的数量
+    revoked: usize,
+    // 导入的数量，只有撤销列表时为 null
+    count: Option<Count>,
+}
+
+// 导入分享给本保险库的条目，sender 不为 None 时要求发送者是指定的公钥，strategy 为 None 时保留两者。
+// 同时记录分享附带的撤销列表，已撤销的分享无法导入
+#[rpc]
+fn import_share(
+    master_password: MasterPassword,
+    source: ShareSource,
+    sender: Option<String>,
+    strategy: Option<Strategy>,
+) -> Result<ShareImport, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let data = match source {
+        ShareSource::File(file) => read(file).map_err(err!())?,
+        ShareSource::Data(data) => data.into_bytes(),
+    };
+    let envelope: share::Envelope = from_slice(&data).map_err(|_| Error::InvalidShare)?;
+    if !envelope.verify() {
+        return Err(Error::InvalidShare);
+    }
+    if sender.is_some_and(|v| v.trim() != envelope.sender) {
+        return Err(Error::UntrustedSender);
+    }
+
+    // 检查、撤销列表、合并和记录在同一个事务中完成，同时导入同一个分享时只有一个成功
+    let db = db();
+    let tx = db
+        .conn()
+        .map_err(err!())?
+        .unchecked_transaction()
+        .map_err(err!())?;
+    if !share::is_recipient(&tx, &envelope)? {
+        return Err(Error::InvalidShare);
+    }
+    let revoked = share::apply_revoked(&tx, &envelope)?;
+    if envelope.data.is_some() {
+        let rejected = if share::is_revoked(&tx, &envelope.sender, &envelope.id)? {
+            Some(Error::ShareRevoked)
+        } else if share::is_received(&tx, &envelope.sender, &envelope.id)? {
+            Some(Error::ShareImported)
+        } else {
+            None
+        };
+        // 拒绝导入时仍然保存附带的撤销列表
+        if let Some(rejected) = rejected {
+            tx.commit().map_err(err!())?;
+            return Err(rejected);
+        }
+    }
+    // 签名和接收者已经校验过，解密失败说明数据损坏
+    let entries = share::open(&tx, &key, &envelope).map_err(|_| Error::InvalidShare)?;
+
+    let fingerprint = share::fingerprint(&envelope.sender);
+    let count = match entries {
+        Some(entries) => {
+            let len = entries.len();
+            let import = Import {
+                entries: entries
+                    .into_iter()
+                    .map(|v| import::Entry {
+                        name: v.name,
+                        password: v.password,
+                        two_factor: v.two_factor,
+                    })
+                    .collect(),
+                warnings: Vec::new(),
+            };
+            let count = merge(&tx, &key, import, &strategy.unwrap_or_default())?;
+            share::record_received(&tx, &envelope, len, now()?)?;
+            let source = format!("share from {}", fingerprint);
+            audit_import(&tx, &key, &count, Some(&source))?;
+            Some(count)
+        }
+        None => None,
+    };
+    tx.commit().map_err(err!())?;
+    Ok(ShareImport {
+        id: envelope.id,
+        sender: envelope.sender,
+        fingerprint,
+        revoked,
+        count,
+    })
+}
+
+// 发出的分享，最新的在前面
+#[rpc]
+fn list_sent_shares(master_password: MasterPassword) -> Result<Vec<share::Sent>, Error> {
+    decrypt_master_key(master_password)?;
+    Ok(share::list_sent(db().conn().map_err(err!())?)?)
+}
+
+// 导入的分享，最新的在前面
+#[rpc]
+fn list_received_shares(master_password: MasterPassword) -> Result<Vec<share::Received>, Error> {
+    decrypt_master_key(master_password)?;
+    Ok(share::list_received(db().conn().map_err(err!())?)?)
+}
+
+#[derive(Serialize)]
+struct AuditLog {
+    items: Vec<audit::Record>,
+    // 哈希链中第一条校验失败的记录的 id，null 表示校验通过
+    broken: Option<u64>,
+}
+
+// 分页查看审计日志，最新的在前面，before 为上一页最后一条记录的 id，同时校验整个哈希链
+#[rpc]
+fn list_audit_log(
+    master_password: MasterPassword,
+    before: Option<u64>,
+    limit: Option<u32>,
+) -> Result<AuditLog, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let limit = limit.unwrap_or(50).clamp(1, 1000);
+    Ok(AuditLog {
+        items: audit::list(conn, &key, before, limit)?,
+        broken: audit::verify(conn, &key)?,
+    })
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum Table {
+    // 密码
+    Vault,
+    // 历史记录
+    History,
+}
+
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Field {
+    Name,
+    Password,
+}
+
+#[derive(Serialize)]
+struct CorruptedItem {
+    table: Table,
+    id: u64,
+    // 所属密码的 id
+    vault_id: u64,
+    field: Field,
+}
+
+#[derive(Serialize)]
+struct VaultCheck {
+    // SQLite integrity_check 发现的问题，没有问题时为空
+    integrity: Vec<String>,
+    // 无法解密的数据
+    corrupted: Vec<CorruptedItem>,
+}
+
+// 检查数据库完整性，并尝试解密所有密码和历史记录（包括回收站）
+#[rpc]
+fn check_vault(master_password: MasterPassword) -> Result<VaultCheck, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    Ok(VaultCheck {
+        integrity: integrity_check(conn)?,
+        corrupted: find_corrupted(conn, &key)?,
+    })
+}
+
+// 把无法解密的密码（连同历史记录）和历史记录移到隔离区，返回移动的记录数
+#[rpc]
+fn quarantine_vault(master_password: MasterPassword) -> Result<usize, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let corrupted = find_corrupted(conn, &key)?;
+    if corrupted.is_empty() {
+        return Ok(0);
+    }
+
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let now = now()?;
+    let mut count = 0;
+    for item in corrupted.iter().filter(|v| v.table == Table::Vault) {
+        const SQL: &str = "INSERT INTO quarantine (source, source_id, vault_id, key, value, time) SELECT 'vault', id, id, key, value, ? FROM vault WHERE id=?";
+        count += tx.execute(SQL, params![now, item.id]).map_err(err!())?;
+        const HISTORY_SQL: &str = "INSERT INTO quarantine (source, source_id, vault_id, key, value, time) SELECT 'history', id, vault_id, NULL, value, ? FROM history WHERE vault_id=?";
+        count += tx
+            .execute(HISTORY_SQL, params![now, item.id])
+            .map_err(err!())?;
+        tx.execute("DELETE FROM history WHERE vault_id=?", [item.id])
+            .map_err(err!())?;
+        let uuid: Option<String> = tx
+            .query_row("SELECT uuid FROM vault WHERE id=?", [item.id], |row| {
+                row.get(0)
+            })
+            .map_err(err!())?;
+        tx.execute("DELETE FROM vault WHERE id=?", [item.id])
+            .map_err(err!())?;
+        // 删除时写入的删除记录不同步给其他设备，其他设备上完好的密码可以同步回来
+        tx.execute("DELETE FROM tombstone WHERE uuid=?", [uuid])
+            .map_err(err!())?;
+    }
+    // 所属密码已经移走的历史记录不会再匹配
+    for item in corrupted.iter().filter(|v| v.table == Table::History) {
+        const SQL: &str = "INSERT INTO quarantine (source, source_id, vault_id, key, value, time) SELECT 'history', id, vault_id, NULL, value, ? FROM history WHERE id=?";
+        count += tx.execute(SQL, params![now, item.id]).map_err(err!())?;
+        tx.execute("DELETE FROM history WHERE id=?", [item.id])
+            .map_err(err!())?;
+    }
+    // 让其他设备下次同步时重新发送所有变更
+    tx.execute("UPDATE peer SET received=0", [])
+        .map_err(err!())?;
+    audit::record(&tx, &key, "quarantine", &count.to_string())?;
+    tx.commit().map_err(err!())?;
+    Ok(count)
+}
+
+// SQLite 完整性检查，没有问题时返回空列表
+fn integrity_check(conn: &Connection) -> crate::Result<Vec<String>> {
+    let mut stmt = conn.prepare("PRAGMA integrity_check").map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    let mut list = Vec::new();
+    while let Some(row) = rows.next().map_err(err!())? {
+        let message: String = row.get(0).map_err(err!())?;
+        if message != "ok" {
+            list.push(message);
+        }
+    }
+    Ok(list)
+}
+
+// 找出无法解密的密码和历史记录
+fn find_corrupted(conn: &Connection, key: &[u8]) -> crate::Result<Vec<CorruptedItem>> {
+    let mut list = Vec::new();
+    let mut stmt = conn
+        .prepare("SELECT id, key, value FROM vault ORDER BY id")
+        .map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    while let Some(row) = rows.next().map_err(err!())? {
+        let id: u64 = row.get(0).map_err(err!())?;
+        let fields = [(Field::Name, 1), (Field::Password, 2)];
+        for (field, index) in fields {
+            let data: Vec<u8> = row.get(index).map_err(err!())?;
+            if !decryptable(key, &data)? {
+                list.push(CorruptedItem {
+                    table: Table::Vault,
+                    id,
+                    vault_id: id,
+                    field,
+                });
+            }
+        }
+    }
+
+    let mut stmt = conn
+        .prepare("SELECT id, vault_id, value FROM history ORDER BY id")
+        .map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    while let Some(row) = rows.next().map_err(err!())? {
+        let data: Vec<u8> = row.get(2).map_err(err!())?;
+        if !decryptable(key, &data)? {
+            list.push(CorruptedItem {
+                table: Table::History,
+                id: row.get(0).map_err(err!())?,
+                vault_id: row.get(1).map_err(err!())?,
+                field: Field::Password,
+            });
+        }
+    }
+    Ok(list)
+}
+
+// 能否解密为 UTF-8 字符串
+fn decryptable(key: &[u8], data: &[u8]) -> crate::Result<bool> {
+    match crate::crypto::key_decrypt(key, data).map_err(err!())? {
+        Some(data) => Ok(String::from_utf8(data).is_ok()),
+        None => Ok(false),
+    }
+}
+
+// 备份列表，最新的在前面
+#[rpc]
+fn list_backup(master_password: MasterPassword) -> Result<Vec<Snapshot>, Error> {
+    decrypt_master_key(master_password)?;
+    Ok(backup::list(&backup::dir(&vault_dir()?))?)
+}
+
+#[derive(Serialize)]
+struct BackupVerify {
+    // 完整性检查是否通过
+    integrity: bool,
+    // 密码数量
+    count: u64,
+    // 当前主密码能否解锁备份，恢复后需要使用备份时的主密码
+    password: bool,
+}
+
+// 检查备份
+#[rpc]
+fn verify_backup(master_password: MasterPassword, name: String) -> Result<BackupVerify, Error> {
+    decrypt_master_key(&master_password)?;
+    let path = backup::path(&backup::dir(&vault_dir()?), &name)?;
+    let verify = backup::verify(&path)?;
+    let password = match verify.key {
+        Some(key) => {
+            let key = base64::decode(key).map_err(err!())?;
+            keyfile::unwrap(verify.key_file.as_deref(), &master_password, &key)?.is_some()
+        }
+        None => false,
+    };
+    Ok(BackupVerify {
+        integrity: verify.integrity,
+        count: verify.count,
+        password,
+    })
+}
+
+// 恢复备份，恢复前会先备份当前数据库
+#[rpc]
+fn restore_backup(master_password: MasterPassword, name: String) -> Result<(), Error> {
+    let key = decrypt_master_key(master_password)?;
+    let vault_dir = vault_dir()?;
+    let dir = backup::dir(&vault_dir);
+    let path = backup::path(&dir, &name)?;
+    let verify = backup::verify(&path)?;
+    if !verify.integrity {
+        return Err(Error::BackupCorrupted);
+    }
+    if verify.version > Some(db::supported_version()) {
+        return Err(Error::NewerVersion);
+    }
+    backup::snapshot(db().conn().map_err(err!())?, &dir)?;
+
+    let mut db = db_mut();
+    let conn = db.conn().map_err(err!())?;
+    backup::restore(conn, &vault_dir, &path)?;
+    audit::record(conn, &key, "restore_backup", &name)?;
+    Ok(())
+}
+
+// 获取备份保留策略
+#[rpc]
+fn get_backup_retention() -> crate::Result<Retention> {
+    backup::retention(db().conn().map_err(err!())?)
+}
+
+// 设置备份保留策略
+#[rpc]
+fn set_backup_retention(
+    master_password: MasterPassword,
+    retention: Retention,
+) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    let value = serde_json::to_string(&retention).map_err(err!())?;
+    set_conf(db().conn().map_err(err!())?, "backup_retention", &value)?;
+    backup::rotate(&backup::dir(&vault_dir()?), retention, now()?)?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct VaultItem {
+    name: String,
+    // 是否已打开
+    opened: bool,
+    // 是否是当前保险库
+    current: bool,
+}
+
+// 保险库列表，默认保险库在最前面。解锁前选择保险库时调用，不需要主密码，
+// opened 和 current 只反映当前连接自己的状态
+#[rpc]
+fn list_vaults() -> crate::Result<Vec<VaultItem>> {
+    let mut names = vec![DEFAULT_VAULT.to_string()];
+    match read_dir(data_dir()?.join("vaults")) {
+        Ok(entries) => {
+            let mut list = Vec::new();
+            for entry in entries {
+                let entry = entry.map_err(err!())?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if valid_vault_name(&name) && entry.path().join("database").is_file() {
+                    list.push(name);
+                }
+            }
+            list.sort();
+            names.extend(list);
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+        Err(err) => return Err(err!(err)),
+    }
+
+    let opened = opened_vaults();
+    let current = current_vault();
+    Ok(names
+        .into_iter()
+        .map(|name| VaultItem {
+            opened: opened.contains(&name),
+            current: current.as_ref() == Some(&name),
+            name,
+        })
+        .collect())
+}
+
+// 创建保险库，使用单独的主密码，创建后需要打开才能使用
+#[rpc]
+fn create_vault(name: String, master_password: MasterPassword) -> Result<(), Error> {
+    if !valid_vault_name(&name) || name == DEFAULT_VAULT {
+        return Err(Error::InvalidVaultName);
+    }
+    let dir = vault_path(&name)?;
+    if dir.exists() {
+        return Err(Error::VaultExists);
+    }
+    create_dir_all(&dir).map_err(err!())?;
+    let conn = open_database(&dir)?;
+    init_master_key(&conn, master_password)?;
+    Ok(())
+}
+
+// 打开保险库并设为当前保险库，已打开时只切换当前保险库，输入胁迫密码时打开诱饵保险库
+#[rpc]
+fn open_vault(name: String, master_password: MasterPassword) -> Result<(), Error> {
+    unlock_vault(&name, &master_password, &name)
+}
+
+// 关闭保险库，需要保险库的主密码，关闭当前保险库后需要打开其他保险库才能使用
+#[rpc]
+fn close_vault(name: String, master_password: MasterPassword) -> Result<(), Error> {
+    verify_vault_password(&name, &master_password)?;
+    server::close_vault(&name);
+    Ok(())
+}
+
+// 重命名保险库，默认保险库不能重命名
+#[rpc]
+fn rename_vault(
+    name: String,
+    new_name: String,
+    master_password: MasterPassword,
+) -> Result<(), Error> {
+    if name == DEFAULT_VAULT || !valid_vault_name(&new_name) || new_name == DEFAULT_VAULT {
+        return Err(Error::InvalidVaultName);
+    }
+    verify_vault_password(&name, &master_password)?;
+    if vault_path(&new_name)?.exists() {
+        return Err(Error::VaultExists);
+    }
+    server::rename_vault(&name, &new_name)?;
+    Ok(())
+}
+
+// 删除保险库及其备份，默认保险库不能删除
+#[rpc]
+fn delete_vault(name: String, master_password: MasterPassword) -> Result<(), Error> {
+    if name == DEFAULT_VAULT {
+        return Err(Error::InvalidVaultName);
+    }
+    verify_vault_password(&name, &master_password)?;
+    server::close_vault(&name);
+    remove_dir_all(vault_path(&name)?).map_err(err!())?;
+    Ok(())
+}
+
+// 验证保险库的主密码，保险库不需要已打开
+fn verify_vault_password(name: &str, master_password: &MasterPassword) -> Result<(), Error> {
+    match with_vault(name, |conn| master_key(conn, master_password)) {
+        Some(key) => key?,
+        None => master_key(&open_existing_vault(name)?, master_password)?,
+    };
+    Ok(())
+}
+
+// 在当前连接上订阅保险库的通知，服务端之后在这个连接上发送通知，需要保险库的主密码。
+// 同一客户端连续输错主密码时暂时拒绝订阅
+#[rpc]
+fn subscribe(name: String, master_password: MasterPassword) -> Result<(), Error> {
+    if !attempt::check(client_addr(), "subscribe", &name) {
+        return Err(Error::TooManyAttempts);
+    }
+    verify_vault_password(&name, &master_password)?;
+    attempt::reset(client_addr(), "subscribe", &name);
+    // 打开的可能是诱饵保险库，通知按实际打开的目录发送
+    let dir = opened_vault_dir(&name).map_or_else(|| vault_path(&name), Ok)?;
+    notify::subscribe(client_addr(), dir, name);
+    Ok(())
+}
+
+// 取消当前连接对保险库的订阅
+#[rpc]
+fn unsubscribe(name: String) -> Result<(), Infallible> {
+    notify::unsubscribe(client_addr(), &name);
+    Ok(())
+}
+
+// 打开已存在的保险库的数据库
+fn open_existing_vault(name: &str) -> Result<Connection, Error> {
+    if !valid_vault_name(name) {
+        return Err(Error::InvalidVaultName);
+    }
+    let dir = vault_path(name)?;
+    if !dir.join("database").is_file() {
+        return Err(Error::VaultNotFound);
+    }
+    Ok(open_database(&dir)?)
+}
+
+// 保险库名称用作目录名，不能包含路径分隔符和控制字符，不能以 . 开头
+fn valid_vault_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.chars().count() <= 64
+        && !name.starts_with('.')
+        && !name
+            .chars()
+            .any(|c| c == '/' || c == '\\' || c.is_control())
+}
+
+#[derive(Serialize)]
+struct SyncReport {
+    // 对方的 id
+    peer: u64,
+    addr: String,
+    #[serde(flatten)]
+    report: sync::Report,
+    // 同步失败时的错误
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SyncEntry {
+    name: String,
+    password: String,
+    // 在回收站中
+    deleted: bool,
+}
+
+#[derive(Serialize)]
+struct SyncConflict {
+    id: u64,
+    // 对方的设备 id
+    peer: String,
+    // None 表示已彻底删除
+    local: Option<SyncEntry>,
+    remote: Option<SyncEntry>,
+    time: i64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Keep {
+    Local,
+    Remote,
+}
+
+// 在对方设备上调用，允许其他设备加入同步，返回一次性的配对码，5 分钟内有效
+#[rpc]
+fn sync_pair(master_password: MasterPassword) -> Result<String, Error> {
+    let key = decrypt_master_key(&master_password)?;
+    let dir = vault_dir()?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    sync::enable(conn)?;
+    sync::unlock(&dir, conn, &key)?;
+    audit::record(conn, &key, "sync_pair", "")?;
+    Ok(sync::pair(&key)?)
+}
+
+// 由加入的设备调用，用配对码派生的 id 取回加密的保险库密钥
+#[rpc]
+fn sync_claim(id: String) -> Result<String, Error> {
+    sync::claim(&id)?.ok_or(Error::InvalidPairingCode)
+}
+
+// 加入 addr 上的设备的同步，code 为对方显示的配对码。本地的密码改用对方的保险库密钥加密，本地主密码不变，
+// 已经和其他设备同步时不能加入
+#[rpc]
+async fn join_sync(
+    master_password: MasterPassword,
+    addr: String,
+    code: String,
+) -> Result<(), Error> {
+    decrypt_master_key(&master_password)?;
+    let mut client = Client::connect(&addr).await?;
+    let sealed = match client
+        .call::<String>("sync_claim", json!([sync::pairing_id(&code)?]))
+        .await?
+    {
+        Ok(v) => v,
+        Err(kind) if kind == "InvalidPairingCode" => return Err(Error::InvalidPairingCode),
+        Err(kind) => return Err(err!(PeerError(kind)).into()),
+    };
+    // 解密失败说明配对码错误，或者回复不是来自显示配对码的设备
+    let new_key = sync::open_pairing(&code, &sealed)?.ok_or(Error::InvalidPairingCode)?;
+
+    let dir = vault_dir()?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let key = master_key(conn, &master_password)?;
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    switch_key(&tx, &master_password, &key, &new_key)?;
+    sync::add_peer(&tx, &addr)?;
+    audit::record(&tx, &key, "join_sync", &addr)?;
+    tx.commit().map_err(err!())?;
+    sync::unlock(&dir, conn, &new_key)?;
+    Ok(())
+}
+
+// 改用其他设备的保险库密钥，并开启同步。已经和其他设备同步时返回错误，它们仍然使用原来的保险库密钥
+fn switch_key(
+    conn: &Connection,
+    master_password: &MasterPassword,
+    key: &[u8],
+    new_key: &[u8],
+) -> Result<(), Error> {
+    if key != new_key {
+        if sync::has_peers(conn)? {
+            return Err(Error::AlreadySyncing);
+        }
+        reencrypt(conn, key, new_key)?;
+        webdav::reencrypt_config(conn, key, new_key)?;
+        audit::reencrypt(conn, key, new_key)?;
+        recovery::reencrypt(conn, key, new_key)?;
+        share::reencrypt(conn, key, new_key)?;
+        duress::reencrypt(conn, key, new_key)?;
+        let key_file = keyfile::current(conn, master_password)?;
+        let new_key = keyfile::wrap(conn, master_password.password(), key_file.as_ref(), new_key)?;
+        const SQL: &str = "UPDATE conf SET value=? WHERE key='key'";
+        conn.execute(SQL, [new_key]).map_err(err!())?;
+    }
+    Ok(sync::enable(conn)?)
+}
+
+// 用新的保险库密钥重新加密所有密码和历史记录
+fn reencrypt(conn: &Connection, key: &[u8], new_key: &[u8]) -> Result<(), Error> {
+    let mut stmt = conn
+        .prepare("SELECT id, key, value FROM vault")
+        .map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    while let Some(row) = rows.next().map_err(err!())? {
+        let id: u64 = row.get(0).map_err(err!())?;
+        let name = key_decrypt(key, row.get::<_, Vec<u8>>(1).map_err(err!())?)?;
+        let value = key_decrypt(key, row.get::<_, Vec<u8>>(2).map_err(err!())?)?;
+        let name = key_encrypt(new_key, name).map_err(err!())?;
+        let value = key_encrypt(new_key, value).map_err(err!())?;
+        const SQL: &str = "UPDATE vault SET key=?, value=? WHERE id=?";
+        conn.execute(SQL, params![name, value, id])
+            .map_err(err!())?;
+    }
+
+    let mut stmt = conn
+        .prepare("SELECT id, value FROM history")
+        .map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    while let Some(row) = rows.next().map_err(err!())? {
+        let id: u64 = row.get(0).map_err(err!())?;
+        let value = key_decrypt(key, row.get::<_, Vec<u8>>(1).map_err(err!())?)?;
+        let value = key_encrypt(new_key, value).map_err(err!())?;
+        const SQL: &str = "UPDATE history SET value=? WHERE id=?";
+        conn.execute(SQL, params![value, id]).map_err(err!())?;
+    }
+    Ok(())
+}
+
+// 由其他设备调用，发起同步前获取一次性的会话号
+#[rpc]
+fn sync_session() -> Result<String, Error> {
+    let addr = client_addr().ok_or(err!(Unavailable))?;
+    Ok(sync::new_session(addr)?)
+}
+
+// 由其他设备调用，交换变更。消息使用同步密钥加密认证并带上会话号，只有已解锁的保险库参与同步
+#[rpc]
+fn sync_exchange(data: String) -> Result<String, Error> {
+    let session = match client_addr() {
+        Some(addr) => sync::take_session(addr)?,
+        None => None,
+    };
+    let session = session.ok_or(WrongPassword)?;
+    let db = db();
+    for (dir, conn) in db.all() {
+        let key = match sync::unlocked_key(dir) {
+            Some(v) => v,
+            None => continue,
+        };
+        if let Some(reply) = sync::exchange(conn, &key, &session, &data)? {
+            return Ok(reply);
+        }
+    }
+    Err(WrongPassword)
+}
+
+// 和所有已知地址的设备同步
+#[rpc]
+async fn sync_now(master_password: MasterPassword) -> Result<Vec<SyncReport>, Error> {
+    let (key, peers) = {
+        let key = decrypt_master_key(master_password)?;
+        let db = db();
+        let conn = db.conn().map_err(err!())?;
+        (sync::sync_key(&key)?, sync::peers(conn)?)
+    };
+    let mut reports = Vec::new();
+    for peer in peers {
+        let addr = match &peer.addr {
+            Some(v) => v.clone(),
+            None => continue,
+        };
+        let (report, error) = match sync_peer(&key, &peer, &addr).await {
+            Ok(v) => (v, None),
+            Err(Error::Any(err)) => {
+                error!("sync with {} failed: {}", addr, err);
+                (Default::default(), Some(err.to_string()))
+            }
+            Err(err) => (Default::default(), Some(format!("{:?}", err))),
+        };
+        reports.push(SyncReport {
+            peer: peer.id,
+            addr,
+            report,
+            error,
+        });
+    }
+    Ok(reports)
+}
+
+async fn sync_peer(key: &[u8], peer: &sync::Peer, addr: &str) -> Result<sync::Report, Error> {
+    let mut client = Client::connect(addr).await?;
+    let session = match client.call::<String>("sync_session", json!([])).await? {
+        Ok(v) => v,
+        Err(kind) => return Err(err!(PeerError(kind)).into()),
+    };
+    let (request, seq, sent) = {
+        let db = db();
+        sync::request(db.conn().map_err(err!())?, key, peer, &session)?
+    };
+    let reply = match client
+        .call::<String>("sync_exchange", json!([request]))
+        .await?
+    {
+        Ok(v) => v,
+        Err(kind) => return Err(err!(PeerError(kind)).into()),
+    };
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let mut report = sync::finish(conn, key, peer, seq, &session, &reply)?;
+    report.sent = sent;
+    Ok(report)
+}
+
+#[rpc]
+fn list_sync_peers(master_password: MasterPassword) -> Result<Vec<sync::Peer>, Error> {
+    decrypt_master_key(master_password)?;
+    Ok(sync::peers(db().conn().map_err(err!())?)?)
+}
+
+#[rpc]
+fn remove_sync_peer(master_password: MasterPassword, id: u64) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    Ok(sync::remove_peer(db().conn().map_err(err!())?, id)?)
+}
+
+// 同时在本地和其他设备上修改的密码
+#[rpc]
+fn list_sync_conflicts(master_password: MasterPassword) -> Result<Vec<SyncConflict>, Error> {
+    let key = decrypt_master_key(master_password)?;
+    let db = db();
+    let conflicts = sync::conflicts(db.conn().map_err(err!())?)?;
+    let entry = |entry: Option<sync::Entry>| -> Result<Option<SyncEntry>, Error> {
+        let entry = match entry {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let name = key_decrypt(&key, base64::decode(entry.key).map_err(err!())?)?;
+        let password = key_decrypt(&key, base64::decode(entry.value).map_err(err!())?)?;
+        Ok(Some(SyncEntry {
+            name: String::from_utf8(name).map_err(err!())?,
+            password: String::from_utf8(password).map_err(err!())?,
+            deleted: entry.deleted_at.is_some(),
+        }))
+    };
+    let mut list = Vec::new();
+    for conflict in conflicts {
+        list.push(SyncConflict {
+            id: conflict.id,
+            peer: conflict.peer,
+            local: entry(conflict.local)?,
+            remote: entry(conflict.remote)?,
+            time: conflict.time,
+        });
+    }
+    Ok(list)
+}
+
+// 解决冲突，保留的内容会在下次同步时发送给其他设备
+#[rpc]
+fn resolve_sync_conflict(
+    master_password: MasterPassword,
+    id: u64,
+    keep: Keep,
+) -> Result<(), Error> {
+    decrypt_master_key(master_password)?;
+    let keep_remote = matches!(keep, Keep::Remote);
+    Ok(sync::resolve(
+        db().conn().map_err(err!())?,
+        id,
+        keep_remote,
+    )?)
+}
+
+// 设置同步文件夹，None 表示关闭。文件夹中已有其他设备保存的保险库密钥时，本地的密码改用该密钥加密，
+// peer_master_password 为该设备的主密码，不设置时使用本地主密码
+#[rpc]
+fn set_sync_folder(
+    master_password: MasterPassword,
+    folder: Option<String>,
+    peer_master_password: Option<MasterPassword>,
+) -> Result<(), Error> {
+    let key = decrypt_master_key(&master_password)?;
+    let dir = vault_dir()?;
+    let db = db();
+    let conn = db.conn().map_err(err!())?;
+    let folder = match folder {
+        Some(v) => PathBuf::from(v),
+        None => return Ok(sync::folder::set_folder(conn, None)?),
+    };
+
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let key = match sync::folder::read_key(&folder)? {
+        Some(sealed) => {
+            let peer = peer_master_password.as_ref().unwrap_or(&master_password);
+            let new_key = keyfile::unseal(peer, &sealed)?.ok_or(WrongPassword)?;
+            switch_key(&tx, &master_password, &key, &new_key)?;
+            new_key
+        }
+        None => {
+            let key_file = keyfile::current(&tx, &master_password)?;
+            let sealed = keyfile::seal(master_password.password(), key_file.as_ref(), &key)?;
+            sync::folder::write_key(&folder, &sealed)?;
+            sync::enable(&tx)?;
+            key
+        }
+    };
+    sync::folder::set_folder(&tx, Some(&folder))?;
+    audit::record(&tx, &key, "set_sync_folder", &folder.to_string_lossy())?;
+    tx.commit().map_err(err!())?;
+    sync::unlock(&dir, conn, &key)?;
+    Ok(())
+}
+
+#[rpc]
+fn get_sync_folder() -> crate::Result<Option<String>> {
+    let folder = sync::folder::folder(db().conn().map_err(err!())?)?;
+    Ok(folder.map(|v| v.to_string_lossy().into_owned()))
+}
+
+// 立即和同步文件夹中的其他设备同步，未设置同步文件夹时什么都不做
+#[rpc]
+fn sync_folder(master_password: MasterPassword) -> Result<FolderReport, Error> {
+    let key = sync::sync_key(&decrypt_master_key(master_password)?)?;
+    let dir = vault_dir()?;
+    let folder = sync::folder::folder(db().conn().map_err(err!())?)?;
+    match folder {
+        Some(folder) => Ok(sync::folder::run(&dir, &key, &folder)?),
+        None => Ok(FolderReport::default()),
+    }
+}
+
+// 设置 WebDAV 同步，None 表示关闭。目录中已有其他设备保存的保险库密钥时，本地的密码改用该密钥加密，
+// peer_master_password 为该设备的主密码，不设置时使用本地主密码
+#[rpc]
+async fn set_webdav(
+    master_password: MasterPassword,
+    config: Option<WebDavConfig>,
+    peer_master_password: Option<MasterPassword>,
+) -> Result<(), Error> {
+    let vault = current_vault().ok_or(err!(Unavailable))?;
+    let key = decrypt_master_key(&master_password)?;
+    let config = match config {
+        Some(v) => v,
+        None => {
+            return Ok(with_conn(&vault, |conn| {
+                webdav::set_config(conn, &key, None)
+            })?)
+        }
+    };
+
+    let client = webdav::Client::new(&config)?;
+    client.mkcol().await?;
+    let new_key = match client.get(webdav::KEY_FILE).await? {
+        Some((_, blob)) => {
+            let peer = peer_master_password.as_ref().unwrap_or(&master_password);
+            let sealed = String::from_utf8(blob).map_err(err!())?;
+            Some(keyfile::unseal(peer, &sealed)?.ok_or(WrongPassword)?)
+        }
+        None => {
+            let key_file = with_conn(&vault, |conn| keyfile::current(conn, &master_password))?;
+            let sealed = keyfile::seal(master_password.password(), key_file.as_ref(), &key)?;
+            match client
+                .put(webdav::KEY_FILE, sealed.as_bytes(), None)
+                .await?
+            {
+                webdav::Put::Done(_) => None,
+                webdav::Put::Conflict => return Err(err!(HttpError(412)).into()),
+            }
+        }
+    };
+
+    let dir = opened_vault_dir(&vault).ok_or(err!(Unavailable))?;
+    let result = with_vault(&vault, |conn| -> Result<(), Error> {
+        let tx = conn.unchecked_transaction().map_err(err!())?;
+        let key = match new_key {
+            Some(new_key) => {
+                switch_key(&tx, &master_password, &key, &new_key)?;
+                new_key
+            }
+            None => {
+                sync::enable(&tx)?;
+                key
+            }
+        };
+        webdav::set_config(&tx, &key, Some(&config))?;
+        audit::record(&tx, &key, "set_webdav", &config.url)?;
+        tx.commit().map_err(err!())?;
+        sync::unlock(&dir, conn, &key)?;
+        Ok(())
+    });
+    result.unwrap_or(Err(err!(Unavailable).into()))
+}
+
+#[rpc]
+fn get_webdav(master_password: MasterPassword) -> Result<Option<WebDavConfig>, Error> {
+    let key = decrypt_master_key(master_password)?;
+    Ok(webdav::config(db().conn().map_err(err!())?, &key)?)
+}
+
+// 立即和 WebDAV 同步，未设置 WebDAV 时什么都不做
+#[rpc]
+async fn sync_webdav(master_password: MasterPassword) -> Result<sync::Report, Error> {
+    let vault = current_vault().ok_or(err!(Unavailable))?;
+    let key = decrypt_master_key(master_password)?;
+    Ok(webdav::sync(&vault, &key).await?)
+}
+
+#[rpc]
+async fn get_network_port() -> Result<Option<u16>, Error> {
+    Ok(query_network_port().await?)
+}
+
+#[rpc]
+async fn enable_network_access() -> Result<u16, Error> {
+    let port = listen_any_addr().await?;
+    audit_current("enable_network_access", &port.to_string())?;
+    Ok(port)
+}
+
+#[rpc]
+fn disable_network_access() -> Result<(), Error> {
+    close_any_addr()?;
+    audit_current("disable_network_access", "")?;
+    Ok(())
+}
+
+// 网络访问不属于某个保险库，记录到当前保险库，没有打开保险库时不记录
+fn audit_current(event: &str, detail: &str) -> crate::Result<()> {
+    match db().conn() {
+        Ok(conn) => audit::record_locked(conn, event, detail),
+        Err(_) => Ok(()),
+    }
+}
+
+// 解密当前保险库的密码加密使用的 key
+fn decrypt_master_key(master_password: impl Borrow<MasterPassword>) -> Result<Vec<u8>, Error> {
+    master_key(db().conn().map_err(err!())?, master_password.borrow())
+}
+
+// 解密密码加密使用的 key
+fn master_key(conn: &Connection, master_password: &MasterPassword) -> Result<Vec<u8>, Error> {
+    const SQL: &str = "SELECT value FROM conf WHERE key='key'";
+    let key: String = conn.query_row(SQL, [], |row| row.get(0)).map_err(err!())?;
+    let key = base64::decode(key).map_err(err!())?;
+    let kdf = keyfile::kdf(conn)?;
+    keyfile::unwrap(kdf.as_deref(), master_password, &key)?.ok_or(WrongPassword)
+}
+
+// 使用主密钥解密，主密钥已经验证过，解密失败说明数据损坏
+fn key_decrypt(key: impl AsRef<[u8]>, data: impl AsRef<[u8]>) -> Result<Vec<u8>, Error> {
+    match crate::crypto::key_decrypt(key.as_ref(), data.as_ref()).map_err(err!())? {
+        Some(data) => Ok(data),
+        None => Err(Error::Corrupted),
+    }
+}
+
+#[derive(Deserialize)]
+struct PasswordOption {
+    len: usize,
+    uppercase: bool,
+    lowercase: bool,
+    digit: bool,
+    special: bool,
+}
+
+impl PasswordOption {
+    #[allow(clippy::byte_char_slices, clippy::redundant_static_lifetimes)]
+    fn chars(&self) -> Vec<&'static [u8]> {
+        static UPPERCASE: &'static [u8] = &[
             b'A', b'B', b'C', b'D', b'E', b'F', b'G', b'H', b'I', b'J', b'K', b'L', b'M', b'N',
             b'O', b'P', b'Q', b'R', b'S', b'T', b'U', b'V', b'W', b'X', b'Y', b'Z',
         ];
@@ -485,11 +2975,159 @@ pub fn methods() -> Vec<(&'static str, Method)> {
         method!(get_password),
         method!(delete_password),
         method!(export_password),
+        method!(get_share_key),
+        method!(share_entries),
+        method!(revoke_share),
+        method!(export_share_revocations),
+        method!(import_share),
+        method!(list_sent_shares),
+        method!(list_received_shares),
+        method!(list_audit_log),
+        method!(check_vault),
+        method!(quarantine_vault),
+        method!(list_vaults),
+        method!(create_vault),
+        method!(open_vault),
+        method!(close_vault),
+        method!(rename_vault),
+        method!(delete_vault),
+        method!(list_backup),
+        method!(verify_backup),
+        method!(restore_backup),
+        method!(get_backup_retention),
+        method!(set_backup_retention),
         method!(import_password),
+        method!(preview_import),
         method!(update_password),
         method!(change_password),
+        method!(set_recovery),
+        method!(remove_recovery),
+        method!(is_recovery_set),
+        method!(recover_master_password),
+        method!(set_key_file),
+        method!(remove_key_file),
+        method!(is_key_file_set),
+        method!(set_duress_password),
+        method!(remove_duress_password),
+        method!(is_duress_password_set),
+        method!(list_trash),
+        method!(restore_trash),
+        method!(purge_trash),
+        method!(get_trash_retention),
+        method!(set_trash_retention),
+        method!(set_two_factor),
+        method!(password_health),
+        method!(load_breach_data),
+        method!(check_breach),
+        method!(check_breach_password),
+        method!(list_password_history),
+        method!(get_password_history),
+        method!(restore_password_history),
+        method!(get_history_limit),
+        method!(set_history_limit),
+        method!(sync_pair),
+        method!(sync_claim),
+        method!(join_sync),
+        method!(sync_session),
+        method!(sync_exchange),
+        method!(sync_now),
+        method!(list_sync_peers),
+        method!(remove_sync_peer),
+        method!(list_sync_conflicts),
+        method!(resolve_sync_conflict),
+        method!(set_sync_folder),
+        method!(get_sync_folder),
+        method!(sync_folder),
+        method!(set_webdav),
+        method!(get_webdav),
+        method!(sync_webdav),
         method!(get_network_port),
         method!(enable_network_access),
         method!(disable_network_access),
+        method!(subscribe),
+        method!(unsubscribe),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::db::setup;
+
+    // CSV 是 import_password 的一种来源，不会被识别为其他来源
+    #[test]
+    fn csv_source() {
+        let data = "name,url,username,password\nGitHub,https://github.com,alice,secret\n";
+        let source = json!({ "csv": { "data": data }, "mapping": null });
+        let source: Source = serde_json::from_value(source).unwrap();
+        assert!(matches!(source, Source::Csv { .. }));
+        let master_password = MasterPassword::Password("password".to_string());
+        let import = read_source(master_password, None, source, false).unwrap();
+        assert_eq!(import.entries.len(), 1);
+        assert_eq!(import.entries[0].password, "secret");
+    }
+
+    // 恢复失败时记录审计日志，同一客户端连续失败后暂时拒绝，正确的恢复密钥也不能使用
+    #[test]
+    fn recover_attempts() {
+        let tmp = tempdir().unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup(&mut conn, &tmp.path().join("backups")).unwrap();
+        const KEY: [u8; 32] = [7; 32];
+        audit::init(&conn, &KEY).unwrap();
+        let kit = recovery::create(&conn, &KEY, None).unwrap();
+
+        let client = Some("192.168.1.10:1006".parse().unwrap());
+        let other = Some("192.168.1.11:1006".parse().unwrap());
+        let wrong = recovery::Secret::Key("wrong".to_string());
+        let right = recovery::Secret::Key(kit.key);
+        let recover =
+            |addr, secret| server::with_client(addr, || recover_key(&conn, "recover", secret));
+        for _ in 0..5 {
+            assert!(matches!(
+                recover(client, &wrong),
+                Err(Error::InvalidRecoveryKey)
+            ));
+        }
+        assert!(matches!(
+            recover(client, &right),
+            Err(Error::TooManyAttempts)
+        ));
+        const SQL: &str = "SELECT count(*) FROM audit";
+        let count: u32 = conn.query_row(SQL, [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 5);
+
+        // 其他客户端不受影响
+        assert_eq!(recover(other, &right).unwrap(), KEY);
+    }
+
+    // 回收站中的条目不能读取、修改或者设置两步验证
+    #[test]
+    fn trashed_entry() {
+        let tmp = tempdir().unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup(&mut conn, &tmp.path().join("backups")).unwrap();
+        const SQL: &str =
+            "INSERT INTO vault (key, value, created_at, modified_at) VALUES (x'01', x'02', 1, 1)";
+        conn.execute(SQL, []).unwrap();
+        let id = conn.last_insert_rowid() as u64;
+
+        assert_eq!(live_entry(&conn, id).unwrap(), (vec![1], vec![2]));
+        update_two_factor(&conn, id, true).unwrap();
+
+        const TRASH_SQL: &str = "UPDATE vault SET deleted_at=1 WHERE id=?";
+        conn.execute(TRASH_SQL, [id]).unwrap();
+        assert!(live_entry(&conn, id).is_err());
+        assert!(update_two_factor(&conn, id, false).is_err());
+        let two_factor: bool = conn
+            .query_row("SELECT two_factor FROM vault WHERE id=?", [id], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(two_factor);
+        assert!(live_entry(&conn, id + 1).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+// 分享条目给其他保险库
+//
+// 每个保险库有一对分享密钥，第一次使用时生成：age X25519 密钥用于接收加密的条目，Ed25519 密钥用于签名。
+// 分享公钥为 `<age 公钥>.<base64 Ed25519 公钥>`，交给同事后对方可以把条目加密分享给本保险库，
+// 私钥使用保险库密钥加密保存。
+//
+// 分享是签名的 JSON 信封，条目加密给接收者，签名包括接收者的分享公钥，只有撤销列表的信封也不能交给
+// 其他接收者。接收者导入前校验签名和接收者，可以要求发送者是指定的公钥，并通过指纹核对发送者。发送者可以撤销分享 (例如被新的分享取代)，之后发给同一接收者的信封都附带已撤销的
+// 分享的 id，也可以单独导出只有撤销列表的信封。接收者拒绝导入已撤销的分享，已导入的分享标记为已撤销，
+// 导入的条目不会删除。
+
+use std::io;
+use std::str::FromStr;
+
+use age::secrecy::ExposeSecret;
+use age::x25519::{Identity, Recipient};
+use openssl::pkey::{Id, PKey};
+use openssl::rand::rand_bytes;
+use openssl::sha::sha256;
+use openssl::sign::{Signer, Verifier};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{key_decrypt, key_encrypt};
+use crate::db::{get_conf, set_conf};
+use crate::recipient::x25519;
+
+// 分享公钥
+const PUBLIC_KEY: &str = "share_public_key";
+
+// 使用保险库密钥加密的 age 私钥
+const IDENTITY: &str = "share_identity";
+
+// 使用保险库密钥加密的 Ed25519 私钥
+const SIGNING_KEY: &str = "share_signing_key";
+
+const VERSION: u8 = 2;
+
+// 签名内容的前缀，签名不能用于其他用途
+const CONTEXT: &str = "vault share v2";
+
+// 分享的条目
+#[derive(Serialize, Deserialize, PartialEq, Debug)]
+pub struct Entry {
+    pub name: String,
+    pub password: String,
+    // 是否开启了两步验证
+    #[serde(default)]
+    pub two_factor: bool,
+}
+
+// 分享的信封
+#[derive(Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u8,
+    // 分享的 id，随机生成，用于撤销
+    pub id: String,
+    // 发送者的分享公钥
+    pub sender: String,
+    // 接收者的分享公钥
+    pub recipient: String,
+    pub created_at: i64,
+    // 发送者撤销的发给同一接收者的分享
+    #[serde(default)]
+    pub revoked: Vec<String>,
+    // 加密给接收者的条目 JSON `[Entry, ...]`，ASCII armor 格式，只有撤销列表时为 None
+    pub data: Option<String>,
+    // 发送者的 Ed25519 签名，base64 编码
+    pub signature: String,
+}
+
+impl Envelope {
+    // 签名的内容，data 在最后，其他字段都不包含换行
+    fn message(&self) -> Vec<u8> {
+        let text = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+            CONTEXT,
+            self.version,
+            self.id,
+            self.sender,
+            self.recipient,
+            self.created_at,
+            self.revoked.join(","),
+            self.data.as_deref().unwrap_or_default()
+        );
+        text.into_bytes()
+    }
+
+    // 校验签名，格式错误或签名无效时返回 false
+    pub fn verify(&self) -> bool {
+        let valid = || -> Option<bool> {
+            let (_, verify_key) = parse_public_key(&self.sender)?;
+            let signature = base64::decode(&self.signature).ok()?;
+            let mut verifier = Verifier::new_without_digest(&verify_key).ok()?;
+            verifier.verify_oneshot(&signature, &self.message()).ok()
+        };
+        let fields = [&self.id]
+            .into_iter()
+            .chain(&self.revoked)
+            .all(|v| valid_id(v));
+        self.version == VERSION && fields && valid() == Some(true)
+    }
+}
+
+#[derive(Serialize)]
+pub struct Sent {
+    pub id: String,
+    // 接收者的分享公钥和指纹
+    pub recipient: String,
+    pub fingerprint: String,
+    // 条目数量
+    pub count: usize,
+    pub created_at: i64,
+    // 撤销时间，没有撤销时为 None
+    pub revoked_at: Option<i64>,
+}
+
+#[derive(Serialize)]
+pub struct Received {
+    pub id: String,
+    // 发送者的分享公钥和指纹
+    pub sender: String,
+    pub fingerprint: String,
+    pub count: usize,
+    pub created_at: i64,
+    pub imported_at: i64,
+    // 发送者是否已撤销
+    pub revoked: bool,
+}
+
+// 本保险库的分享公钥，没有分享密钥时生成
+pub fn public_key(conn: &Connection, key: &[u8]) -> crate::Result<String> {
+    if let Some(public_key) = get_conf(conn, PUBLIC_KEY)? {
+        return Ok(public_key);
+    }
+    let identity = Identity::generate();
+    let signing_key = PKey::generate_ed25519().map_err(err!())?;
+    let public_key = format!(
+        "{}.{}",
+        identity.to_public(),
+        base64::encode(signing_key.raw_public_key().map_err(err!())?)
+    );
+    let secret = key_encrypt(key, identity.to_string().expose_secret()).map_err(err!())?;
+    set_conf(conn, IDENTITY, &base64::encode(secret))?;
+    let secret = key_encrypt(key, signing_key.raw_private_key().map_err(err!())?);
+    set_conf(conn, SIGNING_KEY, &base64::encode(secret.map_err(err!())?))?;
+    set_conf(conn, PUBLIC_KEY, &public_key)?;
+    Ok(public_key)
+}
+
+// 分享公钥的指纹，SHA-256 的前 16 个十六进制字符，每 4 个用空格分隔，用于当面或者电话核对
+pub fn fingerprint(public_key: &str) -> String {
+    let hash = sha256(public_key.as_bytes());
+    let hex: Vec<_> = hash[..8]
+        .chunks(2)
+        .map(|v| format!("{:02x}{:02x}", v[0], v[1]))
+        .collect();
+    hex.join(" ")
+}
+
+// 分享公钥格式是否正确
+pub fn valid_public_key(public_key: &str) -> bool {
+    parse_public_key(public_key).is_some()
+}
+
+// 保险库密钥变化时重新加密分享私钥
+pub fn reencrypt(conn: &Connection, key: &[u8], new_key: &[u8]) -> crate::Result<()> {
+    for name in [IDENTITY, SIGNING_KEY] {
+        if let Some(secret) = secret(conn, key, name)? {
+            let secret = key_encrypt(new_key, secret).map_err(err!())?;
+            set_conf(conn, name, &base64::encode(secret))?;
+        }
+    }
+    Ok(())
+}
+
+// 生成发给 recipient 的信封并记录，附带撤销的发给 recipient 的分享，entries 为 None 时只有撤销列表
+pub fn seal(
+    conn: &Connection,
+    key: &[u8],
+    recipient: &str,
+    entries: Option<&[Entry]>,
+    created_at: i64,
+) -> crate::Result<Envelope> {
+    let (age_recipient, _) = parse_public_key(recipient)
+        .ok_or_else(|| err!(invalid_data("invalid share public key")))?;
+    let data = match entries {
+        Some(entries) => {
+            let data = serde_json::to_vec(entries).map_err(err!())?;
+            let data = x25519::encrypt(&data, &[age_recipient.to_string()])?;
+            Some(String::from_utf8(data).map_err(err!())?)
+        }
+        None => None,
+    };
+    let mut envelope = Envelope {
+        version: VERSION,
+        id: new_id()?,
+        sender: public_key(conn, key)?,
+        recipient: recipient.to_string(),
+        created_at,
+        revoked: revoked_for(conn, recipient)?,
+        data,
+        signature: String::new(),
+    };
+    let signing_key =
+        secret(conn, key, SIGNING_KEY)?.ok_or_else(|| err!(invalid_data("missing signing key")))?;
+    let signing_key =
+        PKey::private_key_from_raw_bytes(&signing_key, Id::ED25519).map_err(err!())?;
+    let mut signer = Signer::new_without_digest(&signing_key).map_err(err!())?;
+    let signature = signer
+        .sign_oneshot_to_vec(&envelope.message())
+        .map_err(err!())?;
+    envelope.signature = base64::encode(signature);
+
+    if let Some(entries) = entries {
+        const SQL: &str =
+            "INSERT INTO share_sent (id, recipient, count, created_at) VALUES (?, ?, ?, ?)";
+        conn.execute(
+            SQL,
+            params![envelope.id, recipient, entries.len(), created_at],
+        )
+        .map_err(err!())?;
+    }
+    Ok(envelope)
+}
+
+// 撤销发出的分享，分享不存在时返回 false
+pub fn revoke(conn: &Connection, id: &str, revoked_at: i64) -> crate::Result<bool> {
+    const SQL: &str = "UPDATE share_sent SET revoked_at=? WHERE id=? AND revoked_at IS NULL";
+    let count = conn.execute(SQL, params![revoked_at, id]).map_err(err!())?;
+    Ok(count > 0 || is_sent(conn, id)?)
+}
+
+// 信封是否是发给本保险库的，没有分享密钥时返回 false。调用前需要校验签名
+pub fn is_recipient(conn: &Connection, envelope: &Envelope) -> crate::Result<bool> {
+    Ok(get_conf(conn, PUBLIC_KEY)?.as_deref() == Some(envelope.recipient.as_str()))
+}
+
+// 记录信封附带的撤销列表，返回新撤销的已导入分享的数量。调用前需要校验签名和接收者
+pub fn apply_revoked(conn: &Connection, envelope: &Envelope) -> crate::Result<usize> {
+    let mut count = 0;
+    for id in &envelope.revoked {
+        const SQL: &str = "INSERT OR IGNORE INTO share_revoked (sender, id) VALUES (?, ?)";
+        let inserted = conn.execute(SQL, [&envelope.sender, id]).map_err(err!())?;
+        if inserted > 0 && is_received(conn, &envelope.sender, id)? {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+// 解密信封中的条目，只有撤销列表时返回 None。调用前需要校验签名和接收者，并检查是否已撤销、已导入
+pub fn open(
+    conn: &Connection,
+    key: &[u8],
+    envelope: &Envelope,
+) -> crate::Result<Option<Vec<Entry>>> {
+    let data = match envelope.data {
+        Some(ref data) => data,
+        None => return Ok(None),
+    };
+    let identity =
+        secret(conn, key, IDENTITY)?.ok_or_else(|| err!(invalid_data("missing share identity")))?;
+    let data = x25519::decrypt(data.as_bytes(), &identity)?;
+    serde_json::from_slice(&data).map(Some).map_err(err!())
+}
+
+// 记录导入的分享
+pub fn record_received(
+    conn: &Connection,
+    envelope: &Envelope,
+    count: usize,
+    imported_at: i64,
+) -> crate::Result<()> {
+    const SQL: &str = "INSERT INTO share_received (id, sender, count, created_at, imported_at) VALUES (?, ?, ?, ?, ?)";
+    let params = params![
+        envelope.id,
+        envelope.sender,
+        count,
+        envelope.created_at,
+        imported_at
+    ];
+    conn.execute(SQL, params).map_err(err!())?;
+    Ok(())
+}
+
+// 发出的分享，最新的在前面
+pub fn list_sent(conn: &Connection) -> crate::Result<Vec<Sent>> {
+    const SQL: &str = "SELECT id, recipient, count, created_at, revoked_at FROM share_sent ORDER BY created_at DESC, rowid DESC";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let rows = stmt
+        .query_map([], |row| {
+            let recipient: String = row.get(1)?;
+            Ok(Sent {
+                id: row.get(0)?,
+                fingerprint: fingerprint(&recipient),
+                recipient,
+                count: row.get(2)?,
+                created_at: row.get(3)?,
+                revoked_at: row.get(4)?,
+            })
+        })
+        .map_err(err!())?;
+    rows.collect::<Result<_, _>>().map_err(err!())
+}
+
+// 导入的分享，最新的在前面
+pub fn list_received(conn: &Connection) -> crate::Result<Vec<Received>> {
+    const SQL: &str = "SELECT id, sender, count, created_at, imported_at, EXISTS (SELECT 1 FROM share_revoked WHERE share_revoked.sender=share_received.sender AND share_revoked.id=share_received.id) FROM share_received ORDER BY imported_at DESC, rowid DESC";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let rows = stmt
+        .query_map([], |row| {
+            let sender: String = row.get(1)?;
+            Ok(Received {
+                id: row.get(0)?,
+                fingerprint: fingerprint(&sender),
+                sender,
+                count: row.get(2)?,
+                created_at: row.get(3)?,
+                imported_at: row.get(4)?,
+                revoked: row.get(5)?,
+            })
+        })
+        .map_err(err!())?;
+    rows.collect::<Result<_, _>>().map_err(err!())
+}
+
+// 撤销的发给 recipient 的分享
+fn revoked_for(conn: &Connection, recipient: &str) -> crate::Result<Vec<String>> {
+    const SQL: &str =
+        "SELECT id FROM share_sent WHERE recipient=? AND revoked_at IS NOT NULL ORDER BY rowid";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let rows = stmt
+        .query_map([recipient], |row| row.get(0))
+        .map_err(err!())?;
+    rows.collect::<Result<_, _>>().map_err(err!())
+}
+
+fn is_sent(conn: &Connection, id: &str) -> crate::Result<bool> {
+    const SQL: &str = "SELECT COUNT(0) FROM share_sent WHERE id=?";
+    let count: u32 = conn
+        .query_row(SQL, [id], |row| row.get(0))
+        .map_err(err!())?;
+    Ok(count > 0)
+}
+
+// 发送者是否撤销了分享
+pub fn is_revoked(conn: &Connection, sender: &str, id: &str) -> crate::Result<bool> {
+    const SQL: &str = "SELECT COUNT(0) FROM share_revoked WHERE sender=? AND id=?";
+    let count: u32 = conn
+        .query_row(SQL, [sender, id], |row| row.get(0))
+        .map_err(err!())?;
+    Ok(count > 0)
+}
+
+// 是否已导入分享
+pub fn is_received(conn: &Connection, sender: &str, id: &str) -> crate::Result<bool> {
+    const SQL: &str = "SELECT COUNT(0) FROM share_received WHERE sender=? AND id=?";
+    let count: u32 = conn
+        .query_row(SQL, [sender, id], |row| row.get(0))
+        .map_err(err!())?;
+    Ok(count > 0)
+}
+
+// 解析分享公钥为 age 公钥和 Ed25519 公钥
+fn parse_public_key(public_key: &str) -> Option<(Recipient, PKey<openssl::pkey::Public>)> {
+    let (recipient, verify_key) = public_key.split_once('.')?;
+    let recipient = Recipient::from_str(recipient).ok()?;
+    let verify_key = base64::decode(verify_key).ok()?;
+    let verify_key = PKey::public_key_from_raw_bytes(&verify_key, Id::ED25519).ok()?;
+    Some((recipient, verify_key))
+}
+
+// 随机的 32 位十六进制 id
+fn new_id() -> crate::Result<String> {
+    let mut id = [0u8; 16];
+    rand_bytes(&mut id).map_err(err!())?;
+    Ok(id.iter().map(|v| format!("{:02x}", v)).collect())
+}
+
+fn valid_id(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|v| matches!(v, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+// 解密使用保险库密钥加密的私钥，没有生成分享密钥时返回 None
+fn secret(conn: &Connection, key: &[u8], name: &str) -> crate::Result<Option<Vec<u8>>> {
+    let secret = match get_conf(conn, name)? {
+        Some(v) => base64::decode(v).map_err(err!())?,
+        None => return Ok(None),
+    };
+    match key_decrypt(key, secret).map_err(err!())? {
+        Some(v) => Ok(Some(v)),
+        None => Err(err!(invalid_data("wrong share key"))),
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vault() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "create table conf (key text not null primary key, value text);
+            create table share_sent (id text not null primary key, recipient text not null, count integer not null, created_at integer not null, revoked_at integer);
+            create table share_received (id text not null, sender text not null, count integer not null, created_at integer not null, imported_at integer not null, primary key (sender, id));
+            create table share_revoked (sender text not null, id text not null, primary key (sender, id));",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn share_and_revoke() {
+        let (alice, bob) = (vault(), vault());
+        let (alice_key, bob_key) = ([1u8; 32], [2u8; 32]);
+        let alice_public = public_key(&alice, &alice_key).unwrap();
+        assert_eq!(public_key(&alice, &alice_key).unwrap(), alice_public);
+        let bob_public = public_key(&bob, &bob_key).unwrap();
+        assert!(valid_public_key(&bob_public));
+        assert!(!valid_public_key("age1invalid"));
+
+        let entries = vec![Entry {
+            name: "name".to_string(),
+            password: "password".to_string(),
+            two_factor: true,
+        }];
+        let envelope = seal(&alice, &alice_key, &bob_public, Some(&entries), 1).unwrap();
+        let json = serde_json::to_string(&envelope).unwrap();
+        let envelope: Envelope = serde_json::from_str(&json).unwrap();
+        assert!(envelope.verify());
+        assert_eq!(envelope.sender, alice_public);
+        assert!(is_recipient(&bob, &envelope).unwrap());
+        assert!(!is_recipient(&alice, &envelope).unwrap());
+        assert_eq!(open(&bob, &bob_key, &envelope).unwrap().unwrap(), entries);
+        // 不是分享给自己的无法解密
+        assert!(open(&alice, &alice_key, &envelope).is_err());
+        record_received(&bob, &envelope, 1, 2).unwrap();
+        assert!(is_received(&bob, &alice_public, &envelope.id).unwrap());
+
+        // 修改任何字段签名都无效，也不能冒充发送者
+        let mut tampered: Envelope = serde_json::from_str(&json).unwrap();
+        tampered.created_at += 1;
+        assert!(!tampered.verify());
+        let mut tampered: Envelope = serde_json::from_str(&json).unwrap();
+        tampered.sender = bob_public.clone();
+        assert!(!tampered.verify());
+        let mut tampered: Envelope = serde_json::from_str(&json).unwrap();
+        tampered.recipient = alice_public.clone();
+        assert!(!tampered.verify());
+
+        // 保险库密钥变化后仍然可以签名
+        let new_key = [3u8; 32];
+        reencrypt(&alice, &alice_key, &new_key).unwrap();
+        assert!(revoke(&alice, &envelope.id, 3).unwrap());
+        assert!(!revoke(&alice, "missing", 3).unwrap());
+        let revocation = seal(&alice, &new_key, &bob_public, None, 4).unwrap();
+        assert!(revocation.verify());
+        assert_eq!(revocation.revoked, vec![envelope.id.clone()]);
+        assert!(open(&bob, &bob_key, &revocation).unwrap().is_none());
+        assert_eq!(apply_revoked(&bob, &revocation).unwrap(), 1);
+        assert_eq!(apply_revoked(&bob, &revocation).unwrap(), 0);
+        assert!(is_revoked(&bob, &alice_public, &envelope.id).unwrap());
+        assert!(list_received(&bob).unwrap()[0].revoked);
+        let sent = list_sent(&alice).unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].revoked_at, Some(3));
+        assert_eq!(sent[0].fingerprint, fingerprint(&bob_public));
+
+        // 只有撤销列表的信封不能交给其他接收者重放
+        let carol = vault();
+        public_key(&carol, &[4u8; 32]).unwrap();
+        assert!(revocation.verify());
+        assert!(!is_recipient(&carol, &revocation).unwrap());
+    }
+}
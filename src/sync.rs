@@ -0,0 +1,1057 @@
+// 局域网同步
+//
+// 每个条目有全局的 uuid 和版本向量 {设备 id: 修改次数}，本地修改由触发器增加本设备的修改次数并写入变更日志。
+// 同步时发送对方还没有收到的变更，收到的变更按版本向量合并：对方较新时覆盖本地，本地较新时忽略，
+// 同时修改时保存为冲突，由用户选择保留哪一方。
+//
+// 消息使用由保险库密钥派生的同步密钥加密认证，条目的名称和密码本身也是用保险库密钥加密的。
+// 同步密钥不保存，保险库解锁后才派生并保存在内存中，关闭保险库时清除，对方的网络服务只在解锁期间响应同步。
+// 发起同步前先向对方获取一次性的会话号，请求和回复都带上会话号，截获的旧消息不能重放。
+//
+// 加入同步使用一次性的配对码：对方设备生成随机的配对码显示给用户，用配对码派生的密钥加密保险库密钥，
+// 加入的设备输入配对码，用配对码派生的 id 取回加密的保险库密钥再解密。主密码不经过网络，
+// 不验证证书时中间人拿到的也只有密文，配对码只能使用一次，5 分钟后过期。
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use openssl::memcmp;
+use openssl::rand::rand_bytes;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{derive_key, hmac_sha256, key_decrypt, key_encrypt};
+use crate::db::now;
+
+pub mod client;
+pub mod folder;
+pub mod webdav;
+
+// 版本向量，设备 id => 修改次数
+pub type Version = BTreeMap<String, u64>;
+
+// AES-GCM 的 iv 和 tag 长度，密文不长于这个长度时 key_decrypt 会原样返回
+const MIN_CIPHERTEXT_LEN: usize = 12 + 16;
+
+// 配对码的有效期（秒）
+const PAIRING_TTL: i64 = 300;
+
+// 会话号的有效期（秒）
+const SESSION_TTL: i64 = 60;
+
+// 已解锁的保险库的目录和同步密钥
+static KEYS: Mutex<Vec<(PathBuf, Vec<u8>)>> = Mutex::new(Vec::new());
+
+// 等待其他设备加入的配对，同时只有一个
+static PAIRING: Mutex<Option<Pairing>> = Mutex::new(None);
+
+// 对方连接获取的会话号和获取的时间
+static SESSIONS: Mutex<Vec<(SocketAddr, String, i64)>> = Mutex::new(Vec::new());
+
+struct Pairing {
+    // 由配对码派生的 id
+    id: [u8; 32],
+    // 用配对码派生的密钥加密的保险库密钥，base64 编码
+    sealed: String,
+    expires_at: i64,
+}
+
+#[derive(PartialEq)]
+enum Order {
+    Equal,
+    Before,
+    After,
+    Concurrent,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    // 加密的名称和密码，base64 编码
+    pub key: String,
+    pub value: String,
+    pub created_at: i64,
+    pub modified_at: i64,
+    pub deleted_at: Option<i64>,
+    pub two_factor: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Change {
+    pub uuid: String,
+    pub version: Version,
+    // None 表示已彻底删除
+    pub entry: Option<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Message {
+    // 发送方的设备 id
+    device: String,
+    // 发送方最新的变更序号
+    seq: u64,
+    // 请求对方发送这个序号之后的变更
+    since: u64,
+    changes: Vec<Change>,
+    // 对方发放的会话号，回复中原样带回
+    session: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct Report {
+    // 发送的变更数量
+    pub sent: usize,
+    // 应用的对方的变更数量
+    pub received: usize,
+    // 新的冲突数量
+    pub conflicts: usize,
+}
+
+#[derive(Serialize)]
+pub struct Peer {
+    pub id: u64,
+    // 对方的设备 id，第一次同步之前为 None
+    pub device: Option<String>,
+    // 对方网络服务的地址，对方发起同步时为 None
+    pub addr: Option<String>,
+    #[serde(skip)]
+    sent: u64,
+    #[serde(skip)]
+    received: u64,
+    pub synced_at: Option<i64>,
+}
+
+pub struct Conflict {
+    pub id: u64,
+    pub peer: String,
+    pub local: Option<Entry>,
+    pub remote: Option<Entry>,
+    pub time: i64,
+}
+
+// 三方合并使用的共同版本，即上次同步后双方都有的内容
+pub struct Base<'a> {
+    pub changes: HashMap<String, Change>,
+    // 保险库密钥，每次保存都会重新加密，需要比较明文
+    pub key: &'a [u8],
+}
+
+impl Base<'_> {
+    // 双方修改了不同的字段时合并，修改了同一个字段且结果不同时返回 None
+    fn merge(&self, remote: &Change, local: Option<&Entry>) -> crate::Result<Option<Entry>> {
+        let base = self
+            .changes
+            .get(&remote.uuid)
+            .and_then(|v| v.entry.as_ref());
+        let (base, local, remote) = match (base, local, remote.entry.as_ref()) {
+            (Some(base), Some(local), Some(remote)) => (base, local, remote),
+            _ => return Ok(None),
+        };
+        let (key, value) = match (
+            self.pick_encrypted(&base.key, &local.key, &remote.key)?,
+            self.pick_encrypted(&base.value, &local.value, &remote.value)?,
+        ) {
+            (Some(key), Some(value)) => (key, value),
+            _ => return Ok(None),
+        };
+        let (deleted_at, two_factor) = match (
+            pick(&base.deleted_at, &local.deleted_at, &remote.deleted_at),
+            pick(&base.two_factor, &local.two_factor, &remote.two_factor),
+        ) {
+            (Some(deleted_at), Some(two_factor)) => (deleted_at, two_factor),
+            _ => return Ok(None),
+        };
+        Ok(Some(Entry {
+            key,
+            value,
+            created_at: local.created_at.min(remote.created_at),
+            modified_at: local.modified_at.max(remote.modified_at),
+            deleted_at,
+            two_factor,
+        }))
+    }
+
+    fn pick_encrypted(
+        &self,
+        base: &str,
+        local: &str,
+        remote: &str,
+    ) -> crate::Result<Option<String>> {
+        let decrypt = |data: &str| -> crate::Result<Option<Vec<u8>>> {
+            let data = base64::decode(data).map_err(err!())?;
+            key_decrypt(self.key, data).map_err(err!())
+        };
+        let (base, local_plain, remote_plain) =
+            match (decrypt(base)?, decrypt(local)?, decrypt(remote)?) {
+                (Some(base), Some(local), Some(remote)) => (base, local, remote),
+                _ => return Ok(None),
+            };
+        Ok(pick(&base, &local_plain, &remote_plain).map(|v| {
+            if v == local_plain {
+                local.to_string()
+            } else {
+                remote.to_string()
+            }
+        }))
+    }
+}
+
+// 三方合并一个字段，只有一方修改时取修改的一方
+fn pick<T: PartialEq + Clone>(base: &T, local: &T, remote: &T) -> Option<T> {
+    if local == remote || remote == base {
+        Some(local.clone())
+    } else if local == base {
+        Some(remote.clone())
+    } else {
+        None
+    }
+}
+
+// 对方返回的错误
+#[derive(Debug)]
+pub struct PeerError(pub String);
+
+impl Display for PeerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "peer error: {}", self.0)
+    }
+}
+
+impl Error for PeerError {}
+
+// 由保险库密钥派生同步密钥
+pub fn sync_key(vault_key: &[u8]) -> crate::Result<Vec<u8>> {
+    hmac_sha256(vault_key, b"vault sync").map_err(err!())
+}
+
+// 开启同步，之后保险库解锁期间对方可以发起同步
+pub fn enable(conn: &Connection) -> crate::Result<()> {
+    const SQL: &str = "INSERT OR REPLACE INTO conf (key, value) VALUES ('sync_enabled', '1')";
+    conn.execute(SQL, []).map_err(err!())?;
+    Ok(())
+}
+
+fn is_enabled(conn: &Connection) -> crate::Result<bool> {
+    const SQL: &str = "SELECT EXISTS(SELECT 1 FROM conf WHERE key='sync_enabled')";
+    conn.query_row(SQL, [], |row| row.get(0)).map_err(err!())
+}
+
+// 保险库解锁后把同步密钥保存在内存中，没有开启同步时什么都不做
+pub fn unlock(dir: &Path, conn: &Connection, vault_key: &[u8]) -> crate::Result<()> {
+    if !is_enabled(conn)? {
+        return Ok(());
+    }
+    let key = sync_key(vault_key)?;
+    let mut keys = KEYS.lock().unwrap();
+    keys.retain(|(v, _)| v != dir);
+    keys.push((dir.to_path_buf(), key));
+    Ok(())
+}
+
+// 保险库关闭后清除同步密钥
+pub fn lock(dir: &Path) {
+    KEYS.lock().unwrap().retain(|(v, _)| v != dir);
+}
+
+// 已解锁的保险库的同步密钥，没有解锁或者没有开启同步时返回 None
+pub fn unlocked_key(dir: &Path) -> Option<Vec<u8>> {
+    let keys = KEYS.lock().unwrap();
+    keys.iter().find(|(v, _)| v == dir).map(|v| v.1.clone())
+}
+
+// 是否已经和其他设备同步，包括局域网、同步文件夹和 WebDAV
+pub fn has_peers(conn: &Connection) -> crate::Result<bool> {
+    const SQL: &str = "SELECT EXISTS(SELECT 1 FROM peer)
+        OR EXISTS(SELECT 1 FROM conf WHERE key IN ('sync_folder', 'webdav'))";
+    conn.query_row(SQL, [], |row| row.get(0)).map_err(err!())
+}
+
+// 生成配对码，用配对码派生的密钥加密保险库密钥，替换之前没有使用的配对码
+pub fn pair(vault_key: &[u8]) -> crate::Result<String> {
+    let mut code = [0u8; 16];
+    rand_bytes(&mut code).map_err(err!())?;
+    // 分成 4 个字符一组，方便输入
+    let code = code
+        .chunks(2)
+        .map(|v| format!("{:02x}{:02x}", v[0], v[1]))
+        .collect::<Vec<_>>()
+        .join("-");
+    let (id, key) = pairing_keys(&code)?;
+    let sealed = base64::encode(key_encrypt(key, vault_key).map_err(err!())?);
+    *PAIRING.lock().unwrap() = Some(Pairing {
+        id,
+        sealed,
+        expires_at: now()? + PAIRING_TTL,
+    });
+    Ok(code)
+}
+
+// 加入的设备发送的 id，由配对码派生，不能由 id 得到配对码
+pub fn pairing_id(code: &str) -> crate::Result<String> {
+    Ok(base64::encode(pairing_keys(code)?.0))
+}
+
+// 用 id 取回加密的保险库密钥，配对码只能使用一次，id 不匹配或者已过期时返回 None
+pub fn claim(id: &str) -> crate::Result<Option<String>> {
+    let id = match base64::decode(id) {
+        Ok(v) if v.len() == 32 => v,
+        _ => return Ok(None),
+    };
+    let now = now()?;
+    let mut pairing = PAIRING.lock().unwrap();
+    match pairing.as_ref() {
+        Some(v) if v.expires_at <= now => {
+            *pairing = None;
+            Ok(None)
+        }
+        Some(v) if memcmp::eq(&v.id, &id) => Ok(pairing.take().map(|v| v.sealed)),
+        _ => Ok(None),
+    }
+}
+
+// 用配对码解密对方的保险库密钥，配对码错误或者数据被修改时返回 None
+pub fn open_pairing(code: &str, sealed: &str) -> crate::Result<Option<Vec<u8>>> {
+    let (_, key) = pairing_keys(code)?;
+    match base64::decode(sealed) {
+        Ok(data) if data.len() > MIN_CIPHERTEXT_LEN => key_decrypt(key, data).map_err(err!()),
+        _ => Ok(None),
+    }
+}
+
+// 由配对码派生 id 和加密保险库密钥的密钥，忽略大小写和分隔符
+fn pairing_keys(code: &str) -> crate::Result<([u8; 32], [u8; 32])> {
+    let code: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let id = derive_key(&code, "vault sync pairing id").map_err(err!())?;
+    let key = derive_key(&code, "vault sync pairing key").map_err(err!())?;
+    Ok((id, key))
+}
+
+// 对方发起同步前获取会话号，同一个连接只保留最新的
+pub fn new_session(addr: SocketAddr) -> crate::Result<String> {
+    let mut session = [0u8; 16];
+    rand_bytes(&mut session).map_err(err!())?;
+    let session = base64::encode(session);
+    let now = now()?;
+    let mut sessions = SESSIONS.lock().unwrap();
+    sessions.retain(|(v, _, time)| *v != addr && now - *time < SESSION_TTL);
+    sessions.push((addr, session.clone(), now));
+    Ok(session)
+}
+
+// 取出连接的会话号，只能使用一次，没有或者已过期时返回 None
+pub fn take_session(addr: SocketAddr) -> crate::Result<Option<String>> {
+    let now = now()?;
+    let mut sessions = SESSIONS.lock().unwrap();
+    let index = sessions.iter().position(|(v, _, _)| *v == addr);
+    Ok(index
+        .map(|i| sessions.remove(i))
+        .filter(|(_, _, time)| now - *time < SESSION_TTL)
+        .map(|v| v.1))
+}
+
+pub fn peers(conn: &Connection) -> crate::Result<Vec<Peer>> {
+    const SQL: &str = "SELECT id, device, addr, sent, received, synced_at FROM peer ORDER BY id";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    let mut list = Vec::new();
+    while let Some(row) = rows.next().map_err(err!())? {
+        list.push(Peer {
+            id: row.get(0).map_err(err!())?,
+            device: row.get(1).map_err(err!())?,
+            addr: row.get(2).map_err(err!())?,
+            sent: row.get(3).map_err(err!())?,
+            received: row.get(4).map_err(err!())?,
+            synced_at: row.get(5).map_err(err!())?,
+        });
+    }
+    Ok(list)
+}
+
+pub fn add_peer(conn: &Connection, addr: &str) -> crate::Result<()> {
+    const SQL: &str = "INSERT INTO peer (addr) VALUES (?)";
+    conn.execute(SQL, [addr]).map_err(err!())?;
+    Ok(())
+}
+
+pub fn remove_peer(conn: &Connection, id: u64) -> crate::Result<()> {
+    conn.execute("DELETE FROM peer WHERE id=?", [id])
+        .map_err(err!())?;
+    Ok(())
+}
+
+// 发起同步的请求，session 为对方发放的会话号，返回加密的消息、发送时最新的变更序号和发送的变更数量
+pub fn request(
+    conn: &Connection,
+    key: &[u8],
+    peer: &Peer,
+    session: &str,
+) -> crate::Result<(String, u64, usize)> {
+    let message = Message {
+        device: device_id(conn)?,
+        seq: latest_seq(conn)?,
+        since: peer.received,
+        changes: changes(conn, peer.sent, &HashMap::new())?,
+        session: session.to_string(),
+    };
+    let count = message.changes.len();
+    Ok((encrypt(key, &message)?, message.seq, count))
+}
+
+// 应用对方的回复，seq 为发送请求时最新的变更序号
+pub fn finish(
+    conn: &Connection,
+    key: &[u8],
+    peer: &Peer,
+    seq: u64,
+    session: &str,
+    reply: &str,
+) -> crate::Result<Report> {
+    let message = match decrypt::<Message>(key, reply)? {
+        Some(v) if v.session == session => v,
+        _ => return Err(err!(PeerError("invalid reply".to_string()))),
+    };
+    let mut report = apply(conn, &message.device, message.changes, None)?;
+    report.sent = 0;
+
+    // 对方发起过同步时已经有对方设备的记录，合并为一条
+    conn.execute(
+        "DELETE FROM peer WHERE device=? AND id<>?",
+        params![message.device, peer.id],
+    )
+    .map_err(err!())?;
+    const SQL: &str = "UPDATE peer SET device=?, sent=?, received=?, synced_at=? WHERE id=?";
+    conn.execute(
+        SQL,
+        params![message.device, seq, message.seq, now()?, peer.id],
+    )
+    .map_err(err!())?;
+    Ok(report)
+}
+
+// 处理对方发起的同步，session 为发放给对方的会话号，同步密钥或会话号不匹配时返回 None
+pub fn exchange(
+    conn: &Connection,
+    key: &[u8],
+    session: &str,
+    data: &str,
+) -> crate::Result<Option<String>> {
+    let message = match decrypt::<Message>(key, data)? {
+        Some(v) if v.session == session => v,
+        _ => return Ok(None),
+    };
+
+    // 刚收到的变更不需要再发回去
+    let received: HashMap<String, Version> = message
+        .changes
+        .iter()
+        .map(|v| (v.uuid.clone(), v.version.clone()))
+        .collect();
+    apply(conn, &message.device, message.changes, None)?;
+
+    const SQL: &str = "INSERT INTO peer (device, received, synced_at) VALUES (?, ?, ?)
+        ON CONFLICT (device) DO UPDATE SET received=excluded.received, synced_at=excluded.synced_at";
+    conn.execute(SQL, params![message.device, message.seq, now()?])
+        .map_err(err!())?;
+
+    let reply = Message {
+        device: device_id(conn)?,
+        seq: latest_seq(conn)?,
+        since: 0,
+        changes: changes(conn, message.since, &received)?,
+        session: message.session,
+    };
+    Ok(Some(encrypt(key, &reply)?))
+}
+
+// 冲突列表
+pub fn conflicts(conn: &Connection) -> crate::Result<Vec<Conflict>> {
+    const SQL: &str = "SELECT id, uuid, peer, data, time FROM conflict ORDER BY id";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let mut rows = stmt.query([]).map_err(err!())?;
+    let mut list = Vec::new();
+    while let Some(row) = rows.next().map_err(err!())? {
+        let uuid: String = row.get(1).map_err(err!())?;
+        let data: String = row.get(3).map_err(err!())?;
+        let remote: Change = serde_json::from_str(&data).map_err(err!())?;
+        list.push(Conflict {
+            id: row.get(0).map_err(err!())?,
+            peer: row.get(2).map_err(err!())?,
+            local: local_entry(conn, &uuid)?,
+            remote: remote.entry,
+            time: row.get(4).map_err(err!())?,
+        });
+    }
+    Ok(list)
+}
+
+// 解决冲突，保留一方的内容，版本向量合并后增加本设备的修改次数，使结果比双方都新
+pub fn resolve(conn: &Connection, id: u64, keep_remote: bool) -> crate::Result<()> {
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    const SQL: &str = "SELECT data FROM conflict WHERE id=?";
+    let data: String = tx.query_row(SQL, [id], |row| row.get(0)).map_err(err!())?;
+    let remote: Change = serde_json::from_str(&data).map_err(err!())?;
+
+    let local = local_version(&tx, &remote.uuid)?;
+    let mut version = match &local {
+        Some((local, _)) => merge(local, &remote.version),
+        None => remote.version.clone(),
+    };
+    *version.entry(device_id(&tx)?).or_default() += 1;
+
+    match (keep_remote, local) {
+        (true, _) | (false, None) => write(
+            &tx,
+            &Change {
+                uuid: remote.uuid.clone(),
+                version,
+                entry: remote.entry,
+            },
+        )?,
+        (false, Some((_, true))) => {
+            const SQL: &str = "UPDATE vault SET version=? WHERE uuid=?";
+            tx.execute(SQL, params![to_json(&version)?, remote.uuid])
+                .map_err(err!())?;
+            log(&tx, &remote.uuid)?;
+        }
+        (false, Some((_, false))) => {
+            const SQL: &str = "UPDATE tombstone SET version=? WHERE uuid=?";
+            tx.execute(SQL, params![to_json(&version)?, remote.uuid])
+                .map_err(err!())?;
+            log(&tx, &remote.uuid)?;
+        }
+    }
+    tx.execute("DELETE FROM conflict WHERE id=?", [id])
+        .map_err(err!())?;
+    tx.commit().map_err(err!())
+}
+
+// 应用对方的变更，同时修改时有共同版本 base 的尝试三方合并，合并不了的保存为冲突
+fn apply(
+    conn: &Connection,
+    peer: &str,
+    changes: Vec<Change>,
+    base: Option<&Base>,
+) -> crate::Result<Report> {
+    let tx = conn.unchecked_transaction().map_err(err!())?;
+    let mut report = Report::default();
+    for change in changes {
+        let local = local_version(&tx, &change.uuid)?;
+        let order = local.as_ref().map(|v| compare(&v.0, &change.version));
+        match order {
+            None | Some(Order::Before) => {
+                write(&tx, &change)?;
+                // 对方的版本包含了本地的修改，之前的冲突已经解决
+                tx.execute("DELETE FROM conflict WHERE uuid=?", [&change.uuid])
+                    .map_err(err!())?;
+                report.received += 1;
+            }
+            Some(Order::Equal) | Some(Order::After) => {}
+            Some(Order::Concurrent) => {
+                if let Some(base) = base {
+                    let entry = local_entry(&tx, &change.uuid)?;
+                    if let Some(entry) = base.merge(&change, entry.as_ref())? {
+                        let local = local.map(|v| v.0).unwrap_or_default();
+                        let mut version = merge(&local, &change.version);
+                        *version.entry(device_id(&tx)?).or_default() += 1;
+                        let change = Change {
+                            uuid: change.uuid,
+                            version,
+                            entry: Some(entry),
+                        };
+                        write(&tx, &change)?;
+                        report.received += 1;
+                        continue;
+                    }
+                }
+                const SQL: &str =
+                    "INSERT OR REPLACE INTO conflict (uuid, peer, data, time) VALUES (?, ?, ?, ?)";
+                let data = serde_json::to_string(&change).map_err(err!())?;
+                tx.execute(SQL, params![change.uuid, peer, data, now()?])
+                    .map_err(err!())?;
+                report.conflicts += 1;
+            }
+        }
+    }
+    tx.commit().map_err(err!())?;
+    Ok(report)
+}
+
+// 使用对方的内容和版本向量覆盖本地
+fn write(conn: &Connection, change: &Change) -> crate::Result<()> {
+    let version = to_json(&change.version)?;
+    match &change.entry {
+        Some(entry) => {
+            let key = base64::decode(&entry.key).map_err(err!())?;
+            let value = base64::decode(&entry.value).map_err(err!())?;
+            conn.execute("DELETE FROM tombstone WHERE uuid=?", [&change.uuid])
+                .map_err(err!())?;
+            const UPDATE_SQL: &str = "UPDATE vault SET key=?, value=?, created_at=?, modified_at=?, deleted_at=?, two_factor=?, version=? WHERE uuid=?";
+            let updated = conn
+                .execute(
+                    UPDATE_SQL,
+                    params![
+                        key,
+                        value,
+                        entry.created_at,
+                        entry.modified_at,
+                        entry.deleted_at,
+                        entry.two_factor,
+                        version,
+                        change.uuid
+                    ],
+                )
+                .map_err(err!())?;
+            if updated == 0 {
+                const INSERT_SQL: &str = "INSERT INTO vault (key, value, created_at, modified_at, deleted_at, two_factor, version, uuid) VALUES (?, ?, ?, ?, ?, ?, ?, ?)";
+                conn.execute(
+                    INSERT_SQL,
+                    params![
+                        key,
+                        value,
+                        entry.created_at,
+                        entry.modified_at,
+                        entry.deleted_at,
+                        entry.two_factor,
+                        version,
+                        change.uuid
+                    ],
+                )
+                .map_err(err!())?;
+            }
+        }
+        None => {
+            // 先写入对方的版本，删除时触发器不会再增加本设备的修改次数
+            const SQL: &str = "INSERT OR REPLACE INTO tombstone (uuid, version) VALUES (?, ?)";
+            conn.execute(SQL, params![change.uuid, version])
+                .map_err(err!())?;
+            conn.execute(
+                "DELETE FROM history WHERE vault_id IN (SELECT id FROM vault WHERE uuid=?)",
+                [&change.uuid],
+            )
+            .map_err(err!())?;
+            conn.execute("DELETE FROM vault WHERE uuid=?", [&change.uuid])
+                .map_err(err!())?;
+        }
+    }
+    log(conn, &change.uuid)
+}
+
+// since 之后有变更的条目，跳过 skip 中版本相同的条目
+fn changes(
+    conn: &Connection,
+    since: u64,
+    skip: &HashMap<String, Version>,
+) -> crate::Result<Vec<Change>> {
+    const SQL: &str = "SELECT uuid FROM changelog WHERE seq>? GROUP BY uuid ORDER BY MAX(seq)";
+    let mut stmt = conn.prepare(SQL).map_err(err!())?;
+    let uuids = stmt
+        .query_map([since], |row| row.get::<_, String>(0))
+        .map_err(err!())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(err!())?;
+
+    let mut list = Vec::new();
+    for uuid in uuids {
+        // 没有记录的条目已经被隔离，不同步
+        let version = match local_version(conn, &uuid)? {
+            Some((version, _)) => version,
+            None => continue,
+        };
+        if skip.get(&uuid) == Some(&version) {
+            continue;
+        }
+        list.push(Change {
+            entry: local_entry(conn, &uuid)?,
+            uuid,
+            version,
+        });
+    }
+    Ok(list)
+}
+
+// 本地的版本向量，以及条目是否存在（不存在时为已彻底删除）
+fn local_version(conn: &Connection, uuid: &str) -> crate::Result<Option<(Version, bool)>> {
+    const SQL: &str = "SELECT version FROM vault WHERE uuid=?";
+    let version: Option<String> = conn
+        .query_row(SQL, [uuid], |row| row.get(0))
+        .optional()
+        .map_err(err!())?;
+    if let Some(version) = version {
+        return Ok(Some((from_json(&version)?, true)));
+    }
+    const TOMBSTONE_SQL: &str = "SELECT version FROM tombstone WHERE uuid=?";
+    let version: Option<String> = conn
+        .query_row(TOMBSTONE_SQL, [uuid], |row| row.get(0))
+        .optional()
+        .map_err(err!())?;
+    match version {
+        Some(version) => Ok(Some((from_json(&version)?, false))),
+        None => Ok(None),
+    }
+}
+
+fn local_entry(conn: &Connection, uuid: &str) -> crate::Result<Option<Entry>> {
+    const SQL: &str = "SELECT key, value, created_at, modified_at, deleted_at, two_factor FROM vault WHERE uuid=?";
+    conn.query_row(SQL, [uuid], |row| {
+        Ok(Entry {
+            key: base64::encode(row.get::<_, Vec<u8>>(0)?),
+            value: base64::encode(row.get::<_, Vec<u8>>(1)?),
+            created_at: row.get(2)?,
+            modified_at: row.get(3)?,
+            deleted_at: row.get(4)?,
+            two_factor: row.get(5)?,
+        })
+    })
+    .optional()
+    .map_err(err!())
+}
+
+fn compare(a: &Version, b: &Version) -> Order {
+    let mut less = false;
+    let mut greater = false;
+    for device in a.keys().chain(b.keys()) {
+        let x = a.get(device).copied().unwrap_or(0);
+        let y = b.get(device).copied().unwrap_or(0);
+        less |= x < y;
+        greater |= x > y;
+    }
+    match (less, greater) {
+        (false, false) => Order::Equal,
+        (true, false) => Order::Before,
+        (false, true) => Order::After,
+        (true, true) => Order::Concurrent,
+    }
+}
+
+// 合并版本向量，每个设备取较大的修改次数
+fn merge(a: &Version, b: &Version) -> Version {
+    let mut version = a.clone();
+    for (device, count) in b {
+        let value = version.entry(device.clone()).or_default();
+        *value = (*value).max(*count);
+    }
+    version
+}
+
+fn log(conn: &Connection, uuid: &str) -> crate::Result<()> {
+    conn.execute("INSERT INTO changelog (uuid) VALUES (?)", [uuid])
+        .map_err(err!())?;
+    Ok(())
+}
+
+fn device_id(conn: &Connection) -> crate::Result<String> {
+    const SQL: &str = "SELECT value FROM conf WHERE key='device_id'";
+    conn.query_row(SQL, [], |row| row.get(0)).map_err(err!())
+}
+
+fn latest_seq(conn: &Connection) -> crate::Result<u64> {
+    const SQL: &str = "SELECT IFNULL(MAX(seq), 0) FROM changelog";
+    conn.query_row(SQL, [], |row| row.get(0)).map_err(err!())
+}
+
+fn encrypt<T: Serialize>(key: &[u8], message: &T) -> crate::Result<String> {
+    let data = serde_json::to_vec(message).map_err(err!())?;
+    Ok(base64::encode(key_encrypt(key, data).map_err(err!())?))
+}
+
+// 解密并验证消息，同步密钥不匹配或数据被修改时返回 None
+fn decrypt<T: DeserializeOwned>(key: &[u8], data: &str) -> crate::Result<Option<T>> {
+    let data = match base64::decode(data) {
+        Ok(v) if v.len() > MIN_CIPHERTEXT_LEN => v,
+        _ => return Ok(None),
+    };
+    match key_decrypt(key, data).map_err(err!())? {
+        Some(data) => Ok(Some(serde_json::from_slice(&data).map_err(err!())?)),
+        None => Ok(None),
+    }
+}
+
+fn to_json(version: &Version) -> crate::Result<String> {
+    serde_json::to_string(version).map_err(err!())
+}
+
+fn from_json(version: &str) -> crate::Result<Version> {
+    serde_json::from_str(version).map_err(err!())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+    use crate::db::setup;
+
+    const VAULT_KEY: [u8; 32] = [7; 32];
+
+    fn open() -> (TempDir, Connection) {
+        let tmp = tempdir().unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup(&mut conn, &tmp.path().join("backups")).unwrap();
+        (tmp, conn)
+    }
+
+    fn version(list: &[(&str, u64)]) -> Version {
+        list.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    // 添加条目，返回 uuid
+    fn add(conn: &Connection, name: &str, password: &str) -> String {
+        let name = key_encrypt(VAULT_KEY, name).unwrap();
+        let password = key_encrypt(VAULT_KEY, password).unwrap();
+        let sql = "INSERT INTO vault (key, value, created_at, modified_at) VALUES (?, ?, 1, 1)";
+        conn.execute(sql, params![name, password]).unwrap();
+        let sql = "SELECT uuid FROM vault WHERE id=?";
+        conn.query_row(sql, [conn.last_insert_rowid()], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn update(conn: &Connection, uuid: &str, column: &str, value: &str) {
+        let value = key_encrypt(VAULT_KEY, value).unwrap();
+        let sql = format!("UPDATE vault SET {}=? WHERE uuid=?", column);
+        conn.execute(&sql, params![value, uuid]).unwrap();
+    }
+
+    // 条目的名称和密码
+    fn get(conn: &Connection, uuid: &str) -> Option<(String, String)> {
+        let decrypt = |v: &str| {
+            let v = key_decrypt(VAULT_KEY, base64::decode(v).unwrap()).unwrap();
+            String::from_utf8(v.unwrap()).unwrap()
+        };
+        local_entry(conn, uuid)
+            .unwrap()
+            .map(|v| (decrypt(&v.key), decrypt(&v.value)))
+    }
+
+    fn entry(name: &str, password: &str) -> Option<(String, String)> {
+        Some((name.to_string(), password.to_string()))
+    }
+
+    #[test]
+    fn compare_versions() {
+        let a = version(&[("a", 2), ("b", 1)]);
+        assert!(compare(&a, &a) == Order::Equal);
+        assert!(compare(&version(&[("a", 1)]), &a) == Order::Before);
+        assert!(compare(&a, &version(&[("a", 2)])) == Order::After);
+        assert!(compare(&a, &version(&[("a", 1), ("b", 2)])) == Order::Concurrent);
+        assert_eq!(
+            merge(&a, &version(&[("a", 1), ("b", 3), ("c", 1)])),
+            version(&[("a", 2), ("b", 3), ("c", 1)])
+        );
+    }
+
+    #[test]
+    fn changes_since() {
+        let (_tmp, conn) = open();
+        let first = add(&conn, "a", "1");
+        let seq = latest_seq(&conn).unwrap();
+        let second = add(&conn, "b", "2");
+
+        let all = changes(&conn, 0, &HashMap::new()).unwrap();
+        let uuids: Vec<_> = all.iter().map(|v| v.uuid.as_str()).collect();
+        assert_eq!(uuids, [first.as_str(), second.as_str()]);
+        let later = changes(&conn, seq, &HashMap::new()).unwrap();
+        assert_eq!(later.len(), 1);
+        assert_eq!(later[0].uuid, second);
+
+        // 对方刚发来的版本不再发回去，版本变化后仍然发送
+        let skip = HashMap::from([(first.clone(), all[0].version.clone())]);
+        assert_eq!(changes(&conn, 0, &skip).unwrap().len(), 1);
+        update(&conn, &first, "value", "3");
+        assert_eq!(changes(&conn, 0, &skip).unwrap().len(), 2);
+
+        // 彻底删除的条目发送墓碑
+        conn.execute("DELETE FROM vault WHERE uuid=?", [&second])
+            .unwrap();
+        let deleted = changes(&conn, seq, &HashMap::new()).unwrap();
+        let deleted = deleted.iter().find(|v| v.uuid == second).unwrap();
+        assert!(deleted.entry.is_none());
+    }
+
+    #[test]
+    fn apply_and_resolve() {
+        let (_a_tmp, a) = open();
+        let (_b_tmp, b) = open();
+        let a_id = device_id(&a).unwrap();
+        let uuid = add(&a, "name", "1");
+
+        let report = apply(&b, &a_id, changes(&a, 0, &HashMap::new()).unwrap(), None).unwrap();
+        assert_eq!((report.received, report.conflicts), (1, 0));
+        assert_eq!(get(&b, &uuid), entry("name", "1"));
+        // 再次应用相同或更旧的版本什么都不做
+        let report = apply(&b, &a_id, changes(&a, 0, &HashMap::new()).unwrap(), None).unwrap();
+        assert_eq!(report.received, 0);
+
+        // 双方同时修改，没有共同版本时保存为冲突
+        update(&a, &uuid, "value", "2");
+        update(&b, &uuid, "value", "3");
+        let remote = changes(&a, 0, &HashMap::new()).unwrap();
+        let report = apply(&b, &a_id, remote, None).unwrap();
+        assert_eq!((report.received, report.conflicts), (0, 1));
+        assert_eq!(get(&b, &uuid), entry("name", "3"));
+        let list = conflicts(&b).unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].peer, a_id);
+
+        // 保留对方的内容，结果比双方都新
+        resolve(&b, list[0].id, true).unwrap();
+        assert!(conflicts(&b).unwrap().is_empty());
+        assert_eq!(get(&b, &uuid), entry("name", "2"));
+        let a_version = local_version(&a, &uuid).unwrap().unwrap().0;
+        let b_version = local_version(&b, &uuid).unwrap().unwrap().0;
+        assert!(compare(&a_version, &b_version) == Order::Before);
+
+        // 对方应用解决后的版本，不会再产生冲突
+        let b_id = device_id(&b).unwrap();
+        let report = apply(&a, &b_id, changes(&b, 0, &HashMap::new()).unwrap(), None).unwrap();
+        assert_eq!((report.received, report.conflicts), (1, 0));
+    }
+
+    #[test]
+    fn resolve_keep_local() {
+        let (_a_tmp, a) = open();
+        let (_b_tmp, b) = open();
+        let uuid = add(&a, "name", "1");
+        let a_id = device_id(&a).unwrap();
+        apply(&b, &a_id, changes(&a, 0, &HashMap::new()).unwrap(), None).unwrap();
+        update(&a, &uuid, "value", "2");
+        update(&b, &uuid, "value", "3");
+        apply(&b, &a_id, changes(&a, 0, &HashMap::new()).unwrap(), None).unwrap();
+
+        let seq = latest_seq(&b).unwrap();
+        resolve(&b, conflicts(&b).unwrap()[0].id, false).unwrap();
+        assert_eq!(get(&b, &uuid), entry("name", "3"));
+        // 保留的内容版本更新，下次同步时发送给对方
+        assert_eq!(changes(&b, seq, &HashMap::new()).unwrap().len(), 1);
+        let a_version = local_version(&a, &uuid).unwrap().unwrap().0;
+        let b_version = local_version(&b, &uuid).unwrap().unwrap().0;
+        assert!(compare(&a_version, &b_version) == Order::Before);
+    }
+
+    #[test]
+    fn three_way_merge() {
+        let (_a_tmp, a) = open();
+        let (_b_tmp, b) = open();
+        let uuid = add(&a, "name", "1");
+        let a_id = device_id(&a).unwrap();
+        let base = changes(&a, 0, &HashMap::new()).unwrap();
+        apply(&b, &a_id, base.clone(), None).unwrap();
+        let base = Base {
+            changes: base.into_iter().map(|v| (v.uuid.clone(), v)).collect(),
+            key: &VAULT_KEY,
+        };
+
+        // 修改不同的字段时合并
+        update(&a, &uuid, "value", "2");
+        update(&b, &uuid, "key", "renamed");
+        let remote = changes(&a, 0, &HashMap::new()).unwrap();
+        let report = apply(&b, &a_id, remote, Some(&base)).unwrap();
+        assert_eq!((report.received, report.conflicts), (1, 0));
+        assert_eq!(get(&b, &uuid), entry("renamed", "2"));
+
+        // 修改同一个字段且结果不同时仍然是冲突
+        update(&a, &uuid, "value", "3");
+        update(&b, &uuid, "value", "4");
+        let remote = changes(&a, 0, &HashMap::new()).unwrap();
+        let report = apply(&b, &a_id, remote, Some(&base)).unwrap();
+        assert_eq!(report.conflicts, 1);
+    }
+
+    #[test]
+    fn exchange_with_session() {
+        let (_a_tmp, a) = open();
+        let (_b_tmp, b) = open();
+        let key = sync_key(&VAULT_KEY).unwrap();
+        let from_a = add(&a, "a", "1");
+        let from_b = add(&b, "b", "2");
+        add_peer(&a, "b:1").unwrap();
+        let peer = peers(&a).unwrap().remove(0);
+        let addr: SocketAddr = "127.0.0.1:40001".parse().unwrap();
+
+        let session = new_session(addr).unwrap();
+        let (request, seq, sent) = request(&a, &key, &peer, &session).unwrap();
+        assert_eq!(sent, 1);
+        // 同步密钥不同
+        let other = sync_key(&[8; 32]).unwrap();
+        assert!(exchange(&b, &other, &session, &request).unwrap().is_none());
+        assert_eq!(take_session(addr).unwrap(), Some(session.clone()));
+        assert_eq!(take_session(addr).unwrap(), None);
+        let reply = exchange(&b, &key, &session, &request).unwrap().unwrap();
+
+        // 回复必须带回本次的会话号
+        let other_session = new_session(addr).unwrap();
+        assert!(finish(&a, &key, &peer, seq, &other_session, &reply).is_err());
+        let report = finish(&a, &key, &peer, seq, &session, &reply).unwrap();
+        assert_eq!(report.received, 1);
+        assert_eq!(get(&a, &from_b), entry("b", "2"));
+        assert_eq!(get(&b, &from_a), entry("a", "1"));
+        let peer = peers(&a).unwrap().remove(0);
+        assert_eq!(peer.device, Some(device_id(&b).unwrap()));
+        assert_eq!(peers(&b).unwrap()[0].device, Some(device_id(&a).unwrap()));
+
+        // 截获的请求不能在其他会话中重放
+        assert!(exchange(&b, &key, &other_session, &request)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn pairing_code() {
+        let code = pair(&VAULT_KEY).unwrap();
+        let sealed = claim(&pairing_id(&code).unwrap()).unwrap().unwrap();
+        // 配对码只能使用一次
+        assert!(claim(&pairing_id(&code).unwrap()).unwrap().is_none());
+        // 输入时忽略大小写和分隔符
+        let typed = code.replace('-', " ").to_uppercase();
+        assert_eq!(
+            open_pairing(&typed, &sealed).unwrap(),
+            Some(VAULT_KEY.to_vec())
+        );
+        assert!(open_pairing("0000-0000", &sealed).unwrap().is_none());
+
+        // 错误的 id 不会作废配对码
+        let code = pair(&VAULT_KEY).unwrap();
+        assert!(claim(&pairing_id("wrong").unwrap()).unwrap().is_none());
+        assert!(claim("not base64").unwrap().is_none());
+        assert!(claim(&pairing_id(&code).unwrap()).unwrap().is_some());
+    }
+
+    #[test]
+    fn sync_key_in_memory() {
+        let (tmp, conn) = open();
+        let dir = tmp.path().join("vault");
+        unlock(&dir, &conn, &VAULT_KEY).unwrap();
+        // 没有开启同步时不保存
+        assert!(unlocked_key(&dir).is_none());
+        enable(&conn).unwrap();
+        unlock(&dir, &conn, &VAULT_KEY).unwrap();
+        assert_eq!(unlocked_key(&dir), Some(sync_key(&VAULT_KEY).unwrap()));
+        let sql = "SELECT COUNT(0) FROM conf WHERE key='sync_key'";
+        let count: u64 = conn.query_row(sql, [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 0);
+        lock(&dir);
+        assert!(unlocked_key(&dir).is_none());
+    }
+
+    #[test]
+    fn peers_block_switching_key() {
+        let (_tmp, conn) = open();
+        assert!(!has_peers(&conn).unwrap());
+        add_peer(&conn, "b:1").unwrap();
+        assert!(has_peers(&conn).unwrap());
+        remove_peer(&conn, peers(&conn).unwrap()[0].id).unwrap();
+        conn.execute(
+            "INSERT INTO conf (key, value) VALUES ('sync_folder', '/tmp')",
+            [],
+        )
+        .unwrap();
+        assert!(has_peers(&conn).unwrap());
+    }
+}
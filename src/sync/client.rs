@@ -0,0 +1,197 @@
+// 连接对方网络服务的 JSON-RPC 客户端
+//
+// 对方使用内置的自签名证书，不验证证书。证书不能证明对方的身份，同步的内容由同步层认证：
+// 配对时用一次性的配对码加密保险库密钥，之后的消息使用同步密钥加密认证并带上会话号。
+
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_native_tls::native_tls::TlsConnector;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{
+    connect_async_tls_with_config, Connector, MaybeTlsStream, WebSocketStream,
+};
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+// 单条消息的最大长度
+const MAX_MESSAGE: usize = 64 * 1024 * 1024;
+
+pub struct Client {
+    stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Response {
+    id: Option<u64>,
+    result: Option<Value>,
+    error: Option<ResponseError>,
+}
+
+#[derive(Deserialize)]
+struct ResponseError {
+    message: String,
+    data: Option<Value>,
+}
+
+impl Client {
+    // 连接 addr (host:port) 并完成 WebSocket 握手
+    pub async fn connect(addr: &str) -> crate::Result<Self> {
+        with_timeout(Self::handshake(addr)).await
+    }
+
+    async fn handshake(addr: &str) -> crate::Result<Self> {
+        let connector = TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .map_err(err!())?;
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(MAX_MESSAGE))
+            .max_frame_size(Some(MAX_MESSAGE));
+        let (stream, _) = connect_async_tls_with_config(
+            format!("wss://{}/ws", addr),
+            Some(config),
+            false,
+            Some(Connector::NativeTls(connector)),
+        )
+        .await
+        .map_err(err!())?;
+        Ok(Self { stream, id: 0 })
+    }
+
+    // 调用对方的方法，对方返回错误时返回 Err(错误类型)
+    pub async fn call<T: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> crate::Result<Result<T, String>> {
+        with_timeout(self.call_inner(method, params)).await
+    }
+
+    async fn call_inner<T: DeserializeOwned>(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> crate::Result<Result<T, String>> {
+        self.id += 1;
+        let request = json!({"jsonrpc": "2.0", "id": self.id, "method": method, "params": params});
+        self.stream
+            .send(Message::text(request.to_string()))
+            .await
+            .map_err(err!())?;
+        loop {
+            let message = self.read_message().await?;
+            let response: Response = serde_json::from_slice(&message).map_err(err!())?;
+            if response.id != Some(self.id) {
+                continue;
+            }
+            if let Some(error) = response.error {
+                let kind = error
+                    .data
+                    .as_ref()
+                    .and_then(|v| v.get("kind"))
+                    .and_then(|v| v.as_str())
+                    .map_or(error.message, String::from);
+                return Ok(Err(kind));
+            }
+            let result = response.result.unwrap_or(Value::Null);
+            return Ok(Ok(serde_json::from_value(result).map_err(err!())?));
+        }
+    }
+
+    // 读取一条文本或二进制消息，ping 由 tungstenite 自动回复
+    async fn read_message(&mut self) -> crate::Result<Vec<u8>> {
+        loop {
+            let message = match self.stream.next().await {
+                Some(v) => v.map_err(err!())?,
+                None => return Err(err!(io::Error::from(io::ErrorKind::ConnectionAborted))),
+            };
+            match message {
+                Message::Text(text) => return Ok(text.as_bytes().to_vec()),
+                Message::Binary(data) => return Ok(data.to_vec()),
+                Message::Ping(_) | Message::Pong(_) => {}
+                Message::Close(_) => {
+                    return Err(err!(io::Error::from(io::ErrorKind::ConnectionAborted)))
+                }
+                Message::Frame(_) => return Err(err!(invalid_data("unexpected frame"))),
+            }
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+async fn with_timeout<T>(future: impl Future<Output = crate::Result<T>>) -> crate::Result<T> {
+    match timeout(TIMEOUT, future).await {
+        Ok(result) => result,
+        Err(_) => Err(err!(io::Error::from(io::ErrorKind::TimedOut))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+    use tokio_native_tls::native_tls::{Identity, TlsAcceptor};
+
+    use super::*;
+
+    // 使用内置证书的 JSON-RPC 服务，echo 原样返回参数，其他方法返回错误
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let identity = Identity::from_pkcs12(include_bytes!("../vault.pfx"), "vault").unwrap();
+        let acceptor = Arc::new(tokio_native_tls::TlsAcceptor::from(
+            TlsAcceptor::new(identity).unwrap(),
+        ));
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let tls = acceptor.accept(tcp).await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(tls).await.unwrap();
+            while let Some(Ok(message)) = ws.next().await {
+                let request: Value = match message {
+                    Message::Text(text) => serde_json::from_str(&text).unwrap(),
+                    _ => continue,
+                };
+                // 先发送一条其他 id 的消息，客户端应该跳过
+                let skipped = json!({"jsonrpc": "2.0", "id": 0, "result": "skipped"});
+                ws.send(Message::text(skipped.to_string())).await.unwrap();
+                let response = match request["method"].as_str() {
+                    Some("echo") => {
+                        json!({"jsonrpc": "2.0", "id": request["id"], "result": request["params"][0]})
+                    }
+                    _ => json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "error": {"code": -32000, "message": "Server Error", "data": {"kind": "WrongPassword"}}
+                    }),
+                };
+                ws.send(Message::text(response.to_string())).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn call() {
+        let addr = serve().await;
+        let mut client = Client::connect(&addr).await.unwrap();
+        let result: Result<String, String> = client.call("echo", json!(["hi"])).await.unwrap();
+        assert_eq!(result, Ok("hi".to_string()));
+        let result: Result<String, String> = client.call("other", json!([])).await.unwrap();
+        assert_eq!(result, Err("WrongPassword".to_string()));
+    }
+}
@@ -0,0 +1,656 @@
+// 文件夹同步，用于 Syncthing、Dropbox 等同步文件夹的工具
+//
+// 每个设备只写自己的目录 <文件夹>/<设备 id>/，目录下是按序号命名的段文件，每行一条加密的操作记录，只追加不修改，
+// 同步工具不会产生冲突。记录使用同步密钥加密认证，包含设备 id、段号、记录序号和上一条记录的 sha256，
+// 被修改、删除或重排的记录都会被发现。
+// 段文件超过一定大小后开始新的段，新段的第一条记录是全部条目的快照，之后删除更早的段。
+// 只在访问数据库时占用保险库的连接，读写文件时不占用，同一时间只有一次同步。
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, read_to_string, remove_file, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::error;
+use openssl::sha::sha256;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    apply, changes, decrypt, device_id, encrypt, latest_seq, now, unlocked_key, Change, Report,
+    Version,
+};
+use crate::server::{db, with_vault_dir, Unavailable};
+
+// 检查是否需要同步的间隔
+pub const TICK: Duration = Duration::from_secs(10);
+
+// 自动同步间隔（秒）
+const INTERVAL: i64 = 60;
+
+// 段文件超过这个大小后开始新的段
+const SEGMENT_SIZE: u64 = 1024 * 1024;
+
+// 保留的旧段数量，读到一半的设备可以继续读完
+const KEEP_SEGMENTS: u64 = 1;
+
+// 保存 keyfile::seal 加密的保险库密钥，其他设备用主密码 (和密钥文件) 解密后加入同步
+pub const KEY_FILE: &str = "key";
+
+// 正在同步时持有，两次同步同时追加日志会破坏记录的顺序
+static RUNNING: Mutex<()> = Mutex::new(());
+
+// 保险库目录和上次自动同步的时间
+static LAST_RUN: Mutex<Vec<(PathBuf, i64)>> = Mutex::new(Vec::new());
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    device: String,
+    segment: u64,
+    index: u64,
+    // 上一条记录的 sha256，第一条为空
+    prev: String,
+    // 全部条目的快照，段的第一条记录
+    snapshot: bool,
+    changes: Vec<Change>,
+}
+
+// 日志读到或写到的位置
+#[derive(Default)]
+struct State {
+    segment: u64,
+    // 下一条记录的序号
+    record: u64,
+    hash: String,
+    // 本设备已写入日志的变更序号
+    seq: u64,
+}
+
+#[derive(Serialize, Default)]
+pub struct FolderReport {
+    #[serde(flatten)]
+    pub report: Report,
+    // 日志被修改或损坏的设备
+    pub tampered: Vec<String>,
+}
+
+// 同步文件夹，未设置时为 None
+pub fn folder(conn: &Connection) -> crate::Result<Option<PathBuf>> {
+    const SQL: &str = "SELECT value FROM conf WHERE key='sync_folder'";
+    let folder: Option<String> = conn
+        .query_row(SQL, [], |row| row.get(0))
+        .optional()
+        .map_err(err!())?;
+    Ok(folder.map(PathBuf::from))
+}
+
+// 设置同步文件夹，换文件夹后从头读取其他设备的日志
+pub fn set_folder(conn: &Connection, folder: Option<&Path>) -> crate::Result<()> {
+    match folder {
+        Some(folder) => {
+            const SQL: &str = "INSERT OR REPLACE INTO conf (key, value) VALUES ('sync_folder', ?)";
+            conn.execute(SQL, [folder.to_string_lossy()])
+                .map_err(err!())?;
+        }
+        None => {
+            conn.execute("DELETE FROM conf WHERE key='sync_folder'", [])
+                .map_err(err!())?;
+        }
+    }
+    const SQL: &str = "DELETE FROM folder_log WHERE device<>?";
+    conn.execute(SQL, [device_id(conn)?]).map_err(err!())?;
+    Ok(())
+}
+
+// 读取文件夹中保存的保险库密钥，没有时返回 None
+pub fn read_key(folder: &Path) -> crate::Result<Option<String>> {
+    match read_to_string(folder.join(KEY_FILE)) {
+        Ok(v) => Ok(Some(v.trim().to_string())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err!(err)),
+    }
+}
+
+// 把加密的保险库密钥保存到文件夹，需要主密码才能解密
+pub fn write_key(folder: &Path, key: &str) -> crate::Result<()> {
+    create_dir_all(folder).map_err(err!())?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(folder.join(KEY_FILE)).map_err(err!())?;
+    file.write_all(key.as_bytes()).map_err(err!())?;
+    file.sync_all().map_err(err!())
+}
+
+// 定时调用，同步已解锁的并设置了同步文件夹的保险库，上次还没有结束时跳过
+pub fn auto() -> crate::Result<()> {
+    let _running = match RUNNING.try_lock() {
+        Ok(v) => v,
+        Err(_) => return Ok(()),
+    };
+    let dirs: Vec<PathBuf> = db().all().map(|(dir, _)| dir.to_path_buf()).collect();
+    for dir in dirs {
+        if !due(&dir, now()?) {
+            continue;
+        }
+        let key = match unlocked_key(&dir) {
+            Some(v) => v,
+            None => continue,
+        };
+        let folder = match with_vault_dir(&dir, folder) {
+            Some(v) => v?,
+            None => continue,
+        };
+        if let Some(folder) = folder {
+            let report = sync(&dir, &key, &folder)?;
+            for device in report.tampered {
+                error!("sync log of {} in {:?} is tampered", device, folder);
+            }
+        }
+    }
+    Ok(())
+}
+
+// 距离上次自动同步是否超过间隔，是则记录本次的时间
+fn due(vault_dir: &Path, now: i64) -> bool {
+    let mut last_run = LAST_RUN.lock().unwrap();
+    match last_run.iter_mut().find(|(dir, _)| dir == vault_dir) {
+        Some((_, time)) if now - *time < INTERVAL => false,
+        Some((_, time)) => {
+            *time = now;
+            true
+        }
+        None => {
+            last_run.push((vault_dir.to_path_buf(), now));
+            true
+        }
+    }
+}
+
+// 同步目录为 vault_dir 的保险库，合并其他设备的日志，再把本地的变更写入日志，key 为同步密钥
+pub fn run(vault_dir: &Path, key: &[u8], folder: &Path) -> crate::Result<FolderReport> {
+    let _running = RUNNING.lock().unwrap();
+    sync(vault_dir, key, folder)
+}
+
+fn sync(vault_dir: &Path, key: &[u8], folder: &Path) -> crate::Result<FolderReport> {
+    let mut report = FolderReport::default();
+    let me = with_conn(vault_dir, device_id)?;
+
+    // 刚收到的变更不需要再写入日志
+    let mut received = HashMap::new();
+    for device in devices(folder)? {
+        if device == me {
+            continue;
+        }
+        let from = with_conn(vault_dir, |conn| Ok(state(conn, &device)?.segment))?;
+        let log = log(&folder.join(&device), from)?;
+        let ok = with_conn(vault_dir, |conn| {
+            read(conn, key, &log, &device, &mut report.report, &mut received)
+        })?;
+        if !ok {
+            report.tampered.push(device);
+        }
+    }
+
+    report.report.sent = write(vault_dir, key, &folder.join(&me), &me, &received)?;
+    Ok(report)
+}
+
+// 在目录为 vault_dir 的保险库的连接上执行，保险库已关闭或者换成了其他保险库时返回错误
+fn with_conn<T>(
+    vault_dir: &Path,
+    f: impl FnOnce(&Connection) -> crate::Result<T>,
+) -> crate::Result<T> {
+    match with_vault_dir(vault_dir, f) {
+        Some(result) => result,
+        None => Err(err!(Unavailable)),
+    }
+}
+
+// 读取设备目录下段号不小于 from 的段文件
+fn log(dir: &Path, from: u64) -> crate::Result<Vec<(u64, Vec<String>)>> {
+    let mut log = Vec::new();
+    for (segment, path) in segments(dir)? {
+        if segment >= from {
+            log.push((segment, lines(&path)?));
+        }
+    }
+    Ok(log)
+}
+
+// 应用一个设备的日志，日志被修改时返回 false，之前的记录已经应用
+fn read(
+    conn: &Connection,
+    key: &[u8],
+    log: &[(u64, Vec<String>)],
+    device: &str,
+    report: &mut Report,
+    received: &mut HashMap<String, Version>,
+) -> crate::Result<bool> {
+    let mut state = state(conn, device)?;
+    for (segment, lines) in log {
+        let segment = *segment;
+        if segment < state.segment {
+            continue;
+        }
+        for (i, line) in lines.iter().enumerate() {
+            let record: Record = match decrypt(key, line)? {
+                Some(v) => v,
+                None => return Ok(false),
+            };
+            if record.device != device || record.segment != segment {
+                return Ok(false);
+            }
+            if record.index < state.record {
+                continue;
+            }
+            let chained =
+                record.index == state.record && (record.index == 0 || record.prev == state.hash);
+            // 中间的记录已经被压缩删除时，只能从段开头的快照继续
+            let resumable = i == 0 && record.snapshot;
+            if !(chained || resumable) {
+                return Ok(false);
+            }
+
+            for change in &record.changes {
+                received.insert(change.uuid.clone(), change.version.clone());
+            }
+            let applied = apply(conn, device, record.changes, None)?;
+            report.received += applied.received;
+            report.conflicts += applied.conflicts;
+            state.segment = segment;
+            state.record = record.index + 1;
+            state.hash = hash(line);
+            save_state(conn, device, &state)?;
+        }
+    }
+    Ok(true)
+}
+
+// 把本地的变更写入日志，返回写入的变更数量
+fn write(
+    vault_dir: &Path,
+    key: &[u8],
+    dir: &Path,
+    me: &str,
+    received: &HashMap<String, Version>,
+) -> crate::Result<usize> {
+    let mut state = with_conn(vault_dir, |conn| state(conn, me))?;
+    let path = dir.join(segment_name(state.segment));
+    let last = lines(&path)?.pop();
+    let size = path.metadata().map_or(0, |v| v.len());
+
+    // 日志和本地记录的位置不一致时（第一次同步、换了文件夹、恢复了备份等），开始新的段
+    let consistent = last.as_ref().map(|v| hash(v)) == Some(state.hash.clone());
+    let snapshot = !consistent || size >= SEGMENT_SIZE;
+    if snapshot {
+        let existing = segments(dir)?;
+        if let Some((segment, path)) = existing.last() {
+            if let Some(line) = lines(path)?.pop() {
+                if let Some(record) = decrypt::<Record>(key, &line)? {
+                    state.record = state.record.max(record.index + 1);
+                    state.hash = hash(&line);
+                }
+            }
+            state.segment = state.segment.max(*segment) + 1;
+        }
+    }
+
+    let record = with_conn(vault_dir, |conn| {
+        let seq = latest_seq(conn)?;
+        let changes = match snapshot {
+            true => changes(conn, 0, &HashMap::new())?,
+            false => changes(conn, state.seq, received)?,
+        };
+        if !snapshot && changes.is_empty() {
+            state.seq = seq;
+            save_state(conn, me, &state)?;
+            return Ok(None);
+        }
+        let record = Record {
+            device: me.to_string(),
+            segment: state.segment,
+            index: state.record,
+            prev: state.hash.clone(),
+            snapshot,
+            changes,
+        };
+        Ok(Some((record, seq)))
+    })?;
+    let (record, seq) = match record {
+        Some(v) => v,
+        None => return Ok(0),
+    };
+
+    let count = record.changes.len();
+    let line = encrypt(key, &record)?;
+    create_dir_all(dir).map_err(err!())?;
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options
+        .open(dir.join(segment_name(state.segment)))
+        .map_err(err!())?;
+    file.write_all(format!("{}\n", line).as_bytes())
+        .map_err(err!())?;
+    file.sync_all().map_err(err!())?;
+
+    // 写入文件后还没有保存位置时中断，下次发现不一致会开始新的段
+    state.record += 1;
+    state.hash = hash(&line);
+    state.seq = seq;
+    with_conn(vault_dir, |conn| save_state(conn, me, &state))?;
+
+    if snapshot {
+        compact(dir, state.segment)?;
+    }
+    Ok(count)
+}
+
+// 删除快照之前的旧段，保留最近的几个
+fn compact(dir: &Path, current: u64) -> crate::Result<()> {
+    for (segment, path) in segments(dir)? {
+        if segment + KEEP_SEGMENTS < current {
+            remove_file(path).map_err(err!())?;
+        }
+    }
+    Ok(())
+}
+
+// 文件夹中的设备目录
+fn devices(folder: &Path) -> crate::Result<Vec<String>> {
+    let entries = match read_dir(folder) {
+        Ok(v) => v,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err!(err)),
+    };
+    let mut list = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(err!())?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_device(&name) && entry.path().is_dir() {
+            list.push(name);
+        }
+    }
+    Ok(list)
+}
+
+// 设备目录下的段文件，按段号排序
+fn segments(dir: &Path) -> crate::Result<Vec<(u64, PathBuf)>> {
+    let entries = match read_dir(dir) {
+        Ok(v) => v,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err!(err)),
+    };
+    let mut list = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(err!())?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // 跳过同步工具产生的冲突副本和临时文件
+        let segment = match name.strip_suffix(".log") {
+            Some(v) if v.len() == 8 && v.bytes().all(|c| c.is_ascii_digit()) => v,
+            _ => continue,
+        };
+        list.push((segment.parse().map_err(err!())?, entry.path()));
+    }
+    list.sort_by_key(|v| v.0);
+    Ok(list)
+}
+
+// 段文件中完整的行，同步工具还没有传完的最后一行不读取
+fn lines(path: &Path) -> crate::Result<Vec<String>> {
+    let content = match read_to_string(path) {
+        Ok(v) => v,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err!(err)),
+    };
+    let complete = match content.rfind('\n') {
+        Some(i) => &content[..i],
+        None => return Ok(Vec::new()),
+    };
+    Ok(complete
+        .split('\n')
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+fn state(conn: &Connection, device: &str) -> crate::Result<State> {
+    const SQL: &str = "SELECT segment, record, hash, seq FROM folder_log WHERE device=?";
+    let state = conn
+        .query_row(SQL, [device], |row| {
+            Ok(State {
+                segment: row.get(0)?,
+                record: row.get(1)?,
+                hash: row.get(2)?,
+                seq: row.get(3)?,
+            })
+        })
+        .optional()
+        .map_err(err!())?;
+    Ok(state.unwrap_or_default())
+}
+
+fn save_state(conn: &Connection, device: &str, state: &State) -> crate::Result<()> {
+    const SQL: &str =
+        "INSERT OR REPLACE INTO folder_log (device, segment, record, hash, seq) VALUES (?, ?, ?, ?, ?)";
+    conn.execute(
+        SQL,
+        params![device, state.segment, state.record, state.hash, state.seq],
+    )
+    .map_err(err!())?;
+    Ok(())
+}
+
+fn segment_name(segment: u64) -> String {
+    format!("{:08}.log", segment)
+}
+
+// 设备 id 是 32 位十六进制
+fn is_device(name: &str) -> bool {
+    name.len() == 32 && name.bytes().all(|c| c.is_ascii_hexdigit())
+}
+
+fn hash(line: &str) -> String {
+    base64::encode(sha256(line.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+    use crate::crypto::{key_decrypt, key_encrypt};
+    use crate::db::setup;
+    use crate::keyfile::{self, MasterPassword};
+    use crate::server::open_test_vault;
+    use crate::sync::sync_key;
+
+    const VAULT_KEY: [u8; 32] = [7; 32];
+
+    struct Device {
+        // 保险库目录，TempDir 释放时删除
+        _tmp: TempDir,
+        dir: PathBuf,
+        id: String,
+    }
+
+    fn open(name: &str) -> Device {
+        let tmp = tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup(&mut conn, &dir.join("backups")).unwrap();
+        let id = device_id(&conn).unwrap();
+        open_test_vault(name, dir.clone(), conn);
+        Device { _tmp: tmp, dir, id }
+    }
+
+    fn add(device: &Device, name: &str, password: &str) {
+        let name = key_encrypt(VAULT_KEY, name).unwrap();
+        let password = key_encrypt(VAULT_KEY, password).unwrap();
+        let sql = "INSERT INTO vault (key, value, created_at, modified_at) VALUES (?, ?, 1, 1)";
+        with_conn(&device.dir, |conn| {
+            conn.execute(sql, params![name, password]).unwrap();
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    // 全部条目的名称，排序后返回
+    fn names(device: &Device) -> Vec<String> {
+        with_conn(&device.dir, |conn| {
+            let mut stmt = conn.prepare("SELECT key FROM vault").unwrap();
+            let mut names: Vec<String> = stmt
+                .query_map([], |row| row.get::<_, Vec<u8>>(0))
+                .unwrap()
+                .map(|v| {
+                    let v = key_decrypt(VAULT_KEY, v.unwrap()).unwrap().unwrap();
+                    String::from_utf8(v).unwrap()
+                })
+                .collect();
+            names.sort();
+            Ok(names)
+        })
+        .unwrap()
+    }
+
+    fn sync(device: &Device, folder: &Path) -> FolderReport {
+        run(&device.dir, &sync_key(&VAULT_KEY).unwrap(), folder).unwrap()
+    }
+
+    fn segment_numbers(dir: &Path) -> Vec<u64> {
+        segments(dir).unwrap().into_iter().map(|v| v.0).collect()
+    }
+
+    #[test]
+    fn hash_chain() {
+        let folder = tempdir().unwrap();
+        let folder = folder.path();
+        let a = open("folder-chain-a");
+        let b = open("folder-chain-b");
+        add(&a, "a1", "1");
+        assert_eq!(sync(&a, folder).report.sent, 1);
+        add(&a, "a2", "2");
+        assert_eq!(sync(&a, folder).report.sent, 1);
+        add(&a, "a3", "3");
+        sync(&a, folder);
+        // 没有变更时不写入
+        assert_eq!(sync(&a, folder).report.sent, 0);
+
+        let report = sync(&b, folder);
+        assert!(report.tampered.is_empty());
+        assert_eq!(report.report.received, 3);
+        assert_eq!(names(&b), ["a1", "a2", "a3"]);
+
+        // 删除中间的记录后，新加入的设备发现日志被修改
+        let path = folder.join(&a.id).join(segment_name(0));
+        let lines = lines(&path).unwrap();
+        assert_eq!(lines.len(), 3);
+        let content = format!("{}\n{}\n", lines[0], lines[2]);
+        std::fs::write(&path, content).unwrap();
+        let c = open("folder-chain-c");
+        let report = sync(&c, folder);
+        assert_eq!(report.tampered, [a.id.as_str()]);
+        // 被修改的记录之后不再读取，b 的日志开头的快照包含全部条目
+        assert_eq!(report.report.received, 3);
+        assert_eq!(names(&c), ["a1", "a2", "a3"]);
+
+        // 其他设备的记录冒充 a 的记录
+        let forged = read_to_string(folder.join(&b.id).join(segment_name(0))).unwrap();
+        std::fs::write(&path, forged).unwrap();
+        let d = open("folder-chain-d");
+        assert_eq!(sync(&d, folder).tampered, [a.id.as_str()]);
+    }
+
+    #[test]
+    fn compact_and_resume() {
+        let folder = tempdir().unwrap();
+        let folder = folder.path();
+        let a = open("folder-compact-a");
+        let b = open("folder-compact-b");
+        add(&a, "a1", "1");
+        sync(&a, folder);
+        assert!(sync(&b, folder).tampered.is_empty());
+        assert_eq!(names(&b), ["a1"]);
+
+        // 本地记录的位置丢失（例如恢复了备份），开始新的段并写入快照
+        let reset = || {
+            with_conn(&a.dir, |conn| {
+                conn.execute("DELETE FROM folder_log WHERE device=?", [&a.id])
+                    .map_err(err!())?;
+                Ok(())
+            })
+            .unwrap()
+        };
+        let a_dir = folder.join(&a.id);
+        add(&a, "a2", "2");
+        reset();
+        sync(&a, folder);
+        assert_eq!(segment_numbers(&a_dir), [0, 1]);
+        add(&a, "a3", "3");
+        reset();
+        sync(&a, folder);
+        // 只保留最近的旧段
+        assert_eq!(segment_numbers(&a_dir), [1, 2]);
+
+        // b 读到的段已经被删除，从新段开头的快照继续
+        let report = sync(&b, folder);
+        assert!(report.tampered.is_empty());
+        assert_eq!(names(&b), ["a1", "a2", "a3"]);
+
+        // 之后的记录接着快照继续
+        add(&a, "a4", "4");
+        sync(&a, folder);
+        assert_eq!(segment_numbers(&a_dir), [1, 2]);
+        let report = sync(&b, folder);
+        assert!(report.tampered.is_empty());
+        assert_eq!(report.report.received, 1);
+        assert_eq!(names(&b), ["a1", "a2", "a3", "a4"]);
+    }
+
+    #[test]
+    fn incomplete_line() {
+        let folder = tempdir().unwrap();
+        let folder = folder.path();
+        let a = open("folder-partial-a");
+        let b = open("folder-partial-b");
+        add(&a, "a1", "1");
+        sync(&a, folder);
+        // 同步工具还没有传完的最后一行不读取，传完后继续
+        let path = folder.join(&a.id).join(segment_name(0));
+        let content = read_to_string(&path).unwrap();
+        std::fs::write(&path, &content[..content.len() / 2]).unwrap();
+        let report = sync(&b, folder);
+        assert!(report.tampered.is_empty());
+        assert_eq!(report.report.received, 0);
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(sync(&b, folder).report.received, 1);
+    }
+
+    // 开启了密钥文件时，文件夹中的保险库密钥同样需要密钥文件
+    #[test]
+    fn key_needs_key_file() {
+        let folder = tempdir().unwrap();
+        let folder = folder.path();
+        let content = base64::encode(b"key file content");
+        let sealed = keyfile::seal("password", keyfile::hash(&content).as_ref(), &VAULT_KEY);
+        write_key(folder, &sealed.unwrap()).unwrap();
+
+        let sealed = read_key(folder).unwrap().unwrap();
+        let password_only = MasterPassword::Password("password".to_string());
+        assert!(keyfile::unseal(&password_only, &sealed).unwrap().is_none());
+        let with_file = MasterPassword::KeyFile {
+            password: "password".to_string(),
+            key_file: content,
+        };
+        let key = keyfile::unseal(&with_file, &sealed).unwrap();
+        assert_eq!(key.as_deref(), Some(&VAULT_KEY[..]));
+    }
+}
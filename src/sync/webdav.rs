@@ -0,0 +1,752 @@
+// WebDAV 同步
+//
+// WebDAV 目录中保存两个文件：vault.key 为用主密码加密的保险库密钥，其他设备用它加入同步；
+// vault.sync 为使用同步密钥加密的全部条目（包括彻底删除的条目）和版本向量的快照。
+// 同步时下载快照合并到本地，再上传合并后的快照，上传时用 If-Match 检查 ETag，其他设备先上传了新的快照时重新下载合并。
+// 上次同步的快照保存在本地，同时修改的条目以它为共同版本三方合并。
+// 地址和账号使用保险库密钥加密保存在 conf 中，同步需要主密码。支持 http，可以使用本地的 WebDAV 服务。
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_native_tls::native_tls;
+
+use super::{apply, changes, decrypt, encrypt, sync_key, Base, Change, Report};
+use crate::crypto::{key_decrypt, key_encrypt};
+use crate::db::{get_conf, set_conf};
+use crate::server::{with_vault, Unavailable};
+
+// 保存用主密码加密的保险库密钥
+pub const KEY_FILE: &str = "vault.key";
+
+// 保存快照
+const SYNC_FILE: &str = "vault.sync";
+
+// 冲突的记录中对方的名称
+const PEER: &str = "webdav";
+
+// 上传时 ETag 不匹配后重试的次数
+const MAX_RETRY: usize = 5;
+
+// 响应的最大长度
+const MAX_RESPONSE: u64 = 64 * 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Config {
+    // WebDAV 目录的地址，如 https://example.com/dav/vault/
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Snapshot {
+    changes: Vec<Change>,
+}
+
+// 不成功的 HTTP 响应
+#[derive(Debug)]
+pub struct HttpError(pub u16);
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "http status {}", self.0)
+    }
+}
+
+impl Error for HttpError {}
+
+pub struct Client {
+    tls: bool,
+    // host:port，IPv6 地址带方括号
+    authority: String,
+    host: String,
+    port: u16,
+    // 目录路径，以 / 结尾
+    path: String,
+    auth: Option<String>,
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// 上传的结果
+pub enum Put {
+    // 新的 ETag，服务器没有返回时为 None
+    Done(Option<String>),
+    // 文件已经被其他设备修改
+    Conflict,
+}
+
+impl Client {
+    pub fn new(config: &Config) -> crate::Result<Self> {
+        let (tls, rest) = if let Some(v) = config.url.strip_prefix("https://") {
+            (true, v)
+        } else if let Some(v) = config.url.strip_prefix("http://") {
+            (false, v)
+        } else {
+            return Err(err!(invalid_data("invalid url")));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.is_empty() && port.bytes().all(|c| c.is_ascii_digit()) => {
+                (host, port.parse().map_err(err!())?)
+            }
+            _ => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(err!(invalid_data("invalid url")));
+        }
+        let auth = config.username.as_ref().map(|username| {
+            let password = config.password.as_deref().unwrap_or_default();
+            format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", username, password))
+            )
+        });
+        Ok(Self {
+            tls,
+            authority: authority.to_string(),
+            host: host.to_string(),
+            port,
+            path: format!("{}/", path.trim_end_matches('/')),
+            auth,
+        })
+    }
+
+    // 下载文件，文件不存在时返回 None
+    pub async fn get(&self, file: &str) -> crate::Result<Option<(Option<String>, Vec<u8>)>> {
+        let response = self.request("GET", file, &[], &[]).await?;
+        match response.status {
+            200 => {
+                let etag = response.header("etag").map(String::from);
+                Ok(Some((etag, response.body)))
+            }
+            404 => Ok(None),
+            status => Err(err!(HttpError(status))),
+        }
+    }
+
+    // 上传文件，etag 为 None 时只在文件不存在时上传
+    pub async fn put(&self, file: &str, data: &[u8], etag: Option<&str>) -> crate::Result<Put> {
+        let condition = match etag {
+            Some(etag) => ("If-Match", etag),
+            None => ("If-None-Match", "*"),
+        };
+        let headers = [condition, ("Content-Type", "application/octet-stream")];
+        let response = self.request("PUT", file, &headers, data).await?;
+        match response.status {
+            200..=299 => Ok(Put::Done(response.header("etag").map(String::from))),
+            412 => Ok(Put::Conflict),
+            status => Err(err!(HttpError(status))),
+        }
+    }
+
+    // 创建目录，已存在时什么都不做
+    pub async fn mkcol(&self) -> crate::Result<()> {
+        let response = self.request("MKCOL", "", &[], &[]).await?;
+        match response.status {
+            200..=299 | 405 => Ok(()),
+            status => Err(err!(HttpError(status))),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: &str,
+        file: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> crate::Result<Response> {
+        let mut request = format!(
+            "{} {}{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            self.path,
+            file,
+            self.authority,
+            body.len()
+        );
+        if let Some(auth) = &self.auth {
+            request.push_str(&format!("Authorization: {}\r\n", auth));
+        }
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        let mut request = request.into_bytes();
+        request.extend_from_slice(body);
+
+        with_timeout(async {
+            let tcp = TcpStream::connect(format!("{}:{}", self.host, self.port))
+                .await
+                .map_err(err!())?;
+            let data = if self.tls {
+                let connector = native_tls::TlsConnector::new().map_err(err!())?;
+                let domain = self.host.trim_start_matches('[').trim_end_matches(']');
+                let stream = tokio_native_tls::TlsConnector::from(connector)
+                    .connect(domain, tcp)
+                    .await
+                    .map_err(err!())?;
+                exchange(stream, &request).await?
+            } else {
+                exchange(tcp, &request).await?
+            };
+            parse(data)
+        })
+        .await
+    }
+}
+
+// 读取配置，未设置时返回 None
+pub fn config(conn: &Connection, key: &[u8]) -> crate::Result<Option<Config>> {
+    let data = match get_conf(conn, "webdav")? {
+        Some(v) => base64::decode(v).map_err(err!())?,
+        None => return Ok(None),
+    };
+    match key_decrypt(key, data).map_err(err!())? {
+        Some(data) => Ok(Some(serde_json::from_slice(&data).map_err(err!())?)),
+        None => Err(err!(invalid_data("invalid webdav config"))),
+    }
+}
+
+// 保存配置，None 表示关闭，同时清除上次同步的状态
+pub fn set_config(conn: &Connection, key: &[u8], config: Option<&Config>) -> crate::Result<()> {
+    conn.execute(
+        "DELETE FROM conf WHERE key IN ('webdav', 'webdav_etag', 'webdav_base')",
+        [],
+    )
+    .map_err(err!())?;
+    if let Some(config) = config {
+        let data = serde_json::to_vec(config).map_err(err!())?;
+        let data = base64::encode(key_encrypt(key, data).map_err(err!())?);
+        set_conf(conn, "webdav", &data)?;
+    }
+    Ok(())
+}
+
+// 更换保险库密钥时用新的密钥重新加密配置
+pub fn reencrypt_config(conn: &Connection, key: &[u8], new_key: &[u8]) -> crate::Result<()> {
+    match config(conn, key)? {
+        Some(config) => set_config(conn, new_key, Some(&config)),
+        None => Ok(()),
+    }
+}
+
+// 同步保险库 vault，key 为保险库密钥，未设置 WebDAV 时什么都不做
+pub async fn sync(vault: &str, key: &[u8]) -> crate::Result<Report> {
+    let config = match with_conn(vault, |conn| config(conn, key))? {
+        Some(v) => v,
+        None => return Ok(Report::default()),
+    };
+    let sync_key = sync_key(key)?;
+    let client = Client::new(&config)?;
+    let mut report = Report::default();
+
+    for _ in 0..MAX_RETRY {
+        let remote = client.get(SYNC_FILE).await?;
+        let etag = remote.as_ref().and_then(|v| v.0.clone());
+        let upload = with_conn(vault, |conn| {
+            let (base_etag, base) = base(conn, &sync_key)?;
+            let mut versions = None;
+            if let Some((etag, data)) = &remote {
+                let data = String::from_utf8_lossy(data);
+                let snapshot: Snapshot = match decrypt(&sync_key, &data)? {
+                    Some(v) => v,
+                    None => return Err(err!(invalid_data("invalid webdav snapshot"))),
+                };
+                versions = Some(
+                    snapshot
+                        .changes
+                        .iter()
+                        .map(|v| (v.uuid.clone(), v.version.clone()))
+                        .collect::<HashMap<_, _>>(),
+                );
+                // ETag 和上次同步时相同说明其他设备没有上传过
+                if etag.is_none() || *etag != base_etag {
+                    let base = Base {
+                        changes: base
+                            .changes
+                            .into_iter()
+                            .map(|v| (v.uuid.clone(), v))
+                            .collect(),
+                        key,
+                    };
+                    let applied = apply(conn, PEER, snapshot.changes, Some(&base))?;
+                    report.received += applied.received;
+                    report.conflicts += applied.conflicts;
+                }
+            }
+
+            let changes = changes(conn, 0, &HashMap::new())?;
+            let local: HashMap<_, _> = changes
+                .iter()
+                .map(|v| (v.uuid.clone(), v.version.clone()))
+                .collect();
+            if let (Some(versions), Some((_, data))) = (versions, &remote) {
+                if versions == local {
+                    // 本地和远端相同，不需要上传
+                    save_base(conn, etag.as_deref(), &String::from_utf8_lossy(data))?;
+                    return Ok(None);
+                }
+            }
+            let count = changes.len();
+            Ok(Some((encrypt(&sync_key, &Snapshot { changes })?, count)))
+        })?;
+        let (data, count) = match upload {
+            Some(v) => v,
+            None => return Ok(report),
+        };
+
+        match client
+            .put(SYNC_FILE, data.as_bytes(), etag.as_deref())
+            .await?
+        {
+            Put::Done(etag) => {
+                with_conn(vault, |conn| save_base(conn, etag.as_deref(), &data))?;
+                report.sent = count;
+                return Ok(report);
+            }
+            // 其他设备刚上传了新的快照，重新下载合并
+            Put::Conflict => continue,
+        }
+    }
+    Err(err!(HttpError(412)))
+}
+
+// 在保险库 vault 的连接上执行，保险库已关闭时返回错误
+pub fn with_conn<T>(
+    vault: &str,
+    f: impl FnOnce(&Connection) -> crate::Result<T>,
+) -> crate::Result<T> {
+    match with_vault(vault, f) {
+        Some(result) => result,
+        None => Err(err!(Unavailable)),
+    }
+}
+
+// 上次同步的 ETag 和快照，同步密钥变化后旧的快照无法解密，当作没有
+fn base(conn: &Connection, sync_key: &[u8]) -> crate::Result<(Option<String>, Snapshot)> {
+    let etag = get_conf(conn, "webdav_etag")?;
+    let snapshot = match get_conf(conn, "webdav_base")? {
+        Some(data) => decrypt(sync_key, &data)?.unwrap_or_default(),
+        None => Snapshot::default(),
+    };
+    Ok((etag, snapshot))
+}
+
+fn save_base(conn: &Connection, etag: Option<&str>, data: &str) -> crate::Result<()> {
+    match etag {
+        Some(etag) => set_conf(conn, "webdav_etag", etag)?,
+        None => {
+            conn.execute("DELETE FROM conf WHERE key='webdav_etag'", [])
+                .map_err(err!())?;
+        }
+    }
+    set_conf(conn, "webdav_base", data)
+}
+
+// 发送请求并读取响应直到连接关闭
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    request: &[u8],
+) -> crate::Result<Vec<u8>> {
+    stream.write_all(request).await.map_err(err!())?;
+    stream.flush().await.map_err(err!())?;
+    let mut data = Vec::new();
+    (&mut stream)
+        .take(MAX_RESPONSE + 1)
+        .read_to_end(&mut data)
+        .await
+        .map_err(err!())?;
+    if data.len() as u64 > MAX_RESPONSE {
+        return Err(err!(invalid_data("response too large")));
+    }
+    Ok(data)
+}
+
+fn parse(data: Vec<u8>) -> crate::Result<Response> {
+    let end = match data.windows(4).position(|v| v == b"\r\n\r\n") {
+        Some(v) => v,
+        None => return Err(err!(invalid_data("invalid response"))),
+    };
+    let head = String::from_utf8_lossy(&data[..end]);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|v| v.split_whitespace().nth(1))
+        .and_then(|v| v.parse().ok());
+    let status = match status {
+        Some(v) => v,
+        None => return Err(err!(invalid_data("invalid status line"))),
+    };
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut response = Response {
+        status,
+        headers,
+        body: data[end + 4..].to_vec(),
+    };
+
+    let chunked = response
+        .header("transfer-encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"));
+    if chunked {
+        response.body = dechunk(&response.body)?;
+    } else if let Some(len) = response.header("content-length") {
+        let len: usize = len.parse().map_err(err!())?;
+        if len > response.body.len() {
+            return Err(err!(invalid_data("incomplete response")));
+        }
+        response.body.truncate(len);
+    }
+    Ok(response)
+}
+
+fn dechunk(mut data: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let end = match data.windows(2).position(|v| v == b"\r\n") {
+            Some(v) => v,
+            None => return Err(err!(invalid_data("invalid chunk"))),
+        };
+        let size = String::from_utf8_lossy(&data[..end]);
+        let size = size.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(err!())?;
+        data = &data[end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if data.len() < size + 2 {
+            return Err(err!(invalid_data("incomplete chunk")));
+        }
+        body.extend_from_slice(&data[..size]);
+        data = &data[size + 2..];
+    }
+}
+
+async fn with_timeout<T>(future: impl Future<Output = crate::Result<T>>) -> crate::Result<T> {
+    match timeout(TIMEOUT, future).await {
+        Ok(result) => result,
+        Err(_) => Err(err!(io::Error::from(io::ErrorKind::TimedOut))),
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use rusqlite::params;
+    use tempfile::{tempdir, TempDir};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::crypto::key_encrypt;
+    use crate::db::setup;
+    use crate::keyfile::{self, MasterPassword};
+    use crate::server::open_test_vault;
+    use crate::sync::enable;
+
+    const VAULT_KEY: [u8; 32] = [7; 32];
+
+    // 本地的 WebDAV 替身，支持 GET、PUT、MKCOL 和 ETag 条件请求
+    #[derive(Default)]
+    struct Files {
+        files: HashMap<String, (u64, Vec<u8>)>,
+        etag: u64,
+        // 下一个条件 PUT 返回 412，模拟其他设备同时上传
+        conflict_once: bool,
+    }
+
+    async fn stand_in(files: Arc<Mutex<Files>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(stream, Arc::clone(&files)));
+            }
+        });
+        format!("http://{}/dav/vault", addr)
+    }
+
+    async fn serve(mut stream: TcpStream, files: Arc<Mutex<Files>>) {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        let (head, body) = loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            data.extend_from_slice(&buf[..n]);
+            if let Some(end) = data.windows(4).position(|v| v == b"\r\n\r\n") {
+                let head = String::from_utf8_lossy(&data[..end]).into_owned();
+                break (head, data[end + 4..].to_vec());
+            }
+        };
+        let mut lines = head.split("\r\n");
+        let mut start = lines.next().unwrap().split(' ');
+        let (method, path) = (start.next().unwrap(), start.next().unwrap().to_string());
+        let headers: HashMap<String, String> = lines
+            .filter_map(|v| v.split_once(':'))
+            .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_string()))
+            .collect();
+        let len: usize = headers
+            .get("content-length")
+            .map_or(0, |v| v.parse().unwrap());
+        let mut body = body;
+        while body.len() < len {
+            let n = stream.read(&mut buf).await.unwrap();
+            body.extend_from_slice(&buf[..n]);
+        }
+
+        let auth = format!("Basic {}", base64::encode("user:secret"));
+        let response = if headers.get("authorization") != Some(&auth) {
+            "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n"
+                .to_string()
+                .into_bytes()
+        } else {
+            let mut files = files.lock().unwrap();
+            match method {
+                "MKCOL" => b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                "GET" => match files.files.get(&path) {
+                    // 分块传输
+                    Some((etag, data)) => {
+                        let mut response = format!(
+                            "HTTP/1.1 200 OK\r\nETag: \"{}\"\r\nTransfer-Encoding: chunked\r\n\r\n",
+                            etag
+                        )
+                        .into_bytes();
+                        for chunk in data.chunks(data.len() / 2 + 1) {
+                            response.extend_from_slice(format!("{:x}\r\n", chunk.len()).as_bytes());
+                            response.extend_from_slice(chunk);
+                            response.extend_from_slice(b"\r\n");
+                        }
+                        response.extend_from_slice(b"0\r\n\r\n");
+                        response
+                    }
+                    None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec(),
+                },
+                "PUT" => {
+                    let current = files.files.get(&path).map(|v| format!("\"{}\"", v.0));
+                    let ok = match (headers.get("if-match"), headers.get("if-none-match")) {
+                        (Some(etag), _) => current.as_ref() == Some(etag) && !files.conflict_once,
+                        (None, Some(_)) => current.is_none(),
+                        (None, None) => true,
+                    };
+                    files.conflict_once = false;
+                    if ok {
+                        files.etag += 1;
+                        let etag = files.etag;
+                        files.files.insert(path, (etag, body));
+                        format!(
+                            "HTTP/1.1 201 Created\r\nETag: \"{}\"\r\nContent-Length: 0\r\n\r\n",
+                            etag
+                        )
+                        .into_bytes()
+                    } else {
+                        b"HTTP/1.1 412 Precondition Failed\r\nContent-Length: 0\r\n\r\n".to_vec()
+                    }
+                }
+                _ => b"HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\n\r\n".to_vec(),
+            }
+        };
+        stream.write_all(&response).await.unwrap();
+    }
+
+    fn config(url: &str, password: &str) -> Config {
+        Config {
+            url: url.to_string(),
+            username: Some("user".to_string()),
+            password: Some(password.to_string()),
+        }
+    }
+
+    // 返回的临时目录在测试结束时删除
+    fn open(name: &str, url: &str) -> TempDir {
+        let tmp = tempdir().unwrap();
+        let mut conn = Connection::open_in_memory().unwrap();
+        setup(&mut conn, tmp.path()).unwrap();
+        enable(&conn).unwrap();
+        set_config(&conn, &VAULT_KEY, Some(&config(url, "secret"))).unwrap();
+        open_test_vault(name, tmp.path().to_path_buf(), conn);
+        tmp
+    }
+
+    fn execute(vault: &str, sql: &str, params: impl rusqlite::Params) {
+        with_conn(vault, |conn| {
+            conn.execute(sql, params).unwrap();
+            Ok(())
+        })
+        .unwrap();
+    }
+
+    fn add(vault: &str, name: &str, password: &str) {
+        let name = key_encrypt(VAULT_KEY, name).unwrap();
+        let password = key_encrypt(VAULT_KEY, password).unwrap();
+        let sql = "INSERT INTO vault (key, value, created_at, modified_at) VALUES (?, ?, 1, 1)";
+        execute(vault, sql, params![name, password]);
+    }
+
+    // 名称、密码和版本向量，按 uuid 排序
+    fn dump(vault: &str) -> Vec<(String, String, String)> {
+        with_conn(vault, |conn| {
+            let sql = "SELECT key, value, version FROM vault ORDER BY uuid";
+            let mut stmt = conn.prepare(sql).unwrap();
+            let rows = stmt
+                .query_map([], |row| {
+                    let name: Vec<u8> = row.get(0)?;
+                    let password: Vec<u8> = row.get(1)?;
+                    Ok((name, password, row.get(2)?))
+                })
+                .unwrap()
+                .map(|v| {
+                    let (name, password, version) = v.unwrap();
+                    let decrypt = |v| {
+                        let v = key_decrypt(VAULT_KEY, v).unwrap().unwrap();
+                        String::from_utf8(v).unwrap()
+                    };
+                    (decrypt(name), decrypt(password), version)
+                })
+                .collect();
+            Ok(rows)
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn etag() {
+        let url = stand_in(Default::default()).await;
+        let client = Client::new(&config(&url, "secret")).unwrap();
+        client.mkcol().await.unwrap();
+        assert!(client.get("a").await.unwrap().is_none());
+
+        let etag = match client.put("a", b"1", None).await.unwrap() {
+            Put::Done(etag) => etag.unwrap(),
+            Put::Conflict => panic!("conflict"),
+        };
+        // 文件已存在
+        assert!(matches!(
+            client.put("a", b"2", None).await.unwrap(),
+            Put::Conflict
+        ));
+        assert_eq!(
+            client.get("a").await.unwrap(),
+            Some((Some(etag.clone()), b"1".to_vec()))
+        );
+        assert!(matches!(
+            client.put("a", b"2", Some(&etag)).await.unwrap(),
+            Put::Done(_)
+        ));
+        // ETag 已经变化
+        assert!(matches!(
+            client.put("a", b"3", Some(&etag)).await.unwrap(),
+            Put::Conflict
+        ));
+
+        let client = Client::new(&config(&url, "wrong")).unwrap();
+        let err = client.get("a").await.unwrap_err();
+        assert!(err.to_string().contains("http status 401"));
+    }
+
+    // 开启了密钥文件时，服务器上的保险库密钥同样需要密钥文件
+    #[tokio::test]
+    async fn key_needs_key_file() {
+        let url = stand_in(Default::default()).await;
+        let client = Client::new(&config(&url, "secret")).unwrap();
+        client.mkcol().await.unwrap();
+        let content = base64::encode(b"key file content");
+        let sealed = keyfile::seal("password", keyfile::hash(&content).as_ref(), &VAULT_KEY);
+        let sealed = sealed.unwrap();
+        assert!(matches!(
+            client.put(KEY_FILE, sealed.as_bytes(), None).await.unwrap(),
+            Put::Done(_)
+        ));
+
+        let (_, blob) = client.get(KEY_FILE).await.unwrap().unwrap();
+        let sealed = String::from_utf8(blob).unwrap();
+        let password_only = MasterPassword::Password("password".to_string());
+        assert!(keyfile::unseal(&password_only, &sealed).unwrap().is_none());
+        let with_file = MasterPassword::KeyFile {
+            password: "password".to_string(),
+            key_file: content,
+        };
+        let key = keyfile::unseal(&with_file, &sealed).unwrap();
+        assert_eq!(key.as_deref(), Some(&VAULT_KEY[..]));
+    }
+
+    #[tokio::test]
+    async fn sync_devices() {
+        let files = Arc::new(Mutex::new(Files::default()));
+        let url = stand_in(Arc::clone(&files)).await;
+        let _a = open("webdav-a", &url);
+        let _b = open("webdav-b", &url);
+        add("webdav-a", "a", "1");
+        add("webdav-b", "b", "2");
+
+        let report = sync("webdav-a", &VAULT_KEY).await.unwrap();
+        assert_eq!((report.sent, report.received), (1, 0));
+        let report = sync("webdav-b", &VAULT_KEY).await.unwrap();
+        assert_eq!((report.sent, report.received), (2, 1));
+        let report = sync("webdav-a", &VAULT_KEY).await.unwrap();
+        assert_eq!((report.sent, report.received), (0, 1));
+        assert_eq!(dump("webdav-a"), dump("webdav-b"));
+
+        // 同时修改同一个密码的不同字段，三方合并
+        let name = key_encrypt(VAULT_KEY, "a2").unwrap();
+        execute("webdav-a", "UPDATE vault SET key=? WHERE id=1", [name]);
+        let password = key_encrypt(VAULT_KEY, "3").unwrap();
+        let sql = "UPDATE vault SET value=? WHERE key=(SELECT key FROM vault ORDER BY id LIMIT 1 OFFSET 1)";
+        execute("webdav-b", sql, [password]);
+        sync("webdav-a", &VAULT_KEY).await.unwrap();
+        // 上传时 ETag 不匹配，重新下载合并
+        files.lock().unwrap().conflict_once = true;
+        let report = sync("webdav-b", &VAULT_KEY).await.unwrap();
+        assert_eq!(report.conflicts, 0);
+        sync("webdav-a", &VAULT_KEY).await.unwrap();
+        assert_eq!(dump("webdav-a"), dump("webdav-b"));
+        let merged: Vec<_> = dump("webdav-a").into_iter().map(|v| (v.0, v.1)).collect();
+        assert!(merged.contains(&("a2".to_string(), "3".to_string())));
+
+        // 同时修改同一个字段，保存为冲突
+        let password = key_encrypt(VAULT_KEY, "4").unwrap();
+        execute(
+            "webdav-a",
+            "UPDATE vault SET value=? WHERE id=1",
+            [password],
+        );
+        let password = key_encrypt(VAULT_KEY, "5").unwrap();
+        execute("webdav-b", sql, [password]);
+        sync("webdav-a", &VAULT_KEY).await.unwrap();
+        let report = sync("webdav-b", &VAULT_KEY).await.unwrap();
+        assert_eq!(report.conflicts, 1);
+    }
+}
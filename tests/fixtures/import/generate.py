@@ -0,0 +1,122 @@
+# 生成导入测试数据，格式参照 Bitwarden 和 1Password 8 的导出文件
+# python3 tests/fixtures/import/generate.py（需要 cryptography）
+import base64, hashlib, hmac, json, os, zipfile
+from cryptography.hazmat.primitives import padding
+from cryptography.hazmat.primitives.ciphers import Cipher, algorithms, modes
+
+out = os.path.dirname(os.path.abspath(__file__)) + '/'
+
+folders = [{'id': 'f0c1d5e2-0000-4000-8000-000000000001', 'name': 'Work'}]
+items = [
+    {
+        'id': 'a1', 'organizationId': None, 'folderId': folders[0]['id'], 'type': 1, 'reprompt': 0,
+        'name': 'GitHub', 'notes': 'recovery codes in safe', 'favorite': False,
+        'fields': [
+            {'name': 'PIN', 'value': '4321', 'type': 1, 'linkedId': None},
+            {'name': 'Empty', 'value': None, 'type': 0, 'linkedId': None},
+            {'name': 'Linked', 'value': None, 'type': 3, 'linkedId': 100},
+        ],
+        'login': {'uris': [{'match': None, 'uri': 'https://github.com/login'}], 'username': 'alice',
+                  'password': 'gh-secret', 'totp': 'otpauth://totp/GitHub?secret=JBSWY3DPEHPK3PXP'},
+        'collectionIds': None,
+    },
+    {
+        'id': 'a2', 'organizationId': None, 'folderId': None, 'type': 1, 'reprompt': 0,
+        'name': '', 'notes': None, 'favorite': False,
+        'login': {'uris': [{'match': None, 'uri': 'https://mail.example.com/inbox'}], 'username': 'bob',
+                  'password': 'mail-secret', 'totp': None},
+        'collectionIds': None,
+    },
+    {
+        'id': 'a3', 'organizationId': None, 'folderId': None, 'type': 2, 'reprompt': 0,
+        'name': 'Wi-Fi', 'notes': 'ssid: home\npsk: 12345678', 'favorite': False,
+        'secureNote': {'type': 0}, 'collectionIds': None,
+    },
+    {
+        'id': 'a4', 'organizationId': None, 'folderId': None, 'type': 3, 'reprompt': 0,
+        'name': 'Visa', 'notes': None, 'favorite': False,
+        'card': {'cardholderName': 'Alice', 'brand': 'Visa', 'number': '4111111111111111',
+                 'expMonth': '1', 'expYear': '2030', 'code': '123'},
+        'collectionIds': None,
+    },
+    {
+        'id': 'a5', 'organizationId': None, 'folderId': None, 'type': 4, 'reprompt': 0,
+        'name': 'Passport', 'notes': None, 'favorite': False, 'identity': {'firstName': 'Alice'},
+        'collectionIds': None,
+    },
+]
+plain = {'encrypted': False, 'folders': folders, 'items': items}
+open(out + 'bitwarden.json', 'w').write(json.dumps(plain, indent=2, ensure_ascii=False) + '\n')
+
+# 使用密码加密的导出：PBKDF2-SHA256 + HKDF-Expand + AES-256-CBC + HMAC-SHA256
+password = 'export-password'
+salt = base64.b64encode(os.urandom(16)).decode()
+iterations = 600000
+key = hashlib.pbkdf2_hmac('sha256', password.encode(), salt.encode(), iterations)
+enc_key = hmac.new(key, b'enc\x01', 'sha256').digest()
+mac_key = hmac.new(key, b'mac\x01', 'sha256').digest()
+
+def encrypt(data):
+    iv = os.urandom(16)
+    padder = padding.PKCS7(128).padder()
+    data = padder.update(data) + padder.finalize()
+    enc = Cipher(algorithms.AES(enc_key), modes.CBC(iv)).encryptor()
+    ct = enc.update(data) + enc.finalize()
+    mac = hmac.new(mac_key, iv + ct, 'sha256').digest()
+    return '2.%s|%s|%s' % tuple(base64.b64encode(v).decode() for v in (iv, ct, mac))
+
+encrypted = {
+    'encrypted': True, 'passwordProtected': True, 'salt': salt, 'kdfType': 0,
+    'kdfIterations': iterations, 'kdfMemory': None, 'kdfParallelism': None,
+    'encKeyValidation_DO_NOT_EDIT': encrypt(os.urandom(32).hex().encode()),
+    'data': encrypt(json.dumps({'encrypted': False, 'folders': folders, 'items': items[:1]}).encode()),
+}
+open(out + 'bitwarden_encrypted.json', 'w').write(json.dumps(encrypted, indent=2) + '\n')
+
+# 1PUX：zip 文件，条目在 export.data 中
+def login_field(value, designation, kind):
+    return {'value': value, 'id': '', 'name': designation, 'fieldType': kind, 'designation': designation}
+
+export = {'accounts': [{'attrs': {'accountName': 'Alice', 'name': 'Alice'}, 'vaults': [{
+    'attrs': {'uuid': 'v1', 'name': 'Personal', 'type': 'P'},
+    'items': [
+        {
+            'uuid': 'i1', 'favIndex': 0, 'createdAt': 1700000000, 'updatedAt': 1700000000, 'state': 'active',
+            'categoryUuid': '001',
+            'details': {
+                'loginFields': [login_field('alice@example.com', 'username', 'E'), login_field('op-secret', 'password', 'P')],
+                'notesPlain': 'security questions: none',
+                'sections': [{'title': '', 'name': 'add more', 'fields': [
+                    {'title': 'one-time password', 'id': 'totp', 'value': {'totp': 'otpauth://totp/Example?secret=JBSWY3DPEHPK3PXP'}},
+                    {'title': 'recovery key', 'id': 'rk', 'value': {'concealed': 'rk-1234'}},
+                    {'title': 'expires', 'id': 'exp', 'value': {'date': 1893456000}},
+                    {'title': 'blank', 'id': 'b', 'value': {'string': ''}},
+                ]}],
+                'passwordHistory': [],
+            },
+            'overview': {'subtitle': 'alice@example.com', 'urls': [{'label': '', 'url': 'https://example.com'}],
+                         'title': 'Example', 'url': 'https://example.com'},
+        },
+        {
+            'uuid': 'i2', 'favIndex': 0, 'createdAt': 1700000000, 'updatedAt': 1700000000, 'state': 'active',
+            'categoryUuid': '005',
+            'details': {'loginFields': [], 'notesPlain': '', 'sections': [], 'password': 'router-admin'},
+            'overview': {'subtitle': '', 'title': 'Router', 'url': ''},
+        },
+        {
+            'uuid': 'i3', 'favIndex': 0, 'createdAt': 1700000000, 'updatedAt': 1700000000, 'state': 'active',
+            'categoryUuid': '003',
+            'details': {'loginFields': [], 'notesPlain': 'door code 2580', 'sections': []},
+            'overview': {'subtitle': '', 'title': 'Office', 'url': ''},
+        },
+        {
+            'uuid': 'i4', 'favIndex': 0, 'createdAt': 1700000000, 'updatedAt': 1700000000, 'state': 'active',
+            'categoryUuid': '006',
+            'details': {'loginFields': [], 'sections': [], 'documentAttributes': {'fileName': 'scan.pdf'}},
+            'overview': {'subtitle': '', 'title': 'Scan', 'url': ''},
+        },
+    ],
+}]}]}
+with zipfile.ZipFile(out + 'export.1pux', 'w', zipfile.ZIP_DEFLATED) as archive:
+    archive.writestr('export.attributes', json.dumps({'version': 3, 'description': '1Password Unencrypted Export', 'createdAt': 1700000000}))
+    archive.writestr('export.data', json.dumps(export, indent=2))
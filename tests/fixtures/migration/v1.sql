@@ -0,0 +1,19 @@
+-- 版本 1 的数据库
+CREATE TABLE conf
+(
+    key text not null primary key,
+    value text
+);
+INSERT INTO "conf" VALUES('version','1');
+INSERT INTO "conf" VALUES('key','c2FtcGxlIG1hc3RlciBrZXk=');
+CREATE TABLE vault
+(
+    id integer primary key autoincrement,
+    key blob not null,
+    value blob not null
+);
+INSERT INTO "vault" VALUES(1,X'6E616D652D31',X'70617373776F72642D31');
+INSERT INTO "vault" VALUES(2,X'6E616D652D32',X'70617373776F72642D32');
+CREATE UNIQUE INDEX vault_key_uindex on vault (key);
+DELETE FROM "sqlite_sequence";
+INSERT INTO "sqlite_sequence" VALUES('vault',2);
@@ -0,0 +1,29 @@
+-- 版本 2 的数据库
+CREATE TABLE conf
+(
+    key text not null primary key,
+    value text
+);
+INSERT INTO "conf" VALUES('version','2');
+INSERT INTO "conf" VALUES('key','c2FtcGxlIG1hc3RlciBrZXk=');
+CREATE TABLE history
+(
+    id integer primary key autoincrement,
+    vault_id integer not null,
+    value blob not null,
+    time integer not null
+);
+INSERT INTO "history" VALUES(1,1,X'6F6C64',1600000000);
+CREATE TABLE vault
+(
+    id integer primary key autoincrement,
+    key blob not null,
+    value blob not null
+);
+INSERT INTO "vault" VALUES(1,X'6E616D652D31',X'70617373776F72642D31');
+INSERT INTO "vault" VALUES(2,X'6E616D652D32',X'70617373776F72642D32');
+CREATE UNIQUE INDEX vault_key_uindex on vault (key);
+CREATE INDEX history_vault_id_index on history (vault_id);
+DELETE FROM "sqlite_sequence";
+INSERT INTO "sqlite_sequence" VALUES('vault',2);
+INSERT INTO "sqlite_sequence" VALUES('history',1);
@@ -0,0 +1,30 @@
+-- 版本 4 的数据库
+CREATE TABLE conf
+(
+    key text not null primary key,
+    value text
+);
+INSERT INTO "conf" VALUES('version','4');
+INSERT INTO "conf" VALUES('key','c2FtcGxlIG1hc3RlciBrZXk=');
+CREATE TABLE history
+(
+    id integer primary key autoincrement,
+    vault_id integer not null,
+    value blob not null,
+    time integer not null
+);
+INSERT INTO "history" VALUES(1,1,X'6F6C64',1600000000);
+CREATE TABLE vault
+(
+    id integer primary key autoincrement,
+    key blob not null,
+    value blob not null
+, deleted_at integer, created_at integer not null default 0, modified_at integer not null default 0, accessed_at integer);
+INSERT INTO "vault" VALUES(1,X'6E616D652D31',X'70617373776F72642D31',NULL,1600000000,1600000050,1600000060);
+INSERT INTO "vault" VALUES(2,X'6E616D652D32',X'70617373776F72642D32',1600000100,0,0,NULL);
+CREATE UNIQUE INDEX vault_key_uindex on vault (key);
+CREATE INDEX history_vault_id_index on history (vault_id);
+CREATE INDEX vault_deleted_at_index on vault (deleted_at);
+DELETE FROM "sqlite_sequence";
+INSERT INTO "sqlite_sequence" VALUES('vault',2);
+INSERT INTO "sqlite_sequence" VALUES('history',1);
@@ -0,0 +1,104 @@
+-- 版本 8 的数据库
+CREATE TABLE audit
+(
+    id integer primary key autoincrement,
+    time integer not null,
+    event text not null,
+    detail text not null
+);
+INSERT INTO "audit" VALUES(1,1600000200,'export_plaintext','csv: /tmp/export.csv');
+CREATE TABLE changelog
+(
+    seq integer primary key autoincrement,
+    uuid text not null
+);
+INSERT INTO "changelog" VALUES(1,'00000000000000000000000000000001');
+INSERT INTO "changelog" VALUES(2,'00000000000000000000000000000002');
+INSERT INTO "changelog" VALUES(3,'00000000000000000000000000000003');
+CREATE TABLE conf
+(
+    key text not null primary key,
+    value text
+);
+INSERT INTO "conf" VALUES('version','8');
+INSERT INTO "conf" VALUES('key','c2FtcGxlIG1hc3RlciBrZXk=');
+INSERT INTO "conf" VALUES('device_id','0123456789abcdef0123456789abcdef');
+CREATE TABLE conflict
+(
+    id integer primary key autoincrement,
+    uuid text not null,
+    peer text not null,
+    data text not null,
+    time integer not null
+);
+CREATE TABLE history
+(
+    id integer primary key autoincrement,
+    vault_id integer not null,
+    value blob not null,
+    time integer not null
+);
+INSERT INTO "history" VALUES(1,1,X'6F6C64',1600000000);
+CREATE TABLE peer
+(
+    id integer primary key autoincrement,
+    device text,
+    addr text,
+    sent integer not null default 0,
+    received integer not null default 0,
+    synced_at integer
+);
+INSERT INTO "peer" VALUES(1,'fedcba9876543210fedcba9876543210','192.168.1.2:8001',3,5,1600000400);
+CREATE TABLE quarantine
+(
+    id integer primary key autoincrement,
+    source text not null,
+    source_id integer not null,
+    vault_id integer not null,
+    key blob,
+    value blob not null,
+    time integer not null
+);
+INSERT INTO "quarantine" VALUES(1,'vault',3,3,X'6E616D652D33',X'626164',1600000300);
+CREATE TABLE tombstone
+(
+    uuid text not null primary key,
+    version text not null
+);
+INSERT INTO "tombstone" VALUES('00000000000000000000000000000003','{"0123456789abcdef0123456789abcdef":2}');
+CREATE TABLE vault
+(
+    id integer primary key autoincrement,
+    key blob not null,
+    value blob not null
+, deleted_at integer, created_at integer not null default 0, modified_at integer not null default 0, accessed_at integer, two_factor integer not null default 0, uuid text, version text not null default '{}');
+INSERT INTO "vault" VALUES(1,X'6E616D652D31',X'70617373776F72642D31',NULL,1600000000,1600000050,1600000060,1,'00000000000000000000000000000001','{"0123456789abcdef0123456789abcdef":1}');
+INSERT INTO "vault" VALUES(2,X'6E616D652D32',X'70617373776F72642D32',1600000100,0,0,NULL,0,'00000000000000000000000000000002','{"0123456789abcdef0123456789abcdef":1}');
+CREATE UNIQUE INDEX vault_key_uindex on vault (key);
+CREATE INDEX history_vault_id_index on history (vault_id);
+CREATE INDEX vault_deleted_at_index on vault (deleted_at);
+CREATE UNIQUE INDEX vault_uuid_uindex on vault (uuid);
+CREATE UNIQUE INDEX peer_device_uindex on peer (device);
+CREATE UNIQUE INDEX conflict_uuid_peer_uindex on conflict (uuid, peer);
+CREATE TRIGGER vault_sync_insert after insert on vault when NEW.uuid is null
+begin
+    update vault set uuid=lower(hex(randomblob(16))), version=json_object((SELECT value FROM conf WHERE key='device_id'), 1) where id=NEW.id;
+    insert into changelog (uuid) select uuid from vault where id=NEW.id;
+end;
+CREATE TRIGGER vault_sync_update after update of key, value, deleted_at, two_factor on vault when NEW.version is OLD.version
+begin
+    update vault set version=json_set(version, '$."' || (SELECT value FROM conf WHERE key='device_id') || '"', ifnull(json_extract(version, '$."' || (SELECT value FROM conf WHERE key='device_id') || '"'), 0) + 1) where id=NEW.id;
+    insert into changelog (uuid) values (NEW.uuid);
+end;
+CREATE TRIGGER vault_sync_delete after delete on vault when OLD.uuid is not null
+begin
+    insert into tombstone (uuid, version) select OLD.uuid, json_set(OLD.version, '$."' || (SELECT value FROM conf WHERE key='device_id') || '"', ifnull(json_extract(OLD.version, '$."' || (SELECT value FROM conf WHERE key='device_id') || '"'), 0) + 1) where not exists (select 1 from tombstone where uuid=OLD.uuid);
+    insert into changelog (uuid) values (OLD.uuid);
+end;
+DELETE FROM "sqlite_sequence";
+INSERT INTO "sqlite_sequence" VALUES('vault',2);
+INSERT INTO "sqlite_sequence" VALUES('history',1);
+INSERT INTO "sqlite_sequence" VALUES('audit',1);
+INSERT INTO "sqlite_sequence" VALUES('quarantine',1);
+INSERT INTO "sqlite_sequence" VALUES('changelog',3);
+INSERT INTO "sqlite_sequence" VALUES('peer',1);
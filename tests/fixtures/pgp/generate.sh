@@ -0,0 +1,27 @@
+#!/bin/sh
+# 使用 GnuPG 2.2 生成 pgp 测试数据：RSA 和 Curve25519 密钥，以及 gpg 加密的消息
+# sh tests/fixtures/pgp/generate.sh
+set -e
+cd "$(dirname "$0")"
+export GNUPGHOME="$(mktemp -d)"
+gpg="gpg --batch --yes --pinentry-mode loopback --passphrase key-password --trust-model always"
+
+$gpg --quick-gen-key 'RSA Test <rsa@example.com>' rsa2048 sign never
+rsa=$($gpg --list-keys --with-colons rsa@example.com | awk -F: '/^fpr/ {print $10; exit}')
+$gpg --quick-add-key "$rsa" rsa2048 encr never
+
+$gpg --quick-gen-key 'ECC Test <ecc@example.com>' ed25519 sign never
+ecc=$($gpg --list-keys --with-colons ecc@example.com | awk -F: '/^fpr/ {print $10; exit}')
+$gpg --quick-add-key "$ecc" cv25519 encr never
+
+for name in rsa ecc; do
+    $gpg --armor --export "$name@example.com" > "$name.pub.asc"
+    $gpg --armor --export-secret-keys "$name@example.com" > "$name.sec.asc"
+done
+
+printf '[["GitHub","gh-secret"]]' > message.json
+# gpg 默认使用 ZIP 压缩和分段长度
+$gpg --armor -r rsa@example.com -o rsa.msg.asc -e message.json
+$gpg -r ecc@example.com --compress-algo zlib -o ecc.msg.gpg -e message.json
+$gpg --armor -r rsa@example.com -r ecc@example.com --compress-algo none -o both.msg.asc -e message.json
+rm -rf message.json "$GNUPGHOME"